@@ -17,5 +17,5 @@ cargo build -p subnet --bin subnet
 
 ./target/debug/subnet --help
 
-./target/debug/subnet genesis "hello world"
+./target/debug/subnet genesis
 ./target/debug/subnet vm-id subnet
\ No newline at end of file
@@ -19,5 +19,5 @@ cargo build -p subnet \
 
 ./target/release/subnet --help
 
-./target/release/subnet genesis "hello world"
+./target/release/subnet genesis
 ./target/release/subnet vm-id subnet
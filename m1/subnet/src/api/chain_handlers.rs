@@ -1,18 +1,21 @@
 use std::io;
 use std::marker::PhantomData;
+use std::time::{Duration, Instant};
 
 use aptos_api::accept_type::AcceptType;
 use aptos_api_types::U64;
 use avalanche_types::proto::http::Element;
 use avalanche_types::subnet::rpc::http::handle::Handle;
 use bytes::Bytes;
-use jsonrpc_core::{BoxFuture, Error, ErrorCode, IoHandler, Result};
+use jsonrpc_core::{BoxFuture, Error, ErrorCode, IoHandler, MethodCall, Output, Result};
 use jsonrpc_derive::rpc;
 use serde::{Deserialize, Serialize};
 
 use crate::api::de_request;
+use crate::api::v2_handlers::RpcV2;
+use crate::metrics;
 use crate::util::HexParser;
-use crate::vm::Vm;
+use crate::vm::{ApiContent, ApiResponse, AptosApiError, Vm};
 
 #[rpc]
 pub trait Rpc {
@@ -221,459 +224,221 @@ impl Rpc for ChainService {
     fn get_transactions(&self, args: PageArgs) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_transactions(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_transactions(args).await)
         })
     }
 
     fn submit_transaction(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
-        log::debug!("submit_transaction called");
         let vm = self.vm.clone();
         Box::pin(async move {
-            let accept = if args.is_bcs_format.unwrap_or(false) {
-                AcceptType::Bcs
-            } else {
-                AcceptType::Json
-            };
-            let r = vm
-                .submit_transaction(hex::decode(args.data).map_err(
-                    |e| {
-                        let mut error = Error::new(ErrorCode::InternalError);
-                        error.message = format!("{}", e);
-                        error
-                    }, 
-                )?, accept)
-                .await.map_err(
-                    |e| {
-                        let mut error = Error::new(ErrorCode::InternalError);
-                        error.message = format!("{}", e);
-                        error
-                    },
-                
-                )?;
-            Ok(r)
+            let data = hex::decode(args.data).map_err(create_jsonrpc_error)?;
+            legacy_response(vm.submit_transaction(data, accept_type(args.is_bcs_format)).await)
         })
     }
 
     fn submit_transaction_batch(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let accept = if args.is_bcs_format.unwrap_or(false) {
-                AcceptType::Bcs
-            } else {
-                AcceptType::Json
-            };
-            let r = vm
-                .submit_transaction_batch(hex::decode(args.data).map_err(
-                    |e| {
-                        let mut error = Error::new(ErrorCode::InternalError);
-                        error.message = format!("{}", e);
-                        error
-                    }, 
-                )?, accept)
-                .await.map_err(
-                    |e| {
-                        let mut error = Error::new(ErrorCode::InternalError);
-                        error.message = format!("{}", e);
-                        error
-                    },
-                
-                )?;
-            Ok(r)
+            let data = hex::decode(args.data).map_err(create_jsonrpc_error)?;
+            legacy_response(vm.submit_transaction_batch(data, accept_type(args.is_bcs_format)).await)
         })
     }
 
     fn get_transaction_by_hash(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_transaction_by_hash(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_transaction_by_hash(args).await)
         })
     }
 
-    fn get_transaction_by_version(
-        &self,
-        args: GetTransactionByVersionArgs,
-    ) -> BoxFuture<Result<RpcRes>> {
+    fn get_transaction_by_version(&self, args: GetTransactionByVersionArgs) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_transaction_by_version(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_transaction_by_version(args).await)
         })
     }
 
     fn get_accounts_transactions(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_accounts_transactions(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_accounts_transactions(args).await)
         })
     }
 
     fn simulate_transaction(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let data = hex::decode(args.data).map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            let accept = if args.is_bcs_format.unwrap_or(false) {
-                AcceptType::Bcs
-            } else {
-                AcceptType::Json
-            };
-            let ret = vm.simulate_transaction(data, accept).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            Ok(ret)
+            let data = hex::decode(args.data).map_err(create_jsonrpc_error)?;
+            legacy_response(vm.simulate_transaction(data, accept_type(args.is_bcs_format)).await)
         })
     }
 
     fn encode_submission(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.encode_submission(args.data.as_str()).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.encode_submission(args.data.as_str()).await)
         })
     }
 
     fn estimate_gas_price(&self) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.estimate_gas_price().await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            Ok(ret)
+            legacy_response(vm.estimate_gas_price().await)
         })
     }
 
     fn faucet_apt(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let s = args.data.as_str();
-            let acc = HexParser::parse_hex_string(s).map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            let accept = if args.is_bcs_format.unwrap_or(false) {
-                AcceptType::Bcs
-            } else {
-                AcceptType::Json
-            };
-            let ret = vm.faucet_apt(acc, accept).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            Ok(ret)
+            let acc = HexParser::parse_hex_string(args.data.as_str()).map_err(create_jsonrpc_error)?;
+            legacy_response(vm.faucet_apt(acc, accept_type(args.is_bcs_format)).await)
         })
     }
 
     fn create_account(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let accept = if args.is_bcs_format.unwrap_or(false) {
-                AcceptType::Bcs
-            } else {
-                AcceptType::Json
-            };
-            let s = args.data.as_str();
-            let acc = HexParser::parse_hex_string(s).map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            let ret = vm.create_account(acc, accept).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            Ok(ret)
+            let acc = HexParser::parse_hex_string(args.data.as_str()).map_err(create_jsonrpc_error)?;
+            legacy_response(vm.create_account(acc, accept_type(args.is_bcs_format)).await)
         })
     }
 
     fn get_account(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_account(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_account(args).await)
         })
     }
 
     fn get_account_resources(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_account_resources(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_account_resources(args).await)
         })
     }
 
     fn get_account_modules(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_account_modules(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_account_modules(args).await)
         })
     }
 
     fn get_account_resources_state(&self, args: AccountStateArgs) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_account_resources_state(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_account_resources_state(args).await)
         })
     }
 
     fn get_account_modules_state(&self, args: AccountStateArgs) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_account_modules_state(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_account_modules_state(args).await)
         })
     }
 
     fn get_block_by_height(&self, args: BlockArgs) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_block_by_height(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_block_by_height(args).await)
         })
     }
 
     fn get_block_by_version(&self, args: BlockArgs) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_block_by_version(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_block_by_version(args).await)
         })
     }
 
     fn view_function(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.view_function(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.view_function(args).await)
         })
     }
 
     fn get_table_item(&self, args: RpcTableReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_table_item(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_table_item(args).await)
         })
     }
 
     fn get_raw_table_item(&self, args: RpcTableReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_raw_table_item(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_raw_table_item(args).await)
         })
     }
 
     fn get_events_by_creation_number(&self, args: RpcEventNumReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_events_by_creation_number(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_events_by_creation_number(args).await)
         })
     }
 
     fn get_events_by_event_handle(&self, args: RpcEventHandleReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_events_by_event_handle(args).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_events_by_event_handle(args).await)
         })
     }
 
     fn get_ledger_info(&self) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let ret = vm.get_ledger_info().await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            return Ok(ret);
+            legacy_response(vm.get_ledger_info().await)
         })
     }
 
     fn faucet_with_cli(&self, args: RpcReq) -> BoxFuture<Result<RpcRes>> {
         let vm = self.vm.clone();
         Box::pin(async move {
-            let s = args.data.as_str();
-            let acc = HexParser::parse_hex_string(s).map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            let ret = vm.faucet_with_cli(acc).await.map_err(
-                |e| {
-                    let mut error = Error::new(ErrorCode::InternalError);
-                    error.message = format!("{}", e);
-                    error
-                },
-            
-            )?;
-            Ok(ret)
+            let acc = HexParser::parse_hex_string(args.data.as_str()).map_err(create_jsonrpc_error)?;
+            legacy_response(vm.faucet_with_cli(acc).await)
         })
     }
 }
 
+/// Converts a Vm response to the legacy response, which carries the data and
+/// header as encoded strings and reports Aptos API errors in `error`.
+fn legacy_response(ret: anyhow::Result<ApiResponse>) -> Result<RpcRes> {
+    let resp = match ret {
+        Ok(resp) => resp,
+        Err(e) => {
+            return match e.downcast_ref::<AptosApiError>() {
+                Some(api_err) => Ok(RpcRes {
+                    data: "".to_string(),
+                    header: "".to_string(),
+                    error: Some(api_err.to_string()),
+                }),
+                None => Err(create_jsonrpc_error(e)),
+            }
+        }
+    };
+    let data = match resp.content {
+        ApiContent::Json(json) => serde_json::to_string(&json).map_err(create_jsonrpc_error)?,
+        ApiContent::Bcs(bytes) => hex::encode(bytes),
+    };
+    let header = serde_json::to_string(&resp.header).map_err(create_jsonrpc_error)?;
+    Ok(RpcRes {
+        data,
+        header,
+        error: None,
+    })
+}
+
+pub(crate) fn accept_type(is_bcs_format: Option<bool>) -> AcceptType {
+    if is_bcs_format.unwrap_or(false) {
+        AcceptType::Bcs
+    } else {
+        AcceptType::Json
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ChainHandler<T> {
     pub handler: IoHandler,
@@ -683,15 +448,20 @@ pub struct ChainHandler<T> {
 #[tonic::async_trait]
 impl<T> Handle for ChainHandler<T>
 where
-    T: Rpc + Send + Sync + Clone + 'static,
+    T: Rpc + RpcV2 + Send + Sync + Clone + 'static,
 {
     async fn request(
         &self,
         req: &Bytes,
         _headers: &[Element],
     ) -> io::Result<(Bytes, Vec<Element>)> {
-        match self.handler.handle_request(&de_request(req)?).await {
-            Some(resp) => Ok((Bytes::from(resp), Vec::new())),
+        let req = de_request(req)?;
+        let started = Instant::now();
+        match self.handler.handle_request(&req).await {
+            Some(resp) => {
+                record_rpc(&req, &resp, started.elapsed());
+                Ok((Bytes::from(resp), Vec::new()))
+            }
             None => Err(io::Error::new(
                 io::ErrorKind::Other,
                 "failed to handle request",
@@ -700,10 +470,31 @@ where
     }
 }
 
-impl<T: Rpc> ChainHandler<T> {
+/// Records the latency and outcome of a JSON-RPC request. Requests for
+/// unknown methods are recorded under a single label.
+fn record_rpc(req: &str, resp: &str, elapsed: Duration) {
+    let output = serde_json::from_str::<Output>(resp).ok();
+    let method = match output.as_ref() {
+        Some(Output::Failure(f)) if f.error.code == ErrorCode::MethodNotFound => {
+            "unknown".to_string()
+        }
+        _ => serde_json::from_str::<MethodCall>(req)
+            .map(|call| call.method)
+            .unwrap_or_else(|_| "unknown".to_string()),
+    };
+    metrics::RPC_SECONDS
+        .with_label_values(&[&method])
+        .observe(elapsed.as_secs_f64());
+    if !matches!(output, Some(Output::Success(_))) {
+        metrics::RPC_ERRORS.with_label_values(&[&method]).inc();
+    }
+}
+
+impl<T: Rpc + RpcV2 + Clone> ChainHandler<T> {
     pub fn new(service: T) -> Self {
         let mut handler = jsonrpc_core::IoHandler::new();
-        handler.extend_with(Rpc::to_delegate(service));
+        handler.extend_with(Rpc::to_delegate(service.clone()));
+        handler.extend_with(RpcV2::to_delegate(service));
         Self {
             handler,
             _marker: PhantomData,
@@ -711,8 +502,7 @@ impl<T: Rpc> ChainHandler<T> {
     }
 }
 
-#[allow(dead_code)]
-fn create_jsonrpc_error(e: std::io::Error) -> Error {
+fn create_jsonrpc_error(e: impl std::fmt::Display) -> Error {
     let mut error = Error::new(ErrorCode::InternalError);
     error.message = format!("{}", e);
     error
@@ -0,0 +1,58 @@
+//! Serves the [`metrics`](crate::metrics) of the Vm in the Prometheus text
+//! exposition format, so that they can be scraped from
+//! `[HOST]/ext/bc/[CHAIN ID]/metrics`.
+
+use std::io;
+
+use avalanche_types::proto::http::Element;
+use avalanche_types::subnet::rpc::http::handle::Handle;
+use bytes::Bytes;
+
+use crate::metrics;
+
+/// Extension the metrics are registered under. It is served without the Vm
+/// lock, so that scrapes do not wait for block building.
+pub const METRICS_EXTENSION: &str = "/metrics";
+
+#[derive(Clone, Default)]
+pub struct MetricsHandler;
+
+impl MetricsHandler {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[tonic::async_trait]
+impl Handle for MetricsHandler {
+    async fn request(
+        &self,
+        _req: &Bytes,
+        _headers: &[Element],
+    ) -> io::Result<(Bytes, Vec<Element>)> {
+        let body = metrics::gather()?;
+        let headers = vec![Element {
+            key: "Content-Type".to_string(),
+            values: vec![metrics::CONTENT_TYPE.to_string()],
+        }];
+        Ok((Bytes::from(body), headers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serves_text_exposition() {
+        metrics::ACCEPTED_HEIGHT.set(3);
+        let (body, headers) = MetricsHandler::new()
+            .request(&Bytes::new(), &[])
+            .await
+            .unwrap();
+        assert!(String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains("m1_accepted_height"));
+        assert_eq!(headers[0].values[0], metrics::CONTENT_TYPE);
+    }
+}
@@ -3,12 +3,54 @@
 
 use std::io;
 
+use avalanche_types::proto::http::Element;
+use avalanche_types::subnet::rpc::http::handle::Handle;
 use bytes::Bytes;
 use jsonrpc_core::MethodCall;
 use serde::{Deserialize, Serialize};
 
 pub mod chain_handlers;
+pub mod metrics_handlers;
+pub mod rest_handlers;
 pub mod static_handlers;
+pub mod subscription_handlers;
+pub mod v2_handlers;
+
+use chain_handlers::{ChainHandler, ChainService};
+use metrics_handlers::MetricsHandler;
+use rest_handlers::AptosRestHandler;
+use subscription_handlers::SubscriptionHandler;
+
+/// Handlers of the chain APIs registered by `create_handlers`.
+#[derive(Clone)]
+pub enum ChainApiHandler {
+    /// JSON-RPC APIs, served on `/rpc`.
+    Rpc(ChainHandler<ChainService>),
+    /// Aptos REST API, served on [`REST_EXTENSION`](rest_handlers::REST_EXTENSION).
+    Rest(AptosRestHandler),
+    /// Block and event subscriptions, served on
+    /// [`SUBSCRIPTION_EXTENSION`](subscription_handlers::SUBSCRIPTION_EXTENSION).
+    Subscription(SubscriptionHandler),
+    /// Prometheus metrics, served on
+    /// [`METRICS_EXTENSION`](metrics_handlers::METRICS_EXTENSION).
+    Metrics(MetricsHandler),
+}
+
+#[tonic::async_trait]
+impl Handle for ChainApiHandler {
+    async fn request(
+        &self,
+        req: &Bytes,
+        headers: &[Element],
+    ) -> io::Result<(Bytes, Vec<Element>)> {
+        match self {
+            Self::Rpc(handler) => handler.request(req, headers).await,
+            Self::Rest(handler) => handler.request(req, headers).await,
+            Self::Subscription(handler) => handler.request(req, headers).await,
+            Self::Metrics(handler) => handler.request(req, headers).await,
+        }
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct PingResponse {
@@ -0,0 +1,195 @@
+//! Serves the standard Aptos REST API (`/v1/accounts/...`, `/v1/transactions`,
+//! `/v1/view`, etc.), so that the Aptos SDK and wallets can talk to the subnet
+//! endpoint directly.
+//!
+//! The REST API routes on the method and URL of a request and reports errors
+//! through the status code, while [`Handle`] only carries the body and
+//! headers. The handler therefore serves the avalanchego HTTP gRPC service
+//! itself, and is registered with the address of that service.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use aptos_api::Context;
+use avalanche_types::proto::http::http_server::{Http, HttpServer};
+use avalanche_types::proto::http::{
+    Element, HandleSimpleHttpRequest, HandleSimpleHttpResponse, HttpRequest,
+};
+use avalanche_types::subnet::rpc::http::handle::Handle;
+use bytes::Bytes;
+use poem::endpoint::BoxEndpoint;
+use poem::http::{Method, Uri};
+use poem::{Endpoint, EndpointExt, Route};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::TcpListenerStream;
+
+/// Extension the REST API is registered under, i.e., the SDK base URL is
+/// `[HOST]/ext/bc/[CHAIN ID]/rest/v1`.
+pub const REST_EXTENSION: &str = "/rest";
+
+/// Prefix every route of the Aptos REST API starts with.
+const API_PREFIX: &str = "/v1";
+
+#[derive(Clone)]
+pub struct AptosRestHandler {
+    endpoint: Arc<BoxEndpoint<'static, poem::Response>>,
+}
+
+impl AptosRestHandler {
+    pub fn new(context: Context) -> Self {
+        let api_service = aptos_api::get_api_service(Arc::new(context));
+        let route = Route::new().nest(API_PREFIX, api_service);
+        Self {
+            endpoint: Arc::new(route.map_to_response().boxed()),
+        }
+    }
+
+    /// Serves a REST request and returns the status code, headers and body of
+    /// the response. `url` may carry the route the extension is mounted at;
+    /// everything before the API prefix is ignored.
+    pub async fn serve(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[Element],
+        body: Vec<u8>,
+    ) -> io::Result<(u16, Vec<Element>, Bytes)> {
+        let method = Method::from_bytes(method.as_bytes()).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("invalid method: {}", e))
+        })?;
+        let uri: Uri = api_path(url).parse().map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("invalid url: {}", e))
+        })?;
+
+        let mut req = poem::Request::builder().method(method).uri(uri);
+        for header in headers.iter() {
+            for value in header.values.iter() {
+                req = req.header(header.key.as_str(), value.as_str());
+            }
+        }
+        let resp = self.endpoint.get_response(req.body(body)).await;
+
+        let status = resp.status().as_u16();
+        let mut resp_headers: Vec<Element> = Vec::new();
+        for (key, value) in resp.headers().iter() {
+            let value = match value.to_str() {
+                Ok(value) => value.to_string(),
+                Err(_) => continue,
+            };
+            match resp_headers.iter_mut().find(|h| h.key == key.as_str()) {
+                Some(h) => h.values.push(value),
+                None => resp_headers.push(Element {
+                    key: key.to_string(),
+                    values: vec![value],
+                }),
+            }
+        }
+        let body = resp.into_body().into_bytes().await.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                format!("failed to read response body: {}", e),
+            )
+        })?;
+        Ok((status, resp_headers, body))
+    }
+
+    /// Starts the HTTP gRPC service on a local port. Returns its address and
+    /// the task serving it, which runs until it is aborted.
+    pub async fn start_server(&self) -> io::Result<(SocketAddr, JoinHandle<()>)> {
+        // serve on the bound listener, so that the port cannot be taken
+        // between picking it and serving on it
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let service = HttpServer::new(self.clone());
+        let task = tokio::spawn(async move {
+            if let Err(e) = tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+            {
+                log::error!("Aptos REST API server failed: {}", e);
+            }
+        });
+        log::info!("serving the Aptos REST API on {}", addr);
+        Ok((addr, task))
+    }
+}
+
+/// Returns the part of `url` the Aptos routes match on, e.g.,
+/// `/v1/accounts/0x1` for `/ext/bc/[CHAIN ID]/rest/v1/accounts/0x1`.
+fn api_path(url: &str) -> &str {
+    let path_end = url.find('?').unwrap_or(url.len());
+    let path = &url[..path_end];
+    let start = path
+        .match_indices(API_PREFIX)
+        .map(|(i, _)| i)
+        .find(|i| {
+            let rest = &path[i + API_PREFIX.len()..];
+            rest.is_empty() || rest.starts_with('/')
+        });
+    match start {
+        Some(i) => &url[i..],
+        None => url,
+    }
+}
+
+#[tonic::async_trait]
+impl Http for AptosRestHandler {
+    async fn handle(
+        &self,
+        _req: tonic::Request<HttpRequest>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        Err(tonic::Status::unimplemented(
+            "the Aptos REST API does not support upgraded connections",
+        ))
+    }
+
+    async fn handle_simple(
+        &self,
+        req: tonic::Request<HandleSimpleHttpRequest>,
+    ) -> Result<tonic::Response<HandleSimpleHttpResponse>, tonic::Status> {
+        let req = req.into_inner();
+        let (code, headers, body) = self
+            .serve(&req.method, &req.url, &req.headers, req.body.to_vec())
+            .await
+            .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
+        Ok(tonic::Response::new(HandleSimpleHttpResponse {
+            code: code as i32,
+            headers,
+            body: body.into(),
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl Handle for AptosRestHandler {
+    async fn request(
+        &self,
+        _req: &Bytes,
+        _headers: &[Element],
+    ) -> io::Result<(Bytes, Vec<Element>)> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the Aptos REST API is served over its own HTTP service",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_path() {
+        assert_eq!(api_path("/v1/accounts/0x1"), "/v1/accounts/0x1");
+        assert_eq!(
+            api_path("/ext/bc/2Zi9Ko/rest/v1/accounts/0x1/resources?limit=10"),
+            "/v1/accounts/0x1/resources?limit=10"
+        );
+        assert_eq!(api_path("/ext/bc/v1chain/rest/v1"), "/v1");
+        assert_eq!(api_path("/ext/bc/abc/rest/"), "/ext/bc/abc/rest/");
+        assert_eq!(api_path("/ext/bc/abc/rest/x?q=/v1"), "/ext/bc/abc/rest/x?q=/v1");
+    }
+}
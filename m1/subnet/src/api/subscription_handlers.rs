@@ -0,0 +1,171 @@
+//! Long-poll subscription to the [`Feed`] of committed blocks.
+//!
+//! The avalanchego handler is request/response, so subscribers repeatedly
+//! call `poll` with the `next_version` of the previous result. A poll waits
+//! for new blocks until its timeout, so that blocks are delivered as soon as
+//! they are accepted.
+
+use std::io;
+use std::time::Duration;
+
+use avalanche_types::proto::http::Element;
+use avalanche_types::subnet::rpc::http::handle::Handle;
+use bytes::Bytes;
+use jsonrpc_core::{BoxFuture, Error, ErrorCode, IoHandler, Result};
+use jsonrpc_derive::rpc;
+use serde::{Deserialize, Serialize};
+
+use crate::api::de_request;
+use crate::api::v2_handlers::VERSION_PRUNED;
+use crate::subscription::{Feed, FeedError, FeedFilter, FeedPoll};
+
+/// Extension the subscription APIs are registered under. It is served
+/// without the Vm lock, since polls wait for blocks to be accepted.
+pub const SUBSCRIPTION_EXTENSION: &str = "/feed";
+
+/// Number of blocks a poll returns by default.
+pub const DEFAULT_MAX_BLOCKS: usize = 100;
+
+/// Maximum number of blocks a poll returns.
+pub const MAX_BLOCKS: usize = 1000;
+
+#[rpc]
+pub trait SubscriptionRpc {
+    #[rpc(name = "poll", alias("aptosvm.poll"))]
+    fn poll(&self, args: PollArgs) -> BoxFuture<Result<FeedPoll>>;
+
+    #[rpc(name = "oldestVersion", alias("aptosvm.oldestVersion"))]
+    fn oldest_version(&self) -> BoxFuture<Result<u64>>;
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PollArgs {
+    /// Ledger version to resume from, i.e., the `next_version` of the
+    /// previous poll.
+    pub from_version: u64,
+    #[serde(flatten)]
+    pub filter: FeedFilter,
+    pub max_blocks: Option<usize>,
+    /// How long to wait for new blocks if there are none yet.
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct SubscriptionService {
+    pub feed: Feed,
+}
+
+impl SubscriptionService {
+    pub fn new(feed: Feed) -> Self {
+        Self { feed }
+    }
+}
+
+impl SubscriptionRpc for SubscriptionService {
+    fn poll(&self, args: PollArgs) -> BoxFuture<Result<FeedPoll>> {
+        let feed = self.feed.clone();
+        Box::pin(async move {
+            let max_blocks = args.max_blocks.unwrap_or(DEFAULT_MAX_BLOCKS).min(MAX_BLOCKS);
+            let timeout = Duration::from_millis(args.timeout_ms.unwrap_or(0));
+            feed.poll(&args.filter, args.from_version, max_blocks, timeout)
+                .await
+                .map_err(|e| {
+                    let FeedError::Pruned { oldest_version } = e;
+                    Error {
+                        code: ErrorCode::ServerError(VERSION_PRUNED),
+                        message: e.to_string(),
+                        data: Some(serde_json::json!({ "oldest_version": oldest_version })),
+                    }
+                })
+        })
+    }
+
+    fn oldest_version(&self) -> BoxFuture<Result<u64>> {
+        let feed = self.feed.clone();
+        Box::pin(async move { Ok(feed.oldest_version().await) })
+    }
+}
+
+#[derive(Clone)]
+pub struct SubscriptionHandler {
+    pub handler: IoHandler,
+}
+
+impl SubscriptionHandler {
+    pub fn new(service: SubscriptionService) -> Self {
+        let mut handler = jsonrpc_core::IoHandler::new();
+        handler.extend_with(SubscriptionRpc::to_delegate(service));
+        Self { handler }
+    }
+}
+
+#[tonic::async_trait]
+impl Handle for SubscriptionHandler {
+    async fn request(
+        &self,
+        req: &Bytes,
+        _headers: &[Element],
+    ) -> io::Result<(Bytes, Vec<Element>)> {
+        match self.handler.handle_request(&de_request(req)?).await {
+            Some(resp) => Ok((Bytes::from(resp), Vec::new())),
+            None => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "failed to handle request",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subscription::FeedBlock;
+
+    #[tokio::test]
+    async fn test_poll_over_json_rpc() {
+        let feed = Feed::new(1);
+        feed.start_at(3).await;
+        let handler = SubscriptionHandler::new(SubscriptionService::new(feed.clone()));
+
+        feed.push(FeedBlock {
+            block_id: "a".to_string(),
+            height: 1,
+            timestamp_usecs: 1,
+            first_version: 3,
+            last_version: 4,
+            transactions: Vec::new(),
+        })
+        .await;
+        let resp = handler
+            .handler
+            .handle_request(
+                r#"{"jsonrpc":"2.0","id":1,"method":"poll","params":[{"from_version":3,"stream":"blocks"}]}"#,
+            )
+            .await
+            .unwrap();
+        let resp: serde_json::Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(resp["result"]["next_version"], 5);
+        assert_eq!(resp["result"]["blocks"][0]["height"], 1);
+
+        // the only buffered block is evicted by the next one
+        feed.push(FeedBlock {
+            block_id: "b".to_string(),
+            height: 2,
+            timestamp_usecs: 2,
+            first_version: 5,
+            last_version: 5,
+            transactions: Vec::new(),
+        })
+        .await;
+        let resp = handler
+            .handler
+            .handle_request(
+                r#"{"jsonrpc":"2.0","id":2,"method":"poll","params":[{"from_version":3}]}"#,
+            )
+            .await
+            .unwrap();
+        let resp: serde_json::Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(resp["error"]["code"], VERSION_PRUNED);
+        assert_eq!(resp["error"]["data"]["oldest_version"], 5);
+    }
+}
@@ -0,0 +1,474 @@
+//! Version 2 of the chain APIs. Results are JSON objects rather than JSON
+//! encoded strings, and failures are reported as JSON-RPC errors.
+//!
+//! The methods take the same arguments as their legacy counterparts in
+//! [`chain_handlers`](crate::api::chain_handlers), under the `v2.` prefix.
+
+use jsonrpc_core::{BoxFuture, Error, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+use serde::{Deserialize, Serialize};
+
+use crate::api::chain_handlers::{
+    accept_type, AccountStateArgs, BlockArgs, ChainService, GetTransactionByVersionArgs, PageArgs,
+    RpcEventHandleReq, RpcEventNumReq, RpcReq, RpcTableReq,
+};
+use crate::faucet::FaucetError;
+use crate::util::HexParser;
+use crate::vm::{ApiContent, ApiResponse, AptosApiError, AptosHeader, InvalidInput};
+
+/// Error code of requests for data that does not exist.
+pub const NOT_FOUND: i64 = -32001;
+
+/// Error code of transactions the mempool refused to admit, e.g., because
+/// they failed validation or the mempool is full.
+pub const MEMPOOL_REJECTED: i64 = -32003;
+
+/// Error code of requests for versions that are no longer retained, e.g.,
+/// a `ledger_version` older than the prune window of the node.
+pub const VERSION_PRUNED: i64 = -32004;
+
+/// Error code of faucet requests refused by the faucet, e.g., because it is
+/// disabled or the rate limit is exceeded.
+pub const FAUCET_REJECTED: i64 = -32005;
+
+#[rpc]
+pub trait RpcV2 {
+    /*******************************TRANSACTION START***************************************/
+    #[rpc(name = "v2.getTransactions", alias("aptosvm.v2.getTransactions"))]
+    fn get_transactions(&self, args: PageArgs) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.submitTransaction", alias("aptosvm.v2.submitTransaction"))]
+    fn submit_transaction(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.submitTransactionBatch",
+        alias("aptosvm.v2.submitTransactionBatch")
+    )]
+    fn submit_transaction_batch(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.getTransactionByHash",
+        alias("aptosvm.v2.getTransactionByHash")
+    )]
+    fn get_transaction_by_hash(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.getTransactionByVersion",
+        alias("aptosvm.v2.getTransactionByVersion")
+    )]
+    fn get_transaction_by_version(
+        &self,
+        args: GetTransactionByVersionArgs,
+    ) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.getAccountsTransactions",
+        alias("aptosvm.v2.getAccountsTransactions")
+    )]
+    fn get_accounts_transactions(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.simulateTransaction",
+        alias("aptosvm.v2.simulateTransaction")
+    )]
+    fn simulate_transaction(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.encodeSubmission", alias("aptosvm.v2.encodeSubmission"))]
+    fn encode_submission(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.estimateGasPrice", alias("aptosvm.v2.estimateGasPrice"))]
+    fn estimate_gas_price(&self) -> BoxFuture<Result<RpcResV2>>;
+    /*******************************TRANSACTION END***************************************/
+
+    /*******************************HELPER API START***************************************/
+    #[rpc(name = "v2.faucet", alias("aptosvm.v2.faucet"))]
+    fn faucet_apt(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.faucetWithCli")]
+    fn faucet_with_cli(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.createAccount", alias("aptosvm.v2.createAccount"))]
+    fn create_account(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+    /*******************************HELPER API END***************************************/
+
+    /******************************* ACCOUNT START ***************************************/
+    #[rpc(name = "v2.getAccount", alias("aptosvm.v2.getAccount"))]
+    fn get_account(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.getAccountResources",
+        alias("aptosvm.v2.getAccountResources")
+    )]
+    fn get_account_resources(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.getAccountModules", alias("aptosvm.v2.getAccountModules"))]
+    fn get_account_modules(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.getAccountResourcesState",
+        alias("aptosvm.v2.getAccountResourcesState")
+    )]
+    fn get_account_resources_state(&self, args: AccountStateArgs)
+        -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.getAccountModulesState",
+        alias("aptosvm.v2.getAccountModulesState")
+    )]
+    fn get_account_modules_state(&self, args: AccountStateArgs) -> BoxFuture<Result<RpcResV2>>;
+    /******************************* ACCOUNT END ***************************************/
+
+    /*******************************BLOCK START***************************************/
+    #[rpc(name = "v2.getBlockByHeight", alias("aptosvm.v2.getBlockByHeight"))]
+    fn get_block_by_height(&self, args: BlockArgs) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.getBlockByVersion", alias("aptosvm.v2.getBlockByVersion"))]
+    fn get_block_by_version(&self, args: BlockArgs) -> BoxFuture<Result<RpcResV2>>;
+    /*******************************BLOCK END***************************************/
+
+    #[rpc(name = "v2.viewFunction", alias("aptosvm.v2.viewFunction"))]
+    fn view_function(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.getTableItem", alias("aptosvm.v2.getTableItem"))]
+    fn get_table_item(&self, args: RpcTableReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.getRawTableItem", alias("aptosvm.v2.getRawTableItem"))]
+    fn get_raw_table_item(&self, args: RpcTableReq) -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.getEventsByCreationNumber",
+        alias("aptosvm.v2.getEventsByCreationNumber")
+    )]
+    fn get_events_by_creation_number(&self, args: RpcEventNumReq)
+        -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(
+        name = "v2.getEventsByEventHandle",
+        alias("aptosvm.v2.getEventsByEventHandle")
+    )]
+    fn get_events_by_event_handle(&self, args: RpcEventHandleReq)
+        -> BoxFuture<Result<RpcResV2>>;
+
+    #[rpc(name = "v2.getLedgerInfo", alias("aptosvm.v2.getLedgerInfo"))]
+    fn get_ledger_info(&self) -> BoxFuture<Result<RpcResV2>>;
+}
+
+/// Result of the v2 methods. `data` is the JSON response of the Aptos API,
+/// or the hex encoded BCS response if `is_bcs_format` was requested.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RpcResV2 {
+    pub data: serde_json::Value,
+    pub header: AptosHeader,
+}
+
+impl From<ApiResponse> for RpcResV2 {
+    fn from(resp: ApiResponse) -> Self {
+        let data = match resp.content {
+            ApiContent::Json(json) => json,
+            ApiContent::Bcs(bytes) => serde_json::Value::String(hex::encode(bytes)),
+        };
+        Self {
+            data,
+            header: resp.header,
+        }
+    }
+}
+
+/// Maps a Vm error to a JSON-RPC error. Aptos API errors keep their
+/// [`AptosError`](aptos_api_types::AptosError) body as the error data.
+pub fn to_rpc_error(e: anyhow::Error) -> Error {
+    if let Some(api_err) = e.downcast_ref::<AptosApiError>() {
+        let code = if api_err.is_mempool_rejection() {
+            ErrorCode::ServerError(MEMPOOL_REJECTED)
+        } else if api_err.is_version_pruned() {
+            ErrorCode::ServerError(VERSION_PRUNED)
+        } else {
+            match api_err.status {
+                404 => ErrorCode::ServerError(NOT_FOUND),
+                400 => ErrorCode::InvalidParams,
+                _ => ErrorCode::InternalError,
+            }
+        };
+        return Error {
+            code,
+            message: api_err.error.message.clone(),
+            data: serde_json::to_value(&api_err.error).ok(),
+        };
+    }
+    if let Some(faucet_err) = e.downcast_ref::<FaucetError>() {
+        let data = match faucet_err {
+            FaucetError::RateLimited { retry_after_secs } => {
+                Some(serde_json::json!({ "retry_after_secs": retry_after_secs }))
+            }
+            _ => None,
+        };
+        return Error {
+            code: ErrorCode::ServerError(FAUCET_REJECTED),
+            message: faucet_err.to_string(),
+            data,
+        };
+    }
+    if e.downcast_ref::<InvalidInput>().is_some() {
+        return Error::invalid_params(format!("{:#}", e));
+    }
+
+    let mut error = Error::new(ErrorCode::InternalError);
+    error.message = format!("{}", e);
+    error
+}
+
+fn respond(ret: anyhow::Result<ApiResponse>) -> Result<RpcResV2> {
+    ret.map(RpcResV2::from).map_err(to_rpc_error)
+}
+
+fn invalid_params(e: impl std::fmt::Display) -> Error {
+    Error::invalid_params(format!("{}", e))
+}
+
+impl RpcV2 for ChainService {
+    fn get_transactions(&self, args: PageArgs) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_transactions(args).await) })
+    }
+
+    fn submit_transaction(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move {
+            let data = hex::decode(args.data).map_err(invalid_params)?;
+            respond(vm.submit_transaction(data, accept_type(args.is_bcs_format)).await)
+        })
+    }
+
+    fn submit_transaction_batch(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move {
+            let data = hex::decode(args.data).map_err(invalid_params)?;
+            respond(vm.submit_transaction_batch(data, accept_type(args.is_bcs_format)).await)
+        })
+    }
+
+    fn get_transaction_by_hash(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_transaction_by_hash(args).await) })
+    }
+
+    fn get_transaction_by_version(
+        &self,
+        args: GetTransactionByVersionArgs,
+    ) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_transaction_by_version(args).await) })
+    }
+
+    fn get_accounts_transactions(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_accounts_transactions(args).await) })
+    }
+
+    fn simulate_transaction(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move {
+            let data = hex::decode(args.data).map_err(invalid_params)?;
+            respond(vm.simulate_transaction(data, accept_type(args.is_bcs_format)).await)
+        })
+    }
+
+    fn encode_submission(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.encode_submission(args.data.as_str()).await) })
+    }
+
+    fn estimate_gas_price(&self) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.estimate_gas_price().await) })
+    }
+
+    fn faucet_apt(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move {
+            let acc = HexParser::parse_hex_string(args.data.as_str()).map_err(invalid_params)?;
+            respond(vm.faucet_apt(acc, accept_type(args.is_bcs_format)).await)
+        })
+    }
+
+    fn faucet_with_cli(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move {
+            let acc = HexParser::parse_hex_string(args.data.as_str()).map_err(invalid_params)?;
+            respond(vm.faucet_with_cli(acc).await)
+        })
+    }
+
+    fn create_account(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move {
+            let acc = HexParser::parse_hex_string(args.data.as_str()).map_err(invalid_params)?;
+            respond(vm.create_account(acc, accept_type(args.is_bcs_format)).await)
+        })
+    }
+
+    fn get_account(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_account(args).await) })
+    }
+
+    fn get_account_resources(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_account_resources(args).await) })
+    }
+
+    fn get_account_modules(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_account_modules(args).await) })
+    }
+
+    fn get_account_resources_state(
+        &self,
+        args: AccountStateArgs,
+    ) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_account_resources_state(args).await) })
+    }
+
+    fn get_account_modules_state(&self, args: AccountStateArgs) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_account_modules_state(args).await) })
+    }
+
+    fn get_block_by_height(&self, args: BlockArgs) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_block_by_height(args).await) })
+    }
+
+    fn get_block_by_version(&self, args: BlockArgs) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_block_by_version(args).await) })
+    }
+
+    fn view_function(&self, args: RpcReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.view_function(args).await) })
+    }
+
+    fn get_table_item(&self, args: RpcTableReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_table_item(args).await) })
+    }
+
+    fn get_raw_table_item(&self, args: RpcTableReq) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_raw_table_item(args).await) })
+    }
+
+    fn get_events_by_creation_number(
+        &self,
+        args: RpcEventNumReq,
+    ) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_events_by_creation_number(args).await) })
+    }
+
+    fn get_events_by_event_handle(
+        &self,
+        args: RpcEventHandleReq,
+    ) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_events_by_event_handle(args).await) })
+    }
+
+    fn get_ledger_info(&self) -> BoxFuture<Result<RpcResV2>> {
+        let vm = self.vm.clone();
+        Box::pin(async move { respond(vm.get_ledger_info().await) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+    use aptos_api_types::{AptosError, AptosErrorCode};
+
+    fn api_error(status: u16, error_code: AptosErrorCode) -> anyhow::Error {
+        AptosApiError {
+            status,
+            error: AptosError::new_with_error_code("failed", error_code),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_api_errors_map_to_rpc_error_codes() {
+        let err = to_rpc_error(api_error(404, AptosErrorCode::AccountNotFound));
+        assert_eq!(err.code, ErrorCode::ServerError(NOT_FOUND));
+        assert_eq!(err.message, "failed");
+        let data = err.data.unwrap();
+        assert_eq!(data["error_code"], "account_not_found");
+
+        let err = to_rpc_error(api_error(410, AptosErrorCode::VersionPruned));
+        assert_eq!(err.code, ErrorCode::ServerError(VERSION_PRUNED));
+        assert_eq!(err.data.unwrap()["error_code"], "version_pruned");
+
+        let err = to_rpc_error(api_error(400, AptosErrorCode::InvalidInput));
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+
+        for code in [AptosErrorCode::VmError, AptosErrorCode::SequenceNumberTooOld] {
+            let err = to_rpc_error(api_error(400, code));
+            assert_eq!(err.code, ErrorCode::ServerError(MEMPOOL_REJECTED));
+        }
+        let err = to_rpc_error(api_error(507, AptosErrorCode::MempoolIsFull));
+        assert_eq!(err.code, ErrorCode::ServerError(MEMPOOL_REJECTED));
+
+        let err = to_rpc_error(api_error(500, AptosErrorCode::InternalError));
+        assert_eq!(err.code, ErrorCode::InternalError);
+    }
+
+    #[test]
+    fn test_vm_errors_map_to_rpc_error_codes() {
+        let err = to_rpc_error(
+            Err::<(), _>(anyhow::anyhow!("odd number of digits"))
+                .context(InvalidInput("Invalid account address"))
+                .unwrap_err(),
+        );
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+        assert!(err.message.starts_with("Invalid account address"));
+
+        let err = to_rpc_error(anyhow::anyhow!("API service not available"));
+        assert_eq!(err.code, ErrorCode::InternalError);
+        assert_eq!(err.message, "API service not available");
+
+        let err = to_rpc_error(FaucetError::RateLimited { retry_after_secs: 7 }.into());
+        assert_eq!(err.code, ErrorCode::ServerError(FAUCET_REJECTED));
+        assert_eq!(err.data.unwrap()["retry_after_secs"], 7);
+        let err = to_rpc_error(FaucetError::Disabled.into());
+        assert_eq!(err.code, ErrorCode::ServerError(FAUCET_REJECTED));
+        assert_eq!(err.message, "faucet is disabled");
+    }
+
+    #[test]
+    fn test_response_is_structured() {
+        let header = AptosHeader {
+            chain_id: 4,
+            ledger_version: 10,
+            ledger_oldest_version: 0,
+            ledger_timestamp_usec: 1_000_000,
+            epoch: 1,
+            block_height: 2,
+            oldest_block_height: 0,
+            cursor: None,
+        };
+        let res = RpcResV2::from(ApiResponse {
+            content: ApiContent::Json(serde_json::json!({"sequence_number": "0"})),
+            header: header.clone(),
+        });
+        let encoded = serde_json::to_value(&res).unwrap();
+        assert_eq!(encoded["data"]["sequence_number"], "0");
+        assert_eq!(encoded["header"]["ledger_version"], 10);
+        assert_eq!(encoded["header"]["chain_id"], 4);
+
+        let res = RpcResV2::from(ApiResponse {
+            content: ApiContent::Bcs(vec![0xab, 0xcd]),
+            header,
+        });
+        assert_eq!(res.data, serde_json::json!("abcd"));
+    }
+}
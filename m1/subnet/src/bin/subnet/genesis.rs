@@ -1,4 +1,13 @@
-use clap::{arg, Command};
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+};
+
+use aptos_crypto::{bls12381, ed25519::Ed25519PublicKey, ValidCryptoMaterialStringExt};
+use aptos_types::account_address::AccountAddress;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command};
+use subnet::genesis::{self, Genesis, GenesisAccount, GenesisValidator};
 
 pub const NAME: &str = "genesis";
 
@@ -6,6 +15,208 @@ pub const NAME: &str = "genesis";
 pub fn command() -> Command {
     Command::new(NAME)
         .about("Write a genesis file")
-        .arg(arg!(<DATA> "Genesis message data"))
-        .arg_required_else_help(true)
+        .arg(
+            arg!(--"chain-id" <CHAIN_ID> "Aptos chain id")
+                .value_parser(value_parser!(u8))
+                .default_value(genesis::DEFAULT_CHAIN_ID.to_string()),
+        )
+        .arg(
+            arg!(--validator <VALIDATOR> "Validator registered at genesis, as ADDRESS,CONSENSUS_PUBLIC_KEY,PROOF_OF_POSSESSION,STAKE")
+                .action(ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            arg!(--"validator-count" <COUNT> "Number of test validators, if no validator is given on the test chain")
+                .value_parser(value_parser!(usize))
+                .default_value(genesis::DEFAULT_VALIDATOR_COUNT.to_string()),
+        )
+        .arg(
+            arg!(--"validator-stake" <OCTAS> "Stake of each test validator")
+                .value_parser(value_parser!(u64))
+                .default_value(genesis::DEFAULT_VALIDATOR_STAKE.to_string()),
+        )
+        .arg(
+            arg!(--"epoch-duration-secs" <SECS> "Length of an Aptos epoch")
+                .value_parser(value_parser!(u64))
+                .default_value(genesis::DEFAULT_EPOCH_DURATION_SECS.to_string()),
+        )
+        .arg(
+            arg!(--"root-key" <PUBLIC_KEY> "Ed25519 public key of the core resources account funding the accounts")
+                .required(false),
+        )
+        .arg(
+            arg!(--account <ACCOUNT> "Account funded at genesis, as ADDRESS=BALANCE")
+                .action(ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            arg!(--framework <PATH> "Framework release bundle (.mrb) installed at genesis")
+                .required(false),
+        )
+        .arg(
+            arg!(--"gas-schedule" <PATH> "JSON gas schedule installed at genesis")
+                .required(false),
+        )
+        .arg(
+            arg!(--output <PATH> "Writes the genesis to a file instead of stdout")
+                .required(false),
+        )
+}
+
+/// Builds the genesis document from the command arguments and writes it out.
+pub fn execute(matches: &ArgMatches) -> io::Result<()> {
+    let bytes = build(matches)?.to_vec()?;
+    match matches.get_one::<String>("output") {
+        Some(path) => fs::write(path, bytes),
+        None => {
+            println!("{}", String::from_utf8_lossy(&bytes));
+            Ok(())
+        }
+    }
+}
+
+/// Builds and verifies the genesis document from the command arguments.
+fn build(matches: &ArgMatches) -> io::Result<Genesis> {
+    let validators = matches
+        .get_many::<String>("validator")
+        .unwrap_or_default()
+        .map(String::as_str)
+        .map(parse_validator)
+        .collect::<io::Result<Vec<GenesisValidator>>>()?;
+    let root_key = matches
+        .get_one::<String>("root-key")
+        .map(|key| {
+            Ed25519PublicKey::from_encoded_string(key).map_err(|e| {
+                Error::new(ErrorKind::InvalidInput, format!("invalid root key {}: {}", key, e))
+            })
+        })
+        .transpose()?;
+    let accounts = matches
+        .get_many::<String>("account")
+        .unwrap_or_default()
+        .map(String::as_str)
+        .map(parse_account)
+        .collect::<io::Result<Vec<GenesisAccount>>>()?;
+
+    let framework = matches
+        .get_one::<String>("framework")
+        .map(|path| fs::read(path).map(|bytes| BASE64.encode(bytes)))
+        .transpose()?;
+    let gas_schedule = matches
+        .get_one::<String>("gas-schedule")
+        .map(|path| {
+            serde_json::from_slice(&fs::read(path)?).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to parse gas schedule {}: {}", path, e),
+                )
+            })
+        })
+        .transpose()?;
+
+    let genesis = Genesis {
+        chain_id: *matches.get_one::<u8>("chain-id").expect("has default"),
+        validators,
+        validator_count: *matches.get_one::<usize>("validator-count").expect("has default"),
+        validator_stake: *matches.get_one::<u64>("validator-stake").expect("has default"),
+        epoch_duration_secs: *matches.get_one::<u64>("epoch-duration-secs").expect("has default"),
+        root_key,
+        accounts,
+        framework,
+        gas_schedule,
+    };
+    genesis.verify()?;
+    Ok(genesis)
+}
+
+fn parse_validator(s: &str) -> io::Result<GenesisValidator> {
+    let invalid = |what: &str, e: &dyn std::fmt::Display| {
+        Error::new(ErrorKind::InvalidInput, format!("invalid {} of validator {}: {}", what, s, e))
+    };
+    let fields: Vec<&str> = s.split(',').collect();
+    let [address, consensus_public_key, proof_of_possession, stake] = fields[..] else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "validator {} is not of the form ADDRESS,CONSENSUS_PUBLIC_KEY,PROOF_OF_POSSESSION,STAKE",
+                s
+            ),
+        ));
+    };
+    Ok(GenesisValidator {
+        address: parse_address(address)?,
+        consensus_public_key: bls12381::PublicKey::from_encoded_string(consensus_public_key)
+            .map_err(|e| invalid("consensus public key", &e))?,
+        proof_of_possession: bls12381::ProofOfPossession::from_encoded_string(proof_of_possession)
+            .map_err(|e| invalid("proof of possession", &e))?,
+        stake: stake.parse::<u64>().map_err(|e| invalid("stake", &e))?,
+    })
+}
+
+fn parse_address(address: &str) -> io::Result<AccountAddress> {
+    AccountAddress::from_hex_literal(address)
+        .or_else(|_| AccountAddress::from_hex(address))
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid address {}: {}", address, e)))
+}
+
+fn parse_account(s: &str) -> io::Result<GenesisAccount> {
+    let (address, balance) = s.split_once('=').ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("account {} is not of the form ADDRESS=BALANCE", s),
+        )
+    })?;
+    let address = parse_address(address)?;
+    let balance = balance
+        .parse::<u64>()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid balance {}: {}", balance, e)))?;
+    Ok(GenesisAccount { address, balance })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_crypto::{ed25519::Ed25519PrivateKey, Uniform};
+    use aptos_types::chain_id::ChainId;
+
+    fn build_from(args: &[&str]) -> io::Result<Genesis> {
+        let matches = command().get_matches_from(std::iter::once(NAME).chain(args.iter().copied()));
+        build(&matches)
+    }
+
+    #[test]
+    fn test_genesis_of_another_chain() {
+        let consensus_key = bls12381::PrivateKey::generate(&mut rand::rngs::OsRng);
+        let root_key = Ed25519PrivateKey::generate(&mut rand::rngs::OsRng);
+        let (validator, account) = (AccountAddress::random(), AccountAddress::random());
+        let validator_arg = format!(
+            "{},{},{},1000",
+            validator.to_hex_literal(),
+            bls12381::PublicKey::from(&consensus_key).to_encoded_string().unwrap(),
+            bls12381::ProofOfPossession::create(&consensus_key).to_encoded_string().unwrap(),
+        );
+        let root_key_arg = Ed25519PublicKey::from(&root_key).to_encoded_string().unwrap();
+        let account_arg = format!("{}=500", account.to_hex_literal());
+
+        let genesis = build_from(&[
+            "--chain-id", "42",
+            "--validator", &validator_arg,
+            "--root-key", &root_key_arg,
+            "--account", &account_arg,
+        ])
+        .unwrap();
+        assert_eq!(genesis.chain_id(), ChainId::new(42));
+        assert_eq!(genesis.validators.len(), 1);
+        assert_eq!(genesis.validators[0].address, validator);
+        assert_eq!(genesis.validators[0].stake, 1000);
+        assert_eq!(genesis.core_resources_key(), Some(Ed25519PublicKey::from(&root_key)));
+        assert_eq!(genesis.accounts, vec![GenesisAccount { address: account, balance: 500 }]);
+        Genesis::from_slice(genesis.to_vec().unwrap()).unwrap();
+
+        // the test validators and the test root key only serve the test chain
+        assert!(build_from(&["--chain-id", "42"]).is_err());
+        assert!(build_from(&["--chain-id", "42", "--validator", &validator_arg, "--account", &account_arg]).is_err());
+        assert!(build_from(&["--account", &account_arg]).is_ok());
+        assert!(build_from(&["--chain-id", "42", "--validator", "0x1,0x2"]).is_err());
+    }
 }
@@ -23,13 +23,7 @@ async fn main() -> io::Result<()> {
     );
 
     match matches.subcommand() {
-        Some((genesis::NAME, sub_matches)) => {
-            let data = sub_matches.get_one::<String>("DATA").expect("required");
-            let genesis = data;
-            println!("{genesis}");
-
-            Ok(())
-        }
+        Some((genesis::NAME, sub_matches)) => genesis::execute(sub_matches),
 
         Some((vm_id::NAME, sub_matches)) => {
             let vm_name = sub_matches.get_one::<String>("VM_NAME").expect("required");
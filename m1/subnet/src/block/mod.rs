@@ -11,7 +11,43 @@ use derivative::{self, Derivative};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
-use crate::state;
+use crate::{metrics, state};
+
+/// Version byte prefixed to the binary encoding of a [`Block`].
+/// Blocks accepted before the binary codec are JSON objects and start with `{`.
+pub const BLOCK_CODEC_VERSION: u8 = 1;
+
+/// Encoding of a [`Block`] and of the [`AptosData`](crate::vm::AptosData) it carries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlockCodec {
+    /// JSON encoding of the blocks accepted before the binary codec.
+    Json,
+    /// Versioned binary encoding, see [`BLOCK_CODEC_VERSION`].
+    #[default]
+    Binary,
+}
+
+impl BlockCodec {
+    /// Returns the codec of the block at `height`, given the height the binary
+    /// codec activates at, if any. The genesis block and the blocks below the
+    /// activation height keep the JSON encoding, so that their Ids, which are
+    /// the hashes of their bytes, do not change.
+    pub fn at_height(height: u64, activation_height: Option<u64>) -> Self {
+        match activation_height {
+            Some(activation_height) if height > 0 && height >= activation_height => Self::Binary,
+            _ => Self::Json,
+        }
+    }
+}
+
+/// Binary wire format of a [`Block`], BCS-encoded after [`BLOCK_CODEC_VERSION`].
+#[derive(Serialize, Deserialize)]
+struct BlockWire {
+    parent_id: [u8; ids::LEN],
+    height: u64,
+    timestamp: u64,
+    data: Vec<u8>,
+}
 
 /// Represents a block, specific to [`Vm`](crate::vm::Vm).
 #[serde_as]
@@ -37,6 +73,9 @@ pub struct Block {
     /// Generated block Id.
     #[serde(skip)]
     id: ids::Id,
+    /// Encoding of this block's bytes.
+    #[serde(skip)]
+    codec: BlockCodec,
 
     /// Reference to the Vm state manager for blocks.
     #[derivative(Debug = "ignore", PartialEq = "ignore")]
@@ -45,12 +84,30 @@ pub struct Block {
 }
 
 impl Block {
+    /// Creates a block encoded with the codec of its height, given the height
+    /// the binary codec activates at, see [`BlockCodec::at_height`]. Its data
+    /// is expected to be encoded with the same codec.
     pub fn new(
         parent_id: ids::Id,
         height: u64,
         timestamp: u64,
         data: Vec<u8>,
         status: choices::status::Status,
+        codec_activation_height: Option<u64>,
+    ) -> io::Result<Self> {
+        let codec = BlockCodec::at_height(height, codec_activation_height);
+        Self::new_with_codec(parent_id, height, timestamp, data, status, codec)
+    }
+
+    /// Creates a block encoded with `codec`, which its data is expected to be
+    /// encoded with too.
+    pub fn new_with_codec(
+        parent_id: ids::Id,
+        height: u64,
+        timestamp: u64,
+        data: Vec<u8>,
+        status: choices::status::Status,
+        codec: BlockCodec,
     ) -> io::Result<Self> {
         let mut b = Self {
             parent_id,
@@ -60,6 +117,7 @@ impl Block {
             status: choices::status::Status::default(),
             bytes: Vec::new(),
             id: ids::Id::empty(),
+            codec,
             state: state::State::default(),
         };
 
@@ -79,25 +137,81 @@ impl Block {
         })
     }
 
-    /// Encodes the [`Block`](Block) to JSON in bytes.
+    /// Encodes the [`Block`](Block) with its codec.
     pub fn to_slice(&self) -> io::Result<Vec<u8>> {
-        serde_json::to_vec(&self).map_err(|e| {
+        if self.codec == BlockCodec::Json {
+            return serde_json::to_vec(&self).map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to serialize Block to JSON bytes {}", e),
+                )
+            });
+        }
+
+        let mut parent_id = [0u8; ids::LEN];
+        parent_id.copy_from_slice(&self.parent_id.to_vec());
+        let wire = BlockWire {
+            parent_id,
+            height: self.height,
+            timestamp: self.timestamp,
+            data: self.data.clone(),
+        };
+        let encoded = bcs::to_bytes(&wire).map_err(|e| {
             Error::new(
                 ErrorKind::Other,
-                format!("failed to serialize Block to JSON bytes {}", e),
+                format!("failed to serialize Block to bytes {}", e),
             )
-        })
+        })?;
+
+        let mut bytes = Vec::with_capacity(1 + encoded.len());
+        bytes.push(BLOCK_CODEC_VERSION);
+        bytes.extend_from_slice(&encoded);
+        Ok(bytes)
     }
 
-    /// Loads [`Block`](Block) from JSON bytes.
+    /// Loads [`Block`](Block) from its versioned binary format, or from the
+    /// legacy JSON format of blocks accepted before the binary codec.
+    /// The block Id is always the hash of the given bytes, so legacy blocks keep their Ids.
     pub fn from_slice(d: impl AsRef<[u8]>) -> io::Result<Self> {
         let dd = d.as_ref();
-        let mut b: Self = serde_json::from_slice(dd).map_err(|e| {
-            Error::new(
-                ErrorKind::Other,
-                format!("failed to deserialize Block from JSON {}", e),
-            )
-        })?;
+        let mut b: Self = match dd.first() {
+            Some(&BLOCK_CODEC_VERSION) => {
+                let wire: BlockWire = bcs::from_bytes(&dd[1..]).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("failed to deserialize Block from bytes {}", e),
+                    )
+                })?;
+                Self {
+                    parent_id: ids::Id::from_slice(&wire.parent_id),
+                    height: wire.height,
+                    timestamp: wire.timestamp,
+                    data: wire.data,
+                    status: choices::status::Status::default(),
+                    bytes: Vec::new(),
+                    id: ids::Id::empty(),
+                    codec: BlockCodec::Binary,
+                    state: state::State::default(),
+                }
+            },
+            Some(b'{') => {
+                let mut b: Self = serde_json::from_slice(dd).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("failed to deserialize Block from JSON {}", e),
+                    )
+                })?;
+                b.codec = BlockCodec::Json;
+                b
+            },
+            Some(v) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unsupported Block encoding version {}", v),
+                ))
+            },
+            None => return Err(Error::new(ErrorKind::InvalidData, "empty Block bytes")),
+        };
 
         b.bytes = dd.to_vec();
         b.id = ids::Id::sha256(&b.bytes);
@@ -149,12 +263,18 @@ impl Block {
         self.id
     }
 
+    /// Returns the encoding of this block.
+    pub fn codec(&self) -> BlockCodec {
+        self.codec
+    }
+
     /// Updates the state of the block.
     pub fn set_state(&mut self, state: state::State) {
         self.state = state;
     }
 
-    /// Verifies [`Block`](Block) properties (e.g., heights),
+    /// Verifies [`Block`](Block) properties (e.g., heights) and speculatively
+    /// executes its transactions on top of its parent,
     /// and once verified, records it to the [`State`](crate::state::State).
     pub async fn verify(&mut self) -> io::Result<()> {
         if self.height == 0 && self.parent_id == ids::Id::empty() {
@@ -172,8 +292,35 @@ impl Block {
             log::debug!("block {} already verified", self.id);
             return Ok(());
         }
+        // ensure the block is encoded as every validator encodes blocks at its
+        // height, since its Id is the hash of its bytes
+        let codec = BlockCodec::at_height(self.height, self.state.block_codec_activation_height);
+        if self.codec != codec {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "block codec {:?} != codec {:?} of height {}",
+                    self.codec, codec, self.height
+                ),
+            ));
+        }
+
         let prnt_blk = self.state.get_block(&self.parent_id).await?;
 
+        // ensure the parent is either processing or the last accepted block,
+        // since decided branches other than the last accepted one cannot grow
+        if !self.state.has_verified(&self.parent_id).await
+            && self.state.get_last_accepted_block_id().await? != self.parent_id
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "parent block {} is neither processing nor the last accepted block",
+                    self.parent_id
+                ),
+            ));
+        }
+
         // ensure the height of the block is immediately following its parent
         if prnt_blk.height != self.height - 1 {
             return Err(Error::new(
@@ -207,29 +354,53 @@ impl Block {
             ));
         }
 
+        // ensure the block data is valid and executes on top of its parent
+        self.execute(&prnt_blk).await?;
+
         // add newly verified block to memory
         self.state.add_verified(&self.clone()).await;
         Ok(())
     }
 
+    async fn execute(&self, parent: &Block) -> io::Result<()> {
+        if let Some(vm_) = self.state.vm.as_ref() {
+            let vm = vm_.read().await;
+            vm.execute_block(self, parent).await.map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to execute block: {}", e),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
     /// Mark this [`Block`](Block) accepted and updates [`State`](crate::state::State) accordingly.
     pub async fn accept(&mut self) -> io::Result<()> {
         log::info!("accept block height {} ", self.height);
-        self.execute_and_commit().await?;
+        // recorded first, so that a node stopped after the AptosDB commit can
+        // complete the acceptance on restart
+        self.state.set_accepting_block(self).await?;
+        self.commit().await?;
         self.set_status(choices::status::Status::Accepted);
         // only decided blocks are persistent -- no reorg
         self.state.write_block(&self.clone()).await?;
+        self.state.set_block_id_at_height(self.height, &self.id()).await?;
         self.state.set_last_accepted_block(&self.id()).await?;
-        // ! this should not be removed from the verified blocks
-        // self.state.remove_verified(&self.id()).await;
+        self.state.clear_accepting_block().await?;
+        metrics::ACCEPTED_HEIGHT.set(self.height as i64);
+        // the block is now persisted, so drop it from memory along with the
+        // branches that conflict with it
+        let pruned = self.state.accept_verified(&self.id()).await;
+        self.discard(&pruned).await;
         Ok(())
     }
 
-    async fn execute_and_commit(&self) -> io::Result<()> {
+    async fn commit(&self) -> io::Result<()> {
         if let Some(vm_) = self.state.vm.as_ref() {
             let vm = vm_.read().await;
-            return vm.execute_and_commit_block(self.data.clone()).await.map_err(
-                |e| Error::new(ErrorKind::Other, format!("failed to build block: {}", e)),
+            return vm.commit_block(self).await.map_err(
+                |e| Error::new(ErrorKind::Other, format!("failed to commit block: {}", e)),
             );
         }
         return Ok(());
@@ -242,9 +413,20 @@ impl Block {
         // only decided blocks are persistent -- no reorg
         self.state.write_block(&self.clone()).await?;
 
-        self.state.remove_verified(&self.id()).await;
+        let removed = self.state.remove_verified(&self.id()).await;
+        self.discard(&removed).await;
         Ok(())
     }
+
+    async fn discard(&self, blk_ids: &[ids::Id]) {
+        if blk_ids.is_empty() {
+            return;
+        }
+        log::debug!("discarding {} processing blocks", blk_ids.len());
+        if let Some(vm_) = self.state.vm.as_ref() {
+            vm_.read().await.discard_blocks(blk_ids).await;
+        }
+    }
 }
 
 /// ref. https://doc.rust-lang.org/std/string/trait.ToString.html
@@ -300,3 +482,174 @@ impl subnet::rpc::consensus::snowman::Decidable for Block {
         self.reject().await
     }
 }
+
+/// Returns a block on `parent_id` at `height`, whose data is filled with
+/// `seed`, for tests.
+#[cfg(test)]
+pub(crate) fn test_block(parent_id: ids::Id, height: u64, seed: u8, status: choices::status::Status) -> Block {
+    Block::new(parent_id, height, height, vec![seed; 16], status, None).unwrap()
+}
+
+/// Returns a chain of `len` blocks starting from a genesis block, for tests.
+#[cfg(test)]
+pub(crate) fn test_chain(len: u64, status: choices::status::Status) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut parent_id = ids::Id::empty();
+    for height in 0..len {
+        let blk = test_block(parent_id, height, height as u8, status.clone());
+        parent_id = blk.id();
+        blocks.push(blk);
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{AptosData, LegacyAptosData};
+    use aptos_crypto::HashValue;
+    use aptos_sdk::transaction_builder::TransactionFactory;
+    use aptos_sdk::types::LocalAccount;
+    use aptos_types::account_address::AccountAddress;
+    use aptos_types::block_metadata::BlockMetadata;
+    use aptos_types::chain_id::ChainId;
+    use aptos_types::transaction::Transaction;
+
+    /// Returns the Aptos transactions of a block with `count` user transfers.
+    fn test_transactions(count: u64) -> Vec<Transaction> {
+        let account = LocalAccount::generate(&mut rand::rngs::OsRng);
+        let factory = TransactionFactory::new(ChainId::test());
+        let mut txs = vec![Transaction::BlockMetadata(BlockMetadata::new(
+            HashValue::random(),
+            1,
+            0,
+            AccountAddress::random(),
+            vec![],
+            vec![],
+            1,
+        ))];
+        for _ in 0..count {
+            let tx = account.sign_with_transaction_builder(factory.transfer(AccountAddress::random(), 100));
+            txs.push(Transaction::UserTransaction(tx));
+        }
+        txs.push(Transaction::StateCheckpoint(HashValue::random()));
+        txs
+    }
+
+    fn test_aptos_data(count: u64) -> AptosData {
+        AptosData {
+            transactions: test_transactions(count),
+            block_id: HashValue::random(),
+            parent_block_id: HashValue::random(),
+            epoch: 1,
+            timestamp_usecs: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        let data = test_aptos_data(4).to_vec().unwrap();
+        let blk = Block::new(
+            ids::Id::sha256(b"parent"),
+            7,
+            1234,
+            data.clone(),
+            choices::status::Status::Processing,
+            Some(0),
+        )
+        .unwrap();
+        assert_eq!(blk.bytes()[0], BLOCK_CODEC_VERSION);
+
+        let decoded = Block::from_slice(blk.bytes()).unwrap();
+        assert_eq!(decoded.parent_id(), blk.parent_id());
+        assert_eq!(decoded.height(), 7);
+        assert_eq!(decoded.timestamp(), 1234);
+        assert_eq!(decoded.data(), &data[..]);
+        assert_eq!(decoded.id(), blk.id());
+        assert_eq!(decoded.to_slice().unwrap(), blk.bytes());
+
+        let aptos_data = AptosData::from_block(&decoded).unwrap();
+        assert_eq!(aptos_data, AptosData::from_slice(&data).unwrap());
+    }
+
+    #[test]
+    fn test_block_rejects_unknown_version() {
+        let blk = Block::new(ids::Id::empty(), 1, 0, vec![], choices::status::Status::Processing, Some(0)).unwrap();
+        let mut bytes = blk.bytes().to_vec();
+        bytes[0] = BLOCK_CODEC_VERSION + 1;
+        assert!(Block::from_slice(&bytes).is_err());
+        assert!(Block::from_slice([]).is_err());
+    }
+
+    #[test]
+    fn test_legacy_json_block() {
+        let aptos_data = test_aptos_data(2);
+        let legacy_data = serde_json::to_vec(&LegacyAptosData(
+            serde_json::to_vec(&aptos_data.transactions).unwrap(),
+            aptos_data.block_id,
+            aptos_data.parent_block_id,
+            aptos_data.epoch,
+            aptos_data.timestamp_usecs,
+        ))
+        .unwrap();
+        let mut legacy = Block::new(ids::Id::sha256(b"parent"), 3, 42, vec![], choices::status::Status::Processing, None).unwrap();
+        legacy.data = legacy_data;
+        let legacy_bytes = serde_json::to_vec(&legacy).unwrap();
+
+        let decoded = Block::from_slice(&legacy_bytes).unwrap();
+        assert_eq!(decoded.id(), ids::Id::sha256(&legacy_bytes));
+        assert_eq!(decoded.bytes(), &legacy_bytes[..]);
+        assert_eq!(decoded.height(), 3);
+        assert_eq!(decoded.codec(), BlockCodec::Json);
+        assert_eq!(decoded.to_slice().unwrap(), legacy_bytes);
+        assert_eq!(AptosData::from_block(&decoded).unwrap(), aptos_data);
+
+        // blocks below the activation height are still built as JSON
+        let rebuilt = Block::new(
+            ids::Id::sha256(b"parent"),
+            3,
+            42,
+            aptos_data.to_vec_with_codec(BlockCodec::Json).unwrap(),
+            choices::status::Status::Processing,
+            Some(4),
+        )
+        .unwrap();
+        assert_eq!(rebuilt.id(), decoded.id());
+    }
+
+    #[test]
+    fn test_codec_activation_height() {
+        assert_eq!(BlockCodec::at_height(0, Some(0)), BlockCodec::Json);
+        assert_eq!(BlockCodec::at_height(5, None), BlockCodec::Json);
+        assert_eq!(BlockCodec::at_height(4, Some(5)), BlockCodec::Json);
+        assert_eq!(BlockCodec::at_height(5, Some(5)), BlockCodec::Binary);
+        assert_eq!(BlockCodec::at_height(1, Some(0)), BlockCodec::Binary);
+    }
+
+    #[test]
+    fn test_binary_codec_is_smaller_than_json() {
+        let aptos_data = test_aptos_data(32);
+        let blk = Block::new(
+            ids::Id::sha256(b"parent"),
+            1,
+            1,
+            aptos_data.to_vec().unwrap(),
+            choices::status::Status::Processing,
+            Some(0),
+        )
+        .unwrap();
+
+        let mut legacy = blk.clone();
+        legacy.data = serde_json::to_vec(&LegacyAptosData(
+            serde_json::to_vec(&aptos_data.transactions).unwrap(),
+            aptos_data.block_id,
+            aptos_data.parent_block_id,
+            aptos_data.epoch,
+            aptos_data.timestamp_usecs,
+        ))
+        .unwrap();
+        let legacy_bytes = serde_json::to_vec(&legacy).unwrap();
+
+        assert!(blk.bytes().len() * 3 < legacy_bytes.len());
+    }
+}
@@ -0,0 +1,379 @@
+//! Operational configuration of the M1 subnet [`Vm`](crate::vm::Vm), decoded
+//! from the config bytes given to `initialize`.
+
+pub mod storage;
+
+use std::{
+    io::{self, Error, ErrorKind},
+    path::PathBuf,
+    time::Duration,
+};
+
+use aptos_config::config::NodeConfig;
+use aptos_crypto::{bls12381, ed25519::Ed25519PrivateKey, ValidCryptoMaterialStringExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{faucet::FaucetConfig, fee_market, health, scheduler, state_sync, subscription};
+use storage::{StorageConfig, StorageMode};
+
+/// Directory, under the chain data directory, that AptosDB is stored in.
+pub const APTOS_DB_DIR: &str = "aptosdb";
+
+/// Prefix of the directory, under the home directory, that AptosDB was stored
+/// in before it moved to the chain data directory, suffixed with `M1_ID`.
+pub const LEGACY_DB_DIR_PREFIX: &str = ".move-chain-data";
+
+/// Returns the directory AptosDB was stored in by nodes that set `M1_ID`,
+/// if it exists. Without `M1_ID` every start used a new directory, so there
+/// is nothing to reuse.
+pub fn legacy_db_dir() -> Option<PathBuf> {
+    let id = std::env::var("M1_ID").ok().filter(|id| !id.is_empty())?;
+    let dir = dirs::home_dir()?.join(format!("{}-{}", LEGACY_DB_DIR_PREFIX, id));
+    dir.is_dir().then_some(dir)
+}
+
+/// Operational parameters of the Vm. Every field is optional in the encoded
+/// JSON document and defaults to the value the Vm used to hardcode.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VmConfig {
+    /// Directory AptosDB is stored in. Defaults to [`APTOS_DB_DIR`] under the
+    /// chain data directory assigned by avalanchego.
+    pub db_dir: Option<PathBuf>,
+    /// Pruning mode and RocksDB tuning of AptosDB.
+    pub storage: StorageConfig,
+    /// Maximum number of transactions held by the mempool.
+    pub mempool_capacity: usize,
+    /// Maximum number of transactions held by the mempool for a single sender.
+    pub mempool_capacity_per_user: usize,
+    /// Maximum number of user transactions in a block.
+    pub max_block_txs: usize,
+    /// Height from which blocks are built with the binary codec. Blocks below
+    /// it, and the genesis block, keep the JSON encoding of older nodes, so
+    /// every validator of a chain must use the same height, and blocks in the
+    /// other codec fail verification. `None`, the default, keeps every block
+    /// JSON, since no height is safe for chains that are already running; set
+    /// it to a height above the tip on every validator to switch.
+    pub block_codec_activation_height: Option<u64>,
+    /// Minimum time between an accepted block and the next build, giving the
+    /// mempool a chance to fill up.
+    pub min_block_interval_ms: u64,
+    /// Number of pending transactions that triggers a build without waiting
+    /// for `min_block_interval_ms`.
+    pub max_batch_txs: usize,
+    /// How long a block build may go without a decision before the engine is
+    /// asked for another one.
+    pub build_timeout_secs: u64,
+    /// Whether the Vm syncs its state from its peers when it starts
+    /// bootstrapping, leaving the engine only the blocks above the synced one.
+    pub state_sync_enabled: bool,
+    /// Minimum number of blocks a peer must be ahead for state sync to be used.
+    pub state_sync_min_blocks_behind: u64,
+    /// Number of peers that must offer the same state summary before it is
+    /// synced to.
+    pub state_sync_min_peers: usize,
+    /// Number of committed blocks kept for subscribers to resume from.
+    pub feed_buffer_blocks: usize,
+    /// Hex-encoded BLS12-381 key this node signs ledger infos with. Required
+    /// when the genesis document lists its validators; chains with the test
+    /// validator set sign with the first test validator.
+    pub consensus_key: Option<String>,
+    /// Hex-encoded Ed25519 key of the core resources account, matching the
+    /// `root_key` of the genesis. Only needed to fund the genesis accounts of
+    /// a chain other than the test chain, which uses the test root key.
+    pub root_key: Option<String>,
+    /// Faucet funding account and limits.
+    pub faucet: FaucetConfig,
+    /// Number of accepted blocks gas prices are estimated from.
+    pub gas_estimation_blocks: usize,
+    /// Gas unit price estimated while blocks are not congested.
+    pub min_gas_unit_price: u64,
+    /// Block utilization, in percent of `max_block_txs`, above which gas
+    /// estimates follow the prices paid by included transactions.
+    pub congestion_threshold_pct: u64,
+    /// How long the last accepted block may be old while transactions are
+    /// pending before the node reports itself unhealthy.
+    pub health_max_block_age_secs: u64,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        let mempool = NodeConfig::default().mempool;
+        Self {
+            db_dir: None,
+            storage: StorageConfig::default(),
+            mempool_capacity: mempool.capacity,
+            mempool_capacity_per_user: mempool.capacity_per_user,
+            max_block_txs: 512,
+            block_codec_activation_height: None,
+            min_block_interval_ms: scheduler::DEFAULT_MIN_BLOCK_INTERVAL_MS,
+            max_batch_txs: 512,
+            build_timeout_secs: scheduler::DEFAULT_BUILD_TIMEOUT_SECS,
+            state_sync_enabled: false,
+            state_sync_min_blocks_behind: state_sync::MIN_BLOCKS_BEHIND,
+            state_sync_min_peers: state_sync::MIN_PEERS,
+            feed_buffer_blocks: subscription::DEFAULT_BUFFER_BLOCKS,
+            consensus_key: None,
+            root_key: None,
+            faucet: FaucetConfig::default(),
+            gas_estimation_blocks: fee_market::DEFAULT_WINDOW_BLOCKS,
+            min_gas_unit_price: fee_market::DEFAULT_MIN_GAS_UNIT_PRICE,
+            congestion_threshold_pct: fee_market::DEFAULT_CONGESTION_THRESHOLD_PCT,
+            health_max_block_age_secs: health::DEFAULT_MAX_BLOCK_AGE_SECS,
+        }
+    }
+}
+
+impl VmConfig {
+    /// Decodes and validates the config from its JSON bytes.
+    /// Empty bytes decode to the default config.
+    pub fn from_slice(d: impl AsRef<[u8]>) -> io::Result<Self> {
+        let dd = d.as_ref();
+        if dd.iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(Self::default());
+        }
+
+        let config: Self = serde_json::from_slice(dd).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to deserialize VmConfig from JSON: {}", e),
+            )
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that the parameters are usable.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: &str| Err(Error::new(ErrorKind::InvalidData, msg.to_string()));
+        if self.mempool_capacity_per_user == 0 {
+            return invalid("mempool_capacity_per_user must not be 0");
+        }
+        if self.mempool_capacity < self.mempool_capacity_per_user {
+            return invalid("mempool_capacity must be at least mempool_capacity_per_user");
+        }
+        if self.max_block_txs == 0 {
+            return invalid("max_block_txs must not be 0");
+        }
+        if self.max_batch_txs == 0 {
+            return invalid("max_batch_txs must not be 0");
+        }
+        if self.build_timeout_secs == 0 {
+            return invalid("build_timeout_secs must not be 0");
+        }
+        if self.state_sync_min_peers == 0 {
+            return invalid("state_sync_min_peers must not be 0");
+        }
+        if self.feed_buffer_blocks == 0 {
+            return invalid("feed_buffer_blocks must not be 0");
+        }
+        if self.health_max_block_age_secs == 0 {
+            return invalid("health_max_block_age_secs must not be 0");
+        }
+        if self.gas_estimation_blocks == 0 {
+            return invalid("gas_estimation_blocks must not be 0");
+        }
+        if self.congestion_threshold_pct == 0 || self.congestion_threshold_pct > 100 {
+            return invalid("congestion_threshold_pct must be between 1 and 100");
+        }
+        if let Some(db_dir) = self.db_dir.as_ref() {
+            if db_dir.as_os_str().is_empty() {
+                return invalid("db_dir must not be empty");
+            }
+        }
+        self.storage.validate()?;
+        self.consensus_key()?;
+        self.root_key()?;
+        self.faucet.validate()
+    }
+
+    /// Returns the key of the core resources account, if configured.
+    pub fn root_key(&self) -> io::Result<Option<Ed25519PrivateKey>> {
+        self.root_key
+            .as_ref()
+            .map(|encoded| {
+                Ed25519PrivateKey::from_encoded_string(encoded).map_err(|e| {
+                    Error::new(ErrorKind::InvalidData, format!("invalid root key: {}", e))
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns the consensus key of this node, if configured.
+    pub fn consensus_key(&self) -> io::Result<Option<bls12381::PrivateKey>> {
+        self.consensus_key
+            .as_ref()
+            .map(|encoded| {
+                bls12381::PrivateKey::from_encoded_string(encoded).map_err(|e| {
+                    Error::new(ErrorKind::InvalidData, format!("invalid consensus key: {}", e))
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns the directory AptosDB is stored in, given the chain data
+    /// directory assigned by avalanchego. A node upgraded from the legacy
+    /// layout keeps its existing AptosDB, see [`legacy_db_dir`].
+    pub fn db_dir(&self, chain_data_dir: &str) -> io::Result<PathBuf> {
+        self.resolve_db_dir(chain_data_dir, legacy_db_dir())
+    }
+
+    fn resolve_db_dir(&self, chain_data_dir: &str, legacy: Option<PathBuf>) -> io::Result<PathBuf> {
+        if let Some(db_dir) = self.db_dir.as_ref() {
+            return Ok(db_dir.clone());
+        }
+        if let Some(legacy) = legacy {
+            log::warn!(
+                "using the legacy AptosDB directory {}, set db_dir to keep it",
+                legacy.display()
+            );
+            return Ok(legacy);
+        }
+        if chain_data_dir.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "no chain data directory given and db_dir is not configured",
+            ));
+        }
+        Ok(PathBuf::from(chain_data_dir).join(APTOS_DB_DIR))
+    }
+
+    /// Returns the Aptos node config the mempool is created from.
+    pub fn node_config(&self) -> NodeConfig {
+        let mut node_config = NodeConfig::default();
+        node_config.mempool.capacity = self.mempool_capacity;
+        node_config.mempool.capacity_per_user = self.mempool_capacity_per_user;
+        node_config
+    }
+
+    /// Returns the config of the block building scheduler.
+    pub fn scheduler(&self) -> scheduler::SchedulerConfig {
+        scheduler::SchedulerConfig {
+            min_block_interval: Duration::from_millis(self.min_block_interval_ms),
+            max_batch_txs: self.max_batch_txs,
+            build_timeout: Duration::from_secs(self.build_timeout_secs),
+        }
+    }
+
+    /// Returns the fee market gas prices are estimated with.
+    pub fn fee_market(&self) -> fee_market::FeeMarket {
+        fee_market::FeeMarket::new(
+            self.gas_estimation_blocks,
+            self.min_gas_unit_price,
+            self.congestion_threshold_pct,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_defaults() {
+        assert_eq!(VmConfig::from_slice(b"").unwrap(), VmConfig::default());
+        assert_eq!(VmConfig::from_slice(b"{}").unwrap(), VmConfig::default());
+
+        let config = VmConfig::default();
+        assert_eq!(config.max_block_txs, 512);
+        assert_eq!(config.block_codec_activation_height, None);
+        assert_eq!(config.scheduler().min_block_interval, Duration::from_millis(200));
+        assert_eq!(config.scheduler().build_timeout, Duration::from_secs(30));
+        assert_eq!(
+            config.db_dir("/data/chain").unwrap(),
+            PathBuf::from("/data/chain").join(APTOS_DB_DIR)
+        );
+        assert!(config.db_dir("").is_err());
+    }
+
+    #[test]
+    fn test_legacy_db_dir_is_kept() {
+        let home = std::env::temp_dir().join(format!("m1-home-{}", uuid::Uuid::new_v4()));
+        let legacy = home.join(format!("{}-{}", LEGACY_DB_DIR_PREFIX, "node"));
+        std::fs::create_dir_all(&legacy).unwrap();
+
+        let config = VmConfig::default();
+        assert_eq!(config.resolve_db_dir("/data/chain", Some(legacy.clone())).unwrap(), legacy);
+        assert_eq!(
+            config.resolve_db_dir("/data/chain", None).unwrap(),
+            PathBuf::from("/data/chain").join(APTOS_DB_DIR)
+        );
+        // a configured directory wins over the legacy one
+        let config = VmConfig {
+            db_dir: Some(PathBuf::from("/var/lib/m1")),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.resolve_db_dir("/data/chain", Some(legacy)).unwrap(),
+            PathBuf::from("/var/lib/m1")
+        );
+
+        let old_home = std::env::var_os("HOME");
+        std::env::set_var("HOME", &home);
+        std::env::set_var("M1_ID", "node");
+        assert_eq!(legacy_db_dir(), Some(home.join(".move-chain-data-node")));
+        std::env::set_var("M1_ID", "other");
+        assert_eq!(legacy_db_dir(), None);
+        std::env::remove_var("M1_ID");
+        if let Some(old_home) = old_home {
+            std::env::set_var("HOME", old_home);
+        }
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn test_config_overrides() {
+        let config = VmConfig::from_slice(
+            br#"{
+                "db_dir": "/var/lib/m1",
+                "mempool_capacity": 1000,
+                "mempool_capacity_per_user": 10,
+                "max_block_txs": 64,
+                "block_codec_activation_height": 1000,
+                "state_sync_enabled": true,
+                "storage": {"mode": "pruned"},
+                "faucet": {"enabled": false, "amount": 100}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.db_dir("/data/chain").unwrap(), PathBuf::from("/var/lib/m1"));
+        assert_eq!(config.max_block_txs, 64);
+        assert_eq!(config.block_codec_activation_height, Some(1000));
+        assert!(config.state_sync_enabled);
+        assert_eq!(config.storage.mode, StorageMode::Pruned);
+        assert_eq!(config.storage.max_open_files, StorageConfig::default().max_open_files);
+        assert_eq!(config.min_block_interval_ms, VmConfig::default().min_block_interval_ms);
+        assert_eq!(config.faucet.enabled, Some(false));
+        assert_eq!(config.faucet.amount, 100);
+        assert_eq!(config.faucet.per_address_requests, FaucetConfig::default().per_address_requests);
+
+        let node_config = config.node_config();
+        assert_eq!(node_config.mempool.capacity, 1000);
+        assert_eq!(node_config.mempool.capacity_per_user, 10);
+    }
+
+    #[test]
+    fn test_config_rejects_invalid() {
+        for d in [
+            &br#"{"max_block_txs": 0}"#[..],
+            br#"{"max_batch_txs": 0}"#,
+            br#"{"build_timeout_secs": 0}"#,
+            br#"{"feed_buffer_blocks": 0}"#,
+            br#"{"state_sync_min_peers": 0}"#,
+            br#"{"faucet": {"amount": 0}}"#,
+            br#"{"gas_estimation_blocks": 0}"#,
+            br#"{"congestion_threshold_pct": 101}"#,
+            br#"{"faucet": {"funding_key": "0x01"}}"#,
+            br#"{"consensus_key": "0x01"}"#,
+            br#"{"root_key": "0x01"}"#,
+            br#"{"mempool_capacity": 1, "mempool_capacity_per_user": 2}"#,
+            br#"{"db_dir": ""}"#,
+            br#"{"storage": {"ledger_prune_window": 0}}"#,
+            br#"{"storage": {"mode": "full"}}"#,
+            br#"{"unknown": true}"#,
+            b"not json",
+        ] {
+            assert!(VmConfig::from_slice(d).is_err());
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Storage settings AptosDB is opened with.
+
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+    path::Path,
+};
+
+use aptos_config::config::{RocksdbConfig, StorageConfig as AptosStorageConfig};
+use serde::{Deserialize, Serialize};
+
+/// File, in the AptosDB directory, recording the mode the DB was last opened
+/// with.
+const STORAGE_MODE_FILE: &str = "storage_mode";
+
+/// Whether old ledger and state data is dropped.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageMode {
+    /// Keeps the last versions within the prune windows, as validators do.
+    Pruned,
+    /// Never prunes, so that every version stays queryable.
+    Archive,
+}
+
+/// Pruner windows and RocksDB tuning of AptosDB. The mode defaults to
+/// [`StorageMode::Archive`], which nodes have always run in, and every other
+/// field to the value of the Aptos node config.
+///
+/// Switching an existing node to [`StorageMode::Pruned`] drops the versions
+/// outside the prune windows on the next start, and they cannot be restored
+/// without resyncing. Nodes serving historical queries should stay archival.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub mode: StorageMode,
+    /// Number of versions of transactions, events and write sets kept.
+    pub ledger_prune_window: u64,
+    /// Number of versions of the state merkle tree kept.
+    pub state_merkle_prune_window: u64,
+    /// Number of versions of the state merkle tree kept at epoch endings.
+    pub epoch_snapshot_prune_window: u64,
+    /// Maximum number of files each RocksDB instance keeps open, -1 for no
+    /// limit.
+    pub max_open_files: i32,
+    /// Size of the block cache of each RocksDB instance, in bytes.
+    pub block_cache_size: u64,
+    /// Total size of the write-ahead logs of each RocksDB instance, in bytes.
+    pub max_total_wal_size: u64,
+    /// Number of background compaction and flush jobs of each RocksDB
+    /// instance.
+    pub max_background_jobs: i32,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        let storage = AptosStorageConfig::default();
+        let pruner = storage.storage_pruner_config;
+        let rocksdb = storage.rocksdb_configs.ledger_db_config;
+        Self {
+            mode: StorageMode::Archive,
+            ledger_prune_window: pruner.ledger_pruner_config.prune_window,
+            state_merkle_prune_window: pruner.state_merkle_pruner_config.prune_window,
+            epoch_snapshot_prune_window: pruner.epoch_snapshot_pruner_config.prune_window,
+            max_open_files: rocksdb.max_open_files,
+            block_cache_size: rocksdb.block_cache_size,
+            max_total_wal_size: rocksdb.max_total_wal_size,
+            max_background_jobs: rocksdb.max_background_jobs,
+        }
+    }
+}
+
+impl StorageConfig {
+    /// Checks that the parameters are usable.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: &str| Err(Error::new(ErrorKind::InvalidData, msg.to_string()));
+        if self.mode == StorageMode::Pruned
+            && (self.ledger_prune_window == 0
+                || self.state_merkle_prune_window == 0
+                || self.epoch_snapshot_prune_window == 0)
+        {
+            return invalid("storage prune windows must not be 0");
+        }
+        if self.max_open_files == 0 || self.max_open_files < -1 {
+            return invalid("storage max_open_files must be positive or -1");
+        }
+        if self.max_background_jobs <= 0 {
+            return invalid("storage max_background_jobs must be positive");
+        }
+        Ok(())
+    }
+
+    /// Records the mode the DB stored in `db_dir` is opened with, warning when
+    /// pruning is enabled on a DB that was so far kept whole.
+    pub fn record_mode(&self, db_dir: &Path) -> io::Result<()> {
+        let mode_file = db_dir.join(STORAGE_MODE_FILE);
+        let previous = match fs::read(&mode_file) {
+            Ok(d) => Some(serde_json::from_slice::<StorageMode>(&d).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to parse {}: {}", mode_file.display(), e),
+                )
+            })?),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        // DBs created before the mode was recorded were never pruned
+        let existing = fs::read_dir(db_dir)?.next().is_some();
+        let previous = previous.or(existing.then_some(StorageMode::Archive));
+        if self.mode == StorageMode::Pruned && previous == Some(StorageMode::Archive) {
+            log::warn!(
+                "enabling pruning on the archival AptosDB at {}, versions outside the prune windows will be deleted",
+                db_dir.display()
+            );
+        }
+        let encoded = serde_json::to_vec(&self.mode)
+            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        fs::write(&mode_file, encoded)
+    }
+
+    /// Returns the Aptos storage config of the DB stored in `db_dir`.
+    pub fn aptos_storage_config(&self, db_dir: &Path) -> AptosStorageConfig {
+        let mut storage = AptosStorageConfig::default();
+        storage.dir = db_dir.to_path_buf();
+
+        let enable = self.mode == StorageMode::Pruned;
+        let pruner = &mut storage.storage_pruner_config;
+        pruner.ledger_pruner_config.enable = enable;
+        pruner.ledger_pruner_config.prune_window = self.ledger_prune_window;
+        pruner.state_merkle_pruner_config.enable = enable;
+        pruner.state_merkle_pruner_config.prune_window = self.state_merkle_prune_window;
+        pruner.epoch_snapshot_pruner_config.enable = enable;
+        pruner.epoch_snapshot_pruner_config.prune_window = self.epoch_snapshot_prune_window;
+
+        let rocksdb = &mut storage.rocksdb_configs;
+        for db in [
+            &mut rocksdb.ledger_db_config,
+            &mut rocksdb.state_merkle_db_config,
+            &mut rocksdb.state_kv_db_config,
+        ] {
+            self.tune(db);
+        }
+        storage
+    }
+
+    fn tune(&self, db: &mut RocksdbConfig) {
+        db.max_open_files = self.max_open_files;
+        db.block_cache_size = self.block_cache_size;
+        db.max_total_wal_size = self.max_total_wal_size;
+        db.max_background_jobs = self.max_background_jobs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_disables_pruners() {
+        let config = StorageConfig {
+            mode: StorageMode::Archive,
+            ledger_prune_window: 0,
+            ..Default::default()
+        };
+        config.validate().unwrap();
+        let storage = config.aptos_storage_config(Path::new("/data/aptosdb"));
+        assert_eq!(storage.dir, Path::new("/data/aptosdb"));
+        let pruner = storage.storage_pruner_config;
+        assert!(!pruner.ledger_pruner_config.enable);
+        assert!(!pruner.state_merkle_pruner_config.enable);
+        assert!(!pruner.epoch_snapshot_pruner_config.enable);
+
+        // an archive window means nothing, a pruned one has to keep something
+        assert!(StorageConfig {
+            mode: StorageMode::Pruned,
+            ..config
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_pruned_windows_and_tuning() {
+        let config = StorageConfig {
+            mode: StorageMode::Pruned,
+            ledger_prune_window: 1000,
+            state_merkle_prune_window: 100,
+            max_open_files: 64,
+            ..Default::default()
+        };
+        let storage = config.aptos_storage_config(Path::new("/data/aptosdb"));
+        let pruner = storage.storage_pruner_config;
+        assert!(pruner.ledger_pruner_config.enable);
+        assert_eq!(pruner.ledger_pruner_config.prune_window, 1000);
+        assert_eq!(pruner.state_merkle_pruner_config.prune_window, 100);
+        assert_eq!(storage.rocksdb_configs.ledger_db_config.max_open_files, 64);
+        assert_eq!(
+            storage
+                .rocksdb_configs
+                .state_merkle_db_config
+                .max_open_files,
+            64
+        );
+    }
+
+    #[test]
+    fn test_record_mode() {
+        let db_dir = std::env::temp_dir().join(format!("m1-storage-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&db_dir).unwrap();
+        let read_mode = || serde_json::from_slice::<StorageMode>(&fs::read(db_dir.join(STORAGE_MODE_FILE)).unwrap()).unwrap();
+
+        assert_eq!(StorageConfig::default().mode, StorageMode::Archive);
+        StorageConfig::default().record_mode(&db_dir).unwrap();
+        assert_eq!(read_mode(), StorageMode::Archive);
+
+        let pruned = StorageConfig {
+            mode: StorageMode::Pruned,
+            ..Default::default()
+        };
+        pruned.record_mode(&db_dir).unwrap();
+        assert_eq!(read_mode(), StorageMode::Pruned);
+
+        fs::write(db_dir.join(STORAGE_MODE_FILE), b"not json").unwrap();
+        assert!(pruned.record_mode(&db_dir).is_err());
+        fs::remove_dir_all(&db_dir).unwrap();
+    }
+}
@@ -0,0 +1,689 @@
+//! Faucet funding accounts from a configurable account, behind rate limits,
+//! a per-address cap and an optional allowlist.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    io::{self, Error, ErrorKind},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use aptos_crypto::ed25519::Ed25519PrivateKey;
+use aptos_crypto::ValidCryptoMaterialStringExt;
+use aptos_sdk::transaction_builder::TransactionFactory;
+use aptos_sdk::types::{AccountKey, LocalAccount};
+use aptos_types::account_address::AccountAddress;
+use aptos_types::account_config::aptos_test_root_address;
+use aptos_types::chain_id::ChainId;
+use aptos_types::transaction::SignedTransaction;
+use aptos_vm_genesis::GENESIS_KEYPAIR;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::metrics;
+
+/// Amount sent per request by default, in octas.
+pub const DEFAULT_AMOUNT: u64 = 10 * 100_000_000;
+
+/// How long a faucet transaction stays valid.
+const TRANSACTION_EXPIRATION_SECS: u64 = 30;
+
+/// Faucet parameters, part of the [`VmConfig`](crate::config::VmConfig).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaucetConfig {
+    /// Whether the faucet serves requests. Defaults to serving them on the
+    /// Aptos test chain only, whose funding account has a well-known key.
+    pub enabled: Option<bool>,
+    /// Hex of the Ed25519 private key of the funding account.
+    /// The genesis key of the core resources account is used if unset, which
+    /// is only allowed on the Aptos test chain.
+    pub funding_key: Option<String>,
+    /// Address of the funding account. Defaults to the address derived from
+    /// the funding key, or to the core resources account.
+    pub funding_account: Option<AccountAddress>,
+    /// Amount sent per request, in octas.
+    pub amount: u64,
+    /// Total amount a single address may receive, in octas.
+    pub max_amount_per_address: Option<u64>,
+    /// Number of requests a single address may make per window.
+    pub per_address_requests: usize,
+    pub per_address_window_secs: u64,
+    /// Number of requests served per window across all addresses.
+    pub global_requests: usize,
+    pub global_window_secs: u64,
+    /// Only these addresses are served, if not empty.
+    pub allowlist: Vec<AccountAddress>,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: None,
+            funding_key: None,
+            funding_account: None,
+            amount: DEFAULT_AMOUNT,
+            max_amount_per_address: None,
+            per_address_requests: 10,
+            per_address_window_secs: 3600,
+            global_requests: 600,
+            global_window_secs: 60,
+            allowlist: Vec::new(),
+        }
+    }
+}
+
+impl FaucetConfig {
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: &str| Err(Error::new(ErrorKind::InvalidData, msg.to_string()));
+        if self.amount == 0 {
+            return invalid("faucet amount must not be 0");
+        }
+        if self.per_address_requests == 0 || self.global_requests == 0 {
+            return invalid("faucet request limits must not be 0");
+        }
+        if self.per_address_window_secs == 0 || self.global_window_secs == 0 {
+            return invalid("faucet rate limit windows must not be 0");
+        }
+        if let Some(max_amount) = self.max_amount_per_address {
+            if max_amount < self.amount {
+                return invalid("faucet max_amount_per_address must be at least amount");
+            }
+        }
+        self.funding_key()?;
+        Ok(())
+    }
+
+    /// Returns whether the faucet serves requests on the chain `chain_id`.
+    pub fn is_enabled(&self, chain_id: ChainId) -> bool {
+        self.enabled.unwrap_or(chain_id == ChainId::test())
+    }
+
+    /// Returns the key and address of the funding account.
+    pub fn funding_key(&self) -> io::Result<(Ed25519PrivateKey, AccountAddress)> {
+        match self.funding_key.as_ref() {
+            Some(encoded) => {
+                let key = Ed25519PrivateKey::from_encoded_string(encoded).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("invalid faucet funding key: {}", e),
+                    )
+                })?;
+                let address = self.funding_account.unwrap_or_else(|| {
+                    AccountKey::from_private_key(key.clone())
+                        .authentication_key()
+                        .derived_address()
+                });
+                Ok((key, address))
+            }
+            None => Ok((
+                GENESIS_KEYPAIR.0.clone(),
+                self.funding_account.unwrap_or_else(aptos_test_root_address),
+            )),
+        }
+    }
+}
+
+/// What a faucet request asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaucetRequest {
+    /// Transfers the configured amount, creating the account if needed.
+    Fund,
+    /// Creates the account without funding it.
+    CreateAccount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaucetError {
+    Disabled,
+    NotAllowed(AccountAddress),
+    RateLimited { retry_after_secs: u64 },
+    CapExceeded(AccountAddress),
+}
+
+impl std::fmt::Display for FaucetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "faucet is disabled"),
+            Self::NotAllowed(address) => {
+                write!(f, "address {} is not allowed to use the faucet", address)
+            }
+            Self::RateLimited { retry_after_secs } => {
+                write!(
+                    f,
+                    "faucet rate limit exceeded, retry in {} seconds",
+                    retry_after_secs
+                )
+            }
+            Self::CapExceeded(address) => write!(
+                f,
+                "address {} has received the maximum faucet amount",
+                address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FaucetError {}
+
+impl FaucetError {
+    /// Returns the result label of the error in the faucet metrics.
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Disabled => "disabled",
+            Self::NotAllowed(_) => "not_allowed",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::CapExceeded(_) => "cap_exceeded",
+        }
+    }
+}
+
+/// Requests served within a sliding window.
+#[derive(Default)]
+struct Window {
+    requests: VecDeque<Instant>,
+}
+
+impl Window {
+    /// Returns how long until another request fits, if the window is full.
+    fn retry_after(&mut self, limit: usize, window: Duration, now: Instant) -> Option<Duration> {
+        while let Some(oldest) = self.requests.front() {
+            if now.duration_since(*oldest) < window {
+                break;
+            }
+            self.requests.pop_front();
+        }
+        if self.requests.len() < limit {
+            return None;
+        }
+        self.requests
+            .front()
+            .map(|oldest| window.saturating_sub(now.duration_since(*oldest)))
+    }
+}
+
+/// Mutable faucet state, locked for the whole of a request so that requests
+/// are signed with consecutive sequence numbers.
+struct FaucetState {
+    global: Window,
+    /// Requests of each address within the per-address window. Addresses
+    /// without such requests are dropped, see [`FaucetState::prune`].
+    per_address: HashMap<AccountAddress, Window>,
+    /// Amount each address received, only kept when it is capped. The cap
+    /// bounds the number of entries by the balance of the funding account.
+    funded: HashMap<AccountAddress, u64>,
+    /// When expired per-address windows were last dropped.
+    pruned_at: Option<Instant>,
+    /// Sequence number of the next faucet transaction, and when the last
+    /// one was submitted.
+    next_sequence_number: Option<(u64, Instant)>,
+}
+
+impl FaucetState {
+    fn check(
+        &mut self,
+        config: &FaucetConfig,
+        to: AccountAddress,
+        amount: u64,
+        now: Instant,
+    ) -> Result<(), FaucetError> {
+        if let Some(max_amount) = config.max_amount_per_address {
+            let funded = self.funded.get(&to).copied().unwrap_or(0);
+            if funded.saturating_add(amount) > max_amount {
+                return Err(FaucetError::CapExceeded(to));
+            }
+        }
+        self.prune(config, now);
+        let per_address_window = Duration::from_secs(config.per_address_window_secs);
+        let retry_after = [
+            self.per_address.get_mut(&to).and_then(|window| {
+                window.retry_after(config.per_address_requests, per_address_window, now)
+            }),
+            self.global.retry_after(
+                config.global_requests,
+                Duration::from_secs(config.global_window_secs),
+                now,
+            ),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+        if let Some(retry_after) = retry_after {
+            return Err(FaucetError::RateLimited {
+                retry_after_secs: retry_after.as_secs().max(1),
+            });
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, config: &FaucetConfig, to: AccountAddress, amount: u64, now: Instant) {
+        self.global.requests.push_back(now);
+        self.per_address
+            .entry(to)
+            .or_default()
+            .requests
+            .push_back(now);
+        if config.max_amount_per_address.is_some() {
+            *self.funded.entry(to).or_default() += amount;
+        }
+    }
+
+    /// Drops the windows of addresses whose requests all expired, at most once
+    /// per per-address window, so that the state does not grow with every
+    /// address ever served.
+    fn prune(&mut self, config: &FaucetConfig, now: Instant) {
+        let window = Duration::from_secs(config.per_address_window_secs);
+        if let Some(pruned_at) = self.pruned_at {
+            if now.duration_since(pruned_at) < window {
+                return;
+            }
+        }
+        self.per_address.retain(|_, requests| {
+            requests
+                .requests
+                .back()
+                .map_or(false, |last| now.duration_since(*last) < window)
+        });
+        self.pruned_at = Some(now);
+    }
+
+    /// Returns the sequence number to sign the next transaction with. The
+    /// local counter runs ahead of the ledger while faucet transactions are
+    /// pending, and falls back to the ledger once they must have expired.
+    fn sequence_number(&self, on_chain: u64, now: Instant) -> u64 {
+        match self.next_sequence_number {
+            Some((next, submitted_at))
+                if now.duration_since(submitted_at)
+                    < Duration::from_secs(TRANSACTION_EXPIRATION_SECS) =>
+            {
+                next.max(on_chain)
+            }
+            _ => on_chain,
+        }
+    }
+}
+
+/// Faucet of the Vm. Cloning returns a handle to the same faucet.
+#[derive(Clone)]
+pub struct Faucet {
+    config: Arc<FaucetConfig>,
+    chain_id: ChainId,
+    key: Arc<Ed25519PrivateKey>,
+    account: AccountAddress,
+    allowlist: Arc<HashSet<AccountAddress>>,
+    state: Arc<Mutex<FaucetState>>,
+}
+
+impl Faucet {
+    pub fn new(config: FaucetConfig, chain_id: ChainId) -> io::Result<Self> {
+        config.validate()?;
+        // only the test chain funds from the well-known genesis key
+        if config.is_enabled(chain_id) && chain_id != ChainId::test() && config.funding_key.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("faucet enabled on chain {} requires a funding key", chain_id),
+            ));
+        }
+        let (key, account) = config.funding_key()?;
+        Ok(Self {
+            allowlist: Arc::new(config.allowlist.iter().copied().collect()),
+            config: Arc::new(config),
+            chain_id,
+            key: Arc::new(key),
+            account,
+            state: Arc::new(Mutex::new(FaucetState {
+                global: Window::default(),
+                per_address: HashMap::new(),
+                funded: HashMap::new(),
+                pruned_at: None,
+                next_sequence_number: None,
+            })),
+        })
+    }
+
+    /// Returns the address of the funding account.
+    pub fn account(&self) -> AccountAddress {
+        self.account
+    }
+
+    /// Serves a request for `to`: checks the limits, signs the transaction
+    /// with the next sequence number of the funding account and hands it to
+    /// `submit`. Requests are served one at a time, and count against the
+    /// limits only once submitted.
+    pub async fn request<T, F, Fut>(
+        &self,
+        to: AccountAddress,
+        request: FaucetRequest,
+        on_chain_sequence_number: u64,
+        submit: F,
+    ) -> Result<T, anyhow::Error>
+    where
+        F: FnOnce(SignedTransaction) -> Fut,
+        Fut: Future<Output = Result<T, anyhow::Error>>,
+    {
+        let ret = self
+            .serve(to, request, on_chain_sequence_number, submit)
+            .await;
+        let result = match ret.as_ref() {
+            Ok(_) => "served",
+            Err(e) => e
+                .downcast_ref::<FaucetError>()
+                .map(FaucetError::label)
+                .unwrap_or("failed"),
+        };
+        metrics::FAUCET_REQUESTS.with_label_values(&[result]).inc();
+        ret
+    }
+
+    async fn serve<T, F, Fut>(
+        &self,
+        to: AccountAddress,
+        request: FaucetRequest,
+        on_chain_sequence_number: u64,
+        submit: F,
+    ) -> Result<T, anyhow::Error>
+    where
+        F: FnOnce(SignedTransaction) -> Fut,
+        Fut: Future<Output = Result<T, anyhow::Error>>,
+    {
+        if !self.config.is_enabled(self.chain_id) {
+            return Err(FaucetError::Disabled.into());
+        }
+        if !self.allowlist.is_empty() && !self.allowlist.contains(&to) {
+            return Err(FaucetError::NotAllowed(to).into());
+        }
+        let amount = match request {
+            FaucetRequest::Fund => self.config.amount,
+            FaucetRequest::CreateAccount => 0,
+        };
+
+        let mut state = self.state.lock().await;
+        let now = Instant::now();
+        state.check(&self.config, to, amount, now)?;
+
+        let sequence_number = state.sequence_number(on_chain_sequence_number, now);
+        let funding_account = LocalAccount::new(
+            self.account,
+            AccountKey::from_private_key(self.key.as_ref().clone()),
+            sequence_number,
+        );
+        let tx_factory = TransactionFactory::new(self.chain_id)
+            .with_transaction_expiration_time(TRANSACTION_EXPIRATION_SECS);
+        let txn = match request {
+            FaucetRequest::Fund => {
+                funding_account.sign_with_transaction_builder(tx_factory.transfer(to, amount))
+            }
+            FaucetRequest::CreateAccount => {
+                funding_account.sign_with_transaction_builder(tx_factory.create_account(to))
+            }
+        };
+
+        let ret = submit(txn).await?;
+        let now = Instant::now();
+        state.next_sequence_number = Some((sequence_number + 1, now));
+        state.record(&self.config, to, amount, now);
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_faucet(config: FaucetConfig) -> Faucet {
+        Faucet::new(config, ChainId::test()).unwrap()
+    }
+
+    async fn fund(
+        faucet: &Faucet,
+        to: AccountAddress,
+        on_chain: u64,
+    ) -> Result<SignedTransaction, anyhow::Error> {
+        faucet
+            .request(
+                to,
+                FaucetRequest::Fund,
+                on_chain,
+                |txn| async move { Ok(txn) },
+            )
+            .await
+    }
+
+    fn faucet_error(e: anyhow::Error) -> FaucetError {
+        e.downcast::<FaucetError>().unwrap()
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(FaucetConfig::default().validate().is_ok());
+        for config in [
+            FaucetConfig {
+                amount: 0,
+                ..Default::default()
+            },
+            FaucetConfig {
+                global_requests: 0,
+                ..Default::default()
+            },
+            FaucetConfig {
+                per_address_window_secs: 0,
+                ..Default::default()
+            },
+            FaucetConfig {
+                max_amount_per_address: Some(1),
+                ..Default::default()
+            },
+            FaucetConfig {
+                funding_key: Some("not a key".to_string()),
+                ..Default::default()
+            },
+        ] {
+            assert!(config.validate().is_err());
+        }
+
+        let (_, address) = FaucetConfig::default().funding_key().unwrap();
+        assert_eq!(address, aptos_test_root_address());
+
+        let key = Ed25519PrivateKey::try_from([7u8; 32].as_slice()).unwrap();
+        let config = FaucetConfig {
+            funding_key: Some(key.to_encoded_string().unwrap()),
+            ..Default::default()
+        };
+        let (_, address) = config.funding_key().unwrap();
+        assert_eq!(
+            address,
+            AccountKey::from_private_key(key.clone())
+                .authentication_key()
+                .derived_address()
+        );
+
+        // other chains must fund from their own key
+        let enabled = FaucetConfig {
+            enabled: Some(true),
+            ..Default::default()
+        };
+        assert!(Faucet::new(enabled.clone(), ChainId::test()).is_ok());
+        assert!(Faucet::new(enabled.clone(), ChainId::new(42)).is_err());
+        assert!(Faucet::new(FaucetConfig::default(), ChainId::new(42)).is_ok());
+        let keyed = FaucetConfig {
+            funding_key: Some(key.to_encoded_string().unwrap()),
+            ..enabled
+        };
+        assert!(Faucet::new(keyed, ChainId::new(42)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_disabled_and_allowlist() {
+        let to = AccountAddress::random();
+        let faucet = new_faucet(FaucetConfig {
+            enabled: Some(false),
+            ..Default::default()
+        });
+        assert_eq!(
+            faucet_error(fund(&faucet, to, 0).await.unwrap_err()),
+            FaucetError::Disabled
+        );
+
+        // off by default on other chains, unless turned on
+        let faucet = Faucet::new(FaucetConfig::default(), ChainId::new(42)).unwrap();
+        assert_eq!(
+            faucet_error(fund(&faucet, to, 0).await.unwrap_err()),
+            FaucetError::Disabled
+        );
+        let config = FaucetConfig {
+            enabled: Some(true),
+            ..Default::default()
+        };
+        assert!(config.is_enabled(ChainId::new(42)));
+        assert!(FaucetConfig::default().is_enabled(ChainId::test()));
+
+        let allowed = AccountAddress::random();
+        let faucet = new_faucet(FaucetConfig {
+            allowlist: vec![allowed],
+            ..Default::default()
+        });
+        assert_eq!(
+            faucet_error(fund(&faucet, to, 0).await.unwrap_err()),
+            FaucetError::NotAllowed(to)
+        );
+        assert!(fund(&faucet, allowed, 0).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_and_cap() {
+        let faucet = new_faucet(FaucetConfig {
+            per_address_requests: 2,
+            global_requests: 3,
+            ..Default::default()
+        });
+        let a = AccountAddress::random();
+        let b = AccountAddress::random();
+        fund(&faucet, a, 0).await.unwrap();
+        fund(&faucet, a, 0).await.unwrap();
+        assert!(matches!(
+            faucet_error(fund(&faucet, a, 0).await.unwrap_err()),
+            FaucetError::RateLimited { .. }
+        ));
+        fund(&faucet, b, 0).await.unwrap();
+        // the global limit applies to every address
+        assert!(matches!(
+            faucet_error(
+                fund(&faucet, AccountAddress::random(), 0)
+                    .await
+                    .unwrap_err()
+            ),
+            FaucetError::RateLimited { .. }
+        ));
+
+        let faucet = new_faucet(FaucetConfig {
+            max_amount_per_address: Some(DEFAULT_AMOUNT),
+            ..Default::default()
+        });
+        fund(&faucet, a, 0).await.unwrap();
+        assert_eq!(
+            faucet_error(fund(&faucet, a, 0).await.unwrap_err()),
+            FaucetError::CapExceeded(a)
+        );
+        // creating an account does not transfer anything
+        faucet
+            .request(
+                a,
+                FaucetRequest::CreateAccount,
+                0,
+                |txn| async move { Ok(txn) },
+            )
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_expired_windows_are_dropped() {
+        let config = FaucetConfig {
+            per_address_window_secs: 10,
+            ..Default::default()
+        };
+        let mut state = FaucetState {
+            global: Window::default(),
+            per_address: HashMap::new(),
+            funded: HashMap::new(),
+            pruned_at: None,
+            next_sequence_number: None,
+        };
+        let start = Instant::now();
+        let (a, b) = (AccountAddress::random(), AccountAddress::random());
+        state.check(&config, a, DEFAULT_AMOUNT, start).unwrap();
+        state.record(&config, a, DEFAULT_AMOUNT, start);
+        // checking does not keep anything for an address
+        state.check(&config, b, DEFAULT_AMOUNT, start).unwrap();
+        assert_eq!(state.per_address.len(), 1);
+        // nor does funding without a cap
+        assert!(state.funded.is_empty());
+
+        let later = start + Duration::from_secs(5);
+        state.record(&config, b, DEFAULT_AMOUNT, later);
+        state.check(&config, b, DEFAULT_AMOUNT, start + Duration::from_secs(11)).unwrap();
+        assert_eq!(state.per_address.keys().collect::<Vec<_>>(), vec![&b]);
+    }
+
+    #[test]
+    fn test_window_expires() {
+        let mut window = Window::default();
+        let start = Instant::now();
+        let limit = Duration::from_secs(10);
+        window.requests.push_back(start);
+        assert_eq!(
+            window.retry_after(1, limit, start + Duration::from_secs(4)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(
+            window.retry_after(1, limit, start + Duration::from_secs(10)),
+            None
+        );
+        assert!(window.requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_use_consecutive_sequence_numbers() {
+        let faucet = new_faucet(FaucetConfig::default());
+        let requests = (0..8).map(|_| {
+            let faucet = faucet.clone();
+            // every request sees the same on-chain sequence number
+            tokio::spawn(async move { fund(&faucet, AccountAddress::random(), 5).await })
+        });
+        let mut sequence_numbers: Vec<u64> = futures::future::join_all(requests)
+            .await
+            .into_iter()
+            .map(|ret| ret.unwrap().unwrap().sequence_number())
+            .collect();
+        sequence_numbers.sort();
+        assert_eq!(sequence_numbers, (5..13).collect::<Vec<u64>>());
+
+        // a failed submission does not use up its sequence number
+        let ret = faucet
+            .request(
+                AccountAddress::random(),
+                FaucetRequest::Fund,
+                5,
+                |_| async move { Err::<SignedTransaction, _>(anyhow::anyhow!("rejected")) },
+            )
+            .await;
+        assert!(ret.is_err());
+        assert_eq!(
+            fund(&faucet, AccountAddress::random(), 5)
+                .await
+                .unwrap()
+                .sequence_number(),
+            13
+        );
+
+        // the ledger moving ahead wins over the local counter
+        assert_eq!(
+            fund(&faucet, AccountAddress::random(), 20)
+                .await
+                .unwrap()
+                .sequence_number(),
+            20
+        );
+    }
+}
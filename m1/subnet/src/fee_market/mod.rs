@@ -0,0 +1,299 @@
+//! Gas price estimation from the transactions of recently accepted blocks,
+//! and the gas price ordering of transactions in built blocks.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, VecDeque},
+    sync::Arc,
+};
+
+use aptos_api_types::GasEstimation;
+use aptos_types::account_address::AccountAddress;
+use aptos_types::transaction::SignedTransaction;
+use tokio::sync::RwLock;
+
+/// Number of accepted blocks gas prices are estimated from by default.
+pub const DEFAULT_WINDOW_BLOCKS: usize = 10;
+
+/// Minimum gas unit price of the Aptos gas schedule, in octas.
+pub const DEFAULT_MIN_GAS_UNIT_PRICE: u64 = 100;
+
+/// Block utilization, in percent of the block limit, above which blocks are
+/// considered congested by default.
+pub const DEFAULT_CONGESTION_THRESHOLD_PCT: u64 = 50;
+
+/// How many times the block limit worth of pending transactions block
+/// building picks the highest paying ones from.
+pub const CANDIDATE_FACTOR: usize = 4;
+
+/// Gas unit prices of the user transactions included in an accepted block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockGasUsage {
+    /// Sorted in ascending order.
+    gas_unit_prices: Vec<u64>,
+    max_txs: usize,
+}
+
+impl BlockGasUsage {
+    pub fn new(mut gas_unit_prices: Vec<u64>, max_txs: usize) -> Self {
+        gas_unit_prices.sort_unstable();
+        Self {
+            gas_unit_prices,
+            max_txs,
+        }
+    }
+
+    /// Returns the share of the block limit used, in percent.
+    pub fn utilization_pct(&self) -> u64 {
+        if self.max_txs == 0 {
+            return 100;
+        }
+        (self.gas_unit_prices.len() as u64 * 100 / self.max_txs as u64).min(100)
+    }
+}
+
+/// Estimates gas unit prices from the last accepted blocks. Cloning returns
+/// a handle to the same window of blocks.
+#[derive(Clone, Debug)]
+pub struct FeeMarket {
+    window_blocks: usize,
+    min_gas_unit_price: u64,
+    congestion_threshold_pct: u64,
+    blocks: Arc<RwLock<VecDeque<BlockGasUsage>>>,
+}
+
+impl Default for FeeMarket {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_WINDOW_BLOCKS,
+            DEFAULT_MIN_GAS_UNIT_PRICE,
+            DEFAULT_CONGESTION_THRESHOLD_PCT,
+        )
+    }
+}
+
+impl FeeMarket {
+    pub fn new(
+        window_blocks: usize,
+        min_gas_unit_price: u64,
+        congestion_threshold_pct: u64,
+    ) -> Self {
+        Self {
+            window_blocks,
+            min_gas_unit_price,
+            congestion_threshold_pct,
+            blocks: Arc::new(RwLock::new(VecDeque::with_capacity(window_blocks))),
+        }
+    }
+
+    /// Records an accepted block, evicting the oldest one out of the window.
+    pub async fn record(&self, usage: BlockGasUsage) {
+        let mut blocks = self.blocks.write().await;
+        while blocks.len() >= self.window_blocks {
+            blocks.pop_front();
+        }
+        blocks.push_back(usage);
+    }
+
+    /// Returns the estimated gas unit prices. While the recent blocks stay
+    /// below the congestion threshold, every transaction gets in at the
+    /// minimum price, and only the prioritized estimate follows the prices
+    /// paid. Once they are congested, the estimates are the low, median and
+    /// high prices paid by included transactions.
+    pub async fn estimate(&self) -> GasEstimation {
+        let blocks = self.blocks.read().await;
+        let mut prices: Vec<u64> = blocks
+            .iter()
+            .flat_map(|b| b.gas_unit_prices.iter().copied())
+            .collect();
+        if prices.is_empty() {
+            return GasEstimation {
+                deprioritized_gas_estimate: Some(self.min_gas_unit_price),
+                gas_estimate: self.min_gas_unit_price,
+                prioritized_gas_estimate: Some(self.min_gas_unit_price),
+            };
+        }
+        prices.sort_unstable();
+
+        let utilization_pct =
+            blocks.iter().map(|b| b.utilization_pct()).sum::<u64>() / blocks.len() as u64;
+        let min = self.min_gas_unit_price;
+        let (low, median) = if utilization_pct >= self.congestion_threshold_pct {
+            (
+                percentile(&prices, 25).max(min),
+                percentile(&prices, 50).max(min),
+            )
+        } else {
+            (min, min)
+        };
+        GasEstimation {
+            deprioritized_gas_estimate: Some(low),
+            gas_estimate: median,
+            prioritized_gas_estimate: Some(percentile(&prices, 90).max(median)),
+        }
+    }
+}
+
+/// Returns the `pct` percentile of the non-empty sorted `prices`.
+fn percentile(prices: &[u64], pct: usize) -> u64 {
+    let i = (prices.len() * pct / 100).min(prices.len() - 1);
+    prices[i]
+}
+
+/// Head transaction of a sender, ordered by gas unit price and then by
+/// first appearance, so the order only depends on the given transactions.
+#[derive(PartialEq, Eq)]
+struct SenderHead {
+    gas_unit_price: u64,
+    first_index: Reverse<usize>,
+    sender: AccountAddress,
+}
+
+impl Ord for SenderHead {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.gas_unit_price, self.first_index).cmp(&(other.gas_unit_price, other.first_index))
+    }
+}
+
+impl PartialOrd for SenderHead {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Orders `txns` by descending gas unit price and keeps the first `max_txs`.
+/// The transactions of a sender stay in sequence number order, so a
+/// transaction is only picked once the ones it depends on are.
+pub fn prioritize(txns: Vec<SignedTransaction>, max_txs: usize) -> Vec<SignedTransaction> {
+    let mut queues: HashMap<AccountAddress, (usize, VecDeque<SignedTransaction>)> = HashMap::new();
+    for (i, txn) in txns.into_iter().enumerate() {
+        queues
+            .entry(txn.sender())
+            .or_insert_with(|| (i, VecDeque::new()))
+            .1
+            .push_back(txn);
+    }
+    let mut heap = BinaryHeap::with_capacity(queues.len());
+    for (sender, (first_index, queue)) in queues.iter_mut() {
+        queue.make_contiguous().sort_by_key(|t| t.sequence_number());
+        heap.push(SenderHead {
+            gas_unit_price: queue[0].gas_unit_price(),
+            first_index: Reverse(*first_index),
+            sender: *sender,
+        });
+    }
+
+    let mut ordered = Vec::with_capacity(max_txs);
+    while ordered.len() < max_txs {
+        let head = match heap.pop() {
+            Some(head) => head,
+            None => break,
+        };
+        let (_, queue) = queues.get_mut(&head.sender).expect("sender has a queue");
+        if let Some(txn) = queue.pop_front() {
+            ordered.push(txn);
+        }
+        if let Some(next) = queue.front() {
+            heap.push(SenderHead {
+                gas_unit_price: next.gas_unit_price(),
+                ..head
+            });
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_crypto::ed25519::Ed25519PrivateKey;
+    use aptos_sdk::transaction_builder::TransactionFactory;
+    use aptos_sdk::types::{AccountKey, LocalAccount};
+    use aptos_types::chain_id::ChainId;
+
+    fn new_account(seed: u8) -> LocalAccount {
+        let key = Ed25519PrivateKey::try_from([seed; 32].as_slice()).unwrap();
+        let key = AccountKey::from_private_key(key);
+        LocalAccount::new(key.authentication_key().derived_address(), key, 0)
+    }
+
+    fn transfer(account: &LocalAccount, gas_unit_price: u64) -> SignedTransaction {
+        let tx_factory =
+            TransactionFactory::new(ChainId::test()).with_gas_unit_price(gas_unit_price);
+        account.sign_with_transaction_builder(tx_factory.transfer(AccountAddress::random(), 1))
+    }
+
+    fn prices(txns: &[SignedTransaction]) -> Vec<u64> {
+        txns.iter().map(|t| t.gas_unit_price()).collect()
+    }
+
+    #[test]
+    fn test_prioritize_by_gas_unit_price() {
+        let (a, b, c) = (new_account(1), new_account(2), new_account(3));
+        let txns = vec![transfer(&a, 100), transfer(&b, 300), transfer(&c, 200)];
+        assert_eq!(prices(&prioritize(txns.clone(), 10)), vec![300, 200, 100]);
+        assert_eq!(prices(&prioritize(txns, 2)), vec![300, 200]);
+
+        // equal prices keep the order they were given in
+        let txns = vec![transfer(&a, 100), transfer(&b, 100)];
+        let ordered = prioritize(txns, 10);
+        assert_eq!(ordered[0].sender(), a.address());
+    }
+
+    #[test]
+    fn test_prioritize_keeps_sequence_numbers_in_order() {
+        let (a, b) = (new_account(1), new_account(2));
+        // a's second transaction pays the most, but needs its first one
+        let a0 = transfer(&a, 100);
+        let a1 = transfer(&a, 500);
+        let b0 = transfer(&b, 300);
+        let ordered = prioritize(vec![a1, b0, a0], 10);
+        assert_eq!(prices(&ordered), vec![300, 100, 500]);
+        assert_eq!(ordered[1].sequence_number(), 0);
+        assert_eq!(ordered[2].sequence_number(), 1);
+
+        let ordered = prioritize(vec![transfer(&a, 500), transfer(&b, 300)], 1);
+        assert_eq!(ordered[0].sequence_number(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_estimate_follows_congestion() {
+        let market = FeeMarket::new(2, 100, 50);
+        let estimate = market.estimate().await;
+        assert_eq!(estimate.gas_estimate, 100);
+        assert_eq!(estimate.prioritized_gas_estimate, Some(100));
+
+        // a quarter full: everything gets in at the minimum price
+        market.record(BlockGasUsage::new(vec![400, 150], 8)).await;
+        let estimate = market.estimate().await;
+        assert_eq!(estimate.deprioritized_gas_estimate, Some(100));
+        assert_eq!(estimate.gas_estimate, 100);
+        assert_eq!(estimate.prioritized_gas_estimate, Some(400));
+
+        // full blocks push the window past the threshold
+        market
+            .record(BlockGasUsage::new(
+                vec![100, 200, 300, 500, 600, 700, 800, 900],
+                8,
+            ))
+            .await;
+        let estimate = market.estimate().await;
+        assert_eq!(estimate.deprioritized_gas_estimate, Some(200));
+        assert_eq!(estimate.gas_estimate, 500);
+        assert_eq!(estimate.prioritized_gas_estimate, Some(900));
+
+        // the first block falls out of the window
+        market.record(BlockGasUsage::new(vec![1000; 8], 8)).await;
+        market.record(BlockGasUsage::new(vec![1000; 8], 8)).await;
+        let estimate = market.estimate().await;
+        assert_eq!(estimate.deprioritized_gas_estimate, Some(1000));
+        assert_eq!(estimate.gas_estimate, 1000);
+    }
+
+    #[test]
+    fn test_utilization() {
+        assert_eq!(BlockGasUsage::new(vec![], 8).utilization_pct(), 0);
+        assert_eq!(BlockGasUsage::new(vec![1; 2], 8).utilization_pct(), 25);
+        assert_eq!(BlockGasUsage::new(vec![1; 9], 8).utilization_pct(), 100);
+    }
+}
@@ -0,0 +1,511 @@
+//! Genesis document of the M1 subnet, decoded from the genesis bytes given to
+//! [`Vm::initialize`](crate::vm::Vm).
+
+use std::{
+    collections::HashSet,
+    io::{self, Error, ErrorKind},
+};
+
+use anyhow::Context as AnyhowContext;
+use aptos_crypto::{bls12381, ed25519::Ed25519PublicKey, HashValue};
+use aptos_framework::ReleaseBundle;
+use aptos_sdk::transaction_builder::TransactionFactory;
+use aptos_sdk::types::LocalAccount;
+use aptos_types::account_address::AccountAddress;
+use aptos_types::chain_id::ChainId;
+use aptos_types::on_chain_config::{GasScheduleV2, OnChainConsensusConfig, OnChainExecutionConfig};
+use aptos_types::transaction::{ChangeSet, SignedTransaction};
+use aptos_types::validator_signer::ValidatorSigner;
+use aptos_vm_genesis::{
+    default_gas_schedule, encode_genesis_change_set, GenesisConfiguration, TestValidator, Validator,
+    GENESIS_KEYPAIR,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+/// Aptos chain id used when the genesis does not set one, i.e., `ChainId::test()`.
+pub const DEFAULT_CHAIN_ID: u8 = 4;
+
+/// Number of validators in the Aptos validator set by default.
+pub const DEFAULT_VALIDATOR_COUNT: usize = 1;
+
+/// Stake of each genesis validator by default, in octas.
+pub const DEFAULT_VALIDATOR_STAKE: u64 = 100_000_000;
+
+/// Length of an Aptos epoch by default.
+pub const DEFAULT_EPOCH_DURATION_SECS: u64 = 3600;
+
+/// Returns the hash of a genesis document, or `None` for the genesis bytes
+/// older nodes were launched with, i.e., anything but a JSON document.
+pub fn document_hash(d: impl AsRef<[u8]>) -> Option<HashValue> {
+    let dd = d.as_ref();
+    match dd.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'{') => Some(HashValue::sha3_256_of(dd)),
+        _ => None,
+    }
+}
+
+/// Typed genesis of the subnet. Every field is optional in the encoded
+/// document; the defaults reproduce the Aptos test genesis the Vm used to
+/// hardcode, so existing chains keep the same state.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Genesis {
+    /// Aptos chain id that transactions must be signed for.
+    pub chain_id: u8,
+    /// Aptos validator set. Required outside the test chain, where
+    /// `validator_count` test validators are used if it is empty.
+    pub validators: Vec<GenesisValidator>,
+    /// Number of test validators in the Aptos validator set of the test chain.
+    /// Their keys are derived deterministically, and hence public, so that
+    /// every node builds the same genesis.
+    pub validator_count: usize,
+    /// Stake of each test validator, in octas.
+    pub validator_stake: u64,
+    /// Length of an Aptos epoch.
+    pub epoch_duration_secs: u64,
+    /// Public key of the core resources account, which can mint coins and
+    /// funds [`accounts`](Genesis::accounts). The test chain defaults to the
+    /// well-known test key; other chains have no core resources account
+    /// unless it is set.
+    pub root_key: Option<Ed25519PublicKey>,
+    /// Accounts funded by the core resources account right after genesis.
+    pub accounts: Vec<GenesisAccount>,
+    /// Base64 of the BCS-encoded framework release bundle.
+    /// The framework the node was built with is used if unset.
+    pub framework: Option<String>,
+    /// Gas schedule installed at genesis.
+    /// The default schedule of the framework is used if unset.
+    pub gas_schedule: Option<GasScheduleV2>,
+}
+
+/// Validator registered at genesis. Its account owns and operates its stake,
+/// and votes for it.
+///
+/// Each node signs the ledger infos it commits with its own consensus key, see
+/// [`VmConfig::consensus_key`](crate::config::VmConfig::consensus_key).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisValidator {
+    pub address: AccountAddress,
+    /// BLS12-381 public key of the validator's consensus key.
+    pub consensus_public_key: bls12381::PublicKey,
+    /// Proof of possession of the consensus key.
+    pub proof_of_possession: bls12381::ProofOfPossession,
+    /// Stake, in octas.
+    pub stake: u64,
+}
+
+/// Account funded at genesis.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisAccount {
+    pub address: AccountAddress,
+    /// Initial balance, in octas.
+    pub balance: u64,
+}
+
+impl Default for Genesis {
+    fn default() -> Self {
+        Self {
+            chain_id: DEFAULT_CHAIN_ID,
+            validators: Vec::new(),
+            validator_count: DEFAULT_VALIDATOR_COUNT,
+            validator_stake: DEFAULT_VALIDATOR_STAKE,
+            epoch_duration_secs: DEFAULT_EPOCH_DURATION_SECS,
+            root_key: None,
+            accounts: Vec::new(),
+            framework: None,
+            gas_schedule: None,
+        }
+    }
+}
+
+impl Genesis {
+    /// Encodes the genesis to pretty-printed JSON bytes.
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        serde_json::to_vec_pretty(self).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize Genesis to JSON bytes: {}", e),
+            )
+        })
+    }
+
+    /// Decodes and verifies the genesis from its JSON bytes.
+    /// Empty bytes, or the plain message older nodes were launched with,
+    /// decode to the default genesis.
+    pub fn from_slice(d: impl AsRef<[u8]>) -> io::Result<Self> {
+        let dd = d.as_ref();
+        match dd.iter().find(|b| !b.is_ascii_whitespace()) {
+            None => return Ok(Self::default()),
+            Some(b'{') => {}
+            Some(_) => {
+                log::warn!("genesis bytes are not a JSON document -- using the default genesis");
+                return Ok(Self::default());
+            }
+        }
+
+        let genesis: Self = serde_json::from_slice(dd).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to deserialize Genesis from JSON: {}", e),
+            )
+        })?;
+        genesis.verify()?;
+        Ok(genesis)
+    }
+
+    /// Checks that the genesis can be turned into an Aptos genesis.
+    pub fn verify(&self) -> io::Result<()> {
+        if self.chain_id == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "chain id must not be 0"));
+        }
+        if self.validators.is_empty() && self.validator_count == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "at least one validator is required",
+            ));
+        }
+        // the keys of the test validators are public
+        if self.validators.is_empty() && !self.is_test() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "validators must be listed on chains other than the test chain",
+            ));
+        }
+        let mut seen = HashSet::new();
+        for validator in self.validators.iter() {
+            if !seen.insert(validator.address) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("validator {} is registered more than once", validator.address),
+                ));
+            }
+            if validator.stake == 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("validator {} has a zero stake", validator.address),
+                ));
+            }
+            validator
+                .proof_of_possession
+                .verify(&validator.consensus_public_key)
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("invalid proof of possession of validator {}: {}", validator.address, e),
+                    )
+                })?;
+        }
+        if self.epoch_duration_secs == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "epoch duration must not be 0",
+            ));
+        }
+
+        if !self.accounts.is_empty() && self.core_resources_key().is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "a root key is required to fund accounts at genesis",
+            ));
+        }
+        let mut seen = HashSet::new();
+        for account in self.accounts.iter() {
+            if !seen.insert(account.address) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("account {} is funded more than once", account.address),
+                ));
+            }
+            if account.balance == 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("account {} has a zero balance", account.address),
+                ));
+            }
+        }
+
+        self.release_bundle()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        Ok(())
+    }
+
+    pub fn chain_id(&self) -> ChainId {
+        ChainId::new(self.chain_id)
+    }
+
+    /// Whether the chain is the Aptos test chain, which may use the test
+    /// validators and the well-known test root key.
+    pub fn is_test(&self) -> bool {
+        self.chain_id() == ChainId::test()
+    }
+
+    /// Returns the key of the core resources account, if the chain has one.
+    pub fn core_resources_key(&self) -> Option<Ed25519PublicKey> {
+        self.root_key
+            .clone()
+            .or_else(|| self.is_test().then(|| GENESIS_KEYPAIR.1.clone()))
+    }
+
+    fn test_validators(&self) -> Vec<TestValidator> {
+        TestValidator::new_test_set(Some(self.validator_count), Some(self.validator_stake))
+    }
+
+    /// Returns the Aptos validator set registered at genesis, i.e., the test
+    /// validators on a test chain that does not list its own.
+    pub fn validators(&self) -> Vec<Validator> {
+        if self.validators.is_empty() {
+            return self.test_validators().into_iter().map(|v| v.data).collect();
+        }
+        self.validators
+            .iter()
+            .map(|v| Validator {
+                owner_address: v.address,
+                operator_address: v.address,
+                voter_address: v.address,
+                stake_amount: v.stake,
+                consensus_pubkey: v.consensus_public_key.to_bytes().to_vec(),
+                proof_of_possession: v.proof_of_possession.to_bytes().to_vec(),
+                network_addresses: vec![],
+                full_node_network_addresses: vec![],
+            })
+            .collect()
+    }
+
+    /// Returns the signer of the validator whose consensus key is given, or of
+    /// the first test validator if no key is given on a test chain that has
+    /// no validator set of its own.
+    pub fn signer(&self, consensus_key: Option<&bls12381::PrivateKey>) -> Result<ValidatorSigner, anyhow::Error> {
+        let consensus_key = match consensus_key {
+            Some(consensus_key) => consensus_key,
+            None if self.validators.is_empty() && self.is_test() => {
+                let validator = self.test_validators().swap_remove(0);
+                return Ok(ValidatorSigner::new(validator.data.owner_address, validator.consensus_key));
+            }
+            None => return Err(anyhow::anyhow!("a consensus key is required to validate a genesis with validators")),
+        };
+        let public_key = bls12381::PublicKey::from(consensus_key).to_bytes().to_vec();
+        let validator = self
+            .validators()
+            .into_iter()
+            .find(|v| v.consensus_pubkey == public_key)
+            .ok_or_else(|| anyhow::anyhow!("the consensus key is not the key of a genesis validator"))?;
+        Ok(ValidatorSigner::new(validator.owner_address, consensus_key.clone()))
+    }
+
+    /// Returns the framework release bundle installed at genesis.
+    pub fn release_bundle(&self) -> Result<ReleaseBundle, anyhow::Error> {
+        match self.framework.as_ref() {
+            Some(encoded) => {
+                let bytes = BASE64
+                    .decode(encoded)
+                    .context("Failed to decode framework release bundle from base64")?;
+                bcs::from_bytes(&bytes).context("Failed to parse framework release bundle")
+            }
+            None => Ok(aptos_cached_packages::head_release_bundle().clone()),
+        }
+    }
+
+    /// Builds the Aptos genesis change set.
+    pub fn change_set(&self) -> Result<ChangeSet, anyhow::Error> {
+        let framework = self.release_bundle()?;
+        let validator_data = self.validators();
+        let gas_schedule = self
+            .gas_schedule
+            .clone()
+            .unwrap_or_else(default_gas_schedule);
+
+        // Aptos only creates the core resources account in test mode, and
+        // ignores the key otherwise
+        let core_resources_key = self.core_resources_key();
+        let change_set = encode_genesis_change_set(
+            core_resources_key.as_ref().unwrap_or(&GENESIS_KEYPAIR.1),
+            &validator_data,
+            &framework,
+            self.chain_id(),
+            &GenesisConfiguration {
+                allow_new_validators: true,
+                epoch_duration_secs: self.epoch_duration_secs,
+                is_test: core_resources_key.is_some(),
+                min_stake: 0,
+                min_voting_threshold: 0,
+                max_stake: 100_000_000_000_000,
+                recurring_lockup_duration_secs: 7200,
+                required_proposer_stake: 0,
+                rewards_apy_percentage: 10,
+                voting_duration_secs: 3600,
+                voting_power_increase_limit: 50,
+                employee_vesting_start: 1663456089,
+                employee_vesting_period_duration: 5 * 60,
+            },
+            &OnChainConsensusConfig::default(),
+            &OnChainExecutionConfig::default_for_genesis(),
+            &gas_schedule,
+        );
+        Ok(change_set)
+    }
+
+    /// Returns the transfers from the core resources account funding
+    /// [`accounts`](Genesis::accounts). They never expire so that every node
+    /// signs exactly the same transactions.
+    pub fn funding_transactions(&self, core_account: &LocalAccount) -> Vec<SignedTransaction> {
+        let tx_factory = TransactionFactory::new(self.chain_id());
+        self.accounts
+            .iter()
+            .map(|account| {
+                core_account.sign_with_transaction_builder(
+                    tx_factory
+                        .transfer(account.address, account.balance)
+                        .expiration_timestamp_secs(u64::MAX),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_crypto::{ed25519::Ed25519PrivateKey, Uniform};
+
+    #[test]
+    fn test_genesis_round_trip() {
+        let genesis = Genesis {
+            validator_count: 3,
+            accounts: vec![GenesisAccount {
+                address: AccountAddress::random(),
+                balance: 1_000,
+            }],
+            ..Default::default()
+        };
+        let decoded = Genesis::from_slice(genesis.to_vec().unwrap()).unwrap();
+        assert_eq!(decoded.validator_count, 3);
+        assert_eq!(decoded.validator_stake, DEFAULT_VALIDATOR_STAKE);
+        assert_eq!(decoded.accounts, genesis.accounts);
+        assert_eq!(decoded.chain_id(), ChainId::test());
+        assert!(decoded.is_test());
+        assert_eq!(decoded.validators().len(), 3);
+        assert_eq!(document_hash(genesis.to_vec().unwrap()), Some(HashValue::sha3_256_of(&genesis.to_vec().unwrap())));
+        assert_eq!(document_hash(b"hello world"), None);
+    }
+
+    #[test]
+    fn test_genesis_validators() {
+        let key = bls12381::PrivateKey::generate(&mut rand::rngs::OsRng);
+        let other_key = bls12381::PrivateKey::generate(&mut rand::rngs::OsRng);
+        let validator = GenesisValidator {
+            address: AccountAddress::random(),
+            consensus_public_key: bls12381::PublicKey::from(&key),
+            proof_of_possession: bls12381::ProofOfPossession::create(&key),
+            stake: 1_000,
+        };
+        let genesis = Genesis {
+            chain_id: 42,
+            validators: vec![validator.clone()],
+            ..Default::default()
+        };
+        let decoded = Genesis::from_slice(genesis.to_vec().unwrap()).unwrap();
+        assert_eq!(decoded.chain_id(), ChainId::new(42));
+        assert!(!decoded.is_test());
+
+        let validators = decoded.validators();
+        assert_eq!(validators.len(), 1);
+        assert_eq!(validators[0].owner_address, validator.address);
+        assert_eq!(validators[0].stake_amount, 1_000);
+        assert_eq!(decoded.signer(Some(&key)).unwrap().author(), validator.address);
+        assert!(decoded.signer(Some(&other_key)).is_err());
+        assert!(decoded.signer(None).is_err());
+        // the test validators sign when the genesis has no validator set
+        assert!(Genesis::default().signer(None).is_ok());
+
+        let mut invalid = genesis.clone();
+        invalid.validators[0].proof_of_possession = bls12381::ProofOfPossession::create(&other_key);
+        assert!(Genesis::from_slice(invalid.to_vec().unwrap()).is_err());
+
+        // funding accounts takes a root key outside the test chain
+        let account = GenesisAccount {
+            address: AccountAddress::random(),
+            balance: 1_000,
+        };
+        let unfunded = Genesis {
+            accounts: vec![account.clone()],
+            ..genesis.clone()
+        };
+        assert!(unfunded.core_resources_key().is_none());
+        assert!(Genesis::from_slice(unfunded.to_vec().unwrap()).is_err());
+        let root_key = Ed25519PrivateKey::generate(&mut rand::rngs::OsRng);
+        let funded = Genesis {
+            root_key: Some(Ed25519PublicKey::from(&root_key)),
+            ..unfunded
+        };
+        let decoded = Genesis::from_slice(funded.to_vec().unwrap()).unwrap();
+        assert_eq!(decoded.accounts, vec![account]);
+        assert_eq!(decoded.core_resources_key(), Some(Ed25519PublicKey::from(&root_key)));
+        assert_eq!(Genesis::default().core_resources_key(), Some(GENESIS_KEYPAIR.1.clone()));
+    }
+
+    #[test]
+    fn test_genesis_defaults() {
+        for d in [&b""[..], b"  \n", b"hello world", b"{}"] {
+            let genesis = Genesis::from_slice(d).unwrap();
+            assert_eq!(genesis.chain_id(), ChainId::test());
+            assert_eq!(genesis.validator_count, DEFAULT_VALIDATOR_COUNT);
+            assert!(genesis.accounts.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_genesis_rejects_invalid_documents() {
+        let address = AccountAddress::random();
+        let invalid = [
+            r#"{"chain_id": 0}"#.to_string(),
+            r#"{"validator_count": 0}"#.to_string(),
+            r#"{"unknown_field": 1}"#.to_string(),
+            r#"{"framework": "not base64!"}"#.to_string(),
+            format!(
+                r#"{{"accounts": [{{"address": "{0}", "balance": 1}}, {{"address": "{0}", "balance": 2}}]}}"#,
+                address.to_hex_literal()
+            ),
+            format!(
+                r#"{{"accounts": [{{"address": "{}", "balance": 0}}]}}"#,
+                address.to_hex_literal()
+            ),
+            // other chains list their validators
+            r#"{"chain_id": 42}"#.to_string(),
+        ];
+        for d in invalid.iter() {
+            assert!(Genesis::from_slice(d.as_bytes()).is_err(), "{}", d);
+        }
+    }
+
+    #[test]
+    fn test_funding_transactions_are_deterministic() {
+        let genesis = Genesis {
+            accounts: vec![
+                GenesisAccount {
+                    address: AccountAddress::random(),
+                    balance: 1_000,
+                },
+                GenesisAccount {
+                    address: AccountAddress::random(),
+                    balance: 2_000,
+                },
+            ],
+            ..Default::default()
+        };
+        let core_account = || {
+            LocalAccount::new(
+                aptos_types::account_config::aptos_test_root_address(),
+                aptos_sdk::types::AccountKey::from_private_key(GENESIS_KEYPAIR.0.clone()),
+                0,
+            )
+        };
+        let a = genesis.funding_transactions(&core_account());
+        let b = genesis.funding_transactions(&core_account());
+        assert_eq!(a.len(), 2);
+        assert_eq!(a, b);
+        assert_eq!(a[1].sequence_number(), 1);
+    }
+}
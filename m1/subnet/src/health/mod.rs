@@ -0,0 +1,127 @@
+//! Health report of the Vm, returned by `health_check` so that avalanchego's
+//! health API surfaces an unhealthy node.
+
+use serde::{Deserialize, Serialize};
+
+/// How long the last accepted block may be old while transactions are
+/// pending, by default.
+pub const DEFAULT_MAX_BLOCK_AGE_SECS: u64 = 3600;
+
+/// State of the Vm the health report is made of.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    pub healthy: bool,
+    /// Reasons the node is unhealthy.
+    pub problems: Vec<String>,
+    pub bootstrapped: bool,
+    pub last_accepted_height: Option<u64>,
+    /// Seconds since the timestamp of the last accepted block.
+    pub last_accepted_age_secs: Option<u64>,
+    /// Latest version committed to AptosDB.
+    pub aptos_version: Option<u64>,
+    /// Whether AptosDB is at the last accepted block.
+    pub aptos_in_sync: bool,
+    /// Number of transactions ready to be included in a block.
+    pub mempool_depth: Option<usize>,
+    /// Seconds the current block build has been in progress for.
+    pub build_in_progress_secs: Option<u64>,
+}
+
+/// Limits the report is checked against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HealthLimits {
+    pub max_block_age_secs: u64,
+    pub max_build_secs: u64,
+}
+
+impl HealthReport {
+    /// Records the problems the report shows and whether the node is healthy.
+    /// Components that are missing are expected to be reported as problems by
+    /// the caller.
+    pub fn check(mut self, limits: HealthLimits) -> Self {
+        if self.last_accepted_height.is_none() {
+            self.problems.push("no accepted block".to_string());
+        } else if !self.aptos_in_sync {
+            self.problems
+                .push("AptosDB is not at the last accepted block".to_string());
+        }
+        // an idle chain builds no blocks, so an old block is only a problem
+        // while transactions are waiting
+        if let (Some(age), Some(depth)) = (self.last_accepted_age_secs, self.mempool_depth) {
+            if self.bootstrapped && depth > 0 && age > limits.max_block_age_secs {
+                self.problems.push(format!(
+                    "no block accepted for {} seconds with {} pending transactions",
+                    age, depth
+                ));
+            }
+        }
+        if let Some(secs) = self.build_in_progress_secs {
+            if secs > limits.max_build_secs {
+                self.problems
+                    .push(format!("block build in progress for {} seconds", secs));
+            }
+        }
+        self.healthy = self.problems.is_empty();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: HealthLimits = HealthLimits {
+        max_block_age_secs: 60,
+        max_build_secs: 10,
+    };
+
+    fn healthy_report() -> HealthReport {
+        HealthReport {
+            bootstrapped: true,
+            last_accepted_height: Some(5),
+            last_accepted_age_secs: Some(120),
+            aptos_version: Some(40),
+            aptos_in_sync: true,
+            mempool_depth: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_idle_chain_is_healthy() {
+        let report = healthy_report().check(LIMITS);
+        assert!(report.healthy);
+        assert!(report.problems.is_empty());
+    }
+
+    #[test]
+    fn test_problems() {
+        let report = HealthReport {
+            mempool_depth: Some(3),
+            ..healthy_report()
+        }
+        .check(LIMITS);
+        assert!(!report.healthy);
+        assert!(report.problems[0].starts_with("no block accepted for 120 seconds"));
+
+        // still catching up with the network
+        let report = HealthReport {
+            bootstrapped: false,
+            mempool_depth: Some(3),
+            ..healthy_report()
+        }
+        .check(LIMITS);
+        assert!(report.healthy);
+
+        let report = HealthReport {
+            aptos_in_sync: false,
+            build_in_progress_secs: Some(11),
+            ..healthy_report()
+        }
+        .check(LIMITS);
+        assert_eq!(report.problems.len(), 2);
+
+        let report = HealthReport::default().check(LIMITS);
+        assert_eq!(report.problems, vec!["no accepted block".to_string()]);
+    }
+}
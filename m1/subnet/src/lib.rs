@@ -1,6 +1,15 @@
 pub mod api;
 pub mod block;
+pub mod config;
+pub mod faucet;
+pub mod fee_market;
+pub mod genesis;
+pub mod health;
+pub mod metrics;
+pub mod scheduler;
 pub mod state;
+pub mod state_sync;
+pub mod subscription;
 pub mod vm;
 pub mod util;
 
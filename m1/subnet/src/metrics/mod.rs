@@ -0,0 +1,134 @@
+//! Prometheus metrics of the subnet Vm, registered in the default registry
+//! along with the metrics of the Aptos components it runs, e.g., the mempool
+//! size is reported by `aptos_core_mempool_index_size`.
+
+use std::io;
+
+use aptos_metrics_core::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramVec, IntCounterVec, IntGauge, TextEncoder,
+};
+use once_cell::sync::Lazy;
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Buckets of the block latency histograms, from 1ms to about 65s.
+fn latency_buckets() -> Vec<f64> {
+    exponential_buckets(0.001, 2.0, 17).expect("valid buckets")
+}
+
+pub static BLOCK_BUILD_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "m1_block_build_seconds",
+        "Time to build a block",
+        latency_buckets()
+    )
+    .unwrap()
+});
+
+pub static BLOCK_EXECUTE_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "m1_block_execute_seconds",
+        "Time to execute a block with the Aptos executor",
+        latency_buckets()
+    )
+    .unwrap()
+});
+
+pub static BLOCK_COMMIT_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "m1_block_commit_seconds",
+        "Time to commit an accepted block to AptosDB",
+        latency_buckets()
+    )
+    .unwrap()
+});
+
+pub static BLOCK_TRANSACTIONS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "m1_block_transactions",
+        "Number of user transactions in accepted blocks",
+        exponential_buckets(1.0, 2.0, 14).expect("valid buckets")
+    )
+    .unwrap()
+});
+
+pub static MEMPOOL_ADMISSIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "m1_mempool_admissions_total",
+        "Transactions submitted to the mempool, by mempool status",
+        &["status"]
+    )
+    .unwrap()
+});
+
+pub static UNDECIDED_BLOCKS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "m1_undecided_blocks",
+        "Number of verified blocks that are neither accepted nor rejected"
+    )
+    .unwrap()
+});
+
+pub static ACCEPTED_HEIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("m1_accepted_height", "Height of the last accepted block").unwrap()
+});
+
+pub static FAUCET_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "m1_faucet_requests_total",
+        "Faucet requests, by result",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static RPC_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "m1_rpc_seconds",
+        "Time to serve a JSON-RPC request, by method",
+        &["method"],
+        latency_buckets()
+    )
+    .unwrap()
+});
+
+pub static RPC_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "m1_rpc_errors_total",
+        "JSON-RPC requests answered with an error, by method",
+        &["method"]
+    )
+    .unwrap()
+});
+
+/// Encodes every registered metric in the text exposition format.
+pub fn gather() -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    TextEncoder::new()
+        .encode(&aptos_metrics_core::gather(), &mut buf)
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                format!("failed to encode metrics: {}", e),
+            )
+        })?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gather_includes_vm_metrics() {
+        // other tests update the same metrics concurrently
+        RPC_ERRORS.with_label_values(&["test.gather"]).inc();
+        BLOCK_BUILD_SECONDS.observe(0.5);
+
+        let text = String::from_utf8(gather().unwrap()).unwrap();
+        assert!(text.contains(r#"m1_rpc_errors_total{method="test.gather"} 1"#));
+        assert!(text.contains("m1_block_build_seconds_count"));
+    }
+}
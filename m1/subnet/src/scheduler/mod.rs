@@ -0,0 +1,429 @@
+//! Block building scheduler. Tells the consensus engine to build a block,
+//! with `PendingTxs`, exactly when the mempool has transactions to include
+//! and no block of ours is waiting for a decision.
+//!
+//! [`Scheduler`] is the state machine, fed with mempool inserts and block
+//! decisions. [`BuildScheduler`] shares it with the task that fires its
+//! deadlines.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{Mutex, Notify};
+
+/// Minimum time between two accepted blocks and the next notification, by
+/// default.
+pub const DEFAULT_MIN_BLOCK_INTERVAL_MS: u64 = 200;
+
+/// How long a notification or a built block may go without a decision before
+/// the engine is notified again, by default.
+pub const DEFAULT_BUILD_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    /// Minimum time between an accepted block and the next notification,
+    /// giving the mempool a chance to fill up.
+    pub min_block_interval: Duration,
+    /// Number of pending transactions that triggers a notification without
+    /// waiting for the minimum block interval.
+    pub max_batch_txs: usize,
+    /// How long a notification or a built block may go without a decision
+    /// before it is given up on.
+    pub build_timeout: Duration,
+}
+
+/// Where block building is at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildState {
+    /// No transactions are pending.
+    Idle,
+    /// Transactions are pending, the engine is notified at `until` unless
+    /// enough of them arrive to fill a batch first.
+    Waiting { until: Instant },
+    /// The engine was notified at `at` and is yet to build the block.
+    Notified { at: Instant },
+    /// A block was built at `at` and is yet to be accepted or rejected.
+    Proposed { at: Instant },
+}
+
+/// What the scheduler is told about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Transactions were newly admitted into the mempool.
+    TxsAdded(usize),
+    /// The engine built a block.
+    BlockBuilt,
+    /// The engine failed to build a block.
+    BuildFailed,
+    /// A block was accepted, leaving `pending` transactions in the mempool.
+    BlockAccepted { pending: usize },
+    /// A block was rejected, leaving `pending` transactions in the mempool.
+    BlockRejected { pending: usize },
+    /// Time passed, firing the deadline if it is due.
+    Tick,
+}
+
+/// What the caller of the scheduler has to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    None,
+    /// Send `PendingTxs` to the engine.
+    NotifyEngine,
+}
+
+#[derive(Clone, Debug)]
+pub struct Scheduler {
+    config: SchedulerConfig,
+    state: BuildState,
+    /// Transactions in the mempool, as far as the scheduler knows.
+    pending: usize,
+    last_accepted_at: Option<Instant>,
+    /// Since when a block build has been in progress. Notifying the engine
+    /// again after a timeout keeps it, so that a stuck build shows.
+    build_started_at: Option<Instant>,
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            state: BuildState::Idle,
+            pending: 0,
+            last_accepted_at: None,
+            build_started_at: None,
+        }
+    }
+
+    pub fn state(&self) -> BuildState {
+        self.state
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Returns when the scheduler wants to be ticked next.
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            BuildState::Idle => None,
+            BuildState::Waiting { until } => Some(until),
+            BuildState::Notified { at } | BuildState::Proposed { at } => {
+                Some(at + self.config.build_timeout)
+            }
+        }
+    }
+
+    /// Returns since when a block build has been in progress, if any.
+    pub fn build_started_at(&self) -> Option<Instant> {
+        self.build_started_at
+    }
+
+    pub fn handle(&mut self, event: Event, now: Instant) -> Action {
+        let action = self.transition(event, now);
+        self.build_started_at = match self.state {
+            BuildState::Notified { at } | BuildState::Proposed { at } => match event {
+                // neither new transactions nor time passing is progress
+                Event::TxsAdded(_) | Event::Tick => Some(self.build_started_at.unwrap_or(at)),
+                _ => Some(at),
+            },
+            BuildState::Idle | BuildState::Waiting { .. } => None,
+        };
+        action
+    }
+
+    fn transition(&mut self, event: Event, now: Instant) -> Action {
+        match event {
+            Event::TxsAdded(n) => {
+                self.pending = self.pending.saturating_add(n);
+                match self.state {
+                    BuildState::Idle | BuildState::Waiting { .. } => self.schedule(now),
+                    // the block in flight or the next one picks them up
+                    BuildState::Notified { .. } | BuildState::Proposed { .. } => Action::None,
+                }
+            }
+            Event::BlockBuilt => {
+                self.state = BuildState::Proposed { at: now };
+                Action::None
+            }
+            Event::BuildFailed => {
+                // back off for an interval instead of notifying right away
+                self.state = if self.pending > 0 {
+                    BuildState::Waiting {
+                        until: now + self.config.min_block_interval,
+                    }
+                } else {
+                    BuildState::Idle
+                };
+                Action::None
+            }
+            Event::BlockAccepted { pending } => {
+                self.pending = pending;
+                self.last_accepted_at = Some(now);
+                self.decided(now)
+            }
+            Event::BlockRejected { pending } => {
+                self.pending = pending;
+                self.decided(now)
+            }
+            Event::Tick => match self.state {
+                BuildState::Waiting { until } if now >= until => self.schedule(now),
+                BuildState::Notified { at } | BuildState::Proposed { at }
+                    if now >= at + self.config.build_timeout =>
+                {
+                    log::warn!(
+                        "no block decided {:?} after the build started, rescheduling",
+                        now - at
+                    );
+                    self.schedule(now)
+                }
+                _ => Action::None,
+            },
+        }
+    }
+
+    /// Reschedules after a block decision. A notification the engine has yet
+    /// to act on stays pending, since it builds on the new preferred block.
+    fn decided(&mut self, now: Instant) -> Action {
+        match self.state {
+            BuildState::Notified { .. } => Action::None,
+            _ => self.schedule(now),
+        }
+    }
+
+    /// Notifies the engine if there is work and the last block is old
+    /// enough, or enough work to fill a batch.
+    fn schedule(&mut self, now: Instant) -> Action {
+        if self.pending == 0 {
+            self.state = BuildState::Idle;
+            return Action::None;
+        }
+        let ready_at = self
+            .last_accepted_at
+            .map(|t| t + self.config.min_block_interval)
+            .unwrap_or(now);
+        if self.pending >= self.config.max_batch_txs || now >= ready_at {
+            self.state = BuildState::Notified { at: now };
+            Action::NotifyEngine
+        } else {
+            self.state = BuildState::Waiting { until: ready_at };
+            Action::None
+        }
+    }
+}
+
+/// Shares the [`Scheduler`] between the Vm and the task firing its
+/// deadlines. Cloning returns a handle to the same scheduler.
+#[derive(Clone, Debug)]
+pub struct BuildScheduler {
+    scheduler: Arc<Mutex<Scheduler>>,
+    /// Wakes the deadline task up when the deadline may have changed.
+    wake: Arc<Notify>,
+}
+
+impl BuildScheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            scheduler: Arc::new(Mutex::new(Scheduler::new(config))),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    pub async fn handle(&self, event: Event) -> Action {
+        let action = self.scheduler.lock().await.handle(event, Instant::now());
+        self.wake.notify_one();
+        action
+    }
+
+    pub async fn state(&self) -> BuildState {
+        self.scheduler.lock().await.state()
+    }
+
+    pub async fn build_started_at(&self) -> Option<Instant> {
+        self.scheduler.lock().await.build_started_at()
+    }
+
+    /// Waits until the deadline is due, or the deadline may have changed.
+    /// The caller is expected to tick the scheduler afterwards.
+    pub async fn wait(&self) {
+        let deadline = self.scheduler.lock().await.deadline();
+        match deadline {
+            Some(deadline) => {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline.into()) => {},
+                    _ = self.wake.notified() => {},
+                }
+            }
+            None => self.wake.notified().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: SchedulerConfig = SchedulerConfig {
+        min_block_interval: Duration::from_millis(200),
+        max_batch_txs: 10,
+        build_timeout: Duration::from_secs(30),
+    };
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_notifies_only_with_work() {
+        let now = Instant::now();
+        let mut s = Scheduler::new(CONFIG);
+        assert_eq!(s.handle(Event::Tick, now), Action::None);
+        assert_eq!(
+            s.handle(Event::BlockAccepted { pending: 0 }, now),
+            Action::None
+        );
+        assert_eq!(s.state(), BuildState::Idle);
+        assert_eq!(s.deadline(), None);
+
+        // an idle chain notifies on the first transaction
+        let mut s = Scheduler::new(CONFIG);
+        assert_eq!(s.handle(Event::TxsAdded(1), now), Action::NotifyEngine);
+        assert_eq!(s.state(), BuildState::Notified { at: now });
+
+        // and only once until the block is decided
+        assert_eq!(s.handle(Event::TxsAdded(20), now), Action::None);
+        assert_eq!(s.handle(Event::BlockBuilt, now + ms(10)), Action::None);
+        assert_eq!(s.state(), BuildState::Proposed { at: now + ms(10) });
+        assert_eq!(s.handle(Event::TxsAdded(1), now + ms(20)), Action::None);
+    }
+
+    #[test]
+    fn test_waits_for_min_block_interval() {
+        let now = Instant::now();
+        let mut s = Scheduler::new(CONFIG);
+        s.handle(Event::TxsAdded(1), now);
+        s.handle(Event::BlockBuilt, now);
+        let accepted_at = now + ms(50);
+        assert_eq!(
+            s.handle(Event::BlockAccepted { pending: 2 }, accepted_at),
+            Action::None
+        );
+        let until = accepted_at + CONFIG.min_block_interval;
+        assert_eq!(s.state(), BuildState::Waiting { until });
+        assert_eq!(s.deadline(), Some(until));
+
+        assert_eq!(s.handle(Event::Tick, until - ms(1)), Action::None);
+        assert_eq!(s.handle(Event::Tick, until), Action::NotifyEngine);
+        assert_eq!(s.state(), BuildState::Notified { at: until });
+
+        // everything went into the block
+        s.handle(Event::BlockBuilt, until);
+        assert_eq!(
+            s.handle(Event::BlockAccepted { pending: 0 }, until),
+            Action::None
+        );
+        assert_eq!(s.state(), BuildState::Idle);
+    }
+
+    #[test]
+    fn test_full_batch_skips_interval() {
+        let now = Instant::now();
+        let mut s = Scheduler::new(CONFIG);
+        s.handle(Event::BlockAccepted { pending: 0 }, now);
+        assert_eq!(s.handle(Event::TxsAdded(4), now + ms(1)), Action::None);
+        assert_eq!(s.handle(Event::TxsAdded(5), now + ms(2)), Action::None);
+        assert_eq!(s.pending(), 9);
+        assert_eq!(
+            s.handle(Event::TxsAdded(1), now + ms(3)),
+            Action::NotifyEngine
+        );
+
+        // a full batch left behind by a block is built right away as well
+        s.handle(Event::BlockBuilt, now + ms(4));
+        assert_eq!(
+            s.handle(Event::BlockAccepted { pending: 10 }, now + ms(5)),
+            Action::NotifyEngine
+        );
+    }
+
+    #[test]
+    fn test_rejected_block_is_rebuilt() {
+        let now = Instant::now();
+        let mut s = Scheduler::new(CONFIG);
+        s.handle(Event::TxsAdded(3), now);
+        s.handle(Event::BlockBuilt, now);
+        assert_eq!(
+            s.handle(Event::BlockRejected { pending: 3 }, now + ms(1)),
+            Action::NotifyEngine
+        );
+
+        // deciding a block of another validator keeps the notification
+        assert_eq!(
+            s.handle(Event::BlockRejected { pending: 3 }, now + ms(2)),
+            Action::None
+        );
+        assert_eq!(s.state(), BuildState::Notified { at: now + ms(1) });
+    }
+
+    #[test]
+    fn test_failed_build_backs_off() {
+        let now = Instant::now();
+        let mut s = Scheduler::new(CONFIG);
+        s.handle(Event::TxsAdded(3), now);
+        assert_eq!(s.handle(Event::BuildFailed, now), Action::None);
+        let until = now + CONFIG.min_block_interval;
+        assert_eq!(s.state(), BuildState::Waiting { until });
+        assert_eq!(s.handle(Event::Tick, until), Action::NotifyEngine);
+
+        let mut s = Scheduler::new(CONFIG);
+        s.handle(Event::BuildFailed, now);
+        assert_eq!(s.state(), BuildState::Idle);
+    }
+
+    #[test]
+    fn test_dropped_build_times_out() {
+        let now = Instant::now();
+        let mut s = Scheduler::new(CONFIG);
+        s.handle(Event::TxsAdded(1), now);
+        assert_eq!(s.build_started_at(), Some(now));
+        let timeout = now + CONFIG.build_timeout;
+        assert_eq!(s.deadline(), Some(timeout));
+        assert_eq!(s.handle(Event::Tick, timeout - ms(1)), Action::None);
+        assert_eq!(s.handle(Event::Tick, timeout), Action::NotifyEngine);
+        assert_eq!(s.state(), BuildState::Notified { at: timeout });
+        // the engine is notified again, but the build is still the same one
+        assert_eq!(s.build_started_at(), Some(now));
+        s.handle(Event::TxsAdded(1), timeout);
+        assert_eq!(s.build_started_at(), Some(now));
+
+        // a built block that is never decided
+        s.handle(Event::BlockBuilt, timeout);
+        assert_eq!(s.build_started_at(), Some(timeout));
+        let timeout = timeout + CONFIG.build_timeout;
+        assert_eq!(s.handle(Event::Tick, timeout), Action::NotifyEngine);
+
+        // a decision ends the build
+        s.handle(Event::BlockAccepted { pending: 0 }, timeout);
+        assert_eq!(s.build_started_at(), None);
+    }
+
+    #[tokio::test]
+    async fn test_wait_wakes_on_events() {
+        let scheduler = BuildScheduler::new(CONFIG);
+        let waiter = scheduler.clone();
+        let task = tokio::spawn(async move { waiter.wait().await });
+        assert_eq!(
+            scheduler.handle(Event::TxsAdded(1)).await,
+            Action::NotifyEngine
+        );
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            scheduler.state().await,
+            BuildState::Notified { .. }
+        ));
+    }
+}
@@ -1,7 +1,8 @@
 //! Manages the virtual machine states.
 
+pub mod tree;
+
 use std::{
-    collections::HashMap,
     io::{self, Error, ErrorKind},
     sync::Arc,
 };
@@ -11,34 +12,49 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
 use crate::block::Block;
+use crate::metrics;
 use crate::vm::Vm;
 
+pub use tree::BlockTree;
+
 /// Manages block and chain states for this Vm, both in-memory and persistent.
 #[derive(Clone)]
 pub struct State {
     pub db: Arc<RwLock<Box<dyn subnet::rpc::database::Database + Send + Sync>>>,
 
-    /// Maps block Id to Block.
-    /// Each element is verified but not yet accepted/rejected (e.g., preferred).
-    pub verified_blocks: Arc<RwLock<HashMap<ids::Id, Block>>>,
+    /// Tree of blocks that are verified but not yet accepted/rejected (e.g., preferred).
+    pub verified_blocks: Arc<RwLock<BlockTree>>,
     // pub vm: Option<Arc<RwLock<Vm>>>,
     pub vm: Option<Arc<RwLock<Vm>>>,
+    /// Height from which blocks are encoded with the binary codec, see
+    /// [`VmConfig`](crate::config::VmConfig).
+    pub block_codec_activation_height: Option<u64>,
 }
 
 impl Default for State {
     fn default() -> State {
         Self {
             db: Arc::new(RwLock::new(subnet::rpc::database::memdb::Database::new())),
-            verified_blocks: Arc::new(RwLock::new(HashMap::new())),
+            verified_blocks: Arc::new(RwLock::new(BlockTree::new())),
             vm: None,
+            block_codec_activation_height: None,
         }
     }
 }
 
 const LAST_ACCEPTED_BLOCK_KEY: &[u8] = b"last_accepted_block";
 
+/// Block being accepted, written before it is committed to AptosDB and
+/// deleted once the acceptance is persisted.
+const ACCEPTING_BLOCK_KEY: &[u8] = b"accepting_block";
+
+/// Mempool transactions persisted on shutdown.
+const PENDING_TXS_KEY: &[u8] = b"pending_txs";
+
 const STATUS_PREFIX: u8 = 0x0;
 
+const HEIGHT_PREFIX: u8 = 0x1;
+
 const DELIMITER: u8 = b'/';
 
 /// Returns a vec of bytes used as a key for identifying blocks in state.
@@ -51,6 +67,16 @@ fn block_with_status_key(blk_id: &ids::Id) -> Vec<u8> {
     k
 }
 
+/// Returns a vec of bytes used as a key for the accepted block id at a height.
+/// 'HEIGHT_PREFIX' + 'BYTE_DELIMITER' + [big-endian height]
+fn block_id_at_height_key(height: u64) -> Vec<u8> {
+    let mut k: Vec<u8> = Vec::with_capacity(8 + 2);
+    k.push(HEIGHT_PREFIX);
+    k.push(DELIMITER);
+    k.extend_from_slice(&height.to_be_bytes());
+    k
+}
+
 /// Wraps a [`Block`](crate::block::Block) and its status.
 /// This is the data format that [`State`](State) uses to persist blocks.
 #[derive(Serialize, Deserialize, Clone)]
@@ -59,24 +85,50 @@ struct BlockWithStatus {
     status: choices::status::Status,
 }
 
+/// Version byte prefixed to the binary encoding of [`BlockWithStatus`].
+/// Records written before the binary codec are JSON objects and start with `{`.
+const BLOCK_WITH_STATUS_CODEC_VERSION: u8 = 1;
+
 impl BlockWithStatus {
     fn encode(&self) -> io::Result<Vec<u8>> {
-        serde_json::to_vec(&self).map_err(|e| {
+        let encoded = bcs::to_bytes(&(&self.block_bytes, self.status.as_str())).map_err(|e| {
             Error::new(
                 ErrorKind::Other,
-                format!("failed to serialize BlockStatus to JSON bytes: {}", e),
+                format!("failed to serialize BlockStatus to bytes: {}", e),
             )
-        })
+        })?;
+        let mut bytes = Vec::with_capacity(1 + encoded.len());
+        bytes.push(BLOCK_WITH_STATUS_CODEC_VERSION);
+        bytes.extend_from_slice(&encoded);
+        Ok(bytes)
     }
 
     fn from_slice(d: impl AsRef<[u8]>) -> io::Result<Self> {
         let dd = d.as_ref();
-        serde_json::from_slice(dd).map_err(|e| {
-            Error::new(
-                ErrorKind::Other,
-                format!("failed to deserialize BlockStatus from JSON: {}", e),
-            )
-        })
+        match dd.first() {
+            Some(&BLOCK_WITH_STATUS_CODEC_VERSION) => {
+                let (block_bytes, status) = bcs::from_bytes::<(Vec<u8>, String)>(&dd[1..]).map_err(|e| {
+                    Error::new(
+                        ErrorKind::Other,
+                        format!("failed to deserialize BlockStatus from bytes: {}", e),
+                    )
+                })?;
+                Ok(Self {
+                    block_bytes,
+                    status: choices::status::Status::from(status.as_str()),
+                })
+            }
+            Some(b'{') => serde_json::from_slice(dd).map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to deserialize BlockStatus from JSON: {}", e),
+                )
+            }),
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                "unsupported BlockStatus encoding",
+            )),
+        }
     }
 }
 
@@ -94,6 +146,73 @@ impl State {
             })
     }
 
+    /// Records `block` as being accepted, so that its acceptance can be
+    /// completed on restart if the node stops after committing it to AptosDB.
+    pub async fn set_accepting_block(&self, block: &Block) -> io::Result<()> {
+        let mut db = self.db.write().await;
+        db.put(ACCEPTING_BLOCK_KEY, &block.to_slice()?)
+            .await
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to put accepting block: {:?}", e),
+                )
+            })
+    }
+
+    /// Returns the block whose acceptance was interrupted, if any.
+    pub async fn get_accepting_block(&self) -> io::Result<Option<Block>> {
+        let db = self.db.read().await;
+        match db.get(ACCEPTING_BLOCK_KEY).await {
+            Ok(d) => Ok(Some(Block::from_slice(&d)?)),
+            Err(e) if subnet::rpc::errors::is_not_found(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Clears the record of the block being accepted.
+    pub async fn clear_accepting_block(&self) -> io::Result<()> {
+        let mut db = self.db.write().await;
+        db.delete(ACCEPTING_BLOCK_KEY).await.map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to delete accepting block: {:?}", e),
+            )
+        })
+    }
+
+    /// Persists the encoded mempool transactions.
+    pub async fn set_pending_txs(&self, txs: &[u8]) -> io::Result<()> {
+        let mut db = self.db.write().await;
+        db.put(PENDING_TXS_KEY, txs).await.map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to put pending transactions: {:?}", e),
+            )
+        })
+    }
+
+    /// Returns the persisted mempool transactions, if any.
+    pub async fn get_pending_txs(&self) -> io::Result<Option<Vec<u8>>> {
+        let db = self.db.read().await;
+        match db.get(PENDING_TXS_KEY).await {
+            Ok(d) => Ok(Some(d)),
+            Err(e) if subnet::rpc::errors::is_not_found(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Deletes the persisted mempool transactions, once they are re-admitted.
+    pub async fn clear_pending_txs(&self) -> io::Result<()> {
+        let mut db = self.db.write().await;
+        db.delete(PENDING_TXS_KEY).await.map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to delete pending transactions: {:?}", e),
+            )
+        })
+    }
+
     pub fn set_vm(&mut self, vm: Vm) {
         self.vm = Some(Arc::new(RwLock::new(vm)));
     }
@@ -126,22 +245,34 @@ impl State {
 
     /// Adds a block to "verified_blocks".
     pub async fn add_verified(&mut self, block: &Block) {
-        let blk_id = block.id();
         let mut verified_blocks = self.verified_blocks.write().await;
-        verified_blocks.insert(blk_id, block.clone());
+        verified_blocks.insert(block.clone());
+        metrics::UNDECIDED_BLOCKS.set(verified_blocks.len() as i64);
     }
 
-    /// Removes a block from "verified_blocks".
-    pub async fn remove_verified(&mut self, blk_id: &ids::Id) {
+    /// Removes an accepted block from "verified_blocks" and prunes the
+    /// branches conflicting with it. Returns the Ids of the pruned blocks.
+    pub async fn accept_verified(&mut self, blk_id: &ids::Id) -> Vec<ids::Id> {
         let mut verified_blocks = self.verified_blocks.write().await;
-        verified_blocks.remove(blk_id);
+        let pruned = verified_blocks.accept(blk_id);
+        metrics::UNDECIDED_BLOCKS.set(verified_blocks.len() as i64);
+        pruned
+    }
+
+    /// Removes a rejected block and its descendants from "verified_blocks".
+    /// Returns the Ids of the removed blocks.
+    pub async fn remove_verified(&mut self, blk_id: &ids::Id) -> Vec<ids::Id> {
+        let mut verified_blocks = self.verified_blocks.write().await;
+        let removed = verified_blocks.reject(blk_id);
+        metrics::UNDECIDED_BLOCKS.set(verified_blocks.len() as i64);
+        removed
     }
 
     /// Returns "true" if the block Id has been already verified.
     #[allow(dead_code)]
     pub async fn has_verified(&self, blk_id: &ids::Id) -> bool {
         let verified_blocks = self.verified_blocks.read().await;
-        verified_blocks.contains_key(blk_id)
+        verified_blocks.contains(blk_id)
     }
 
     /// Writes a block to the state storage.
@@ -162,6 +293,64 @@ impl State {
             .map_err(|e| Error::new(ErrorKind::Other, format!("failed to put block: {:?}", e)))
     }
 
+    /// Indexes an accepted block id by its height.
+    pub async fn set_block_id_at_height(&self, height: u64, blk_id: &ids::Id) -> io::Result<()> {
+        let mut db = self.db.write().await;
+        db.put(&block_id_at_height_key(height), &blk_id.to_vec())
+            .await
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to put block id at height {}: {:?}", height, e),
+                )
+            })
+    }
+
+    /// Returns the accepted block id at the given height.
+    /// Returns a "not found" error if no block has been accepted at that height.
+    pub async fn get_block_id_at_height(&self, height: u64) -> io::Result<ids::Id> {
+        let db = self.db.read().await;
+        let blk_id_bytes = db.get(&block_id_at_height_key(height)).await?;
+        Ok(ids::Id::from_slice(&blk_id_bytes))
+    }
+
+    /// Ensures every accepted block from the last accepted block back to genesis
+    /// is indexed by height, backfilling entries for databases written before the
+    /// index existed. Returns the number of entries written.
+    ///
+    /// Walks back from the last accepted block and stops at the first height that
+    /// is already indexed with the expected block id, so on an up-to-date database
+    /// this only reads a single entry.
+    pub async fn repair_height_index(&self) -> io::Result<u64> {
+        if !self.has_last_accepted_block().await? {
+            return Ok(0);
+        }
+
+        let mut blk_id = self.get_last_accepted_block_id().await?;
+        let mut repaired = 0;
+        loop {
+            let blk = self.get_block(&blk_id).await?;
+            match self.get_block_id_at_height(blk.height()).await {
+                Ok(indexed) if indexed == blk_id => break,
+                Ok(_) => {},
+                Err(e) if subnet::rpc::errors::is_not_found(&e) => {},
+                Err(e) => return Err(e),
+            }
+            self.set_block_id_at_height(blk.height(), &blk_id).await?;
+            repaired += 1;
+
+            if blk.height() == 0 {
+                break;
+            }
+            blk_id = blk.parent_id();
+        }
+
+        if repaired > 0 {
+            log::info!("repaired height index with {} entries", repaired);
+        }
+        Ok(repaired)
+    }
+
     /// Reads a block from the state storage using the block_with_status_key.
     pub async fn get_block(&self, blk_id: &ids::Id) -> io::Result<Block> {
         // check if the block exists in memory as previously verified.
@@ -180,3 +369,130 @@ impl State {
         Ok(blk)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test_chain;
+
+    /// Accepts a chain of `len` blocks through [`Block::accept`].
+    async fn accept_chain(state: &State, len: u64) -> Vec<Block> {
+        let mut blocks = test_chain(len, choices::status::Status::Processing);
+        for blk in blocks.iter_mut() {
+            blk.set_state(state.clone());
+            blk.accept().await.unwrap();
+        }
+        blocks
+    }
+
+    #[tokio::test]
+    async fn test_accept_indexes_height() {
+        let state = State::default();
+        let blocks = accept_chain(&state, 5).await;
+
+        for blk in blocks.iter() {
+            assert_eq!(state.get_block_id_at_height(blk.height()).await.unwrap(), blk.id());
+        }
+        let err = state.get_block_id_at_height(5).await.unwrap_err();
+        assert!(subnet::rpc::errors::is_not_found(&err));
+
+        // nothing to repair on an up-to-date index
+        assert_eq!(state.repair_height_index().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_repair_height_index_backfills_legacy_db() {
+        let mut state = State::default();
+
+        // blocks persisted without a height index, as before the index existed
+        let blocks = test_chain(6, choices::status::Status::Accepted);
+        for blk in blocks.iter() {
+            state.write_block(blk).await.unwrap();
+        }
+        state.set_last_accepted_block(&blocks[5].id()).await.unwrap();
+        assert!(state.get_block_id_at_height(0).await.is_err());
+
+        assert_eq!(state.repair_height_index().await.unwrap(), 6);
+        for blk in blocks.iter() {
+            assert_eq!(state.get_block_id_at_height(blk.height()).await.unwrap(), blk.id());
+        }
+        assert_eq!(state.repair_height_index().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_block_reads_legacy_json_record() {
+        let mut state = State::default();
+        let blk = Block::new(
+            ids::Id::empty(),
+            0,
+            0,
+            vec![7; 16],
+            choices::status::Status::Accepted,
+            None,
+        )
+        .unwrap();
+
+        // record written before the binary codec
+        let legacy = serde_json::to_vec(&BlockWithStatus {
+            block_bytes: blk.bytes().to_vec(),
+            status: choices::status::Status::Accepted,
+        })
+        .unwrap();
+        state
+            .db
+            .write()
+            .await
+            .put(&block_with_status_key(&blk.id()), &legacy)
+            .await
+            .unwrap();
+
+        let got = state.get_block(&blk.id()).await.unwrap();
+        assert_eq!(got.id(), blk.id());
+        assert_eq!(got.status(), choices::status::Status::Accepted);
+
+        // rewritten records use the binary codec
+        state.write_block(&got).await.unwrap();
+        let raw = state.db.read().await.get(&block_with_status_key(&blk.id())).await.unwrap();
+        assert_eq!(raw[0], BLOCK_WITH_STATUS_CODEC_VERSION);
+        assert_eq!(state.get_block(&blk.id()).await.unwrap().id(), blk.id());
+    }
+
+    #[tokio::test]
+    async fn test_accepting_block_and_pending_txs() {
+        let state = State::default();
+        assert!(state.get_accepting_block().await.unwrap().is_none());
+
+        let blk = Block::new(
+            ids::Id::empty(),
+            1,
+            1,
+            vec![1; 16],
+            choices::status::Status::Processing,
+            None,
+        )
+        .unwrap();
+        state.set_accepting_block(&blk).await.unwrap();
+        assert_eq!(state.get_accepting_block().await.unwrap().unwrap().id(), blk.id());
+        state.clear_accepting_block().await.unwrap();
+        assert!(state.get_accepting_block().await.unwrap().is_none());
+
+        // accepting through the block clears the record
+        let blocks = accept_chain(&state, 2).await;
+        assert!(state.get_accepting_block().await.unwrap().is_none());
+        assert_eq!(state.get_last_accepted_block_id().await.unwrap(), blocks[1].id());
+
+        assert!(state.get_pending_txs().await.unwrap().is_none());
+        state.set_pending_txs(&[1, 2, 3]).await.unwrap();
+        assert_eq!(state.get_pending_txs().await.unwrap().unwrap(), vec![1, 2, 3]);
+        // kept until cleared
+        assert_eq!(state.get_pending_txs().await.unwrap().unwrap(), vec![1, 2, 3]);
+        state.clear_pending_txs().await.unwrap();
+        assert!(state.get_pending_txs().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_repair_height_index_empty_db() {
+        let state = State::default();
+        assert_eq!(state.repair_height_index().await.unwrap(), 0);
+    }
+}
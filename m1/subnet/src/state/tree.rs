@@ -0,0 +1,171 @@
+//! Tree of verified blocks that are not yet decided.
+
+use std::collections::{HashMap, HashSet};
+
+use avalanche_types::ids;
+
+use crate::block::Block;
+
+/// Tracks processing blocks by parent, so that competing branches can be
+/// kept side by side and pruned once one of them is decided.
+#[derive(Default)]
+pub struct BlockTree {
+    /// Maps block Id to Block.
+    blocks: HashMap<ids::Id, Block>,
+    /// Maps block Id to the Ids of its processing children.
+    children: HashMap<ids::Id, HashSet<ids::Id>>,
+}
+
+impl BlockTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a verified block as a child of its parent.
+    pub fn insert(&mut self, block: Block) {
+        let blk_id = block.id();
+        self.children
+            .entry(block.parent_id())
+            .or_default()
+            .insert(blk_id);
+        self.blocks.insert(blk_id, block);
+    }
+
+    pub fn get(&self, blk_id: &ids::Id) -> Option<&Block> {
+        self.blocks.get(blk_id)
+    }
+
+    pub fn contains(&self, blk_id: &ids::Id) -> bool {
+        self.blocks.contains_key(blk_id)
+    }
+
+    /// Returns the number of processing blocks.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Returns the Ids of the processing children of `blk_id`.
+    pub fn children(&self, blk_id: &ids::Id) -> Vec<ids::Id> {
+        self.children
+            .get(blk_id)
+            .map(|c| c.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Removes an accepted block from the tree, along with every sibling
+    /// branch that conflicts with it. Its own children stay, now rooted at a
+    /// decided block. Returns the Ids of the pruned blocks.
+    pub fn accept(&mut self, blk_id: &ids::Id) -> Vec<ids::Id> {
+        let mut pruned = Vec::new();
+        let parent_id = match self.blocks.remove(blk_id) {
+            Some(blk) => blk.parent_id(),
+            None => return pruned,
+        };
+        if let Some(siblings) = self.children.remove(&parent_id) {
+            for sibling in siblings.iter().filter(|id| *id != blk_id) {
+                self.remove_branch(sibling, &mut pruned);
+            }
+        }
+        pruned
+    }
+
+    /// Removes a rejected block and all of its descendants.
+    /// Returns the Ids of the removed blocks.
+    pub fn reject(&mut self, blk_id: &ids::Id) -> Vec<ids::Id> {
+        let mut pruned = Vec::new();
+        if let Some(blk) = self.blocks.get(blk_id) {
+            let parent_id = blk.parent_id();
+            if let Some(siblings) = self.children.get_mut(&parent_id) {
+                siblings.remove(blk_id);
+                if siblings.is_empty() {
+                    self.children.remove(&parent_id);
+                }
+            }
+        }
+        self.remove_branch(blk_id, &mut pruned);
+        pruned
+    }
+
+    fn remove_branch(&mut self, blk_id: &ids::Id, pruned: &mut Vec<ids::Id>) {
+        let mut stack = vec![*blk_id];
+        while let Some(id) = stack.pop() {
+            if self.blocks.remove(&id).is_some() {
+                pruned.push(id);
+            }
+            if let Some(children) = self.children.remove(&id) {
+                stack.extend(children);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test_block;
+    use avalanche_types::choices;
+
+    fn new_block(parent_id: ids::Id, height: u64, seed: u8) -> Block {
+        test_block(parent_id, height, seed, choices::status::Status::Processing)
+    }
+
+    #[test]
+    fn test_accept_prunes_conflicting_branches() {
+        let root = ids::Id::empty();
+        let a = new_block(root, 1, 1);
+        let a_child = new_block(a.id(), 2, 2);
+        let b = new_block(root, 1, 3);
+        let b_child = new_block(b.id(), 2, 4);
+
+        let mut tree = BlockTree::new();
+        for blk in [&a, &a_child, &b, &b_child] {
+            tree.insert(blk.clone());
+        }
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.children(&root).len(), 2);
+
+        // the second child wins
+        let mut pruned = tree.accept(&b.id());
+        pruned.sort();
+        let mut expected = vec![a.id(), a_child.id()];
+        expected.sort();
+        assert_eq!(pruned, expected);
+
+        assert_eq!(tree.len(), 1);
+        assert!(tree.contains(&b_child.id()));
+        assert!(tree.children(&root).is_empty());
+
+        // accepting the remaining child leaves nothing behind
+        assert!(tree.accept(&b_child.id()).is_empty());
+        assert!(tree.is_empty());
+        assert!(tree.children.is_empty());
+    }
+
+    #[test]
+    fn test_reject_removes_descendants() {
+        let root = ids::Id::empty();
+        let a = new_block(root, 1, 1);
+        let a_child = new_block(a.id(), 2, 2);
+        let b = new_block(root, 1, 3);
+
+        let mut tree = BlockTree::new();
+        for blk in [&a, &a_child, &b] {
+            tree.insert(blk.clone());
+        }
+
+        let mut removed = tree.reject(&a.id());
+        removed.sort();
+        let mut expected = vec![a.id(), a_child.id()];
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert_eq!(tree.children(&root), vec![b.id()]);
+
+        // rejecting an already pruned block is a no-op
+        assert!(tree.reject(&a_child.id()).is_empty());
+        assert_eq!(tree.len(), 1);
+    }
+}
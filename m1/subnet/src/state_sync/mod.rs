@@ -0,0 +1,406 @@
+//! State sync for the M1 subnet [`Vm`](crate::vm::Vm).
+//!
+//! A syncing node asks its peers for their last [`StateSummary`] over
+//! `app_request`, restores the Aptos state snapshot at the summary's version
+//! chunk by chunk, then finalizes the snapshot and adopts the summary's block
+//! as its last accepted block. Peers answer from their own AptosDB in [`serve`].
+//!
+//! A summary is only trusted once its ledger info is signed by the validators
+//! of its epoch, as known from the local AptosDB and the epoch changes the
+//! summary carries, see [`verify_summary`].
+
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context as AnyhowContext;
+use aptos_crypto::HashValue;
+use aptos_storage_interface::DbReaderWriter;
+use aptos_types::epoch_change::{EpochChangeProof, Verifier};
+use aptos_types::epoch_state::EpochState;
+use aptos_types::ledger_info::LedgerInfoWithSignatures;
+use aptos_types::state_store::state_value::StateValueChunkWithProof;
+use aptos_types::transaction::{TransactionOutputListWithProof, Version};
+use avalanche_types::ids;
+use avalanche_types::subnet::rpc::snow::engine::common::appsender::AppSender;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::{oneshot, Mutex};
+
+use crate::block::Block;
+use crate::state;
+
+/// Number of state values requested per chunk.
+pub const STATE_CHUNK_SIZE: u64 = 1000;
+
+/// How long to wait for a peer to answer a single request.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Minimum number of blocks a summary must be ahead of the local chain for
+/// state sync to be worth it over bootstrapping block by block.
+pub const MIN_BLOCKS_BEHIND: u64 = 256;
+
+/// Default number of peers that must offer the same state summary before
+/// it is synced to.
+pub const MIN_PEERS: usize = 2;
+
+/// Describes an accepted block together with the AptosDB state it committed,
+/// i.e., everything a syncing node needs to resume from that block.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StateSummary {
+    /// Encoded bytes of the accepted [`Block`](crate::block::Block).
+    pub block_bytes: Vec<u8>,
+    /// Height of the accepted block.
+    pub height: u64,
+    /// Aptos version committed by the block.
+    pub version: Version,
+    /// Root hash of the state merkle tree at `version`.
+    pub state_root_hash: HashValue,
+    /// Number of state values at `version`.
+    pub leaf_count: u64,
+    /// Ledger info committing `version`.
+    pub ledger_info: LedgerInfoWithSignatures,
+    /// Epoch ending ledger infos from genesis up to `ledger_info`'s epoch.
+    pub epoch_ending_ledger_infos: Vec<LedgerInfoWithSignatures>,
+}
+
+impl StateSummary {
+    /// Returns the Id of this summary, the hash of its encoded bytes.
+    pub fn id(&self) -> io::Result<ids::Id> {
+        Ok(ids::Id::sha256(encode(self)?))
+    }
+}
+
+/// Requests served by [`serve`].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum StateSyncRequest {
+    /// Asks for the summary of the peer's last accepted block.
+    LastSummary,
+    /// Asks for the state values at `version` starting at `start_index`.
+    StateChunk {
+        version: Version,
+        start_index: u64,
+        chunk_size: u64,
+    },
+    /// Asks for the output of the transaction at `version`, proven against
+    /// the ledger info at `ledger_version`.
+    TransactionOutput {
+        version: Version,
+        ledger_version: Version,
+    },
+}
+
+/// Responses to [`StateSyncRequest`]s.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum StateSyncResponse {
+    Summary(Option<StateSummary>),
+    StateChunk(StateValueChunkWithProof),
+    TransactionOutput(TransactionOutputListWithProof),
+    Error(String),
+}
+
+impl StateSyncRequest {
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        encode(self)
+    }
+
+    pub fn from_slice(d: impl AsRef<[u8]>) -> io::Result<Self> {
+        decode(d)
+    }
+}
+
+impl StateSyncResponse {
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        encode(self)
+    }
+
+    pub fn from_slice(d: impl AsRef<[u8]>) -> io::Result<Self> {
+        decode(d)
+    }
+}
+
+fn encode<T: Serialize>(v: &T) -> io::Result<Vec<u8>> {
+    bcs::to_bytes(v).map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to serialize state sync message: {}", e),
+        )
+    })
+}
+
+fn decode<T: DeserializeOwned>(d: impl AsRef<[u8]>) -> io::Result<T> {
+    bcs::from_bytes(d.as_ref()).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("failed to deserialize state sync message: {}", e),
+        )
+    })
+}
+
+/// Answers a [`StateSyncRequest`] from the local state and AptosDB.
+pub async fn serve(
+    state: &state::State,
+    db: &DbReaderWriter,
+    req: StateSyncRequest,
+) -> StateSyncResponse {
+    let ret = match req {
+        StateSyncRequest::LastSummary => last_summary(state, db).await.map(StateSyncResponse::Summary),
+        StateSyncRequest::StateChunk {
+            version,
+            start_index,
+            chunk_size,
+        } => db
+            .reader
+            .get_state_value_chunk_with_proof(
+                version,
+                start_index as usize,
+                chunk_size.min(STATE_CHUNK_SIZE) as usize,
+            )
+            .map(StateSyncResponse::StateChunk),
+        StateSyncRequest::TransactionOutput {
+            version,
+            ledger_version,
+        } => db
+            .reader
+            .get_transaction_outputs(version, 1, ledger_version)
+            .map(StateSyncResponse::TransactionOutput),
+    };
+    ret.unwrap_or_else(|e| StateSyncResponse::Error(e.to_string()))
+}
+
+/// Builds the summary of the last accepted block.
+/// Returns `None` if there is nothing worth syncing to yet, or if the state
+/// snapshot for the latest committed version has not been written yet.
+async fn last_summary(
+    state: &state::State,
+    db: &DbReaderWriter,
+) -> Result<Option<StateSummary>, anyhow::Error> {
+    if !state.has_last_accepted_block().await? {
+        return Ok(None);
+    }
+    let blk_id = state.get_last_accepted_block_id().await?;
+    let blk = state.get_block(&blk_id).await?;
+    if blk.height() == 0 {
+        return Ok(None);
+    }
+
+    let ledger_info = db.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?;
+    let version = ledger_info.ledger_info().version();
+    let state_root_hash = match db.reader.get_state_snapshot_before(version + 1)? {
+        Some((snapshot_version, root_hash)) if snapshot_version == version => root_hash,
+        _ => {
+            log::debug!("state snapshot at version {} not yet available", version);
+            return Ok(None);
+        },
+    };
+    let leaf_count = db.reader.get_state_leaf_count(version)? as u64;
+    let epoch_ending_ledger_infos = db
+        .reader
+        .get_epoch_ending_ledger_infos(0, ledger_info.ledger_info().epoch())?
+        .ledger_info_with_sigs;
+
+    Ok(Some(StateSummary {
+        block_bytes: blk.bytes().to_vec(),
+        height: blk.height(),
+        version,
+        state_root_hash,
+        leaf_count,
+        ledger_info,
+        epoch_ending_ledger_infos,
+    }))
+}
+
+/// Tracks outstanding state sync requests and matches `app_response`s to them.
+#[derive(Clone, Default)]
+pub struct StateSyncClient {
+    next_request_id: Arc<AtomicU32>,
+    pending: Arc<Mutex<HashMap<u32, oneshot::Sender<Vec<u8>>>>>,
+}
+
+impl StateSyncClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends `req` to `node_id` and waits for its response.
+    pub async fn request(
+        &self,
+        app_sender: &(dyn AppSender + Send + Sync),
+        node_id: ids::node::Id,
+        req: &StateSyncRequest,
+    ) -> Result<StateSyncResponse, anyhow::Error> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert(request_id, tx);
+
+        let mut node_ids = ids::node::Set::new();
+        node_ids.insert(node_id);
+        if let Err(e) = app_sender.send_app_request(node_ids, request_id, req.to_vec()?).await {
+            self.pending.lock().await.remove(&request_id);
+            return Err(e).context("Failed to send state sync request");
+        }
+
+        let ret = tokio::time::timeout(REQUEST_TIMEOUT, rx).await;
+        self.pending.lock().await.remove(&request_id);
+        let bytes = ret
+            .map_err(|_| anyhow::anyhow!("state sync request {} to {} timed out", request_id, node_id))?
+            .map_err(|_| anyhow::anyhow!("state sync request {} to {} failed", request_id, node_id))?;
+
+        match StateSyncResponse::from_slice(bytes)? {
+            StateSyncResponse::Error(e) => Err(anyhow::anyhow!("peer {} failed to serve request: {}", node_id, e)),
+            resp => Ok(resp),
+        }
+    }
+
+    /// Delivers a response to the pending request.
+    /// Returns "false" if no such request is outstanding.
+    pub async fn on_response(&self, request_id: u32, response: &[u8]) -> bool {
+        match self.pending.lock().await.remove(&request_id) {
+            Some(tx) => tx.send(response.to_vec()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Fails the pending request, if any.
+    pub async fn on_request_failed(&self, request_id: u32) {
+        // dropping the sender wakes up the waiting request with an error
+        self.pending.lock().await.remove(&request_id);
+    }
+}
+
+/// Checks that `summary` is internally consistent and that its ledger info is
+/// signed by the validators of its epoch, returning its block.
+///
+/// `epoch_state` is the trusted epoch state of the local AptosDB. The epoch
+/// ending ledger infos of the summary carry it forward to the summary's epoch.
+pub fn verify_summary(summary: &StateSummary, epoch_state: &EpochState) -> Result<Block, anyhow::Error> {
+    verify_ledger_info(summary, epoch_state)?;
+    let li = summary.ledger_info.ledger_info();
+    if li.version() != summary.version {
+        return Err(anyhow::anyhow!(
+            "summary version {} does not match ledger info version {}",
+            summary.version,
+            li.version()
+        ));
+    }
+    let blk = Block::from_slice(&summary.block_bytes)?;
+    if blk.height() != summary.height {
+        return Err(anyhow::anyhow!(
+            "summary height {} does not match block height {}",
+            summary.height,
+            blk.height()
+        ));
+    }
+    let aptos_data = crate::vm::AptosData::from_block(&blk)
+        .context("Failed to parse AptosData from summary block")?;
+    if aptos_data.block_id != li.consensus_block_id() {
+        return Err(anyhow::anyhow!(
+            "summary block {} does not commit ledger info block {}",
+            aptos_data.block_id,
+            li.consensus_block_id()
+        ));
+    }
+    Ok(blk)
+}
+
+fn verify_ledger_info(summary: &StateSummary, epoch_state: &EpochState) -> Result<(), anyhow::Error> {
+    let epoch = summary.ledger_info.ledger_info().epoch();
+    let epoch_state = if epoch_state.epoch_change_verification_required(epoch) {
+        let proof = EpochChangeProof::new(summary.epoch_ending_ledger_infos.clone(), false);
+        proof
+            .verify(epoch_state)
+            .context("Failed to verify the epoch changes of the summary")?
+            .ledger_info()
+            .next_epoch_state()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("last epoch ending ledger info has no next epoch state"))?
+    } else {
+        epoch_state.clone()
+    };
+    epoch_state
+        .verify(&summary.ledger_info)
+        .context("Failed to verify the summary ledger info signatures")
+}
+
+/// Restores the AptosDB state described by `summary` from `node_id`.
+pub async fn restore(
+    db: &DbReaderWriter,
+    client: &StateSyncClient,
+    app_sender: &(dyn AppSender + Send + Sync),
+    node_id: ids::node::Id,
+    summary: &StateSummary,
+) -> Result<(), anyhow::Error> {
+    log::info!(
+        "restoring state at version {} ({} values) from {}",
+        summary.version,
+        summary.leaf_count,
+        node_id
+    );
+
+    let mut receiver = db
+        .writer
+        .get_state_snapshot_receiver(summary.version, summary.state_root_hash)
+        .context("Failed to create state snapshot receiver")?;
+    let mut next_index = 0;
+    while next_index < summary.leaf_count {
+        let req = StateSyncRequest::StateChunk {
+            version: summary.version,
+            start_index: next_index,
+            chunk_size: STATE_CHUNK_SIZE,
+        };
+        let chunk = match client.request(app_sender, node_id, &req).await? {
+            StateSyncResponse::StateChunk(chunk) => chunk,
+            other => return Err(anyhow::anyhow!("unexpected response to state chunk request: {:?}", other)),
+        };
+        if chunk.first_index != next_index || chunk.raw_values.is_empty() {
+            return Err(anyhow::anyhow!(
+                "peer returned chunk starting at {} with {} values, expected start {}",
+                chunk.first_index,
+                chunk.raw_values.len(),
+                next_index
+            ));
+        }
+        if chunk.root_hash != summary.state_root_hash {
+            return Err(anyhow::anyhow!("state chunk root hash does not match summary"));
+        }
+        next_index = chunk.last_index + 1;
+        // proofs are checked against the expected root hash by the receiver
+        receiver.add_chunk(chunk.raw_values, chunk.proof)?;
+        log::debug!("restored {}/{} state values", next_index, summary.leaf_count);
+    }
+    receiver.finish_box()?;
+
+    let req = StateSyncRequest::TransactionOutput {
+        version: summary.version,
+        ledger_version: summary.version,
+    };
+    let outputs = match client.request(app_sender, node_id, &req).await? {
+        StateSyncResponse::TransactionOutput(outputs) => outputs,
+        other => return Err(anyhow::anyhow!("unexpected response to transaction output request: {:?}", other)),
+    };
+    outputs
+        .verify(summary.ledger_info.ledger_info(), Some(summary.version))
+        .context("Failed to verify transaction output")?;
+    let checkpoint_hash = outputs
+        .proof
+        .transaction_infos
+        .last()
+        .and_then(|info| info.state_checkpoint_hash());
+    if checkpoint_hash != Some(summary.state_root_hash) {
+        return Err(anyhow::anyhow!("transaction output does not commit the summary state root"));
+    }
+
+    let mut ledger_infos = summary.epoch_ending_ledger_infos.clone();
+    ledger_infos.push(summary.ledger_info.clone());
+    db.writer
+        .finalize_state_snapshot(summary.version, outputs, &ledger_infos)
+        .context("Failed to finalize state snapshot")?;
+
+    log::info!("restored state at version {}", summary.version);
+    Ok(())
+}
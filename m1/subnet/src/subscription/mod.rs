@@ -0,0 +1,426 @@
+//! Feed of accepted blocks, the transactions they committed and the events
+//! those emitted, for indexers to follow without polling the ledger.
+//!
+//! The feed keeps a bounded replay buffer of the latest blocks, so that a
+//! client that reconnects can resume from the ledger version it stopped at.
+
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, RwLock};
+
+/// Number of blocks kept in the replay buffer by default.
+pub const DEFAULT_BUFFER_BLOCKS: usize = 1024;
+
+/// Longest a poll waits for new blocks.
+pub const MAX_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Block accepted by consensus and committed to the ledger.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FeedBlock {
+    /// Consensus block id.
+    pub block_id: String,
+    /// Consensus block height.
+    pub height: u64,
+    pub timestamp_usecs: u64,
+    /// Ledger version of the first transaction of the block.
+    pub first_version: u64,
+    /// Ledger version of the last transaction of the block.
+    pub last_version: u64,
+    pub transactions: Vec<FeedTransaction>,
+}
+
+/// Transaction committed by a block, along with the events it emitted.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FeedTransaction {
+    pub version: u64,
+    pub hash: String,
+    pub success: bool,
+    pub vm_status: String,
+    pub events: Vec<FeedEvent>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FeedEvent {
+    /// Move type of the event, e.g., `0x1::coin::DepositEvent`.
+    pub type_tag: String,
+    /// Hex of the BCS-encoded event data.
+    pub data: String,
+}
+
+/// What a poll returns for every block.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedStream {
+    /// Blocks only, without their transactions.
+    Blocks,
+    /// Blocks with their transactions and events.
+    #[default]
+    Transactions,
+    /// Events matching the filter, within their transactions and blocks.
+    /// Blocks without any matching event are skipped.
+    Events,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FeedFilter {
+    #[serde(default)]
+    pub stream: FeedStream,
+    /// Event types to return. A type matches an entry that is either the
+    /// full type or its module, e.g., `0x1::coin`. All events match if empty.
+    #[serde(default)]
+    pub event_types: Vec<String>,
+}
+
+impl FeedFilter {
+    fn matches(&self, event: &FeedEvent) -> bool {
+        self.event_types.is_empty()
+            || self.event_types.iter().any(|t| {
+                event.type_tag == *t
+                    || event
+                        .type_tag
+                        .strip_prefix(t.as_str())
+                        .map_or(false, |rest| rest.starts_with("::"))
+            })
+    }
+
+    /// Returns the part of `blk` at or after `from_version` selected by the
+    /// filter, if any.
+    fn apply(&self, blk: &FeedBlock, from_version: u64) -> Option<FeedBlock> {
+        let transactions = blk
+            .transactions
+            .iter()
+            .filter(|t| t.version >= from_version);
+        match self.stream {
+            FeedStream::Blocks => Some(FeedBlock {
+                transactions: Vec::new(),
+                ..blk.clone()
+            }),
+            FeedStream::Transactions => Some(FeedBlock {
+                transactions: transactions.cloned().collect(),
+                ..blk.clone()
+            }),
+            FeedStream::Events => {
+                let transactions: Vec<FeedTransaction> = transactions
+                    .filter_map(|t| {
+                        let events: Vec<FeedEvent> =
+                            t.events.iter().filter(|e| self.matches(e)).cloned().collect();
+                        if events.is_empty() {
+                            return None;
+                        }
+                        Some(FeedTransaction {
+                            events,
+                            ..t.clone()
+                        })
+                    })
+                    .collect();
+                if transactions.is_empty() {
+                    return None;
+                }
+                Some(FeedBlock {
+                    transactions,
+                    ..blk.clone()
+                })
+            }
+        }
+    }
+}
+
+/// Result of a poll.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FeedPoll {
+    pub blocks: Vec<FeedBlock>,
+    /// Version to resume from with the next poll.
+    pub next_version: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeedError {
+    /// The requested version is older than the replay buffer. The client
+    /// should backfill from the ledger up to `oldest_version`.
+    Pruned { oldest_version: u64 },
+}
+
+impl std::fmt::Display for FeedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pruned { oldest_version } => write!(
+                f,
+                "version is no longer in the replay buffer, oldest version is {}",
+                oldest_version
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+struct FeedBuffer {
+    capacity: usize,
+    blocks: VecDeque<FeedBlock>,
+    /// Version of the first transaction the feed has seen, or will see.
+    start_version: u64,
+}
+
+impl FeedBuffer {
+    fn oldest_version(&self) -> u64 {
+        self.blocks
+            .front()
+            .map(|blk| blk.first_version)
+            .unwrap_or(self.start_version)
+    }
+}
+
+/// Feed of accepted blocks. Cloning returns a handle to the same feed.
+#[derive(Clone)]
+pub struct Feed {
+    buffer: Arc<RwLock<FeedBuffer>>,
+    notify: Arc<Notify>,
+}
+
+impl Default for Feed {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUFFER_BLOCKS)
+    }
+}
+
+impl Feed {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffer: Arc::new(RwLock::new(FeedBuffer {
+                capacity: capacity.max(1),
+                blocks: VecDeque::new(),
+                start_version: 0,
+            })),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Sets the version the first pushed block will start at, i.e., the
+    /// version after the latest committed one when the Vm starts.
+    pub async fn start_at(&self, version: u64) {
+        let mut buffer = self.buffer.write().await;
+        if buffer.blocks.is_empty() {
+            buffer.start_version = version;
+        }
+    }
+
+    /// Appends a committed block, evicting the oldest one once the buffer is
+    /// full, and wakes up pending polls.
+    pub async fn push(&self, blk: FeedBlock) {
+        {
+            let mut buffer = self.buffer.write().await;
+            if buffer.blocks.len() == buffer.capacity {
+                buffer.blocks.pop_front();
+            }
+            buffer.blocks.push_back(blk);
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// Returns the oldest version a poll can resume from.
+    pub async fn oldest_version(&self) -> u64 {
+        self.buffer.read().await.oldest_version()
+    }
+
+    /// Returns up to `max_blocks` blocks from `from_version` on, selected by
+    /// `filter`. Waits up to `timeout` for new blocks if there are none yet.
+    pub async fn poll(
+        &self,
+        filter: &FeedFilter,
+        from_version: u64,
+        max_blocks: usize,
+        timeout: Duration,
+    ) -> Result<FeedPoll, FeedError> {
+        let deadline = Instant::now() + timeout.min(MAX_POLL_TIMEOUT);
+        loop {
+            // registered before reading the buffer, so no push is missed
+            let notified = self.notify.notified();
+
+            let ret = self.read(filter, from_version, max_blocks.max(1)).await?;
+            if ret.next_version > from_version {
+                return Ok(ret);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(ret);
+            }
+            if tokio::time::timeout(deadline - now, notified).await.is_err() {
+                return Ok(ret);
+            }
+        }
+    }
+
+    async fn read(
+        &self,
+        filter: &FeedFilter,
+        from_version: u64,
+        max_blocks: usize,
+    ) -> Result<FeedPoll, FeedError> {
+        let buffer = self.buffer.read().await;
+        let oldest_version = buffer.oldest_version();
+        if from_version < oldest_version {
+            return Err(FeedError::Pruned { oldest_version });
+        }
+
+        let mut blocks = Vec::new();
+        let mut next_version = from_version;
+        for blk in buffer
+            .blocks
+            .iter()
+            .filter(|blk| blk.last_version >= from_version)
+            .take(max_blocks)
+        {
+            if let Some(blk) = filter.apply(blk, from_version) {
+                blocks.push(blk);
+            }
+            next_version = blk.last_version + 1;
+        }
+        Ok(FeedPoll {
+            blocks,
+            next_version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_block(height: u64, first_version: u64, event_types: &[&str]) -> FeedBlock {
+        let transactions: Vec<FeedTransaction> = event_types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| FeedTransaction {
+                version: first_version + i as u64,
+                hash: format!("0x{:x}", first_version + i as u64),
+                success: true,
+                vm_status: "Executed successfully".to_string(),
+                events: vec![FeedEvent {
+                    type_tag: t.to_string(),
+                    data: "00".to_string(),
+                }],
+            })
+            .collect();
+        FeedBlock {
+            block_id: format!("block-{}", height),
+            height,
+            timestamp_usecs: height * 1_000_000,
+            first_version,
+            last_version: first_version + transactions.len() as u64 - 1,
+            transactions,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_poll_resumes_from_version() {
+        let feed = Feed::new(8);
+        feed.start_at(10).await;
+        feed.push(new_block(1, 10, &["0x1::coin::WithdrawEvent", "0x1::coin::DepositEvent"])).await;
+        feed.push(new_block(2, 12, &["0x1::account::CoinRegisterEvent"])).await;
+
+        let filter = FeedFilter::default();
+        let ret = feed.poll(&filter, 10, 1, Duration::ZERO).await.unwrap();
+        assert_eq!(ret.blocks.len(), 1);
+        assert_eq!(ret.blocks[0].height, 1);
+        assert_eq!(ret.next_version, 12);
+
+        // resuming in the middle of a block returns the rest of it
+        let ret = feed.poll(&filter, 11, 10, Duration::ZERO).await.unwrap();
+        assert_eq!(ret.blocks.len(), 2);
+        assert_eq!(ret.blocks[0].transactions.len(), 1);
+        assert_eq!(ret.blocks[0].transactions[0].version, 11);
+        assert_eq!(ret.next_version, 13);
+
+        // caught up
+        let ret = feed.poll(&filter, 13, 10, Duration::ZERO).await.unwrap();
+        assert!(ret.blocks.is_empty());
+        assert_eq!(ret.next_version, 13);
+    }
+
+    #[tokio::test]
+    async fn test_poll_filters_streams() {
+        let feed = Feed::new(8);
+        feed.push(new_block(0, 0, &["0x1::coin::WithdrawEvent", "0x1::coin::DepositEvent"])).await;
+        feed.push(new_block(1, 2, &["0x1::account::CoinRegisterEvent"])).await;
+
+        let blocks = FeedFilter {
+            stream: FeedStream::Blocks,
+            event_types: Vec::new(),
+        };
+        let ret = feed.poll(&blocks, 0, 10, Duration::ZERO).await.unwrap();
+        assert_eq!(ret.blocks.len(), 2);
+        assert!(ret.blocks.iter().all(|blk| blk.transactions.is_empty()));
+
+        let events = FeedFilter {
+            stream: FeedStream::Events,
+            event_types: vec!["0x1::coin::DepositEvent".to_string()],
+        };
+        let ret = feed.poll(&events, 0, 10, Duration::ZERO).await.unwrap();
+        assert_eq!(ret.blocks.len(), 1);
+        assert_eq!(ret.blocks[0].transactions.len(), 1);
+        assert_eq!(ret.blocks[0].transactions[0].version, 1);
+        // the block without matches is skipped, but the cursor moves past it
+        assert_eq!(ret.next_version, 3);
+
+        let module = FeedFilter {
+            stream: FeedStream::Events,
+            event_types: vec!["0x1::coin".to_string()],
+        };
+        let ret = feed.poll(&module, 0, 10, Duration::ZERO).await.unwrap();
+        assert_eq!(ret.blocks[0].transactions.len(), 2);
+
+        let prefix_only = FeedFilter {
+            stream: FeedStream::Events,
+            event_types: vec!["0x1::co".to_string()],
+        };
+        let ret = feed.poll(&prefix_only, 0, 10, Duration::ZERO).await.unwrap();
+        assert!(ret.blocks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_poll_fails_once_pruned() {
+        let feed = Feed::new(2);
+        feed.start_at(5).await;
+        assert_eq!(
+            feed.poll(&FeedFilter::default(), 4, 10, Duration::ZERO).await,
+            Err(FeedError::Pruned { oldest_version: 5 })
+        );
+
+        feed.push(new_block(1, 5, &["a::b::C"])).await;
+        feed.push(new_block(2, 6, &["a::b::C"])).await;
+        feed.push(new_block(3, 7, &["a::b::C"])).await;
+        assert_eq!(feed.oldest_version().await, 6);
+        assert_eq!(
+            feed.poll(&FeedFilter::default(), 5, 10, Duration::ZERO).await,
+            Err(FeedError::Pruned { oldest_version: 6 })
+        );
+        let ret = feed.poll(&FeedFilter::default(), 6, 10, Duration::ZERO).await.unwrap();
+        assert_eq!(ret.blocks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_poll_waits_for_new_blocks() {
+        let feed = Feed::new(8);
+        let filter = FeedFilter::default();
+
+        // times out without new blocks
+        let ret = feed.poll(&filter, 0, 10, Duration::from_millis(20)).await.unwrap();
+        assert!(ret.blocks.is_empty());
+
+        let pusher = feed.clone();
+        let handle = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            pusher.push(new_block(0, 0, &["a::b::C"])).await;
+        });
+        let ret = feed.poll(&filter, 0, 10, Duration::from_secs(10)).await.unwrap();
+        assert_eq!(ret.blocks.len(), 1);
+        assert_eq!(ret.next_version, 1);
+        handle.await.unwrap();
+    }
+}
@@ -20,17 +20,18 @@ use chrono::{DateTime, Utc};
 use futures::{channel::mpsc as futures_mpsc, StreamExt};
 use hex::{self, ToHex};
 use serde::{Deserialize, Serialize};
-use tokio::time::sleep;
 use std::hash::Hash;
 use std::str::FromStr;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
     io::{self, Error, ErrorKind},
+    path::Path,
     sync::Arc,
 };
-use tokio::sync::{mpsc::Sender, RwLock};
+use tokio::sync::{mpsc::Sender, Mutex, RwLock};
+use tokio::task::JoinHandle;
 
 use aptos_api::accept_type::AcceptType;
 use aptos_api::response::{AptosResponseContent, BasicResponse};
@@ -40,11 +41,10 @@ use aptos_api::transactions::{
 };
 use aptos_api::{get_raw_api_service, Context, RawApi};
 use aptos_api_types::{
-    Address, EncodeSubmissionRequest, IdentifierWrapper, MoveStructTag, RawTableItemRequest,
-    StateKeyWrapper, TableItemRequest, ViewRequest, U64,
+    Address, AptosError, AptosErrorCode, EncodeSubmissionRequest, IdentifierWrapper, MoveStructTag,
+    RawTableItemRequest, StateKeyWrapper, TableItemRequest, ViewRequest, U64,
 };
-use aptos_config::config::NodeConfig;
-use aptos_crypto::HashValue;
+use aptos_crypto::{ed25519::Ed25519PublicKey, HashValue};
 use aptos_db::AptosDB;
 use aptos_executor::block_executor::BlockExecutor;
 use aptos_executor::db_bootstrapper::{generate_waypoint, maybe_bootstrap};
@@ -64,20 +64,36 @@ use aptos_types::block_executor::partitioner::{ExecutableBlock, ExecutableTransa
 use aptos_types::block_info::BlockInfo;
 use aptos_types::block_metadata::BlockMetadata;
 use aptos_types::chain_id::ChainId;
+use aptos_types::epoch_state::EpochState;
 use aptos_types::ledger_info::{generate_ledger_info_with_sig, LedgerInfo};
 use aptos_types::mempool_status::{MempoolStatus, MempoolStatusCode};
 use aptos_types::transaction::Transaction::UserTransaction;
-use aptos_types::transaction::{SignedTransaction, Transaction, WriteSetPayload};
+use aptos_types::transaction::{ExecutionStatus, SignedTransaction, Transaction, TransactionStatus, WriteSetPayload};
 use aptos_types::validator_signer::ValidatorSigner;
-use aptos_vm::AptosVM;
-use aptos_vm_genesis::{test_genesis_change_set_and_validators, GENESIS_KEYPAIR};
+use aptos_vm::{AptosVM, VMValidator};
+use aptos_vm_genesis::GENESIS_KEYPAIR;
 
 use crate::api::chain_handlers::{
     AccountStateArgs, BlockArgs, ChainHandler, ChainService, GetTransactionByVersionArgs, PageArgs,
-    RpcEventHandleReq, RpcEventNumReq, RpcReq, RpcRes, RpcTableReq,
+    RpcEventHandleReq, RpcEventNumReq, RpcReq, RpcTableReq,
 };
+use crate::api::rest_handlers::{AptosRestHandler, REST_EXTENSION};
 use crate::api::static_handlers::{StaticHandler, StaticService};
-use crate::{block::Block, state};
+use crate::api::metrics_handlers::{MetricsHandler, METRICS_EXTENSION};
+use crate::api::subscription_handlers::{
+    SubscriptionHandler, SubscriptionService, SUBSCRIPTION_EXTENSION,
+};
+use crate::api::ChainApiHandler;
+use crate::config::VmConfig;
+use crate::faucet::{Faucet, FaucetRequest};
+use crate::fee_market::{self, BlockGasUsage, FeeMarket};
+use crate::subscription::{Feed, FeedBlock, FeedEvent, FeedTransaction};
+use crate::genesis::{self, Genesis};
+use crate::health::{HealthLimits, HealthReport};
+use crate::metrics;
+use crate::scheduler::{Action, BuildScheduler, Event};
+use crate::state_sync::{self, StateSyncClient, StateSyncRequest, StateSyncResponse, StateSummary};
+use crate::{block::{Block, BlockCodec}, state};
 use anyhow::Context as AnyhowContext;
 use aptos_types::account_config::AccountResource;
 use std::collections::BTreeMap;
@@ -87,14 +103,34 @@ use aptos_consensus_types::common::{
 };
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const MOVE_DB_DIR: &str = ".move-chain-data";
-pub fn get_db_name(suffix : &str) -> String {
-    format!("{}-{}", MOVE_DB_DIR, suffix)
-}
 
+/// Per-block overhead of the length prefix avalanchego adds when it packs
+/// ancestors into a single message (`wrappers.IntLen`).
+const ANCESTOR_LEN_PREFIX: usize = 4;
+
+/// Version byte prefixed to the binary encoding of [`AptosData`].
+/// Data of blocks accepted before the binary codec is a JSON array and starts with `[`.
+pub const APTOS_DATA_CODEC_VERSION: u8 = 1;
+
+/// The Aptos block carried in the data of a consensus [`Block`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AptosData {
+    /// Block metadata, followed by the user transactions and a state checkpoint.
+    pub transactions: Vec<Transaction>,
+    /// Aptos block id.
+    pub block_id: HashValue,
+    /// Aptos block id of the parent block.
+    pub parent_block_id: HashValue,
+    /// Epoch the block is executed in.
+    pub epoch: u64,
+    /// Aptos timestamp of the block, in microseconds.
+    pub timestamp_usecs: u64,
+}
 
+/// JSON encoding of [`AptosData`] used before the binary codec, with the
+/// transactions JSON-encoded in the first field.
 #[derive(Serialize, Deserialize, Clone)]
-pub struct AptosData(
+pub(crate) struct LegacyAptosData(
     pub Vec<u8>,   // block info
     pub HashValue, // block id
     pub HashValue,
@@ -102,16 +138,263 @@ pub struct AptosData(
     pub u64,
 );
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Data the legacy genesis block carries instead of transactions.
+const LEGACY_GENESIS_MESSAGE: &[u8] = b"hello world";
+
+impl AptosData {
+    /// Encodes the data of the genesis block. It carries the hex-encoded hash
+    /// of the genesis document, or the message of the legacy genesis block
+    /// for chains started from an empty genesis, in place of transactions.
+    pub fn genesis_to_vec(genesis_hash: Option<HashValue>) -> Result<Vec<u8>, anyhow::Error> {
+        let message = match genesis_hash {
+            Some(hash) => hash.to_hex().into_bytes(),
+            None => LEGACY_GENESIS_MESSAGE.to_vec(),
+        };
+        let legacy = LegacyAptosData(message, HashValue::zero(), HashValue::zero(), 0, 0);
+        serde_json::to_vec(&legacy).context("Failed to serialize genesis AptosData to JSON")
+    }
+
+    /// Encodes the [`AptosData`] with `codec`, the codec of the block it is
+    /// carried by.
+    pub fn to_vec_with_codec(&self, codec: BlockCodec) -> Result<Vec<u8>, anyhow::Error> {
+        match codec {
+            BlockCodec::Binary => self.to_vec(),
+            BlockCodec::Json => {
+                if self.block_id == HashValue::zero() {
+                    return Self::genesis_to_vec(None);
+                }
+                let legacy = LegacyAptosData(
+                    serde_json::to_vec(&self.transactions).context("Failed to serialize transactions to JSON")?,
+                    self.block_id,
+                    self.parent_block_id,
+                    self.epoch,
+                    self.timestamp_usecs,
+                );
+                serde_json::to_vec(&legacy).context("Failed to serialize AptosData to JSON")
+            },
+        }
+    }
+
+    /// Encodes the [`AptosData`] to its versioned binary format.
+    pub fn to_vec(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let encoded = bcs::to_bytes(self).context("Failed to serialize AptosData")?;
+        let mut bytes = Vec::with_capacity(1 + encoded.len());
+        bytes.push(APTOS_DATA_CODEC_VERSION);
+        bytes.extend_from_slice(&encoded);
+        Ok(bytes)
+    }
+
+    /// Decodes [`AptosData`] from its versioned binary format, or from the
+    /// legacy JSON format of blocks accepted before the binary codec.
+    pub fn from_slice(d: &[u8]) -> Result<Self, anyhow::Error> {
+        match d.first() {
+            Some(&APTOS_DATA_CODEC_VERSION) => {
+                bcs::from_bytes(&d[1..]).context("Failed to parse AptosData from bytes")
+            },
+            Some(b'[') => {
+                let legacy = serde_json::from_slice::<LegacyAptosData>(d).context("Failed to parse legacy AptosData from JSON")?;
+                // the legacy genesis block carries a plain message instead of transactions
+                let transactions = if legacy.1 == HashValue::zero() {
+                    vec![]
+                } else {
+                    serde_json::from_slice(&legacy.0).context("Failed to parse transactions from legacy AptosData")?
+                };
+                Ok(Self {
+                    transactions,
+                    block_id: legacy.1,
+                    parent_block_id: legacy.2,
+                    epoch: legacy.3,
+                    timestamp_usecs: legacy.4,
+                })
+            },
+            Some(v) => Err(anyhow::anyhow!("Unsupported AptosData encoding version {}", v)),
+            None => Err(anyhow::anyhow!("Empty AptosData")),
+        }
+    }
+
+    /// Decodes the [`AptosData`] carried by a consensus block.
+    pub fn from_block(block: &Block) -> Result<Self, anyhow::Error> {
+        Self::from_slice(block.data())
+    }
+}
+
+/// Version byte prefixed to transaction gossip messages.
+/// Nodes running before the binary encoding gossip one JSON transaction, starting with `{`.
+pub const TX_GOSSIP_VERSION: u8 = 1;
+
+/// Encodes transactions to gossip to peers.
+pub fn encode_tx_gossip(txns: &[SignedTransaction]) -> Result<Vec<u8>, anyhow::Error> {
+    let encoded = bcs::to_bytes(txns).context("Failed to serialize gossiped transactions")?;
+    let mut bytes = Vec::with_capacity(1 + encoded.len());
+    bytes.push(TX_GOSSIP_VERSION);
+    bytes.extend_from_slice(&encoded);
+    Ok(bytes)
+}
+
+/// Decodes transactions gossiped by peers.
+pub fn decode_tx_gossip(d: &[u8]) -> Result<Vec<SignedTransaction>, anyhow::Error> {
+    match d.first() {
+        Some(&TX_GOSSIP_VERSION) => {
+            bcs::from_bytes_with_limit(&d[1..], MAX_RECURSIVE_TYPES_ALLOWED as usize)
+                .context("Failed to parse gossiped transactions")
+        },
+        Some(b'{') => Ok(vec![serde_json::from_slice(d).context("Failed to parse legacy gossiped transaction")?]),
+        Some(v) => Err(anyhow::anyhow!("Unsupported gossip encoding version {}", v)),
+        None => Err(anyhow::anyhow!("Empty gossip message")),
+    }
+}
+
+/// Derives the Aptos block id of the consensus block with the given parent,
+/// height and timestamp, carrying the given user transactions.
+///
+/// Every validator derives the same id for the same consensus block, and
+/// re-executing a block yields the same id.
+pub fn aptos_block_id(
+    parent_id: &ids::Id,
+    height: u64,
+    timestamp: u64,
+    txs: &[SignedTransaction],
+) -> HashValue {
+    let mut bytes = Vec::with_capacity(ids::LEN + 16 + txs.len() * HashValue::LENGTH);
+    bytes.extend_from_slice(b"M1::BlockId");
+    bytes.extend_from_slice(&parent_id.to_vec());
+    bytes.extend_from_slice(&height.to_be_bytes());
+    bytes.extend_from_slice(&timestamp.to_be_bytes());
+    for tx in txs {
+        bytes.extend_from_slice(tx.clone().committed_hash().as_ref());
+    }
+    HashValue::sha3_256_of(&bytes)
+}
+
+/// Derives the hash of the trailing `StateCheckpoint` of an Aptos block.
+pub fn state_checkpoint_hash(block_id: &HashValue) -> HashValue {
+    let mut bytes = b"M1::StateCheckpoint".to_vec();
+    bytes.extend_from_slice(block_id.as_ref());
+    HashValue::sha3_256_of(&bytes)
+}
+
+/// Returns the id of the Aptos block funding the genesis accounts, committed
+/// on top of the genesis transaction when AptosDB is bootstrapped.
+pub fn genesis_funding_block_id() -> HashValue {
+    HashValue::sha3_256_of(b"M1::GenesisFunding")
+}
+
+/// Returns whether `blk` is the block AptosDB is at, given the id of the Aptos
+/// block committed last. The genesis block maps to the AptosDB bootstrap.
+fn is_committed_block(blk: &Block, committed_block_id: HashValue) -> Result<bool, anyhow::Error> {
+    if AptosData::from_block(blk)?.block_id == committed_block_id {
+        return Ok(true);
+    }
+    Ok(blk.height() == 0 && committed_block_id == genesis_funding_block_id())
+}
+
+/// Returns the Aptos timestamp of a block given its consensus timestamp in
+/// seconds and its parent's Aptos timestamp. Aptos requires strictly
+/// increasing timestamps, while several consensus blocks may share a second.
+pub fn aptos_timestamp_usecs(timestamp: u64, parent_timestamp_usecs: u64) -> u64 {
+    (timestamp * 1_000_000).max(parent_timestamp_usecs + 1)
+}
+
+/// Ledger state an Aptos API response was served at.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct AptosHeader {
-    chain_id: u8,
-    ledger_version: u64,
-    ledger_oldest_version: u64,
-    ledger_timestamp_usec: u64,
-    epoch: u64,
-    block_height: u64,
-    oldest_block_height: u64,
-    cursor: Option<String>,
+    pub chain_id: u8,
+    pub ledger_version: u64,
+    pub ledger_oldest_version: u64,
+    pub ledger_timestamp_usec: u64,
+    pub epoch: u64,
+    pub block_height: u64,
+    pub oldest_block_height: u64,
+    pub cursor: Option<String>,
+}
+
+/// Body of an Aptos API response.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApiContent {
+    Json(serde_json::Value),
+    Bcs(Vec<u8>),
+}
+
+/// Successful Aptos API response, decoded from the raw API service.
+#[derive(Clone, Debug)]
+pub struct ApiResponse {
+    pub content: ApiContent,
+    pub header: AptosHeader,
+}
+
+impl ApiResponse {
+    pub fn new<T: poem_openapi::types::ToJSON + Send + Sync + serde::Serialize>(
+        content: AptosResponseContent<T>,
+        header: AptosHeader,
+    ) -> Result<Self, anyhow::Error> {
+        let content = match content {
+            AptosResponseContent::Json(json) => ApiContent::Json(
+                serde_json::to_value(&json.0).context("Failed to serialize API response")?,
+            ),
+            AptosResponseContent::Bcs(bytes) => ApiContent::Bcs(bytes.0),
+        };
+        Ok(Self { content, header })
+    }
+}
+
+/// Error returned by the Aptos API, along with the HTTP status the REST
+/// API would have served it with.
+#[derive(Clone, Debug)]
+pub struct AptosApiError {
+    pub status: u16,
+    pub error: AptosError,
+}
+
+impl AptosApiError {
+    /// Decodes the status and the [`AptosError`] body of an error response.
+    pub async fn from_response(e: impl poem::IntoResponse + ToString) -> Self {
+        let message = e.to_string();
+        let resp = e.into_response();
+        let status = resp.status().as_u16();
+        let error = resp
+            .into_body()
+            .into_json::<AptosError>()
+            .await
+            .unwrap_or_else(|_| AptosError::new_with_error_code(message, AptosErrorCode::InternalError));
+        Self { status, error }
+    }
+
+    /// Returns true if the requested version is older than the versions the
+    /// DB retains.
+    pub fn is_version_pruned(&self) -> bool {
+        self.status == 410 || matches!(self.error.error_code, AptosErrorCode::VersionPruned)
+    }
+
+    /// Returns true if the transaction was turned away by the mempool or
+    /// failed validation, as opposed to being malformed.
+    pub fn is_mempool_rejection(&self) -> bool {
+        matches!(
+            self.error.error_code,
+            AptosErrorCode::VmError
+                | AptosErrorCode::MempoolIsFull
+                | AptosErrorCode::SequenceNumberTooOld
+                | AptosErrorCode::InvalidTransactionUpdate
+        )
+    }
+}
+
+impl std::fmt::Display for AptosApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error.message)
+    }
+}
+
+impl std::error::Error for AptosApiError {}
+
+/// Context of errors caused by malformed request arguments, as opposed to
+/// failures of the Vm itself.
+#[derive(Clone, Copy, Debug)]
+pub struct InvalidInput(pub &'static str);
+
+impl std::fmt::Display for InvalidInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
 /// Represents VM-specific states.
@@ -146,7 +429,7 @@ impl Default for VmState {
 pub struct Vm {
     pub state: Arc<RwLock<VmState>>,
 
-    pub app_sender: Option<AppSenderClient>,
+    pub app_sender: Option<Box<dyn AppSender + Send + Sync>>,
 
     pub api_service: Option<RawApi>,
 
@@ -163,9 +446,30 @@ pub struct Vm {
 
     pub executor: Option<Arc<RwLock<BlockExecutor<AptosVM>>>>,
 
-    pub build_status: Arc<RwLock<u8>>,
-    // 0 done 1 building
-    pub has_pending_tx: Arc<RwLock<bool>>,
+    /// Decides when the engine is notified to build a block.
+    pub scheduler: BuildScheduler,
+
+    /// Operational parameters decoded from the config bytes.
+    pub config: VmConfig,
+
+    /// Matches state sync responses to outstanding requests.
+    pub state_sync: StateSyncClient,
+
+    /// Currently connected peers.
+    pub peers: Arc<RwLock<HashSet<ids::node::Id>>>,
+    /// Ledger infos of verified blocks executed by the Aptos executor but not
+    /// yet committed, keyed by consensus block id.
+    pub executed_blocks: Arc<RwLock<HashMap<ids::Id, LedgerInfo>>>,
+    /// Aptos chain id set by the genesis.
+    pub chain_id: ChainId,
+    /// Feed of committed blocks, served to subscribers.
+    pub feed: Feed,
+    /// Faucet funding accounts, set up once the chain id is known.
+    pub faucet: Option<Faucet>,
+    /// Gas prices paid in recently accepted blocks.
+    pub fee_market: FeeMarket,
+    /// Background tasks, cancelled on shutdown.
+    pub tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
 }
 
 impl Default for Vm {
@@ -186,8 +490,16 @@ impl Vm {
             signer: None,
             executor: None,
             db: None,
-            build_status: Arc::new(RwLock::new(0)),
-            has_pending_tx: Arc::new(RwLock::new(false)),
+            scheduler: BuildScheduler::new(VmConfig::default().scheduler()),
+            config: VmConfig::default(),
+            state_sync: StateSyncClient::new(),
+            peers: Arc::new(RwLock::new(HashSet::new())),
+            executed_blocks: Arc::new(RwLock::new(HashMap::new())),
+            chain_id: ChainId::test(),
+            feed: Feed::default(),
+            faucet: None,
+            fee_market: FeeMarket::default(),
+            tasks: Arc::new(Mutex::new(Vec::new())),
         }
     }
     #[allow(dead_code)]
@@ -196,57 +508,32 @@ impl Vm {
         vm_state.bootstrapped
     }
 
-    fn process_response<
+    async fn process_response<
         T: poem_openapi::types::ToJSON + Send + Sync + serde::Serialize,
-        E: ToString + std::fmt::Debug,
+        E: poem::IntoResponse + ToString,
     >(
         &self,
         ret: Result<BasicResponse<T>, E>,
-    ) -> Result<RpcRes, anyhow::Error> {
-        let mut ret_str = "".to_string();
-        let mut error = None;
-        let mut header_str = "".to_string();
-        if ret.is_err() {
-            error = match ret {
-                Err(e) => Some(e.to_string()),
-                _ => Some("Unknown error".to_string()),
-            };
-        } else {
-            let ret = ret.map_err(|e| anyhow::anyhow!(e.to_string()))?;
-            let header;
-            ret_str = match ret {
-                BasicResponse::Ok(c, a, b, d, e, f, g, h, k) => {
-                    header = AptosHeader {
-                        chain_id: a,
-                        ledger_version: b,
-                        ledger_oldest_version: d,
-                        ledger_timestamp_usec: e,
-                        epoch: f,
-                        block_height: g,
-                        oldest_block_height: h,
-                        cursor: k,
-                    };
-                    match c {
-                        AptosResponseContent::Json(json) => serde_json::to_string(&json.0)?,
-                        AptosResponseContent::Bcs(bytes) => {
-                            format!("{}", hex::encode(bytes.0))
-                        },
-                    }
+    ) -> Result<ApiResponse, anyhow::Error> {
+        match ret {
+            Ok(BasicResponse::Ok(c, a, b, d, e, f, g, h, k)) => ApiResponse::new(
+                c,
+                AptosHeader {
+                    chain_id: a,
+                    ledger_version: b,
+                    ledger_oldest_version: d,
+                    ledger_timestamp_usec: e,
+                    epoch: f,
+                    block_height: g,
+                    oldest_block_height: h,
+                    cursor: k,
                 },
-            };
-            header_str = serde_json::to_string(&header)?;
+            ),
+            Err(e) => Err(AptosApiError::from_response(e).await.into()),
         }
-
-        Ok(RpcRes {
-            data: ret_str,
-            header: header_str,
-            error,
-        })
-
     }
 
-   
-    pub async fn get_transactions(&self, args: PageArgs) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_transactions(&self, args: PageArgs) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -257,10 +544,10 @@ impl Vm {
             .transactions_api
             .get_transactions_raw(accept, args.start, args.limit)
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn get_block_by_height(&self, args: BlockArgs) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_block_by_height(&self, args: BlockArgs) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -271,10 +558,10 @@ impl Vm {
             .blocks_api
             .get_block_by_height_raw(accept, args.height_or_version, args.with_transactions)
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
 
-    pub async fn get_block_by_version(&self, args: BlockArgs) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_block_by_version(&self, args: BlockArgs) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -287,13 +574,13 @@ impl Vm {
             .blocks_api
             .get_block_by_version_raw(accept, args.height_or_version, args.with_transactions)
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
 
     // ^ refactored
 
-    pub async fn get_accounts_transactions(&self, args: RpcReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_accounts_transactions(&self, args: RpcReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -304,20 +591,20 @@ impl Vm {
         let start = args.start.as_deref()
             .map(U64::from_str)
             .transpose()
-            .context("Failed to parse start parameter")?;
+            .context(InvalidInput("Failed to parse start parameter"))?;
         let ret = api
             .transactions_api
             .get_accounts_transactions_raw(
                 accept,
-                Address::from_str(account).context("Invalid account address")?,
+                Address::from_str(account).context(InvalidInput("Invalid account address"))?,
                 start,
                 args.limit,
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
 
-    pub async fn get_account_resources(&self, args: RpcReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_account_resources(&self, args: RpcReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -329,23 +616,23 @@ impl Vm {
             .as_ref()
             .map(|s| StateKeyWrapper::from_str(s.as_str()))
             .transpose()
-            .context("Failed to parse start parameter into StateKeyWrapper")?;
+            .context(InvalidInput("Failed to parse start parameter into StateKeyWrapper"))?;
         let ret = api
             .accounts_api
             .get_account_resources_raw(
                 accept,
-                Address::from_str(account).context("Invalid account address")?,
+                Address::from_str(account).context(InvalidInput("Invalid account address"))?,
                 args.ledger_version,
                 start,
                 args.limit,
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
     // refactored
 
-    pub async fn get_account(&self, args: RpcReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_account(&self, args: RpcReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -357,35 +644,35 @@ impl Vm {
             .accounts_api
             .get_account_raw(
                 accept,
-                Address::from_str(account).context("Invalid account address")?,
+                Address::from_str(account).context(InvalidInput("Invalid account address"))?,
                 args.ledger_version,
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
 
-    pub async fn get_account_modules_state(&self, args: AccountStateArgs) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_account_modules_state(&self, args: AccountStateArgs) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
             AcceptType::Json
         };
         let account = args.account.as_str();
-        let module_name = IdentifierWrapper::from_str(args.resource.as_str()).context("Invalid module name")?;
+        let module_name = IdentifierWrapper::from_str(args.resource.as_str()).context(InvalidInput("Invalid module name"))?;
         let api = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
         let ret = api
             .state_api
             .get_account_module_raw(
                 accept,
-                Address::from_str(account).context("Invalid account address")?,
+                Address::from_str(account).context(InvalidInput("Invalid account address"))?,
                 module_name,
                 args.ledger_version,
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
 
-    pub async fn get_account_resources_state(&self, args: AccountStateArgs) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_account_resources_state(&self, args: AccountStateArgs) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -398,15 +685,15 @@ impl Vm {
             .state_api
             .get_account_resource_raw(
                 accept,
-                Address::from_str(account).context("Invalid account address")?,
-                MoveStructTag::from_str(resource).context("Invalid resource tag")?,
+                Address::from_str(account).context(InvalidInput("Invalid account address"))?,
+                MoveStructTag::from_str(resource).context(InvalidInput("Invalid resource tag"))?,
                 args.ledger_version,
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn get_account_modules(&self, args: RpcReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_account_modules(&self, args: RpcReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -417,40 +704,40 @@ impl Vm {
             .as_ref()
             .map(|s| StateKeyWrapper::from_str(s.as_str()))
             .transpose()
-            .context("Failed to parse start parameter into StateKeyWrapper")?;
+            .context(InvalidInput("Failed to parse start parameter into StateKeyWrapper"))?;
         let api = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
-        let address = Address::from_str(account).context("Invalid account address")?;
+        let address = Address::from_str(account).context(InvalidInput("Invalid account address"))?;
         let ret = api
             .accounts_api
             .get_account_modules_raw(accept, address, args.ledger_version, start, args.limit)
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
 
     // 
 
-    pub async fn get_ledger_info(&self) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_ledger_info(&self) -> Result<ApiResponse, anyhow::Error> {
         let api = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
         let ret = api.index_api.get_ledger_info_raw(AcceptType::Json).await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn view_function(&self, args: RpcReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn view_function(&self, args: RpcReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
             AcceptType::Json
         };
         let api = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
-        let req = serde_json::from_str::<ViewRequest>(args.data.as_str()).context("Failed to parse view function request")?;
+        let req = serde_json::from_str::<ViewRequest>(args.data.as_str()).context(InvalidInput("Failed to parse view function request"))?;
         let ret = api
             .view_function_api
             .view_function_raw(accept, req, args.ledger_version)
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn get_transaction_by_hash(&self, args: RpcReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_transaction_by_hash(&self, args: RpcReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -460,14 +747,14 @@ impl Vm {
         if h.starts_with("0x") {
             h = &h[2..];
         }
-        let h1 = HashValue::from_hex(h).context("Failed to parse hash value")?;
+        let h1 = HashValue::from_hex(h).context(InvalidInput("Failed to parse hash value"))?;
         let hash = aptos_api_types::hash::HashValue::from(h1);
         let api = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
         let ret = api.transactions_api.get_transaction_by_hash_raw(accept, hash).await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn get_transaction_by_version(&self, args: GetTransactionByVersionArgs) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_transaction_by_version(&self, args: GetTransactionByVersionArgs) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -478,32 +765,39 @@ impl Vm {
             .transactions_api
             .get_transaction_by_version_raw(accept, args.version)
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
 
     // refactor
-    pub async fn encode_submission(&self, data: &str) -> Result<RpcRes, anyhow::Error> {
+    pub async fn encode_submission(&self, data: &str) -> Result<ApiResponse, anyhow::Error> {
         let service = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
-        let payload = serde_json::from_str::<EncodeSubmissionRequest>(data).context("Failed to parse encode submission request")?;
+        let payload = serde_json::from_str::<EncodeSubmissionRequest>(data).context(InvalidInput("Failed to parse encode submission request"))?;
         let ret = service.transactions_api.encode_submission_raw(AcceptType::Json, payload).await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn submit_transaction(&self, data: Vec<u8>, accept: AcceptType) -> Result<RpcRes, anyhow::Error> {
+    pub async fn submit_transaction(&self, data: Vec<u8>, accept: AcceptType) -> Result<ApiResponse, anyhow::Error> {
         let service = self.api_service.as_ref().ok_or_else(|| anyhow::Error::msg("API service not available"))?;
 
-        let signed_transaction : SignedTransaction = bcs::from_bytes_with_limit(&data, MAX_RECURSIVE_TYPES_ALLOWED as usize)?;
+        let signed_transaction : SignedTransaction = bcs::from_bytes_with_limit(&data, MAX_RECURSIVE_TYPES_ALLOWED as usize)
+            .context(InvalidInput("Failed to parse signed transaction"))?;
         log::info!("submitting transaction {} {} {}", signed_transaction.clone().committed_hash(), signed_transaction.sender(), signed_transaction.sequence_number());
     
         let payload = SubmitTransactionPost::Bcs(aptos_api::bcs_payload::Bcs(data.clone()));
-        let response = service.transactions_api.submit_transaction_raw(accept, payload).await?;
+        let response = match service.transactions_api.submit_transaction_raw(accept, payload).await {
+            Ok(response) => response,
+            Err(e) => {
+                // rejected by the mempool, e.g., invalid signature or sequence number
+                log::info!("rejected transaction {}: {}", signed_transaction.committed_hash(), e);
+                return Err(AptosApiError::from_response(e).await.into());
+            },
+        };
         log::info!("submitted transaction");
     
-        // Process the response
-        let (ret_str, header_str) = match response {
+        // admission, gossip and block notification happen in the mempool client task
+        match response {
             SubmitTransactionResponse::Accepted(content, chain_id, ledger_version, ledger_oldest_version, ledger_timestamp_usec, epoch, block_height, oldest_block_height, cursor) => {
-
-                let header = AptosHeader {
+                ApiResponse::new(content, AptosHeader {
                     chain_id,
                     ledger_version,
                     ledger_oldest_version,
@@ -512,45 +806,25 @@ impl Vm {
                     block_height,
                     oldest_block_height,
                     cursor,
-                };
-                let content_str = match content {
-                    AptosResponseContent::Json(json) => serde_json::to_string(&json.0)?,
-                    AptosResponseContent::Bcs(bytes) => format!("{}", hex::encode(bytes.0)),
-                };
-                let header_str = serde_json::to_string(&header)?;
-                (content_str, header_str)
+                })
             }
-        };
-    
-        // Additional processing logic remains the same
-        let sender = self.app_sender.as_ref().ok_or_else(|| anyhow::Error::msg("App sender not available"))?;
-        sender.send_app_gossip(serde_json::to_vec(&signed_transaction.clone())?).await?;
-        self.add_pool(signed_transaction).await?;
-       
-        self.notify_block_ready().await;
-    
-    
-        Ok(RpcRes {
-            data: ret_str,
-            header: header_str,
-            error: None,
-        })
+        }
     }    
     
-    pub async fn submit_transaction_batch(&self, data: Vec<u8>, accept: AcceptType) -> Result<RpcRes, anyhow::Error> {
+    pub async fn submit_transaction_batch(&self, data: Vec<u8>, accept: AcceptType) -> Result<ApiResponse, anyhow::Error> {
         log::info!("submit_transaction_batch length {}", data.len());
         let service = self.api_service.as_ref().ok_or_else(|| anyhow::Error::msg("API service not available"))?;
         
         let payload = SubmitTransactionsBatchPost::Bcs(aptos_api::bcs_payload::Bcs(data.clone()));
-        let response = service.transactions_api.submit_transactions_batch_raw(accept, payload)
-            .await
-            .context("Failed to submit transaction batch")?;
+        let response = match service.transactions_api.submit_transactions_batch_raw(accept, payload).await {
+            Ok(response) => response,
+            Err(e) => return Err(AptosApiError::from_response(e).await.into()),
+        };
     
-        // Directly handling the response parsing as before, but within the Result context
-        let (ret_str, header_str, error) = match response {
+        match response {
             SubmitTransactionsBatchResponse::Accepted(content, chain_id, ledger_version, ledger_oldest_version, ledger_timestamp_usec, epoch, block_height, oldest_block_height, cursor) |
             SubmitTransactionsBatchResponse::AcceptedPartial(content, chain_id, ledger_version, ledger_oldest_version, ledger_timestamp_usec, epoch, block_height, oldest_block_height, cursor) => {
-                let header = AptosHeader {
+                ApiResponse::new(content, AptosHeader {
                     chain_id,
                     ledger_version,
                     ledger_oldest_version,
@@ -559,29 +833,15 @@ impl Vm {
                     block_height,
                     oldest_block_height,
                     cursor,
-                };
-                let content_str = match content {
-                    AptosResponseContent::Json(json) => serde_json::to_string(&json.0)?,
-                    AptosResponseContent::Bcs(bytes) => format!("{}", hex::encode(bytes.0)),
-                };
-                let header_str = serde_json::to_string(&header)?;
-                (content_str, header_str, None)
+                })
             },
-        };
-    
-        // Since the original structure includes an error field, we handle it by returning an error string if applicable
-        // In this context, error handling is managed above, so error here would be None.
-        Ok(RpcRes {
-            data: ret_str,
-            header: header_str,
-            error,
-        })
+        }
     }
     
 
     //refactor
 
-    pub async fn get_table_item(&self, args: RpcTableReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_table_item(&self, args: RpcTableReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -589,21 +849,21 @@ impl Vm {
         };
         let account = args.query;
         let body = args.body;
-        let payload = serde_json::from_str::<TableItemRequest>(body.as_str()).context("Failed to parse table item request")?;
+        let payload = serde_json::from_str::<TableItemRequest>(body.as_str()).context(InvalidInput("Failed to parse table item request"))?;
         let api = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
         let ret = api
             .state_api
             .get_table_item_raw(
                 accept,
-                Address::from_str(account.as_str()).context("Invalid account address")?,
+                Address::from_str(account.as_str()).context(InvalidInput("Invalid account address"))?,
                 payload,
                 args.ledger_version,
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn get_raw_table_item(&self, args: RpcTableReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_raw_table_item(&self, args: RpcTableReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -611,21 +871,21 @@ impl Vm {
         };
         let account = args.query;
         let body = args.body;
-        let payload = serde_json::from_str::<RawTableItemRequest>(body.as_str()).context("Failed to parse raw table item request")?;
+        let payload = serde_json::from_str::<RawTableItemRequest>(body.as_str()).context(InvalidInput("Failed to parse raw table item request"))?;
         let api = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
         let ret = api
             .state_api
             .get_raw_table_item_raw(
                 accept,
-                Address::from_str(account.as_str()).context("Invalid account address")?,
+                Address::from_str(account.as_str()).context(InvalidInput("Invalid account address"))?,
                 payload,
                 args.ledger_version,
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn get_events_by_creation_number(&self, args: RpcEventNumReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_events_by_creation_number(&self, args: RpcEventNumReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
@@ -636,41 +896,41 @@ impl Vm {
             .events_api
             .get_events_by_creation_number_raw(
                 accept,
-                Address::from_str(args.address.as_str()).context("Invalid address")?,
+                Address::from_str(args.address.as_str()).context(InvalidInput("Invalid address"))?,
                 args.creation_number,
                 args.start,
                 args.limit,
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn get_events_by_event_handle(&self, args: RpcEventHandleReq) -> Result<RpcRes, anyhow::Error> {
+    pub async fn get_events_by_event_handle(&self, args: RpcEventHandleReq) -> Result<ApiResponse, anyhow::Error> {
         let accept = if args.is_bcs_format.unwrap_or(false) {
             AcceptType::Bcs
         } else {
             AcceptType::Json
         };
-        let event_handle = MoveStructTag::from_str(args.event_handle.as_str()).context("Invalid event handle")?;
-        let field_name = IdentifierWrapper::from_str(args.field_name.as_str()).context("Invalid field name")?;
+        let event_handle = MoveStructTag::from_str(args.event_handle.as_str()).context(InvalidInput("Invalid event handle"))?;
+        let field_name = IdentifierWrapper::from_str(args.field_name.as_str()).context(InvalidInput("Invalid field name"))?;
         let api = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
         let ret = api
             .events_api
             .get_events_by_event_handle_raw(
                 accept,
-                Address::from_str(args.address.as_str()).context("Invalid address")?,
+                Address::from_str(args.address.as_str()).context(InvalidInput("Invalid address"))?,
                 event_handle,
                 field_name,
                 args.start,
                 args.limit,
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
 
     // refactor
 
-    pub async fn simulate_transaction(&self, data: Vec<u8>, accept: AcceptType) -> Result<RpcRes, anyhow::Error> {
+    pub async fn simulate_transaction(&self, data: Vec<u8>, accept: AcceptType) -> Result<ApiResponse, anyhow::Error> {
         let service = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
         let ret = service
             .transactions_api
@@ -682,27 +942,88 @@ impl Vm {
                 SubmitTransactionPost::Bcs(aptos_api::bcs_payload::Bcs(data)),
             )
             .await;
-        self.process_response(ret)
+        self.process_response(ret).await
     }
     
-    pub async fn estimate_gas_price(&self) -> Result<RpcRes, anyhow::Error> {
+    /// Estimates gas prices from the recently accepted blocks. The Aptos API
+    /// only provides the ledger header, since its own estimate knows nothing
+    /// about the subnet's blocks.
+    pub async fn estimate_gas_price(&self) -> Result<ApiResponse, anyhow::Error> {
         let service = self.api_service.as_ref().ok_or_else(|| anyhow::anyhow!("API service not available"))?;
         let ret = service.transactions_api.estimate_gas_price_raw(AcceptType::Json).await;
-        self.process_response(ret)
+        let mut res = self.process_response(ret).await?;
+        res.content = ApiContent::Json(serde_json::to_value(self.fee_market.estimate().await)?);
+        Ok(res)
     }
     
-    async fn add_pool(&self, signed_transaction: SignedTransaction) -> Result<(), anyhow::Error> {
-        log::info!("Adding transaction to pool: {:?}", signed_transaction.clone().committed_hash());
-        let mut core_pool = self.core_mempool.as_ref().ok_or_else(|| anyhow::anyhow!("Core mempool not available"))?.write().await;
+    /// Validates `txn` with the Aptos VM against the latest committed state
+    /// (signature, sequence number, balance and gas price) and adds it to the
+    /// mempool. Returns its submission status and whether it was not known before.
+    pub async fn admit_transaction(&self, txn: SignedTransaction) -> Result<(SubmissionStatus, bool), anyhow::Error> {
+        let core_mempool = self.core_mempool.as_ref().ok_or_else(|| anyhow::anyhow!("Core mempool not available"))?;
+        let hash = txn.committed_hash();
+        let known = || ((MempoolStatus::new(MempoolStatusCode::Accepted), None), false);
+        if core_mempool.read().await.get_by_hash(hash).is_some() {
+            return Ok(known());
+        }
+
+        let (validation, db_sequence_number) = {
+            let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("DB not available"))?.read().await;
+            let version = db.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?.ledger_info().version();
+            let state_view = db.reader.state_view_at_version(Some(version)).context("Failed to get DB state view at version")?;
+            let db_sequence_number = state_view
+                .as_account_with_state_view(&txn.sender())
+                .get_account_resource()?
+                .map(|a| a.sequence_number())
+                .unwrap_or(0);
+            let vm = AptosVM::new_from_state_view(&state_view);
+            (vm.validate_transaction(txn.clone(), &state_view), db_sequence_number)
+        };
+        if let Some(vm_status) = validation.status() {
+            log::info!("transaction {} failed validation: {:?}", hash, vm_status);
+            let status = MempoolStatus::new(MempoolStatusCode::VmError).with_message(format!("{:?}", vm_status));
+            metrics::MEMPOOL_ADMISSIONS.with_label_values(&[&format!("{:?}", status.code)]).inc();
+            return Ok(((status, Some(vm_status)), false));
+        }
+
+        let mut core_pool = core_mempool.write().await;
+        if core_pool.get_by_hash(hash).is_some() {
+            return Ok(known());
+        }
         let status = core_pool.add_txn(
-            signed_transaction.clone(),
-            0,
-            signed_transaction.sequence_number(),
-            TimelineState::NonQualified,
+            txn,
+            validation.score(),
+            db_sequence_number,
+            TimelineState::NotReady,
             true,
         );
-        println!("add_pool status: {:?}", status);
-        Ok(())
+        log::info!("transaction {} mempool status: {:?}", hash, status);
+        metrics::MEMPOOL_ADMISSIONS.with_label_values(&[&format!("{:?}", status.code)]).inc();
+        let is_new = status.code == MempoolStatusCode::Accepted;
+        Ok(((status, None), is_new))
+    }
+
+    /// Admits transactions into the mempool and gossips the newly admitted
+    /// ones to peers, so that they reach the block proposer.
+    pub async fn admit_and_gossip(&self, txns: Vec<SignedTransaction>) -> Result<Vec<SubmissionStatus>, anyhow::Error> {
+        let mut statuses = Vec::with_capacity(txns.len());
+        let mut admitted = Vec::new();
+        for txn in txns {
+            let (status, is_new) = self.admit_transaction(txn.clone()).await?;
+            if is_new {
+                admitted.push(txn);
+            }
+            statuses.push(status);
+        }
+        if admitted.is_empty() {
+            return Ok(statuses);
+        }
+
+        if let Some(sender) = self.app_sender.as_ref() {
+            sender.send_app_gossip(encode_tx_gossip(&admitted)?).await?;
+        }
+        self.schedule(Event::TxsAdded(admitted.len())).await;
+        Ok(statuses)
     }
     
     async fn get_pending_tx(&self, count: u64) -> Result<Vec<SignedTransaction>, anyhow::Error> {
@@ -723,9 +1044,10 @@ impl Vm {
                 }
             }).collect::<Vec<TransactionInProgress>>();
 
+            let remaining = count - pending_txs.len() as u64;
             let txs = core_pool.get_batch(
-                    count, 
-                    1024 * 5 * 1000 * count, 
+                    remaining,
+                    1024 * 5 * 1000 * remaining,
                     true, 
                     true, 
                     transactions_in_progress
@@ -746,133 +1068,84 @@ impl Vm {
     }
     
 
-    async fn check_pending_tx(&self) -> Result<(), anyhow::Error> {
-        let shared_self = Arc::new(self.clone());
-        let check_duration = Duration::from_millis(2000);
-        tokio::spawn(async move {
-            let mut last_check_build_time = get_current_time_seconds();
+    /// Spawns the task that fires the deadlines of the block building
+    /// scheduler, e.g., the end of the minimum block interval.
+    async fn start_scheduler(&self) {
+        let vm = self.clone();
+        let task = tokio::spawn(async move {
             loop {
-                tokio::time::sleep(check_duration).await;
-                let build_status_result = shared_self.build_status.try_read();
-                match build_status_result {
-                    Ok(build_status) => {
-                        if *build_status == 0 {
-                            let has_pending_tx = shared_self.has_pending_tx.try_read();
-                            match has_pending_tx {
-                                Ok(has_pending) => {
-                                    if *has_pending {
-                                        shared_self.update_pending_tx_flag(false).await;
-                                        shared_self.notify_block_ready2().await;
-                                    }
-                                },
-                                Err(e) => log::error!("Failed to read has_pending_tx: {}", e),
-                            }
-                        }
-                    },
-                    Err(e) => log::error!("Failed to read build status: {}", e),
-                }
-
-                if get_current_time_seconds() - last_check_build_time > 120 {
-                    shared_self.update_build_block_status(0).await; 
-                    last_check_build_time = get_current_time_seconds();
-                }
+                vm.scheduler.wait().await;
+                vm.schedule(Event::Tick).await;
             }
         });
-        Ok(())
-    }
-
-
-        // Updates the build block status safely within an asynchronous context.
-    // This function already handles its main purpose well. Consider logging or acting on error conditions if needed.
-    async fn update_build_block_status(&self, s: u8) {
-        let mut status = self.build_status.write().await;
-        if *status != s {
-            *status = s;
-        }
+        self.tasks.lock().await.push(task);
     }
 
-    // Toggles the pending transaction flag, indicating whether there are transactions pending processing.
-    // Similar to `update_build_block_status`, it's straightforward and handles its purpose well.
-    async fn update_pending_tx_flag(&self, n: bool) {
-        let mut tx = self.has_pending_tx.write().await;
-        if *tx != n {
-            *tx = n;
+    /// Tells the block building scheduler about `event`, and notifies the
+    /// engine if it decides a block is to be built.
+    async fn schedule(&self, event: Event) {
+        if self.scheduler.handle(event).await != Action::NotifyEngine {
+            return;
         }
-    }
-
-    // Notifies that the block is ready to be processed, attempting to send a message to the engine.
-    // Added error handling or logging for the send operation could be beneficial.
-    async fn notify_block_ready2(&self) {
-        if let Some(to_engine) = &self.to_engine {
-            match to_engine.read().await.send(PendingTxs).await {
-                Ok(_) => {
-                    self.update_build_block_status(1).await;
-                    log::info!("notify_block_ready:success");
-                },
+        match &self.to_engine {
+            Some(to_engine) => match to_engine.read().await.send(PendingTxs).await {
+                Ok(_) => log::info!("notified the engine of pending transactions"),
                 Err(e) => log::error!("send tx to_engine error: {}", e),
-            }
-        } else {
-            log::error!("to_engine is None, cannot send tx");
+            },
+            None => log::error!("to_engine is None, cannot send tx"),
         }
     }
 
-    // Checks the current block status and decides whether to notify that the block is ready.
-    // This function primarily orchestrates the condition checking and delegating.
-    async fn notify_block_ready(&self) {
-        let status = *self.build_status.read().await;
-        let tx = *self.has_pending_tx.read().await;
-
-        match status {
-            1 if !tx => self.update_pending_tx_flag(true).await, // If building and no transactions are pending, flag is set.
-            0 => self.notify_block_ready2().await, // If not building, proceed to notify.
-            _ => log::info!("notify_block_ready ignore or unhandled status: {}", status),
+    /// Returns the number of pending transactions, up to a full batch.
+    async fn pending_tx_count(&self) -> usize {
+        match self.get_pending_tx(self.config.max_batch_txs as u64).await {
+            Ok(txs) => txs.len(),
+            Err(e) => {
+                log::error!("failed to count pending transactions: {}", e);
+                0
+            },
         }
     }
 
+    pub async fn faucet_apt(&self, acc: Vec<u8>, accept: AcceptType) -> Result<ApiResponse, anyhow::Error> {
+        let (_, res) = self.faucet_request(acc, FaucetRequest::Fund, accept).await?;
+        Ok(res)
+    }
 
-    pub async fn faucet_apt(&self, acc: Vec<u8>, accept: AcceptType) -> Result<RpcRes, anyhow::Error> {
-        let to = AccountAddress::from_bytes(acc).context("Failed to convert account address")?;
-        let core_account = {
-            let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("Database reference not found"))?.read().await;
-            // you must also acquire the lock on the core mempool, otherwise sequence number will be wrong
-            // let mut _core_mempool = self.core_mempool.as_ref().ok_or_else(|| anyhow::anyhow!("Core mempool not available"))?.write().await;
-            self.get_core_account(&db).await?
-        };
-
+    pub async fn faucet_with_cli(&self, acc: Vec<u8>) -> Result<ApiResponse, anyhow::Error> {
+        let (txn, mut res) = self.faucet_request(acc, FaucetRequest::Fund, AcceptType::Bcs).await?;
+        let txs = vec![txn];
+        res.content = ApiContent::Bcs(bcs::to_bytes(&txs)?);
+        Ok(res)
+    }
 
-        let tx_factory = TransactionFactory::new(ChainId::test());
-        let tx_acc_mint = core_account.sign_with_transaction_builder(tx_factory.transfer(to, 10 * 100_000_000));
-        self.submit_transaction(bcs::to_bytes(&tx_acc_mint)?, accept).await
+    pub async fn create_account(&self, acc: Vec<u8>, accept: AcceptType) -> Result<ApiResponse, anyhow::Error> {
+        let (_, res) = self.faucet_request(acc, FaucetRequest::CreateAccount, accept).await?;
+        Ok(res)
     }
 
-   
-    
-    pub async fn faucet_with_cli(&self, acc: Vec<u8>) -> Result<RpcRes, anyhow::Error> {
-        let to = AccountAddress::from_bytes(acc).context("Failed to convert account address")?;
-        let core_account = {
+    /// Serves a faucet request for `acc`, returning the submitted transaction
+    /// along with the submission response.
+    async fn faucet_request(
+        &self,
+        acc: Vec<u8>,
+        request: FaucetRequest,
+        accept: AcceptType,
+    ) -> Result<(SignedTransaction, ApiResponse), anyhow::Error> {
+        let to = AccountAddress::from_bytes(acc).context(InvalidInput("Failed to convert account address"))?;
+        let faucet = self.faucet.as_ref().ok_or_else(|| anyhow::anyhow!("Faucet not available"))?;
+        let sequence_number = {
             let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("Database reference not found"))?.read().await;
-            // you must also acquire the lock on the core mempool, otherwise sequence number will be wrong
-            // let mut _core_mempool = self.core_mempool.as_ref().ok_or_else(|| anyhow::anyhow!("Core mempool not available"))?.write().await;
-            self.get_core_account(&db).await?
+            self.get_sequence_number(&db, faucet.account()).await?
         };
-
-        let tx_factory = TransactionFactory::new(ChainId::test());
-        let tx_acc_mint = core_account.sign_with_transaction_builder(tx_factory.transfer(to, 10 * 100_000_000));
-        let mut res = self.submit_transaction(bcs::to_bytes(&tx_acc_mint)?, AcceptType::Bcs).await?;
-        let txs = vec![tx_acc_mint];
-        res.data = hex::encode(bcs::to_bytes(&txs)?);
-        Ok(res)
-    }
-    
-    pub async fn create_account(&self, acc: Vec<u8>, accept: AcceptType) -> Result<RpcRes, anyhow::Error> {
-        let to = AccountAddress::from_bytes(acc).context("Failed to convert account address")?;
-        let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("Database reference not found"))?.read().await;
-        let core_account = self.get_core_account(&db).await?;
-        let tx_factory = TransactionFactory::new(ChainId::test());
-        let tx_acc_create = core_account.sign_with_transaction_builder(tx_factory.create_account(to));
-        self.submit_transaction(bcs::to_bytes(&tx_acc_create)?, accept).await
+        faucet
+            .request(to, request, sequence_number, |txn| async move {
+                let res = self.submit_transaction(bcs::to_bytes(&txn)?, accept).await?;
+                Ok((txn, res))
+            })
+            .await
     }
-    
+
     pub async fn set_state(&self, snow_state: snow::State) -> Result<(), anyhow::Error> {
         let mut vm_state = self.state.write().await;
         match snow_state {
@@ -882,12 +1155,22 @@ impl Vm {
                 Ok(())
             },
             snow::State::StateSyncing => {
+                // not entered, see `state_sync_enabled`
                 log::info!("set_state: state syncing");
-                Err(anyhow::anyhow!("state sync is not supported"))
+                vm_state.bootstrapped = false;
+                Ok(())
             },
             snow::State::Bootstrapping => {
                 log::info!("set_state: bootstrapping");
                 vm_state.bootstrapped = false;
+                drop(vm_state);
+                // the engine bootstraps from the last accepted block once this
+                // returns, so a synced block saves it fetching every block below
+                if self.config.state_sync_enabled {
+                    if let Err(e) = self.state_sync().await {
+                        log::error!("state sync failed, bootstrapping block by block: {}", e);
+                    }
+                }
                 Ok(())
             },
             snow::State::NormalOp => {
@@ -898,6 +1181,111 @@ impl Vm {
         }
     }    
 
+    /// Asks every connected peer for its last [`StateSummary`] and returns the
+    /// highest one signed by the validators and offered by at least
+    /// `state_sync_min_peers` peers, along with the peers that offered it.
+    async fn best_state_summary(&self, epoch_state: &EpochState) -> Result<Option<(Vec<ids::node::Id>, StateSummary)>, anyhow::Error> {
+        let app_sender = self.app_sender.as_ref().ok_or_else(|| anyhow::anyhow!("App sender not available"))?;
+        let peers: Vec<ids::node::Id> = self.peers.read().await.iter().cloned().collect();
+
+        // peers offering each accepted block, by height and block Id
+        let mut offers: HashMap<(u64, ids::Id), (Vec<ids::node::Id>, StateSummary)> = HashMap::new();
+        for peer in peers {
+            match self.state_sync.request(app_sender.as_ref(), peer, &StateSyncRequest::LastSummary).await {
+                Ok(StateSyncResponse::Summary(Some(summary))) => {
+                    let blk = match state_sync::verify_summary(&summary, epoch_state) {
+                        Ok(blk) => blk,
+                        Err(e) => {
+                            log::warn!("ignoring invalid state summary from {}: {}", peer, e);
+                            continue;
+                        },
+                    };
+                    offers
+                        .entry((summary.height, blk.id()))
+                        .or_insert_with(|| (Vec::new(), summary))
+                        .0
+                        .push(peer);
+                },
+                Ok(StateSyncResponse::Summary(None)) => log::debug!("peer {} has no state summary", peer),
+                Ok(other) => log::warn!("unexpected state summary response from {}: {:?}", peer, other),
+                Err(e) => log::warn!("failed to get state summary from {}: {}", peer, e),
+            }
+        }
+        Ok(offers
+            .into_iter()
+            .filter(|(_, (peers, _))| peers.len() >= self.config.state_sync_min_peers)
+            .max_by_key(|((height, _), _)| *height)
+            .map(|(_, offer)| offer))
+    }
+
+    /// Restores the AptosDB state of the best summary offered by peers and
+    /// adopts its block as the last accepted block.
+    /// Does nothing if no peer is far enough ahead of the local chain.
+    pub async fn state_sync(&self) -> Result<(), anyhow::Error> {
+        let state = self.state.read().await.state.clone().ok_or_else(|| anyhow::anyhow!("State manager not found"))?;
+        let last_accepted = state.get_block(&state.get_last_accepted_block_id().await?).await?;
+
+        let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("Database reference not found"))?.read().await.clone();
+        let epoch_state = db.reader.get_latest_epoch_state().context("Failed to get latest epoch state")?;
+
+        let (peers, summary) = match self.best_state_summary(&epoch_state).await? {
+            Some(best) => best,
+            None => {
+                log::info!("state sync: no summary offered by enough peers");
+                return Ok(());
+            },
+        };
+        if summary.height < last_accepted.height() + self.config.state_sync_min_blocks_behind {
+            log::info!(
+                "state sync: summary height {} is not far enough ahead of {}",
+                summary.height,
+                last_accepted.height()
+            );
+            return Ok(());
+        }
+        log::info!("state sync: syncing to height {} offered by {} peers", summary.height, peers.len());
+
+        // the chunks are verified against the summary, so any peer offering it will do
+        let app_sender = self.app_sender.as_ref().ok_or_else(|| anyhow::anyhow!("App sender not available"))?;
+        let mut restored = Err(anyhow::anyhow!("No peer to restore from"));
+        for peer in peers {
+            restored = state_sync::restore(&db, &self.state_sync, app_sender.as_ref(), peer, &summary).await;
+            match restored.as_ref() {
+                Ok(()) => break,
+                Err(e) => log::warn!("state sync: failed to restore from {}: {}", peer, e),
+            }
+        }
+        restored?;
+
+        // the executor caches the committed block, which just moved under it
+        let executor = self.executor.as_ref().ok_or_else(|| anyhow::anyhow!("Executor not available"))?.read().await;
+        executor.reset().context("Failed to reset executor")?;
+
+        let mut blk = state_sync::verify_summary(&summary, &epoch_state)?;
+        blk.set_status(choices::status::Status::Accepted);
+        let mut state_w = state.clone();
+        state_w.write_block(&blk).await?;
+        state.set_block_id_at_height(blk.height(), &blk.id()).await?;
+        state.set_last_accepted_block(&blk.id()).await?;
+        metrics::ACCEPTED_HEIGHT.set(blk.height() as i64);
+        self.state.write().await.preferred = blk.id();
+
+        log::info!("state sync: synced to block {} at height {}", blk.id(), blk.height());
+        Ok(())
+    }
+
+    async fn serve_state_sync(&self, req: StateSyncRequest) -> StateSyncResponse {
+        let state = match self.state.read().await.state.clone() {
+            Some(state) => state,
+            None => return StateSyncResponse::Error("state manager not found".to_string()),
+        };
+        let db = match self.db.as_ref() {
+            Some(db) => db.read().await.clone(),
+            None => return StateSyncResponse::Error("database not found".to_string()),
+        };
+        state_sync::serve(&state, &db, req).await
+    }
+
     pub async fn set_preference(&self, id: ids::Id) -> Result<(), anyhow::Error> {
         let mut vm_state = self.state.write().await;
         vm_state.preferred = id;
@@ -930,7 +1318,16 @@ impl Vm {
     }
     
     pub async fn get_core_account(&self, db: &DbReaderWriter) -> Result<LocalAccount, anyhow::Error> {
-        let acc = aptos_test_root_address();
+        let sn = self.get_sequence_number(db, aptos_test_root_address()).await?;
+        Ok(LocalAccount::new(
+            aptos_test_root_address(),
+            AccountKey::from_private_key(GENESIS_KEYPAIR.0.clone()),
+            sn,
+        ))
+    }
+
+    /// Returns the sequence number of `acc` at the latest committed version.
+    pub async fn get_sequence_number(&self, db: &DbReaderWriter, acc: AccountAddress) -> Result<u64, anyhow::Error> {
         let state_proof = db.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?;
         let current_version = state_proof.ledger_info().version();
         let db_state_view = db
@@ -941,37 +1338,88 @@ impl Vm {
         let av = view.get_account_resource()?
         .ok_or_else(|| anyhow::Error::msg("Account resource not found"))
         .context("Failed to get account resource")?;
-        let sn = av.sequence_number();
-        Ok(LocalAccount::new(
-            aptos_test_root_address(),
-            AccountKey::from_private_key(GENESIS_KEYPAIR.0.clone()),
-            sn,
-        ))
+        Ok(av.sequence_number())
     }
     
-    pub async fn execute_and_commit_block(&self, data: Vec<u8>) -> Result<(), anyhow::Error> {
+    /// Checks that the Aptos transactions carried by `block` are exactly the
+    /// ones [`build_block_data`](Vm::build_block_data) would have built for it.
+    fn check_block_data(
+        &self,
+        block: &Block,
+        parent: &Block,
+        aptos_data: &AptosData,
+        block_tx: &[Transaction],
+        next_epoch: u64,
+    ) -> Result<(), anyhow::Error> {
+        let (first, rest) = block_tx.split_first().ok_or_else(|| anyhow::anyhow!("Block metadata not found in transactions"))?;
+        let (last, user_txs) = rest.split_last().ok_or_else(|| anyhow::anyhow!("State checkpoint not found in transactions"))?;
+        let block_meta = first.try_as_block_metadata().context("Failed to convert transaction to block metadata")?;
+        let user_txs = user_txs
+            .iter()
+            .map(|t| match t {
+                UserTransaction(t) => Ok(t.clone()),
+                _ => Err(anyhow::anyhow!("Unexpected non-user transaction in block")),
+            })
+            .collect::<Result<Vec<SignedTransaction>, anyhow::Error>>()?;
+
+        let parent_data = AptosData::from_block(parent)?;
+        let expected_id = aptos_block_id(&block.parent_id(), block.height(), block.timestamp(), &user_txs);
+        let expected_ts = aptos_timestamp_usecs(block.timestamp(), parent_data.timestamp_usecs);
+        if block_meta.id() != expected_id || aptos_data.block_id != expected_id {
+            return Err(anyhow::anyhow!("Aptos block id {} does not match expected {}", block_meta.id(), expected_id));
+        }
+        if aptos_data.parent_block_id != parent_data.block_id {
+            return Err(anyhow::anyhow!("Aptos parent block id {} does not match parent {}", aptos_data.parent_block_id, parent_data.block_id));
+        }
+        if block_meta.timestamp_usecs() != expected_ts || aptos_data.timestamp_usecs != expected_ts {
+            return Err(anyhow::anyhow!("Aptos timestamp {} does not match expected {}", block_meta.timestamp_usecs(), expected_ts));
+        }
+        if block_meta.epoch() != next_epoch || aptos_data.epoch != next_epoch {
+            return Err(anyhow::anyhow!("Aptos epoch {} does not match expected {}", block_meta.epoch(), next_epoch));
+        }
+        if *last != Transaction::StateCheckpoint(state_checkpoint_hash(&expected_id)) {
+            return Err(anyhow::anyhow!("Unexpected trailing transaction in block"));
+        }
+        Ok(())
+    }
+
+    /// Speculatively executes `block` on top of `parent` without committing it,
+    /// keeping the resulting ledger info until the block is accepted or rejected.
+    pub async fn execute_block(&self, block: &Block, parent: &Block) -> Result<LedgerInfo, anyhow::Error> {
+        if let Some(li) = self.executed_blocks.read().await.get(&block.id()) {
+            return Ok(li.clone());
+        }
 
-        // get executor and metadata
-        log::info!("inner_build_block");
         let executor = self.executor.as_ref().ok_or_else(|| anyhow::anyhow!("Executor not available"))?.read().await;
-        let aptos_data = serde_json::from_slice::<AptosData>(&data).context("Failed to parse AptosData from bytes")?;
-        let block_tx = serde_json::from_slice::<Vec<Transaction>>(&aptos_data.0).context("Failed to parse transactions from AptosData")?;
+        let aptos_data = AptosData::from_block(block)?;
+        let parent_data = AptosData::from_block(parent)?;
+        let block_tx = &aptos_data.transactions;
         let block_meta = block_tx.get(0).ok_or_else(|| anyhow::anyhow!("Block metadata not found in transactions"))?.try_as_block_metadata().context("Failed to convert transaction to block metadata")?;
 
-        // execute block
-        log::info!("executing block");
+        let next_epoch = {
+            let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("DB not available"))?.read().await;
+            let latest_ledger_info = db.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?;
+            latest_ledger_info.ledger_info().next_block_epoch()
+        };
+        self.check_block_data(block, parent, &aptos_data, block_tx, next_epoch)?;
+
+        // the genesis block has no Aptos block of its own and maps to the executor root
+        let parent_block_id = if parent_data.block_id == HashValue::zero() {
+            executor.committed_block_id()
+        } else {
+            parent_data.block_id
+        };
+
+        log::info!("executing block {}", block.id());
         let block_id = block_meta.id();
-        let parent_block_id = executor.committed_block_id();
-        let next_epoch = aptos_data.3;
-        let ts = aptos_data.4;
+        let timer = metrics::BLOCK_EXECUTE_SECONDS.start_timer();
         let output = executor.execute_block(
             ExecutableBlock::new(block_id, ExecutableTransactions::Unsharded(block_tx.clone())),
             parent_block_id,
             None,
         ).context("Failed to execute block")?;
+        timer.observe_duration();
 
-        // commit block
-        log::info!("committing block");
         let ledger_info = LedgerInfo::new(
             BlockInfo::new(
                 next_epoch,
@@ -979,118 +1427,492 @@ impl Vm {
                 block_id,
                 output.root_hash(),
                 output.version(),
-                ts,
+                aptos_data.timestamp_usecs,
                 output.epoch_state().clone(),
             ),
             HashValue::zero(),
         );
+        self.executed_blocks.write().await.insert(block.id(), ledger_info.clone());
+        Ok(ledger_info)
+    }
+
+    /// Commits an accepted block to AptosDB, executing it first if it was not
+    /// executed during verification.
+    pub async fn commit_block(&self, block: &Block) -> Result<(), anyhow::Error> {
+        let executed = self.executed_blocks.read().await.get(&block.id()).cloned();
+        let ledger_info = match executed {
+            Some(li) => li,
+            None => {
+                let state = self.state.read().await.state.clone().ok_or_else(|| anyhow::anyhow!("State manager not found"))?;
+                let parent = state.get_block(&block.parent_id()).await.context("Failed to get parent block")?;
+                self.execute_block(block, &parent).await?
+            },
+        };
+
+        log::info!("committing block {}", block.id());
+        let block_id = ledger_info.consensus_block_id();
+        let last_version = ledger_info.version();
+        let first_version = {
+            let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("DB not available"))?.read().await;
+            db.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?.ledger_info().version() + 1
+        };
         let signer = self.signer.as_ref().ok_or_else(|| anyhow::anyhow!("Signer not available"))?;
         let li = generate_ledger_info_with_sig(
             &[signer.clone()],
             ledger_info,
         );
-        executor.commit_blocks(vec![block_id], li.clone())?;
+        {
+            let executor = self.executor.as_ref().ok_or_else(|| anyhow::anyhow!("Executor not available"))?.read().await;
+            let _timer = metrics::BLOCK_COMMIT_SECONDS.start_timer();
+            executor.commit_blocks(vec![block_id], li)?;
+        }
+        self.executed_blocks.write().await.remove(&block.id());
+
+        match self.feed_block(block, first_version, last_version).await {
+            Ok(feed_block) => self.feed.push(feed_block).await,
+            Err(e) => log::error!("failed to publish block {} to the feed: {}", block.id(), e),
+        }
 
         // commit transactions to mempools
         log::info!("committing transactions to mempool");
+        let aptos_data = AptosData::from_block(block)?;
         let mut core_pool = self.core_mempool.as_ref().ok_or_else(|| anyhow::anyhow!("Core mempool not available"))?.write().await;
-        for t in block_tx.iter() {
+        let mut gas_unit_prices = Vec::new();
+        for t in aptos_data.transactions.iter() {
             if let UserTransaction(t) = t {
-                println!("committing tx: {:?}", t.clone().committed_hash());
                 let sender = t.sender();
                 let sequence_number = t.sequence_number();
                 core_pool.commit_transaction(&AccountAddress::from(sender), sequence_number);
+                gas_unit_prices.push(t.gas_unit_price());
             }
         }
-        self.update_build_block_status(0).await;
+        drop(core_pool);
+        metrics::BLOCK_TRANSACTIONS.observe(gas_unit_prices.len() as f64);
+        self.fee_market.record(BlockGasUsage::new(gas_unit_prices, self.config.max_block_txs)).await;
+        let pending = self.pending_tx_count().await;
+        self.schedule(Event::BlockAccepted { pending }).await;
 
         log::info!("block committed");
 
         Ok(())
     }
 
+    /// Reads the transactions committed by `block` back from the ledger,
+    /// along with their events.
+    async fn feed_block(&self, block: &Block, first_version: u64, last_version: u64) -> Result<FeedBlock, anyhow::Error> {
+        let aptos_data = AptosData::from_block(block)?;
+        let outputs = {
+            let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("DB not available"))?.read().await;
+            db.reader
+                .get_transaction_outputs(first_version, last_version - first_version + 1, last_version)
+                .context("Failed to get committed transaction outputs")?
+        };
+        let transactions = outputs
+            .transactions_and_outputs
+            .iter()
+            .zip(outputs.proof.transaction_infos.iter())
+            .enumerate()
+            .map(|(i, ((_, output), info))| FeedTransaction {
+                version: first_version + i as u64,
+                hash: info.transaction_hash().to_hex_literal(),
+                success: info.status().is_success(),
+                vm_status: format!("{:?}", info.status()),
+                events: output
+                    .events()
+                    .iter()
+                    .map(|e| FeedEvent {
+                        type_tag: e.type_tag().to_string(),
+                        data: hex::encode(e.event_data()),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(FeedBlock {
+            block_id: block.id().to_string(),
+            height: block.height(),
+            timestamp_usecs: aptos_data.timestamp_usecs,
+            first_version,
+            last_version,
+            transactions,
+        })
+    }
 
+    /// Drops the speculative execution results of rejected or pruned blocks.
+    pub async fn discard_blocks(&self, blk_ids: &[ids::Id]) {
+        if blk_ids.is_empty() {
+            return;
+        }
+        {
+            let mut executed_blocks = self.executed_blocks.write().await;
+            for blk_id in blk_ids {
+                executed_blocks.remove(blk_id);
+            }
+        }
+        // the transactions of a rejected block of ours are pending again
+        let pending = self.pending_tx_count().await;
+        self.schedule(Event::BlockRejected { pending }).await;
+    }
 
-    async fn init_aptos(&mut self, uuid: &str) -> Result<(), anyhow::Error> {
-        let db_name = get_db_name(uuid);
-        let (genesis, validators) = test_genesis_change_set_and_validators(Some(1));
-        let signer = ValidatorSigner::new(validators[0].data.owner_address, validators[0].consensus_key.clone());
-        self.signer = Some(signer);
-
-        let genesis_txn = Transaction::GenesisTransaction(WriteSetPayload::Direct(genesis));
-        let p = format!("{}/{}", dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Home directory not found"))?.to_str().ok_or_else(|| anyhow::anyhow!("Failed to convert home directory to string"))?, db_name);
+    async fn init_aptos(&mut self, db_dir: &Path, genesis: &Genesis) -> Result<(), anyhow::Error> {
+        let change_set = genesis.change_set()?;
+        let consensus_key = self.config.consensus_key()?;
+        self.signer = Some(genesis.signer(consensus_key.as_ref())?);
+        self.chain_id = genesis.chain_id();
+        self.faucet = Some(Faucet::new(self.config.faucet.clone(), self.chain_id)?);
 
-        if fs::metadata(&p).is_err() {
-            fs::create_dir_all(&p).context("Failed to create directory")?;
+        let genesis_txn = Transaction::GenesisTransaction(WriteSetPayload::Direct(change_set));
+        if fs::metadata(db_dir).is_err() {
+            fs::create_dir_all(db_dir).context("Failed to create directory")?;
         }
 
-        let db = DbReaderWriter::wrap(AptosDB::new_for_test(&p));
+        self.config.storage.record_mode(db_dir).context("Failed to record storage mode")?;
+        let storage = self.config.storage.aptos_storage_config(db_dir);
+        log::info!("opening AptosDB at {} with {:?} storage", db_dir.display(), self.config.storage.mode);
+        let aptos_db = AptosDB::open(
+            &storage.dir,
+            false,
+            storage.storage_pruner_config,
+            storage.rocksdb_configs,
+            storage.enable_indexer,
+            storage.buffered_state_target_items,
+            storage.max_num_nodes_per_lru_cache_shard,
+        )
+        .context("Failed to open AptosDB")?;
+        let db = DbReaderWriter::wrap(aptos_db);
         let waypoint = generate_waypoint::<AptosVM>(&db.1, &genesis_txn).context("Failed to generate waypoint")?;
         maybe_bootstrap::<AptosVM>(&db.1, &genesis_txn, waypoint).context("Failed to bootstrap DB")?;
 
         self.db = Some(Arc::new(RwLock::new(db.1.clone())));
         let executor = BlockExecutor::new(db.1.clone());
         self.executor = Some(Arc::new(RwLock::new(executor)));
+        self.fund_genesis_accounts(genesis).await?;
+        let latest_version = db.1.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?.ledger_info().version();
+        self.feed.start_at(latest_version + 1).await;
 
         let (mempool_client_sender, mut mempool_client_receiver) = futures_mpsc::channel::<MempoolClientRequest>(10);
         let sender = MempoolClientSender::from(mempool_client_sender);
-        let node_config = NodeConfig::default();
-        let context = Context::new(ChainId::test(), db.1.reader.clone(), sender, node_config.clone());
+        let node_config = self.config.node_config();
+        let context = Context::new(self.chain_id, db.1.reader.clone(), sender, node_config.clone());
         self.api_context = Some(context.clone());
         let service = get_raw_api_service(Arc::new(context));
         self.api_service = Some(service);
         self.core_mempool = Some(Arc::new(RwLock::new(CoreMempool::new(&node_config))));
-        self.check_pending_tx().await?;
+        self.start_scheduler().await;
 
-        tokio::task::spawn(async move {
+        let vm = self.clone();
+        let task = tokio::task::spawn(async move {
             while let Some(request) = mempool_client_receiver.next().await {
                 match request {
-                    MempoolClientRequest::SubmitTransaction(_t, callback) => {
-                        // accept all the transaction
-                        let ms = MempoolStatus::new(MempoolStatusCode::Accepted);
-                        let status: SubmissionStatus = (ms, None);
-                        callback.send(Ok(status)).unwrap();
+                    MempoolClientRequest::SubmitTransaction(t, callback) => {
+                        let ret = vm.admit_and_gossip(vec![t]).await.and_then(|mut statuses| {
+                            statuses.pop().ok_or_else(|| anyhow::anyhow!("No submission status"))
+                        });
+                        if callback.send(ret).is_err() {
+                            log::debug!("submission caller went away");
+                        }
+                    },
+                    MempoolClientRequest::GetTransactionByHash(hash, callback) => {
+                        let txn = match vm.core_mempool.as_ref() {
+                            Some(pool) => pool.read().await.get_by_hash(hash),
+                            None => None,
+                        };
+                        let _ = callback.send(txn);
                     },
-                    MempoolClientRequest::GetTransactionByHash(_, _) => {},
                 }
             }
         });
+        self.tasks.lock().await.push(task);
+
+        Ok(())
+    }
+
+    /// Funds the genesis accounts on a freshly bootstrapped AptosDB, by
+    /// committing a block of transfers from the core resources account on top
+    /// of the genesis transaction. The block only depends on the genesis, so
+    /// every node commits the same one before the consensus genesis block.
+    async fn fund_genesis_accounts(&self, genesis: &Genesis) -> Result<(), anyhow::Error> {
+        if genesis.accounts.is_empty() {
+            return Ok(());
+        }
+        let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("DB not available"))?.read().await;
+        let latest_ledger_info = db.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?;
+        if latest_ledger_info.ledger_info().version() > 0 {
+            // funded when the database was bootstrapped
+            return Ok(());
+        }
+        let root_key = match self.config.root_key()? {
+            Some(root_key) => root_key,
+            None if genesis.root_key.is_none() && genesis.is_test() => GENESIS_KEYPAIR.0.clone(),
+            None => return Err(anyhow::anyhow!("A root key is required to fund the genesis accounts")),
+        };
+        if Some(Ed25519PublicKey::from(&root_key)) != genesis.core_resources_key() {
+            return Err(anyhow::anyhow!("The root key is not the root key of the genesis"));
+        }
+        let core_account = LocalAccount::new(
+            aptos_test_root_address(),
+            AccountKey::from_private_key(root_key),
+            self.get_sequence_number(&db, aptos_test_root_address()).await?,
+        );
+        let signer = self.signer.as_ref().ok_or_else(|| anyhow::anyhow!("Signer not available"))?;
+        let executor = self.executor.as_ref().ok_or_else(|| anyhow::anyhow!("Executor not available"))?.read().await;
+
+        let block_id = genesis_funding_block_id();
+        let next_epoch = latest_ledger_info.ledger_info().next_block_epoch();
+        let timestamp_usecs = 1;
+        let block_meta = Transaction::BlockMetadata(BlockMetadata::new(block_id, next_epoch, 0, signer.author(), vec![], vec![], timestamp_usecs));
+        let mut txs: Vec<Transaction> = genesis.funding_transactions(&core_account).into_iter().map(UserTransaction).collect();
+        txs.insert(0, block_meta);
+        txs.push(Transaction::StateCheckpoint(state_checkpoint_hash(&block_id)));
+
+        let output = executor.execute_block(
+            ExecutableBlock::new(block_id, ExecutableTransactions::Unsharded(txs)),
+            executor.committed_block_id(),
+            None,
+        ).context("Failed to execute genesis funding block")?;
+        // the block metadata comes first, then a status per funded account
+        for (account, status) in genesis.accounts.iter().zip(output.compute_status().iter().skip(1)) {
+            if *status != TransactionStatus::Keep(ExecutionStatus::Success) {
+                return Err(anyhow::anyhow!("Failed to fund genesis account {}: {:?}", account.address, status));
+            }
+        }
+        let ledger_info = LedgerInfo::new(
+            BlockInfo::new(
+                next_epoch,
+                0,
+                block_id,
+                output.root_hash(),
+                output.version(),
+                timestamp_usecs,
+                output.epoch_state().clone(),
+            ),
+            HashValue::zero(),
+        );
+        let li = generate_ledger_info_with_sig(&[signer.clone()], ledger_info);
+        executor.commit_blocks(vec![block_id], li).context("Failed to commit genesis funding block")?;
+        log::info!("funded {} genesis accounts", genesis.accounts.len());
+        Ok(())
+    }
+
+    /// Prefers the last accepted block, accepting the genesis block first on a
+    /// fresh database, and reports its height. The genesis block carries
+    /// `genesis_hash`, the hash of the genesis document, so that chains with
+    /// different genesis documents have different genesis blocks.
+    async fn init_last_accepted(&self, state: &state::State, genesis_hash: Option<HashValue>) -> io::Result<()> {
+        let mut vm_state = self.state.write().await;
+        let has_last_accepted = state.has_last_accepted_block().await?;
+        if has_last_accepted {
+            let last_accepted_blk_id = state.get_last_accepted_block_id().await?;
+            let last_accepted = state.get_block(&last_accepted_blk_id).await?;
+            metrics::ACCEPTED_HEIGHT.set(last_accepted.height() as i64);
+            vm_state.preferred = last_accepted_blk_id;
+        } else {
+            // the genesis block keeps the JSON encoding, so that its Id does not change
+            let data = AptosData::genesis_to_vec(genesis_hash).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            let mut genesis_block = Block::new_with_codec(
+                ids::Id::empty(),
+                0,
+                0,
+                data,
+                choices::status::Status::default(),
+                BlockCodec::Json,
+            )?;
+            genesis_block.set_state(state.clone());
+            genesis_block.accept().await?;
+
+            let genesis_blk_id = genesis_block.id();
+            vm_state.preferred = genesis_blk_id;
+        }
+        Ok(())
+    }
+
+    /// Reconciles AptosDB with the last accepted block after a restart, so that
+    /// a node stopped at any point does not need its databases wiped. Completes
+    /// the acceptance of a block that was committed to AptosDB but not recorded
+    /// as accepted, re-commits accepted blocks AptosDB lost, and re-admits the
+    /// transactions the mempool held on shutdown.
+    async fn recover(&self, state: &state::State) -> Result<(), anyhow::Error> {
+        let committed_block_id = {
+            let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("DB not available"))?.read().await;
+            db.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?.ledger_info().consensus_block_id()
+        };
+
+        if let Some(mut blk) = state.get_accepting_block().await? {
+            let last_accepted = state.get_last_accepted_block_id().await?;
+            if blk.id() != last_accepted && AptosData::from_block(&blk)?.block_id == committed_block_id {
+                log::warn!("completing the acceptance of block {} at height {}", blk.id(), blk.height());
+                blk.set_status(choices::status::Status::Accepted);
+                let mut state_w = state.clone();
+                state_w.write_block(&blk).await?;
+                state.set_block_id_at_height(blk.height(), &blk.id()).await?;
+                state.set_last_accepted_block(&blk.id()).await?;
+                metrics::ACCEPTED_HEIGHT.set(blk.height() as i64);
+                let pruned = state_w.accept_verified(&blk.id()).await;
+                self.discard_blocks(&pruned).await;
+                self.state.write().await.preferred = blk.id();
+            }
+            state.clear_accepting_block().await?;
+        }
+
+        // walk back from the last accepted block to the one AptosDB is at
+        let mut missing: Vec<Block> = Vec::new();
+        let mut blk = state.get_block(&state.get_last_accepted_block_id().await?).await?;
+        while !is_committed_block(&blk, committed_block_id)? {
+            if blk.height() == 0 {
+                return Err(anyhow::anyhow!("AptosDB block {} is not part of the accepted chain", committed_block_id));
+            }
+            let parent_id = blk.parent_id();
+            missing.push(blk);
+            blk = state.get_block(&parent_id).await?;
+        }
+        if !missing.is_empty() {
+            log::warn!("re-committing {} accepted blocks missing from AptosDB", missing.len());
+        }
+        for blk in missing.iter().rev() {
+            self.commit_block(blk).await?;
+        }
+
+        // the transactions stay persisted until they are re-admitted, so that
+        // a node stopped while recovering does not lose them
+        if let Some(d) = state.get_pending_txs().await? {
+            let txs: Vec<SignedTransaction> = bcs::from_bytes(&d).context("Failed to decode pending transactions")?;
+            let mut admitted = 0;
+            for txn in txs {
+                let hash = txn.clone().committed_hash();
+                match self.admit_transaction(txn).await {
+                    Ok(((status, _), _)) if status.code == MempoolStatusCode::Accepted => admitted += 1,
+                    Ok(((status, _), _)) => log::debug!("pending transaction {} not re-admitted: {:?}", hash, status.code),
+                    Err(e) => log::warn!("failed to re-admit pending transaction {}: {}", hash, e),
+                }
+            }
+            log::info!("re-admitted {} pending transactions", admitted);
+            state.clear_pending_txs().await?;
+            if admitted > 0 {
+                self.schedule(Event::TxsAdded(admitted)).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects the health report of the Vm.
+    pub async fn health_report(&self) -> HealthReport {
+        let mut report = HealthReport {
+            bootstrapped: self.is_bootstrapped().await,
+            ..Default::default()
+        };
+        if let Err(e) = self.collect_health(&mut report).await {
+            report.problems.push(e.to_string());
+        }
+        if let Some(started_at) = self.scheduler.build_started_at().await {
+            report.build_in_progress_secs = Some(started_at.elapsed().as_secs());
+        }
+        report.check(HealthLimits {
+            max_block_age_secs: self.config.health_max_block_age_secs,
+            max_build_secs: self.config.build_timeout_secs,
+        })
+    }
+
+    async fn collect_health(&self, report: &mut HealthReport) -> Result<(), anyhow::Error> {
+        let state = self.state.read().await.state.clone().ok_or_else(|| anyhow::anyhow!("State manager not found"))?;
+        if self.executor.is_none() {
+            report.problems.push("Executor not available".to_string());
+        }
+        if let Some(core_mempool) = self.core_mempool.as_ref() {
+            // transactions waiting on an earlier sequence number cannot be built on
+            let capacity = self.config.mempool_capacity as u64;
+            let ready = core_mempool.read().await.get_batch(capacity, u64::MAX, true, true, vec![]);
+            report.mempool_depth = Some(ready.len());
+        } else {
+            report.problems.push("Core mempool not available".to_string());
+        }
+
+        if !state.has_last_accepted_block().await? {
+            return Ok(());
+        }
+        let blk = state.get_block(&state.get_last_accepted_block_id().await?).await?;
+        report.last_accepted_height = Some(blk.height());
+        report.last_accepted_age_secs = Some(get_current_time_seconds().saturating_sub(blk.timestamp()));
+
+        let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("DB not available"))?.read().await;
+        let li = db.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?;
+        report.aptos_version = Some(li.ledger_info().version());
+        report.aptos_in_sync = is_committed_block(&blk, li.ledger_info().consensus_block_id())?;
+        Ok(())
+    }
 
+    /// Persists the transactions held by the mempool, so that they are
+    /// re-admitted on restart.
+    async fn persist_pending_txs(&self) -> Result<(), anyhow::Error> {
+        let state = self.state.read().await.state.clone().ok_or_else(|| anyhow::anyhow!("State manager not found"))?;
+        let txs = self.get_pending_tx(self.config.mempool_capacity as u64).await?;
+        state.set_pending_txs(&bcs::to_bytes(&txs)?).await?;
+        log::info!("persisted {} pending transactions", txs.len());
         Ok(())
     }
 
-    async fn build_block_data(&self) -> Result<Vec<u8>, anyhow::Error> {
+    /// Builds a block on the preferred block out of the pending transactions.
+    async fn build_block_inner(&self) -> io::Result<Block> {
+        let _timer = metrics::BLOCK_BUILD_SECONDS.start_timer();
+        let vm_state = self.state.read().await;
+        if let Some(state_b) = vm_state.state.as_ref() {
+            let prnt_blk = state_b.get_block(&vm_state.preferred).await
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to get parent block: {}", e)))?;
+            // never propose a timestamp before the parent's, even if the local clock went back
+            let unix_now = (Utc::now().timestamp() as u64).max(prnt_blk.timestamp());
+
+            let data = self.build_block_data(&prnt_blk, unix_now).await
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to build block data: {}", e)))?;
+            let mut block_ = Block::new(prnt_blk.id(), prnt_blk.height() + 1, unix_now, data, choices::status::Status::Processing, self.config.block_codec_activation_height)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to create new block: {}", e)))?;
+            log::info!("build_block: block created");
+            block_.set_state(state_b.clone());
+            block_.verify().await
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Block verification failed: {}", e)))?;
+            Ok(block_)
+        } else {
+            Err(io::Error::new(io::ErrorKind::Other, "VM state not initialized"))
+        }
+    }
+
+    /// Builds the Aptos data of the block at `parent.height() + 1` with the
+    /// given consensus timestamp. Everything but the set of transactions is
+    /// derived from the consensus block, so any validator rebuilds the same data.
+    async fn build_block_data(&self, parent: &Block, timestamp: u64) -> Result<Vec<u8>, anyhow::Error> {
 
         log::info!("build_block_data");
-        let unix_now_micro = Utc::now().timestamp_micros() as u64;
         let mut tx_arr: Vec<SignedTransaction> = vec![];
-        log::info!("Sleeping to allow mempool to fill up");
-        sleep(Duration::from_millis(200)).await;
         log::info!("fetching transactions from mempool");
 
-        let txns = self.get_pending_tx(512).await?;
+        // pick the highest paying transactions out of more than fit in a block
+        let max_block_txs = self.config.max_block_txs;
+        let candidates = self.get_pending_tx((max_block_txs * fee_market::CANDIDATE_FACTOR) as u64).await?;
+        let txns = fee_market::prioritize(candidates, max_block_txs);
         for tx in txns {
             log::info!("tx: {:?}", tx.clone().committed_hash());
             tx_arr.push(tx.clone());
         }
 
         log::info!("build_block pool tx count {}", tx_arr.len());
-        let executor = self.executor.as_ref().ok_or_else(|| anyhow::anyhow!("Executor not available"))?.read().await;
         let signer = self.signer.as_ref().ok_or_else(|| anyhow::anyhow!("Signer not available"))?;
         let db = self.db.as_ref().ok_or_else(|| anyhow::anyhow!("DB not available"))?.read().await;
         let latest_ledger_info = db.reader.get_latest_ledger_info().context("Failed to get latest ledger info")?;
         let next_epoch = latest_ledger_info.ledger_info().next_block_epoch();
-        let block_id = HashValue::random();
-        let block_meta = Transaction::BlockMetadata(BlockMetadata::new(block_id, next_epoch, 0, signer.author(), vec![], vec![], unix_now_micro));
+        let parent_data = AptosData::from_block(parent)?;
+        let timestamp_usecs = aptos_timestamp_usecs(timestamp, parent_data.timestamp_usecs);
+        let block_id = aptos_block_id(&parent.id(), parent.height() + 1, timestamp, &tx_arr);
+        let block_meta = Transaction::BlockMetadata(BlockMetadata::new(block_id, next_epoch, 0, signer.author(), vec![], vec![], timestamp_usecs));
     
         let mut txs: Vec<Transaction> = tx_arr.into_iter().map(UserTransaction).collect();
         txs.insert(0, block_meta);
-        txs.push(Transaction::StateCheckpoint(HashValue::random()));
-    
-        let parent_block_id = executor.committed_block_id();
-        let data = AptosData(serde_json::to_vec(&txs)?, block_id, parent_block_id, next_epoch, unix_now_micro);
+        txs.push(Transaction::StateCheckpoint(state_checkpoint_hash(&block_id)));
     
-        serde_json::to_vec(&data).context("Failed to serialize block data")
+        let data = AptosData {
+            transactions: txs,
+            block_id,
+            parent_block_id: parent_data.block_id,
+            epoch: next_epoch,
+            timestamp_usecs,
+        };
+        data.to_vec_with_codec(BlockCodec::at_height(parent.height() + 1, self.config.block_codec_activation_height))
     }
     
 
@@ -1100,24 +1922,75 @@ impl Vm {
 impl BatchedChainVm for Vm {
     type Block = Block;
 
+    /// Returns the block with id `block_id` followed by its ancestors, walking
+    /// back through `parent_id` until any of the count, size or time budgets
+    /// is exhausted. Mirrors avalanchego's `block.GetAncestors`.
     async fn get_ancestors(
         &self,
-        _block_id: ids::Id,
-        _max_block_num: i32,
-        _max_block_size: i32,
-        _max_block_retrival_time: Duration,
+        block_id: ids::Id,
+        max_block_num: i32,
+        max_block_size: i32,
+        max_block_retrival_time: Duration,
     ) -> io::Result<Vec<Bytes>> {
-        Err(Error::new(
-            ErrorKind::Unsupported,
-            "get_ancestors not implemented",
-        ))
+        let started = Instant::now();
+        let vm_state = self.state.read().await;
+        let state = vm_state
+            .state
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "state manager not found"))?;
+
+        let max_block_num = max_block_num.max(0) as usize;
+        let max_block_size = max_block_size.max(0) as usize;
+
+        // an unknown block is not an error, there is simply nothing to serve
+        let mut blk = match state.get_block(&block_id).await {
+            Ok(blk) => blk,
+            Err(e) if subnet::rpc::errors::is_not_found(&e) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        // the requested block is always returned, the budgets only apply to its ancestors
+        let mut ancestors = Vec::with_capacity(max_block_num.min(1024).max(1));
+        let mut ancestors_size = blk.bytes().len() + ANCESTOR_LEN_PREFIX;
+        ancestors.push(Bytes::copy_from_slice(blk.bytes()));
+
+        while ancestors.len() < max_block_num && started.elapsed() < max_block_retrival_time {
+            if blk.height() == 0 {
+                break;
+            }
+            blk = match state.get_block(&blk.parent_id()).await {
+                Ok(parent) => parent,
+                Err(e) => {
+                    log::debug!("get_ancestors: stopping at missing parent {}: {}", blk.parent_id(), e);
+                    break;
+                },
+            };
+
+            let size = ancestors_size + blk.bytes().len() + ANCESTOR_LEN_PREFIX;
+            if size > max_block_size {
+                break;
+            }
+            ancestors.push(Bytes::copy_from_slice(blk.bytes()));
+            ancestors_size = size;
+        }
+
+        log::debug!(
+            "get_ancestors: returning {} blocks ({} bytes) for {}",
+            ancestors.len(),
+            ancestors_size,
+            block_id
+        );
+        Ok(ancestors)
     }
 
-    async fn batched_parse_block(&self, _blocks: &[Vec<u8>]) -> io::Result<Vec<Self::Block>> {
-        Err(Error::new(
-            ErrorKind::Unsupported,
-            "batched_parse_block not implemented",
-        ))
+    /// Parses each block with the same semantics as [`Parser::parse_block`],
+    /// failing the whole batch if any block fails to parse.
+    async fn batched_parse_block(&self, blocks: &[Vec<u8>]) -> io::Result<Vec<Self::Block>> {
+        let mut parsed = Vec::with_capacity(blocks.len());
+        for bytes in blocks {
+            parsed.push(Parser::parse_block(self, bytes).await?);
+        }
+        Ok(parsed)
     }
 }
 
@@ -1126,24 +1999,9 @@ impl ChainVm for Vm {
     type Block = Block;
 
     async fn build_block(&self) -> io::Result<<Self as ChainVm>::Block> {
-        let vm_state = self.state.read().await;
-        if let Some(state_b) = vm_state.state.as_ref() {
-            let prnt_blk = state_b.get_block(&vm_state.preferred).await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to get parent block: {}", e)))?;
-            let unix_now = Utc::now().timestamp() as u64;
-
-            let data = self.build_block_data().await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to build block data: {}", e)))?;
-            let mut block_ = Block::new(prnt_blk.id(), prnt_blk.height() + 1, unix_now, data, choices::status::Status::Processing)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to create new block: {}", e)))?;
-            log::info!("build_block: block created");
-            block_.set_state(state_b.clone());
-            block_.verify().await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Block verification failed: {}", e)))?;
-            Ok(block_)
-        } else {
-            Err(io::Error::new(io::ErrorKind::Other, "VM state not initialized"))
-        }
+        let built = self.build_block_inner().await;
+        self.schedule(if built.is_ok() { Event::BlockBuilt } else { Event::BuildFailed }).await;
+        built
     }
 
     async fn issue_tx(&self) ->  io::Result<<Self as ChainVm>::Block> {
@@ -1162,14 +2020,28 @@ impl ChainVm for Vm {
         )
     }
 
+    /// Backfills the height index for accepted blocks written before it existed.
     async fn verify_height_index(&self) -> io::Result<()> {
-        Ok(())
+        let vm_state = self.state.read().await;
+        let state = vm_state
+            .state
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "state manager not found"))?;
+        state.repair_height_index().await.map(|_| ())
     }
 
-    async fn get_block_id_at_height(&self, _height: u64) -> io::Result<ids::Id> {
-        Err(io::Error::new(io::ErrorKind::NotFound, "block id not found"))
+    async fn get_block_id_at_height(&self, height: u64) -> io::Result<ids::Id> {
+        let vm_state = self.state.read().await;
+        let state = vm_state
+            .state
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "state manager not found"))?;
+        state.get_block_id_at_height(height).await
     }
 
+    /// The Vm does not serve state summaries to the engine, so that the engine
+    /// never state syncs. The Vm syncs from its peers on its own when it
+    /// starts bootstrapping instead, see `state_sync_enabled` in [`VmConfig`].
     async fn state_sync_enabled(&self) -> io::Result<bool> {
         Ok(false)
     }
@@ -1177,25 +2049,45 @@ impl ChainVm for Vm {
 
 #[tonic::async_trait]
 impl NetworkAppHandler for Vm {
-    async fn app_request(&self, _node_id: &ids::node::Id, _request_id: u32, _deadline: DateTime<Utc>, _request: &[u8]) -> io::Result<()> {
-        Ok(())
+    async fn app_request(&self, node_id: &ids::node::Id, request_id: u32, _deadline: DateTime<Utc>, request: &[u8]) -> io::Result<()> {
+        let req = match StateSyncRequest::from_slice(request) {
+            Ok(req) => req,
+            Err(e) => {
+                log::warn!("dropping malformed app request {} from {}: {}", request_id, node_id, e);
+                return Ok(());
+            },
+        };
+        let response = self.serve_state_sync(req).await;
+        let sender = self.app_sender.as_ref().ok_or_else(
+            || io::Error::new(io::ErrorKind::Other, "App sender not available")
+        )?;
+        sender.send_app_response(*node_id, request_id, response.to_vec()?).await
     }
 
-    async fn app_request_failed(&self, _node_id: &ids::node::Id, _request_id: u32) -> io::Result<()> {
+    async fn app_request_failed(&self, _node_id: &ids::node::Id, request_id: u32) -> io::Result<()> {
+        self.state_sync.on_request_failed(request_id).await;
         Ok(())
     }
 
-    async fn app_response(&self, _node_id: &ids::node::Id, _request_id: u32, _response: &[u8]) -> io::Result<()> {
+    async fn app_response(&self, node_id: &ids::node::Id, request_id: u32, response: &[u8]) -> io::Result<()> {
+        if !self.state_sync.on_response(request_id, response).await {
+            log::debug!("dropping unexpected app response {} from {}", request_id, node_id);
+        }
         Ok(())
     }
 
     async fn app_gossip(&self, node_id: &ids::node::Id, msg: &[u8]) -> io::Result<()> {
-        log::info!("Received gossip message from: {}", node_id);
-        if let Ok(s) = serde_json::from_slice::<SignedTransaction>(msg) {
-            self.add_pool(s).await.map_err(
-                |e| io::Error::new(io::ErrorKind::Other, format!("Failed to add transaction to pool: {}", e))
-            )?;
-        }
+        let txns = match decode_tx_gossip(msg) {
+            Ok(txns) => txns,
+            Err(e) => {
+                log::debug!("dropping gossip message from {}: {}", node_id, e);
+                return Ok(());
+            },
+        };
+        log::debug!("received {} gossiped transactions from {}", txns.len(), node_id);
+        self.admit_and_gossip(txns).await.map_err(
+            |e| io::Error::new(io::ErrorKind::Other, format!("Failed to admit gossiped transactions: {}", e))
+        )?;
         Ok(())
     }
 }
@@ -1219,19 +2111,28 @@ impl AppHandler for Vm {}
 
 #[tonic::async_trait]
 impl Connector for Vm {
-    async fn connected(&self, _id: &ids::node::Id) -> io::Result<()> {
+    async fn connected(&self, id: &ids::node::Id) -> io::Result<()> {
+        self.peers.write().await.insert(*id);
         Ok(())
     }
 
-    async fn disconnected(&self, _id: &ids::node::Id) -> io::Result<()> {
+    async fn disconnected(&self, id: &ids::node::Id) -> io::Result<()> {
+        self.peers.write().await.remove(id);
         Ok(())
     }
 }
 
 #[tonic::async_trait]
 impl Checkable for Vm {
+    /// Returns the JSON health report, or an error carrying it if the node is
+    /// unhealthy.
     async fn health_check(&self) -> io::Result<Vec<u8>> {
-        Ok("200".as_bytes().to_vec())
+        let report = self.health_report().await;
+        let d = serde_json::to_vec(&report)?;
+        if report.healthy {
+            return Ok(d);
+        }
+        Err(io::Error::new(io::ErrorKind::Other, String::from_utf8_lossy(&d).to_string()))
     }
 }
 
@@ -1283,7 +2184,7 @@ impl Parser for Vm {
 impl CommonVm for Vm {
     type DatabaseManager = DatabaseManager;
     type AppSender = AppSenderClient;
-    type ChainHandler = ChainHandler<ChainService>;
+    type ChainHandler = ChainApiHandler;
     type StaticHandler = StaticHandler;
     type ValidatorState = ValidatorStateClient;
     
@@ -1291,15 +2192,21 @@ impl CommonVm for Vm {
         &mut self,
         ctx: Option<subnet::rpc::context::Context<Self::ValidatorState>>,
         db_manager: Self::DatabaseManager,
-        _genesis_bytes: &[u8],
+        genesis_bytes: &[u8],
         _upgrade_bytes: &[u8],
-        _config_bytes: &[u8],
+        config_bytes: &[u8],
         to_engine: Sender<snow::engine::common::message::Message>,
         _fxs: &[snow::engine::common::vm::Fx],
         app_sender: Self::AppSender,
     ) -> io::Result<()> {
-        let uuid = std::env::var("M1_ID").unwrap_or(uuid::Uuid::new_v4().to_string());
-        log::info!("Initializing M1 Vm {}", uuid);
+        let genesis = Genesis::from_slice(genesis_bytes)?;
+        self.config = VmConfig::from_slice(config_bytes)?;
+        self.feed = Feed::new(self.config.feed_buffer_blocks);
+        self.fee_market = self.config.fee_market();
+        self.scheduler = BuildScheduler::new(self.config.scheduler());
+        let chain_data_dir = ctx.as_ref().map(|c| c.chain_data_dir.clone()).unwrap_or_default();
+        let db_dir = self.config.db_dir(&chain_data_dir)?;
+        log::info!("Initializing M1 Vm with AptosDB at {}", db_dir.display());
 
         let state = {
             let mut vm_state = self.state.write().await;
@@ -1308,46 +2215,23 @@ impl CommonVm for Vm {
             let current = db_manager.current().await.map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to get current DB manager: {}", e)))?;
             let state = state::State {
                 db: Arc::new(RwLock::new(current.db)),
-                verified_blocks: Arc::new(RwLock::new(HashMap::new())),
+                verified_blocks: Arc::new(RwLock::new(state::BlockTree::new())),
                 vm: None,
+                block_codec_activation_height: self.config.block_codec_activation_height,
             };
             vm_state.state = Some(state.clone());
             self.to_engine = Some(Arc::new(RwLock::new(to_engine)));
-            self.app_sender = Some(app_sender);
+            self.app_sender = Some(Box::new(app_sender));
             state
         };
        
-        if let Err(e) = self.init_aptos(&uuid).await {
+        if let Err(e) = self.init_aptos(&db_dir, &genesis).await {
             return Err(io::Error::new(io::ErrorKind::Other, format!("Failed to initialize Aptos: {}", e)));
         }
 
-        let mut vm_state = self.state.write().await;
-        let genesis = "hello world";
-        let has_last_accepted = state.has_last_accepted_block().await?;
-        if has_last_accepted {
-            let last_accepted_blk_id = state.get_last_accepted_block_id().await?;
-            vm_state.preferred = last_accepted_blk_id;
-        } else {
-            let genesis_bytes = genesis.as_bytes().to_vec();
-            let data = AptosData(
-                genesis_bytes.clone(),
-                HashValue::zero(),
-                HashValue::zero(),
-                0,
-                0,
-            );
-            let mut genesis_block = Block::new(
-                ids::Id::empty(),
-                0,
-                0,
-                serde_json::to_vec(&data)?,
-                choices::status::Status::default(),
-            )?;
-            genesis_block.set_state(state.clone());
-            genesis_block.accept().await?;
-
-            let genesis_blk_id = genesis_block.id();
-            vm_state.preferred = genesis_blk_id;
+        self.init_last_accepted(&state, genesis::document_hash(genesis_bytes)).await?;
+        if let Err(e) = self.recover(&state).await {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("Failed to recover: {}", e)));
         }
         log::info!("successfully initialized Vm");
 
@@ -1362,8 +2246,22 @@ impl CommonVm for Vm {
         )
     }
 
-    /// Called when the node is shutting down.
+    /// Called when the node is shutting down. Stops the background tasks,
+    /// persists the mempool and waits for an in-flight AptosDB commit, whose
+    /// writes are synced by the time it returns.
     async fn shutdown(&self) -> io::Result<()> {
+        log::info!("shutting down Vm");
+        for task in self.tasks.lock().await.drain(..) {
+            task.abort();
+        }
+        if self.core_mempool.is_some() {
+            if let Err(e) = self.persist_pending_txs().await {
+                log::error!("failed to persist pending transactions: {}", e);
+            }
+        }
+        if let Some(executor) = self.executor.as_ref() {
+            let _executor = executor.write().await;
+        }
         Ok(())
     }
 
@@ -1397,11 +2295,47 @@ impl CommonVm for Vm {
             "/rpc".to_string(),
             HttpHandler {
                 lock_option: LockOptions::WriteLock,
-                handler,
+                handler: ChainApiHandler::Rpc(handler),
                 server_addr: None,
             },
         );
 
+        handlers.insert(
+            SUBSCRIPTION_EXTENSION.to_string(),
+            HttpHandler {
+                lock_option: LockOptions::NoLock,
+                handler: ChainApiHandler::Subscription(SubscriptionHandler::new(
+                    SubscriptionService::new(self.feed.clone()),
+                )),
+                server_addr: None,
+            },
+        );
+
+        handlers.insert(
+            METRICS_EXTENSION.to_string(),
+            HttpHandler {
+                lock_option: LockOptions::NoLock,
+                handler: ChainApiHandler::Metrics(MetricsHandler::new()),
+                server_addr: None,
+            },
+        );
+
+        // the REST API only reads through the Aptos context, and submits
+        // through the mempool client, so it does not need the Vm lock
+        if let Some(context) = self.api_context.clone() {
+            let handler = AptosRestHandler::new(context);
+            let (server_addr, task) = handler.start_server().await?;
+            self.tasks.lock().await.push(task);
+            handlers.insert(
+                REST_EXTENSION.to_string(),
+                HttpHandler {
+                    lock_option: LockOptions::NoLock,
+                    handler: ChainApiHandler::Rest(handler),
+                    server_addr: Some(server_addr.to_string()),
+                },
+            );
+        }
+
         Ok(handlers)
     }
 }
@@ -1412,3 +2346,897 @@ fn get_current_time_seconds() -> u64 {
         .expect("Failed to get timestamp")
         .as_secs()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_crypto::{bls12381, ed25519::Ed25519PrivateKey, Uniform, ValidCryptoMaterialStringExt};
+    use crate::genesis::GenesisValidator;
+    use crate::subscription::{FeedFilter, FeedStream};
+    use crate::scheduler::BuildState;
+
+    /// Returns a Vm whose state manager is backed by an in-memory database.
+    async fn new_test_vm() -> (Vm, state::State) {
+        let vm = Vm::new();
+        let state = state::State::default();
+        vm.state.write().await.state = Some(state.clone());
+        (vm, state)
+    }
+
+    /// Writes a chain of `len` accepted blocks, starting from genesis.
+    async fn write_chain(state: &mut state::State, len: u64) -> Vec<Block> {
+        let blocks = crate::block::test_chain(len, choices::status::Status::Accepted);
+        for blk in blocks.iter() {
+            state.write_block(blk).await.unwrap();
+        }
+        blocks
+    }
+
+    #[tokio::test]
+    async fn test_get_ancestors_walks_back_to_genesis() {
+        let (vm, mut state) = new_test_vm().await;
+        let blocks = write_chain(&mut state, 10).await;
+        let tip = blocks.last().unwrap();
+
+        let ancestors = vm
+            .get_ancestors(tip.id(), 100, i32::MAX, Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        assert_eq!(ancestors.len(), blocks.len());
+        for (bytes, blk) in ancestors.iter().zip(blocks.iter().rev()) {
+            assert_eq!(bytes.as_ref(), blk.bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_ancestors_respects_budgets() {
+        let (vm, mut state) = new_test_vm().await;
+        let blocks = write_chain(&mut state, 10).await;
+        let tip = blocks.last().unwrap();
+
+        let ancestors = vm
+            .get_ancestors(tip.id(), 3, i32::MAX, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(ancestors.len(), 3);
+        assert_eq!(ancestors[2].as_ref(), blocks[7].bytes());
+
+        // room for exactly two blocks, including their length prefixes
+        let max_size = (tip.bytes().len() + blocks[8].bytes().len() + 2 * ANCESTOR_LEN_PREFIX) as i32;
+        let ancestors = vm
+            .get_ancestors(tip.id(), 100, max_size, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(ancestors.len(), 2);
+
+        // the requested block is returned even if it alone exceeds the size budget
+        let ancestors = vm
+            .get_ancestors(tip.id(), 100, 1, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(ancestors.len(), 1);
+        assert_eq!(ancestors[0].as_ref(), tip.bytes());
+    }
+
+    #[tokio::test]
+    async fn test_get_ancestors_unknown_block() {
+        let (vm, mut state) = new_test_vm().await;
+        write_chain(&mut state, 3).await;
+
+        let ancestors = vm
+            .get_ancestors(ids::Id::sha256(b"unknown"), 100, i32::MAX, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(ancestors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batched_parse_block() {
+        let (vm, mut state) = new_test_vm().await;
+        let blocks = write_chain(&mut state, 5).await;
+
+        let unknown = Block::new(
+            blocks[4].id(),
+            5,
+            5,
+            vec![5; 64],
+            choices::status::Status::Processing,
+            None,
+        )
+        .unwrap();
+
+        let mut raw: Vec<Vec<u8>> = blocks.iter().map(|b| b.bytes().to_vec()).collect();
+        raw.push(unknown.bytes().to_vec());
+
+        let parsed = vm.batched_parse_block(&raw).await.unwrap();
+        assert_eq!(parsed.len(), raw.len());
+        for (p, b) in parsed.iter().zip(blocks.iter()) {
+            assert_eq!(p.id(), b.id());
+            assert_eq!(p.status(), choices::status::Status::Accepted);
+        }
+        assert_eq!(parsed[5].id(), unknown.id());
+        assert_eq!(parsed[5].status(), choices::status::Status::Processing);
+
+        raw.push(b"not a block".to_vec());
+        assert!(vm.batched_parse_block(&raw).await.is_err());
+    }
+
+    /// Delivers app requests, responses and gossip straight to another in-process Vm.
+    #[derive(Clone)]
+    struct LoopbackAppSender {
+        node_id: ids::node::Id,
+        peer: Arc<RwLock<Option<Vm>>>,
+    }
+
+    #[tonic::async_trait]
+    impl AppSender for LoopbackAppSender {
+        async fn send_app_request(&self, _node_ids: ids::node::Set, request_id: u32, request: Vec<u8>) -> io::Result<()> {
+            let peer = self.peer.read().await.clone().expect("peer not connected");
+            let node_id = self.node_id;
+            tokio::spawn(async move {
+                peer.app_request(&node_id, request_id, Utc::now(), &request).await.unwrap();
+            });
+            Ok(())
+        }
+
+        async fn send_app_response(&self, _node_id: ids::node::Id, request_id: u32, response: Vec<u8>) -> io::Result<()> {
+            let peer = self.peer.read().await.clone().expect("peer not connected");
+            let node_id = self.node_id;
+            tokio::spawn(async move {
+                peer.app_response(&node_id, request_id, &response).await.unwrap();
+            });
+            Ok(())
+        }
+
+        async fn send_app_gossip(&self, msg: Vec<u8>) -> io::Result<()> {
+            // delivered inline, so gossip has reached the peer when submission returns
+            let peer = self.peer.read().await.clone();
+            if let Some(peer) = peer {
+                peer.app_gossip(&self.node_id, &msg).await?;
+            }
+            Ok(())
+        }
+
+        async fn send_app_gossip_specific(&self, _node_ids: ids::node::Set, _msg: Vec<u8>) -> io::Result<()> {
+            Ok(())
+        }
+
+        async fn send_cross_chain_app_request(&self, _chain_id: ids::Id, _request_id: u32, _app_request_bytes: Vec<u8>) -> io::Result<()> {
+            Ok(())
+        }
+
+        async fn send_cross_chain_app_response(&self, _chain_id: ids::Id, _request_id: u32, _app_response_bytes: Vec<u8>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns a Vm with Aptos initialized from the test genesis and its
+    /// genesis block accepted, sending app messages through `app_sender`.
+    async fn new_aptos_test_vm(app_sender: LoopbackAppSender) -> Vm {
+        new_aptos_test_vm_with_genesis(app_sender, &Genesis::default()).await
+    }
+
+    async fn new_aptos_test_vm_with_genesis(app_sender: LoopbackAppSender, genesis: &Genesis) -> Vm {
+        new_aptos_test_vm_with_config(app_sender, genesis, VmConfig::default()).await
+    }
+
+    async fn new_aptos_test_vm_with_config(app_sender: LoopbackAppSender, genesis: &Genesis, config: VmConfig) -> Vm {
+        let mut vm = Vm::new();
+        vm.scheduler = BuildScheduler::new(config.scheduler());
+        let state = state::State {
+            block_codec_activation_height: config.block_codec_activation_height,
+            ..state::State::default()
+        };
+        vm.config = config;
+        vm.state.write().await.state = Some(state.clone());
+        vm.app_sender = Some(Box::new(app_sender));
+        let db_dir = std::env::temp_dir().join(format!("m1-test-{}", uuid::Uuid::new_v4()));
+        vm.init_aptos(&db_dir, genesis).await.unwrap();
+        let genesis_hash = genesis::document_hash(genesis.to_vec().unwrap());
+        vm.init_last_accepted(&state, genesis_hash).await.unwrap();
+        vm
+    }
+
+    /// Builds and accepts a block on top of the preferred block.
+    async fn build_and_accept(vm: &Vm) -> Block {
+        let built = ChainVm::build_block(vm).await.unwrap();
+        let mut blk = Getter::get_block(vm, built.id()).await.unwrap();
+        blk.accept().await.unwrap();
+        ChainVm::set_preference(vm, blk.id()).await.unwrap();
+        blk
+    }
+
+    #[tokio::test]
+    async fn test_state_sync_between_vms() {
+        let id_a = ids::node::Id::from_slice(&[1; ids::node::LEN]);
+        let id_b = ids::node::Id::from_slice(&[2; ids::node::LEN]);
+        let peer_of_a = Arc::new(RwLock::new(None));
+        let peer_of_b = Arc::new(RwLock::new(None));
+
+        let vm_a = new_aptos_test_vm(LoopbackAppSender { node_id: id_a, peer: peer_of_a.clone() }).await;
+        let mut vm_b = new_aptos_test_vm(LoopbackAppSender { node_id: id_b, peer: peer_of_b.clone() }).await;
+        vm_b.config.state_sync_min_blocks_behind = 1;
+        vm_b.config.state_sync_min_peers = 1;
+        *peer_of_a.write().await = Some(vm_b.clone());
+        *peer_of_b.write().await = Some(vm_a.clone());
+        vm_b.connected(&id_a).await.unwrap();
+
+        let mut tip = None;
+        for _ in 0..3 {
+            tip = Some(build_and_accept(&vm_a).await);
+        }
+        let tip = tip.unwrap();
+
+        vm_b.state_sync().await.unwrap();
+
+        assert_eq!(ChainVm::last_accepted(&vm_b).await.unwrap(), tip.id());
+        assert_eq!(ChainVm::get_block_id_at_height(&vm_b, tip.height()).await.unwrap(), tip.id());
+        let li_a = vm_a.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        let li_b = vm_b.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        assert_eq!(li_a.ledger_info(), li_b.ledger_info());
+        assert!(vm_b.view_account(aptos_test_root_address().to_vec()).await.unwrap().is_some());
+
+        // the synced node keeps building on top of the synced block
+        let next = build_and_accept(&vm_b).await;
+        assert_eq!(next.parent_id(), tip.id());
+        assert_eq!(next.height(), tip.height() + 1);
+    }
+
+    #[tokio::test]
+    async fn test_state_sync_needs_summary_from_enough_peers() {
+        let id_a = ids::node::Id::from_slice(&[1; ids::node::LEN]);
+        let id_b = ids::node::Id::from_slice(&[2; ids::node::LEN]);
+        let peer_of_a = Arc::new(RwLock::new(None));
+        let peer_of_b = Arc::new(RwLock::new(None));
+
+        let vm_a = new_aptos_test_vm(LoopbackAppSender { node_id: id_a, peer: peer_of_a.clone() }).await;
+        let mut vm_b = new_aptos_test_vm(LoopbackAppSender { node_id: id_b, peer: peer_of_b.clone() }).await;
+        vm_b.config.state_sync_min_blocks_behind = 1;
+        *peer_of_a.write().await = Some(vm_b.clone());
+        *peer_of_b.write().await = Some(vm_a.clone());
+        vm_b.connected(&id_a).await.unwrap();
+        for _ in 0..3 {
+            build_and_accept(&vm_a).await;
+        }
+        let genesis_id = ChainVm::get_block_id_at_height(&vm_b, 0).await.unwrap();
+
+        // a single peer is not trusted with the state
+        vm_b.state_sync().await.unwrap();
+        assert_eq!(ChainVm::last_accepted(&vm_b).await.unwrap(), genesis_id);
+        assert!(!ChainVm::state_sync_enabled(&vm_b).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_state_summary_must_be_signed_by_validators() {
+        let vm_a = new_standalone_aptos_test_vm().await;
+        let vm_b = new_standalone_aptos_test_vm().await;
+        build_and_accept(&vm_a).await;
+        let mut summary = match vm_a.serve_state_sync(StateSyncRequest::LastSummary).await {
+            StateSyncResponse::Summary(Some(summary)) => summary,
+            other => panic!("unexpected response {:?}", other),
+        };
+        let epoch_state = vm_b.db.as_ref().unwrap().read().await.reader.get_latest_epoch_state().unwrap();
+        assert!(state_sync::verify_summary(&summary, &epoch_state).is_ok());
+
+        // the same ledger info, signed by a validator outside of the set
+        summary.ledger_info = generate_ledger_info_with_sig(
+            &[ValidatorSigner::random(None)],
+            summary.ledger_info.ledger_info().clone(),
+        );
+        assert!(state_sync::verify_summary(&summary, &epoch_state).is_err());
+    }
+
+    /// Returns a standalone Vm with Aptos initialized and no peers.
+    async fn new_standalone_aptos_test_vm() -> Vm {
+        new_aptos_test_vm(LoopbackAppSender {
+            node_id: ids::node::Id::from_slice(&[0; ids::node::LEN]),
+            peer: Arc::new(RwLock::new(None)),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_blocks_below_codec_activation_height_are_json() {
+        let config = VmConfig { block_codec_activation_height: Some(2), ..VmConfig::default() };
+        let app_sender = LoopbackAppSender {
+            node_id: ids::node::Id::from_slice(&[0; ids::node::LEN]),
+            peer: Arc::new(RwLock::new(None)),
+        };
+        let vm = new_aptos_test_vm_with_config(app_sender, &Genesis::default(), config).await;
+        let genesis_id = ChainVm::get_block_id_at_height(&vm, 0).await.unwrap();
+        let genesis = Getter::get_block(&vm, genesis_id).await.unwrap();
+        // the genesis block of older nodes
+        let legacy_data = serde_json::to_vec(&LegacyAptosData(
+            b"hello world".to_vec(),
+            HashValue::zero(),
+            HashValue::zero(),
+            0,
+            0,
+        ))
+        .unwrap();
+        assert_eq!(genesis.codec(), BlockCodec::Json);
+        assert_eq!(genesis.data(), &legacy_data[..]);
+
+        let first = build_and_accept(&vm).await;
+        assert_eq!(first.codec(), BlockCodec::Json);
+        assert_eq!(first.data()[0], b'[');
+
+        // a block at the activation height is only valid in the binary codec
+        let built = ChainVm::build_block(&vm).await.unwrap();
+        let json = Block::new_with_codec(
+            built.parent_id(),
+            built.height(),
+            built.timestamp(),
+            AptosData::from_block(&built).unwrap().to_vec_with_codec(BlockCodec::Json).unwrap(),
+            choices::status::Status::Processing,
+            BlockCodec::Json,
+        )
+        .unwrap();
+        let mut blk = Parser::parse_block(&vm, json.bytes()).await.unwrap();
+        assert!(blk.verify().await.is_err());
+
+        let second = build_and_accept(&vm).await;
+        assert_eq!(second.codec(), BlockCodec::Binary);
+        assert_eq!(second.data()[0], APTOS_DATA_CODEC_VERSION);
+        assert_eq!(
+            AptosData::from_block(&second).unwrap().parent_block_id,
+            AptosData::from_block(&first).unwrap().block_id
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_reexecutes_identically_on_fresh_vms() {
+        let vm_a = new_standalone_aptos_test_vm().await;
+        let vm_b = new_standalone_aptos_test_vm().await;
+
+        let to = AccountAddress::random();
+        vm_a.create_account(to.to_vec(), AcceptType::Json).await.unwrap();
+        let built = ChainVm::build_block(&vm_a).await.unwrap();
+        let aptos_data = AptosData::from_block(&built).unwrap();
+        assert_eq!(aptos_data.transactions.len(), 3);
+
+        // the block travels to the other validator as bytes only
+        let bytes = built.bytes().to_vec();
+        let mut blk_a = Getter::get_block(&vm_a, built.id()).await.unwrap();
+        let mut blk_b = Parser::parse_block(&vm_b, &bytes).await.unwrap();
+        assert_eq!(blk_a.id(), blk_b.id());
+        blk_a.accept().await.unwrap();
+        blk_b.accept().await.unwrap();
+
+        let li_a = vm_a.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        let li_b = vm_b.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        assert_eq!(li_a.ledger_info().consensus_block_id(), aptos_data.block_id);
+        assert_eq!(li_a.ledger_info().commit_info(), li_b.ledger_info().commit_info());
+        assert_eq!(
+            li_a.ledger_info().transaction_accumulator_hash(),
+            li_b.ledger_info().transaction_accumulator_hash()
+        );
+        assert!(vm_b.view_account(to.to_vec()).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_execute_rejects_tampered_block_data() {
+        let vm = new_standalone_aptos_test_vm().await;
+        let built = ChainVm::build_block(&vm).await.unwrap();
+        let mut aptos_data = AptosData::from_block(&built).unwrap();
+
+        // swap the trailing checkpoint for one the consensus block does not commit to
+        aptos_data.transactions.pop();
+        aptos_data.transactions.push(Transaction::StateCheckpoint(HashValue::random()));
+
+        let tampered = Block::new(
+            built.parent_id(),
+            built.height(),
+            built.timestamp(),
+            aptos_data.to_vec_with_codec(built.codec()).unwrap(),
+            choices::status::Status::Processing,
+            vm.config.block_codec_activation_height,
+        )
+        .unwrap();
+        let mut blk = Parser::parse_block(&vm, tampered.bytes()).await.unwrap();
+        assert!(blk.verify().await.is_err());
+        assert!(!vm.executed_blocks.read().await.contains_key(&blk.id()));
+        assert!(blk.accept().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify_executes_and_accept_commits() {
+        let vm = new_standalone_aptos_test_vm().await;
+        let version_before = vm.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap().ledger_info().version();
+
+        let built = ChainVm::build_block(&vm).await.unwrap();
+        let executed = vm.executed_blocks.read().await.get(&built.id()).cloned().unwrap();
+        assert_eq!(executed.consensus_block_id(), AptosData::from_block(&built).unwrap().block_id);
+
+        // nothing is committed until the block is accepted
+        let li = vm.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        assert_eq!(li.ledger_info().version(), version_before);
+
+        let mut blk = Getter::get_block(&vm, built.id()).await.unwrap();
+        blk.accept().await.unwrap();
+        let li = vm.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        assert_eq!(li.ledger_info().commit_info(), executed.commit_info());
+        assert!(vm.executed_blocks.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_accept_publishes_to_feed() {
+        let vm = new_standalone_aptos_test_vm().await;
+        let from_version = vm.feed.oldest_version().await;
+
+        vm.create_account(AccountAddress::random().to_vec(), AcceptType::Json).await.unwrap();
+        let built = ChainVm::build_block(&vm).await.unwrap();
+        let mut blk = Getter::get_block(&vm, built.id()).await.unwrap();
+        blk.accept().await.unwrap();
+        let li = vm.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+
+        let ret = vm.feed.poll(&FeedFilter::default(), from_version, 10, Duration::ZERO).await.unwrap();
+        assert_eq!(ret.blocks.len(), 1);
+        let feed_block = &ret.blocks[0];
+        assert_eq!(feed_block.block_id, built.id().to_string());
+        assert_eq!(feed_block.first_version, from_version);
+        assert_eq!(feed_block.last_version, li.ledger_info().version());
+        // block metadata, the user transaction and the state checkpoint
+        assert_eq!(feed_block.transactions.len(), 3);
+        assert!(feed_block.transactions.iter().all(|t| t.success));
+        assert_eq!(ret.next_version, li.ledger_info().version() + 1);
+
+        let filter = FeedFilter {
+            stream: FeedStream::Events,
+            event_types: vec!["0x1::block::NewBlockEvent".to_string()],
+        };
+        let ret = vm.feed.poll(&filter, from_version, 10, Duration::ZERO).await.unwrap();
+        assert_eq!(ret.blocks[0].transactions.len(), 1);
+        assert_eq!(ret.blocks[0].transactions[0].version, from_version);
+    }
+
+    #[tokio::test]
+    async fn test_accepted_blocks_drive_gas_estimate() {
+        let mut vm = new_standalone_aptos_test_vm().await;
+        vm.config.max_block_txs = 1;
+        vm.fee_market = FeeMarket::new(10, 1, 50);
+        let gas_estimate = |res: ApiResponse| match res.content {
+            ApiContent::Json(v) => v["gas_estimate"].as_u64().unwrap(),
+            ApiContent::Bcs(_) => panic!("unexpected BCS content"),
+        };
+        assert_eq!(gas_estimate(vm.estimate_gas_price().await.unwrap()), 1);
+
+        // a full block of faucet transactions paying the default price
+        vm.create_account(AccountAddress::random().to_vec(), AcceptType::Json).await.unwrap();
+        build_and_accept(&vm).await;
+        assert_eq!(gas_estimate(vm.estimate_gas_price().await.unwrap()), 100);
+    }
+
+    #[tokio::test]
+    async fn test_recover_completes_interrupted_accept() {
+        let vm = new_standalone_aptos_test_vm().await;
+        let state = vm.state.read().await.state.clone().unwrap();
+        let genesis_id = ChainVm::last_accepted(&vm).await.unwrap();
+
+        // stopped right after the AptosDB commit
+        let to = AccountAddress::random();
+        vm.create_account(to.to_vec(), AcceptType::Json).await.unwrap();
+        let built = ChainVm::build_block(&vm).await.unwrap();
+        state.set_accepting_block(&built).await.unwrap();
+        vm.commit_block(&built).await.unwrap();
+        assert_eq!(ChainVm::last_accepted(&vm).await.unwrap(), genesis_id);
+
+        vm.recover(&state).await.unwrap();
+        assert_eq!(ChainVm::last_accepted(&vm).await.unwrap(), built.id());
+        assert_eq!(state.get_block_id_at_height(built.height()).await.unwrap(), built.id());
+        assert!(state.get_accepting_block().await.unwrap().is_none());
+        assert!(vm.view_account(to.to_vec()).await.unwrap().is_some());
+
+        // the chain keeps growing on the recovered block
+        let next = build_and_accept(&vm).await;
+        assert_eq!(next.parent_id(), built.id());
+    }
+
+    #[tokio::test]
+    async fn test_recover_recommits_blocks_missing_from_aptos_db() {
+        let vm_a = new_standalone_aptos_test_vm().await;
+        let vm_b = new_standalone_aptos_test_vm().await;
+
+        let to = AccountAddress::random();
+        vm_a.create_account(to.to_vec(), AcceptType::Json).await.unwrap();
+        let blocks = vec![build_and_accept(&vm_a).await, build_and_accept(&vm_a).await];
+
+        // vm_b's consensus state has the blocks accepted, but its AptosDB does not
+        let mut state_b = vm_b.state.read().await.state.clone().unwrap();
+        for blk in blocks.iter() {
+            state_b.write_block(blk).await.unwrap();
+            state_b.set_block_id_at_height(blk.height(), &blk.id()).await.unwrap();
+        }
+        state_b.set_last_accepted_block(&blocks[1].id()).await.unwrap();
+        assert!(vm_b.view_account(to.to_vec()).await.unwrap().is_none());
+
+        vm_b.recover(&state_b).await.unwrap();
+        assert!(vm_b.view_account(to.to_vec()).await.unwrap().is_some());
+        let li_a = vm_a.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        let li_b = vm_b.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        assert_eq!(li_a.ledger_info().commit_info(), li_b.ledger_info().commit_info());
+
+        // nothing left to do on the next restart
+        vm_b.recover(&state_b).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_persists_pending_txs() {
+        let vm = new_standalone_aptos_test_vm().await;
+        let state = vm.state.read().await.state.clone().unwrap();
+        vm.create_account(AccountAddress::random().to_vec(), AcceptType::Json).await.unwrap();
+
+        CommonVm::shutdown(&vm).await.unwrap();
+        assert!(vm.tasks.lock().await.is_empty());
+        let d = state.get_pending_txs().await.unwrap().unwrap();
+        let txs: Vec<SignedTransaction> = bcs::from_bytes(&d).unwrap();
+        assert_eq!(txs.len(), 1);
+
+        // a restarted node re-admits them
+        let restarted = new_standalone_aptos_test_vm().await;
+        let restarted_state = restarted.state.read().await.state.clone().unwrap();
+        restarted_state.set_pending_txs(&d).await.unwrap();
+        restarted.recover(&restarted_state).await.unwrap();
+        let hash = txs[0].clone().committed_hash();
+        assert!(restarted.core_mempool.as_ref().unwrap().read().await.get_by_hash(hash).is_some());
+        assert!(restarted_state.get_pending_txs().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let vm = new_standalone_aptos_test_vm().await;
+        let report: HealthReport = serde_json::from_slice(&vm.health_check().await.unwrap()).unwrap();
+        assert!(report.healthy);
+        assert_eq!(report.last_accepted_height, Some(0));
+        assert!(report.aptos_in_sync);
+        assert_eq!(report.mempool_depth, Some(0));
+
+        vm.create_account(AccountAddress::random().to_vec(), AcceptType::Json).await.unwrap();
+        assert_eq!(vm.health_report().await.mempool_depth, Some(1));
+
+        build_and_accept(&vm).await;
+        let report = vm.health_report().await;
+        assert!(report.healthy);
+        assert_eq!(report.last_accepted_height, Some(1));
+        assert_eq!(report.mempool_depth, Some(0));
+
+        // a transaction waiting on an earlier sequence number is not ready
+        let mut core_account = {
+            let db = vm.db.as_ref().unwrap().read().await;
+            vm.get_core_account(&db).await.unwrap()
+        };
+        core_account.increment_sequence_number();
+        let parked = core_account.sign_with_transaction_builder(TransactionFactory::new(vm.chain_id).transfer(AccountAddress::random(), 1));
+        let ((status, _), _) = vm.admit_transaction(parked).await.unwrap();
+        assert_eq!(status.code, MempoolStatusCode::Accepted);
+        assert_eq!(vm.health_report().await.mempool_depth, Some(0));
+
+        assert_eq!(report.build_in_progress_secs, None);
+
+        // a built block waiting for a decision
+        vm.scheduler.handle(Event::BlockBuilt).await;
+        let report = vm.health_report().await;
+        assert!(report.healthy);
+        assert_eq!(report.build_in_progress_secs, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_health_check_fails_on_a_stuck_build() {
+        let config = VmConfig {
+            build_timeout_secs: 1,
+            min_block_interval_ms: 0,
+            ..Default::default()
+        };
+        let app_sender = LoopbackAppSender {
+            node_id: ids::node::Id::from_slice(&[0; ids::node::LEN]),
+            peer: Arc::new(RwLock::new(None)),
+        };
+        let vm = new_aptos_test_vm_with_config(app_sender, &Genesis::default(), config).await;
+        build_and_accept(&vm).await;
+
+        // the engine is notified but never builds the block
+        vm.create_account(AccountAddress::random().to_vec(), AcceptType::Json).await.unwrap();
+        assert!(matches!(vm.scheduler.state().await, BuildState::Notified { .. }));
+        assert!(vm.health_check().await.is_ok());
+
+        // notifying it again on timeout does not hide the stuck build
+        tokio::time::sleep(Duration::from_millis(2_100)).await;
+        assert!(matches!(vm.scheduler.state().await, BuildState::Notified { .. }));
+        let err = vm.health_check().await.unwrap_err();
+        assert!(err.to_string().contains("block build in progress"));
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_follows_mempool_and_blocks() {
+        let vm = new_standalone_aptos_test_vm().await;
+        assert_eq!(vm.scheduler.state().await, BuildState::Idle);
+
+        vm.create_account(AccountAddress::random().to_vec(), AcceptType::Json).await.unwrap();
+        assert!(matches!(vm.scheduler.state().await, BuildState::Notified { .. }));
+
+        let built = ChainVm::build_block(&vm).await.unwrap();
+        assert!(matches!(vm.scheduler.state().await, BuildState::Proposed { .. }));
+
+        // the block took every pending transaction
+        let mut blk = Getter::get_block(&vm, built.id()).await.unwrap();
+        blk.accept().await.unwrap();
+        assert_eq!(vm.scheduler.state().await, BuildState::Idle);
+    }
+
+    #[tokio::test]
+    async fn test_second_competing_child_wins() {
+        let vm = new_standalone_aptos_test_vm().await;
+        let genesis_id = ChainVm::last_accepted(&vm).await.unwrap();
+
+        // first branch: two empty blocks on top of genesis
+        let a = ChainVm::build_block(&vm).await.unwrap();
+        ChainVm::set_preference(&vm, a.id()).await.unwrap();
+        let a_child = ChainVm::build_block(&vm).await.unwrap();
+        assert_eq!(a_child.parent_id(), a.id());
+
+        // second branch: a competing child of genesis carrying a transaction
+        let to = AccountAddress::random();
+        vm.create_account(to.to_vec(), AcceptType::Json).await.unwrap();
+        ChainVm::set_preference(&vm, genesis_id).await.unwrap();
+        let b = ChainVm::build_block(&vm).await.unwrap();
+        assert_eq!(b.parent_id(), genesis_id);
+        assert_ne!(a.id(), b.id());
+        assert_eq!(vm.executed_blocks.read().await.len(), 3);
+
+        let mut b = Getter::get_block(&vm, b.id()).await.unwrap();
+        b.accept().await.unwrap();
+        ChainVm::set_preference(&vm, b.id()).await.unwrap();
+
+        // the losing branch is pruned from memory
+        let state = vm.state.read().await.state.clone().unwrap();
+        assert!(state.verified_blocks.read().await.is_empty());
+        assert!(vm.executed_blocks.read().await.is_empty());
+        assert!(vm.view_account(to.to_vec()).await.unwrap().is_some());
+
+        // the engine rejecting the pruned blocks afterwards is harmless
+        let mut a = Parser::parse_block(&vm, a.bytes()).await.unwrap();
+        a.reject().await.unwrap();
+        let mut a_child = Parser::parse_block(&vm, a_child.bytes()).await.unwrap();
+        a_child.reject().await.unwrap();
+
+        assert_eq!(state.get_block(&a.id()).await.unwrap().status(), choices::status::Status::Rejected);
+
+        // and the chain keeps growing on the winner
+        let next = build_and_accept(&vm).await;
+        assert_eq!(next.parent_id(), b.id());
+        assert_eq!(ChainVm::last_accepted(&vm).await.unwrap(), next.id());
+    }
+
+    #[tokio::test]
+    async fn test_init_aptos_from_custom_genesis() {
+        let funded = AccountAddress::random();
+        let genesis = Genesis {
+            validator_count: 2,
+            accounts: vec![crate::genesis::GenesisAccount {
+                address: funded,
+                balance: 5 * 100_000_000,
+            }],
+            ..Default::default()
+        };
+        let new_vm = || {
+            new_aptos_test_vm_with_genesis(
+                LoopbackAppSender {
+                    node_id: ids::node::Id::from_slice(&[0; ids::node::LEN]),
+                    peer: Arc::new(RwLock::new(None)),
+                },
+                &genesis,
+            )
+        };
+        let vm_a = new_vm().await;
+        let vm_b = new_vm().await;
+
+        assert_eq!(vm_a.chain_id, ChainId::test());
+        assert!(vm_a.view_account(funded.to_vec()).await.unwrap().is_some());
+
+        // every node commits the same state before the consensus genesis block
+        let li_a = vm_a.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        let li_b = vm_b.db.as_ref().unwrap().read().await.reader.get_latest_ledger_info().unwrap();
+        assert!(li_a.ledger_info().version() > 0);
+        assert_eq!(li_a.ledger_info().commit_info(), li_b.ledger_info().commit_info());
+
+        // and keeps building on top of it
+        let to = AccountAddress::random();
+        vm_a.create_account(to.to_vec(), AcceptType::Json).await.unwrap();
+        build_and_accept(&vm_a).await;
+        assert!(vm_a.view_account(to.to_vec()).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_init_aptos_with_genesis_validators() {
+        let key = bls12381::PrivateKey::generate(&mut rand::rngs::OsRng);
+        let root_key = Ed25519PrivateKey::generate(&mut rand::rngs::OsRng);
+        let address = AccountAddress::random();
+        let funded = AccountAddress::random();
+        let genesis = Genesis {
+            chain_id: 42,
+            validators: vec![GenesisValidator {
+                address,
+                consensus_public_key: bls12381::PublicKey::from(&key),
+                proof_of_possession: bls12381::ProofOfPossession::create(&key),
+                stake: 1_000_000,
+            }],
+            root_key: Some(Ed25519PublicKey::from(&root_key)),
+            accounts: vec![crate::genesis::GenesisAccount {
+                address: funded,
+                balance: 5 * 100_000_000,
+            }],
+            ..Default::default()
+        };
+        let app_sender = || LoopbackAppSender {
+            node_id: ids::node::Id::from_slice(&[0; ids::node::LEN]),
+            peer: Arc::new(RwLock::new(None)),
+        };
+        let config = VmConfig {
+            consensus_key: Some(key.to_encoded_string().unwrap()),
+            root_key: Some(root_key.to_encoded_string().unwrap()),
+            ..Default::default()
+        };
+        let vm = new_aptos_test_vm_with_config(app_sender(), &genesis, config.clone()).await;
+        assert_eq!(vm.chain_id, ChainId::new(42));
+        assert_eq!(vm.signer.as_ref().unwrap().author(), address);
+        assert!(vm.view_account(funded.to_vec()).await.unwrap().is_some());
+        build_and_accept(&vm).await;
+
+        // funding the accounts takes the root key of the genesis
+        let mut wrong_root = Vm::new();
+        wrong_root.config = VmConfig {
+            root_key: Some(Ed25519PrivateKey::generate(&mut rand::rngs::OsRng).to_encoded_string().unwrap()),
+            ..config
+        };
+        wrong_root.state.write().await.state = Some(state::State::default());
+        let db_dir = std::env::temp_dir().join(format!("m1-test-{}", uuid::Uuid::new_v4()));
+        assert!(wrong_root.init_aptos(&db_dir, &genesis).await.is_err());
+
+        // a node without the consensus key of a genesis validator cannot commit
+        let mut unkeyed = Vm::new();
+        unkeyed.state.write().await.state = Some(state::State::default());
+        let db_dir = std::env::temp_dir().join(format!("m1-test-{}", uuid::Uuid::new_v4()));
+        assert!(unkeyed.init_aptos(&db_dir, &genesis).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_genesis_block_commits_to_the_genesis_document() {
+        let app_sender = || LoopbackAppSender {
+            node_id: ids::node::Id::from_slice(&[0; ids::node::LEN]),
+            peer: Arc::new(RwLock::new(None)),
+        };
+        let genesis_block = |vm: Vm| async move {
+            let genesis_id = ChainVm::get_block_id_at_height(&vm, 0).await.unwrap();
+            Getter::get_block(&vm, genesis_id).await.unwrap()
+        };
+        let default = genesis_block(new_aptos_test_vm_with_genesis(app_sender(), &Genesis::default()).await).await;
+        let other = Genesis {
+            epoch_duration_secs: 60,
+            ..Default::default()
+        };
+        let other = genesis_block(new_aptos_test_vm_with_genesis(app_sender(), &other).await).await;
+        assert_ne!(default.id(), other.id());
+
+        let hash = genesis::document_hash(Genesis::default().to_vec().unwrap()).unwrap();
+        let legacy: LegacyAptosData = serde_json::from_slice(default.data()).unwrap();
+        assert_eq!(legacy.0, hash.to_hex().into_bytes());
+        assert!(AptosData::from_slice(default.data()).unwrap().transactions.is_empty());
+
+        // chains launched from empty genesis bytes keep the legacy genesis block
+        let legacy: LegacyAptosData = serde_json::from_slice(&AptosData::genesis_to_vec(None).unwrap()).unwrap();
+        assert_eq!(legacy.0, LEGACY_GENESIS_MESSAGE);
+    }
+
+    #[tokio::test]
+    async fn test_rest_api_is_served_end_to_end() {
+        use avalanche_types::proto::http::{http_client::HttpClient, HandleSimpleHttpRequest};
+
+        let mut vm = new_standalone_aptos_test_vm().await;
+        let tasks = vm.tasks.lock().await.len();
+        let handlers = CommonVm::create_handlers(&mut vm).await.unwrap();
+        // the server task is stopped on shutdown like the other background tasks
+        assert_eq!(vm.tasks.lock().await.len(), tasks + 1);
+
+        let server_addr = handlers[REST_EXTENSION].server_addr.clone().unwrap();
+        let mut client = HttpClient::connect(format!("http://{}", server_addr)).await.unwrap();
+        let resp = client
+            .handle_simple(HandleSimpleHttpRequest {
+                method: "GET".to_string(),
+                url: "/ext/bc/chain/rest/v1".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.code, 200);
+        let index: serde_json::Value = serde_json::from_slice(&resp.body).unwrap();
+        assert_eq!(index["chain_id"], ChainId::test().id());
+
+        let resp = client
+            .handle_simple(HandleSimpleHttpRequest {
+                method: "GET".to_string(),
+                url: format!("/ext/bc/chain/rest/v1/accounts/{}", AccountAddress::random()),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.code, 404);
+
+        CommonVm::shutdown(&vm).await.unwrap();
+    }
+
+    #[test]
+    fn test_tx_gossip_codec() {
+        let account = LocalAccount::generate(&mut rand::rngs::OsRng);
+        let txns: Vec<SignedTransaction> = (0..3)
+            .map(|_| account.sign_with_transaction_builder(TransactionFactory::new(ChainId::test()).transfer(AccountAddress::random(), 1)))
+            .collect();
+
+        let encoded = encode_tx_gossip(&txns).unwrap();
+        assert_eq!(encoded[0], TX_GOSSIP_VERSION);
+        assert_eq!(decode_tx_gossip(&encoded).unwrap(), txns);
+        assert!(encoded.len() < serde_json::to_vec(&txns).unwrap().len());
+
+        // single JSON transaction gossiped by older nodes
+        let legacy = serde_json::to_vec(&txns[0]).unwrap();
+        assert_eq!(decode_tx_gossip(&legacy).unwrap(), vec![txns[0].clone()]);
+
+        assert!(decode_tx_gossip(&[]).is_err());
+        assert!(decode_tx_gossip(&[0xff, 1, 2]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_submitted_transaction_reaches_peer_once() {
+        let id_a = ids::node::Id::from_slice(&[1; ids::node::LEN]);
+        let id_b = ids::node::Id::from_slice(&[2; ids::node::LEN]);
+        let peer_of_a = Arc::new(RwLock::new(None));
+        let peer_of_b = Arc::new(RwLock::new(None));
+        let vm_a = new_aptos_test_vm(LoopbackAppSender { node_id: id_a, peer: peer_of_a.clone() }).await;
+        let vm_b = new_aptos_test_vm(LoopbackAppSender { node_id: id_b, peer: peer_of_b.clone() }).await;
+        *peer_of_a.write().await = Some(vm_b.clone());
+        *peer_of_b.write().await = Some(vm_a.clone());
+
+        vm_a.create_account(AccountAddress::random().to_vec(), AcceptType::Json).await.unwrap();
+
+        let pending_a = vm_a.get_pending_tx(10).await.unwrap();
+        let pending_b = vm_b.get_pending_tx(10).await.unwrap();
+        assert_eq!(pending_a.len(), 1);
+        assert_eq!(pending_a, pending_b);
+
+        // already known on both sides, so it is not admitted or gossiped again
+        let (_, is_new) = vm_a.admit_transaction(pending_a[0].clone()).await.unwrap();
+        assert!(!is_new);
+        let (_, is_new) = vm_b.admit_transaction(pending_a[0].clone()).await.unwrap();
+        assert!(!is_new);
+    }
+
+    #[tokio::test]
+    async fn test_submit_rejects_invalid_transaction() {
+        let vm = new_standalone_aptos_test_vm().await;
+
+        // the sender account does not exist on chain
+        let account = LocalAccount::generate(&mut rand::rngs::OsRng);
+        let txn = account.sign_with_transaction_builder(TransactionFactory::new(vm.chain_id).transfer(AccountAddress::random(), 1));
+        let ((status, vm_status), is_new) = vm.admit_transaction(txn.clone()).await.unwrap();
+        assert_eq!(status.code, MempoolStatusCode::VmError);
+        assert!(vm_status.is_some());
+        assert!(!is_new);
+
+        let err = vm.submit_transaction(bcs::to_bytes(&txn).unwrap(), AcceptType::Json).await.unwrap_err();
+        let api_err = err.downcast_ref::<AptosApiError>().unwrap();
+        assert_eq!(api_err.status, 400);
+        assert!(api_err.is_mempool_rejection());
+        assert!(vm.get_pending_tx(10).await.unwrap().is_empty());
+
+        // a transaction for another chain fails validation too
+        let core_account = {
+            let db = vm.db.as_ref().unwrap().read().await;
+            vm.get_core_account(&db).await.unwrap()
+        };
+        let txn = core_account.sign_with_transaction_builder(TransactionFactory::new(ChainId::new(99)).transfer(AccountAddress::random(), 1));
+        let ((status, _), _) = vm.admit_transaction(txn).await.unwrap();
+        assert_eq!(status.code, MempoolStatusCode::VmError);
+    }
+}
@@ -1,15 +1,38 @@
 use tokio::sync::RwLock;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
+use aptos_crypto::hash::HashValue;
 use aptos_executor::block_executor::BlockExecutor;
-use aptos_types::transaction::Transaction;
-use aptos_vm::AptosVM;
+use aptos_executor_types::BlockExecutorTrait;
+use aptos_storage_interface::state_view::DbStateViewAtVersion;
+use aptos_storage_interface::DbReaderWriter;
+use aptos_types::block_executor::partitioner::{ExecutableBlock, ExecutableTransactions};
+use aptos_types::block_info::BlockInfo;
+use aptos_types::ledger_info::{generate_ledger_info_with_sig, LedgerInfo};
+use aptos_types::validator_signer::ValidatorSigner;
+use aptos_vm::{AptosVM, VMExecutor};
 use movement_sdk::{ExecutionLayer, Layer};
 use aptos_helper_types::block::Block;
+use crate::change_set::AptosChangeSet;
 
-
+/// Execution layer running Aptos blocks through the Aptos [`BlockExecutor`].
+///
+/// Blocks are executed on top of the committed block, and only written to
+/// AptosDB when their change set is committed, i.e., sent to the storage
+/// layer. A block is executed once: executing it again returns the change set
+/// of the first execution until it is committed or discarded.
 #[derive(Clone)]
 pub struct AptosBlockExecutor {
     pub executor: Arc<RwLock<BlockExecutor<AptosVM>>>,
+    pub db: DbReaderWriter,
+    /// Signs the ledger infos blocks are committed with.
+    pub signer: ValidatorSigner,
+    /// Blocks to execute, oldest first.
+    pub blocks: Arc<RwLock<VecDeque<Block>>>,
+    /// Blocks that are executed but not yet committed or discarded.
+    pub executed_blocks: Arc<RwLock<HashMap<HashValue, (Block, AptosChangeSet)>>>,
+    /// Committed change sets not yet taken by the storage layer, oldest first.
+    pub change_sets: Arc<RwLock<VecDeque<AptosChangeSet>>>,
 }
 
 impl std::fmt::Debug for AptosBlockExecutor {
@@ -20,9 +43,58 @@ impl std::fmt::Debug for AptosBlockExecutor {
 }
 
 impl AptosBlockExecutor {
-    pub fn new(executor: Arc<RwLock<BlockExecutor<AptosVM>>>) -> Self {
-        AptosBlockExecutor { executor }
+
+    pub fn new(
+        executor: Arc<RwLock<BlockExecutor<AptosVM>>>,
+        db: DbReaderWriter,
+        signer: ValidatorSigner,
+    ) -> Self {
+        AptosBlockExecutor {
+            executor,
+            db,
+            signer,
+            blocks: Arc::new(RwLock::new(VecDeque::new())),
+            executed_blocks: Arc::new(RwLock::new(HashMap::new())),
+            change_sets: Arc::new(RwLock::new(VecDeque::new())),
+        }
+    }
+
+    /// Queues a block, e.g., an accepted one, for execution.
+    pub async fn push_block(&self, block: Block) {
+        self.blocks.write().await.push_back(block);
+    }
+
+    /// Gets the next committed change set, for the storage layer.
+    pub async fn get_next_change_set(&self) -> Option<AptosChangeSet> {
+        self.change_sets.write().await.pop_front()
+    }
+
+    /// Commits an executed block to AptosDB, and returns its change set.
+    pub async fn commit_change_set(
+        &self,
+        change_set: AptosChangeSet,
+    ) -> Result<AptosChangeSet, anyhow::Error> {
+        if change_set.is_committed() {
+            return Ok(change_set);
+        }
+
+        let ledger_info_with_sigs = generate_ledger_info_with_sig(
+            &[self.signer.clone()],
+            change_set.ledger_info.clone(),
+        );
+        {
+            let executor = self.executor.read().await;
+            executor.commit_blocks(vec![change_set.block_id], ledger_info_with_sigs)?;
+        }
+        self.executed_blocks.write().await.remove(&change_set.block_id);
+        Ok(change_set.committed())
     }
+
+    /// Forgets an executed block that will not be committed, e.g., a rejected one.
+    pub async fn discard_block(&self, block_id: HashValue) -> Option<Block> {
+        self.executed_blocks.write().await.remove(&block_id).map(|(block, _)| block)
+    }
+
 }
 
 impl Layer for AptosBlockExecutor {}
@@ -31,14 +103,14 @@ impl Layer for AptosBlockExecutor {}
 impl ExecutionLayer for AptosBlockExecutor {
 
     type Block = Block;
-    type BlockId = String; // todo: change later
-    type ChangeSet = u64; // todo: change later
+    type BlockId = HashValue;
+    type ChangeSet = AptosChangeSet;
 
     // Gets the next block from the previous layer.
     async fn get_next_block(
         &self
     ) -> Result<Option<Self::Block>, anyhow::Error> {
-        unimplemented!();
+        Ok(self.blocks.write().await.pop_front())
     }
 
     // Executes a block and produces a change set.
@@ -46,34 +118,64 @@ impl ExecutionLayer for AptosBlockExecutor {
         &self,
         block: Self::Block
     ) -> Result<Self::ChangeSet, anyhow::Error> {
+        if let Some((_, change_set)) = self.executed_blocks.read().await.get(&block.block_id) {
+            return Ok(change_set.clone());
+        }
+
+        let executor = self.executor.read().await;
+        // a block without a parent block builds on whatever is committed
+        let committed_block_id = executor.committed_block_id();
+        let parent_block_id = if block.parent_block_id == HashValue::zero() {
+            committed_block_id
+        } else {
+            block.parent_block_id
+        };
+        // the outputs are computed on the state of the parent, which is only
+        // readable once committed
+        if parent_block_id != committed_block_id {
+            return Err(anyhow::anyhow!(
+                "parent block {} of block {} is not committed",
+                parent_block_id,
+                block.block_id
+            ));
+        }
+
+        let compute_result = executor.execute_block(
+            ExecutableBlock::new(
+                block.block_id,
+                ExecutableTransactions::Unsharded(block.transactions.clone()),
+            ),
+            parent_block_id,
+            None,
+        )?;
+        drop(executor);
 
-        let mut executor = self.executor.write().await;
-        // let parent_block_id_now = executor.committed_block_id();
-   
-        // todo: update for aptos 1.70
-        /*// execute the block
-        let output = executor
-            .execute_block((block_id, block_tx.clone()), parent_block_id)
-            .unwrap();
+        let version = self.db.reader.get_latest_ledger_info()?.ledger_info().version();
+        let state_view = self.db.reader.state_view_at_version(Some(version))?;
+        let outputs = AptosVM::execute_block(block.transactions.clone(), &state_view, None)?;
+        let transaction_outputs = block.transactions.iter().cloned().zip(outputs).collect();
 
-        // sign for the the ledger
         let ledger_info = LedgerInfo::new(
             BlockInfo::new(
-                next_epoch,
+                block.next_epoch,
                 0,
-                block_id,
-                output.root_hash(),
-                output.version(),
-                ts,
-                output.epoch_state().clone(),
+                block.block_id,
+                compute_result.root_hash(),
+                compute_result.version(),
+                block.timestamp,
+                compute_result.epoch_state().clone(),
             ),
             HashValue::zero(),
         );
-        let li = generate_ledger_info_with_sig(&[self.signer.as_ref().unwrap().clone()], ledger_info);
-        executor.commit_blocks(vec![block_id], li.clone()).unwrap();*/
-
-        Ok(0)
-
+        let change_set = AptosChangeSet::new(
+            block.block_id,
+            parent_block_id,
+            compute_result,
+            ledger_info,
+            transaction_outputs,
+        );
+        self.executed_blocks.write().await.insert(block.block_id, (block, change_set.clone()));
+        Ok(change_set)
     }
 
     // Sends a change set to the next layer,  i.e., the storage layer.
@@ -81,15 +183,124 @@ impl ExecutionLayer for AptosBlockExecutor {
         &self,
         change_set: Self::ChangeSet
     ) -> Result<(), anyhow::Error> {
-        unimplemented!();
+        let change_set = self.commit_change_set(change_set).await?;
+        self.change_sets.write().await.push_back(change_set);
+        Ok(())
     }
 
-    // Gets an executed block
+    // Gets an executed block that is not yet committed.
     async fn get_block(
         &self,
         block_id: Self::BlockId
     ) -> Result<Option<Self::Block>, anyhow::Error> {
-        unimplemented!();
+        Ok(self.executed_blocks.read().await.get(&block_id).map(|(block, _)| block.clone()))
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use aptos_db::AptosDB;
+    use aptos_executor::db_bootstrapper::{generate_waypoint, maybe_bootstrap};
+    use aptos_sdk::transaction_builder::TransactionFactory;
+    use aptos_sdk::types::{AccountKey, LocalAccount};
+    use aptos_temppath::TempPath;
+    use aptos_types::account_address::AccountAddress;
+    use aptos_types::account_config::aptos_test_root_address;
+    use aptos_types::block_metadata::BlockMetadata;
+    use aptos_types::chain_id::ChainId;
+    use aptos_types::transaction::{ExecutionStatus, Transaction, TransactionStatus, WriteSetPayload};
+    use aptos_vm_genesis::{test_genesis_change_set_and_validators, GENESIS_KEYPAIR};
+
+    fn bootstrapped_executor(db_dir: &TempPath) -> Result<AptosBlockExecutor, anyhow::Error> {
+        let (change_set, validators) = test_genesis_change_set_and_validators(Some(1));
+        let genesis_txn = Transaction::GenesisTransaction(WriteSetPayload::Direct(change_set));
+        let (_, db) = DbReaderWriter::wrap(AptosDB::new_for_test(db_dir));
+        let waypoint = generate_waypoint::<AptosVM>(&db, &genesis_txn)?;
+        maybe_bootstrap::<AptosVM>(&db, &genesis_txn, waypoint)?;
+
+        let signer = ValidatorSigner::new(
+            validators[0].data.owner_address,
+            validators[0].consensus_key.clone(),
+        );
+        let executor = Arc::new(RwLock::new(BlockExecutor::new(db.clone())));
+        Ok(AptosBlockExecutor::new(executor, db, signer))
+    }
+
+    #[tokio::test]
+    async fn test_execute_and_commit_block() -> Result<(), anyhow::Error> {
+
+        let db_dir = TempPath::new();
+        let executor = bootstrapped_executor(&db_dir)?;
+        let genesis_info = executor.db.reader.get_latest_ledger_info()?;
+        assert_eq!(genesis_info.ledger_info().version(), 0);
+
+        let core_account = LocalAccount::new(
+            aptos_test_root_address(),
+            AccountKey::from_private_key(GENESIS_KEYPAIR.0.clone()),
+            0,
+        );
+        let recipient = AccountAddress::random();
+        let transfer = core_account.sign_with_transaction_builder(
+            TransactionFactory::new(ChainId::test()).transfer(recipient, 1_000),
+        );
+
+        let block_id = HashValue::random();
+        let next_epoch = genesis_info.ledger_info().next_block_epoch();
+        let block_metadata = BlockMetadata::new(
+            block_id,
+            next_epoch,
+            0,
+            executor.signer.author(),
+            vec![],
+            vec![],
+            1,
+        );
+        executor.push_block(Block {
+            transactions: vec![
+                Transaction::BlockMetadata(block_metadata),
+                Transaction::UserTransaction(transfer),
+                Transaction::StateCheckpoint(HashValue::random()),
+            ],
+            block_id,
+            parent_block_id: HashValue::zero(),
+            next_epoch,
+            timestamp: 1,
+        }).await;
+
+        let block = executor.get_next_block().await?.unwrap();
+        let change_set = executor.execute_block(block).await?;
+        assert_eq!(change_set.version(), 3);
+        assert_eq!(change_set.ledger_info.transaction_accumulator_hash(), change_set.root_hash());
+        assert!(executor.get_block(block_id).await?.is_some());
+        // events and write sets are known before the block is committed
+        assert!(!change_set.is_committed());
+        assert_eq!(change_set.write_sets().count(), 3);
+        let (_, transfer_output) = &change_set.transaction_outputs[1];
+        assert_eq!(transfer_output.status(), &TransactionStatus::Keep(ExecutionStatus::Success));
+        assert!(change_set.events().next().is_some());
+
+        // nothing is written to AptosDB before the change set is sent on
+        assert_eq!(executor.db.reader.get_latest_ledger_info()?.ledger_info().version(), 0);
+        executor.send_change_set(change_set.clone()).await?;
+        let committed = executor.get_next_change_set().await.unwrap();
+        assert!(executor.get_block(block_id).await?.is_none());
+
+        let ledger_info = executor.db.reader.get_latest_ledger_info()?;
+        assert_eq!(ledger_info.ledger_info().version(), 3);
+        assert_eq!(ledger_info.ledger_info().transaction_accumulator_hash(), change_set.root_hash());
+
+        assert!(committed.is_committed());
+        assert_eq!(committed.transaction_outputs, change_set.transaction_outputs);
+
+        // the outputs of a committed block match the ones stored in AptosDB
+        let stored = executor.db.reader.get_transaction_outputs(1, 3, 3)?;
+        assert_eq!(stored.transactions_and_outputs, committed.transaction_outputs);
+
+        Ok(())
+
     }
 
 }
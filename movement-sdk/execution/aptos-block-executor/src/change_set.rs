@@ -0,0 +1,78 @@
+use aptos_crypto::hash::HashValue;
+use aptos_executor_types::StateComputeResult;
+use aptos_types::contract_event::ContractEvent;
+use aptos_types::ledger_info::LedgerInfo;
+use aptos_types::transaction::{Transaction, TransactionOutput, Version};
+use aptos_types::write_set::WriteSet;
+
+/// Change set produced by executing an Aptos block.
+///
+/// An executed change set carries the [`StateComputeResult`] of the block, the
+/// ledger info to commit it with, and the outputs of its transactions, i.e.,
+/// their events and write sets.
+#[derive(Debug, Clone)]
+pub struct AptosChangeSet {
+    pub block_id: HashValue,
+    pub parent_block_id: HashValue,
+    pub compute_result: StateComputeResult,
+    /// Ledger info the block is committed with, signed on commit.
+    pub ledger_info: LedgerInfo,
+    /// Transactions of the block and their outputs.
+    pub transaction_outputs: Vec<(Transaction, TransactionOutput)>,
+    committed: bool,
+}
+
+impl AptosChangeSet {
+
+    pub fn new(
+        block_id: HashValue,
+        parent_block_id: HashValue,
+        compute_result: StateComputeResult,
+        ledger_info: LedgerInfo,
+        transaction_outputs: Vec<(Transaction, TransactionOutput)>,
+    ) -> Self {
+        Self {
+            block_id,
+            parent_block_id,
+            compute_result,
+            ledger_info,
+            transaction_outputs,
+            committed: false,
+        }
+    }
+
+    /// Marks the change set committed to AptosDB.
+    pub fn committed(mut self) -> Self {
+        self.committed = true;
+        self
+    }
+
+    pub fn is_committed(&self) -> bool {
+        self.committed
+    }
+
+    /// Root hash of the accumulator after the block.
+    pub fn root_hash(&self) -> HashValue {
+        self.compute_result.root_hash()
+    }
+
+    /// Version of the last transaction of the block.
+    pub fn version(&self) -> Version {
+        self.compute_result.version()
+    }
+
+    /// Events emitted by the transactions of the block, in order.
+    pub fn events(&self) -> impl Iterator<Item = &ContractEvent> {
+        self.transaction_outputs
+            .iter()
+            .flat_map(|(_, output)| output.events().iter())
+    }
+
+    /// Write sets of the transactions of the block, in order.
+    pub fn write_sets(&self) -> impl Iterator<Item = &WriteSet> {
+        self.transaction_outputs
+            .iter()
+            .map(|(_, output)| output.write_set())
+    }
+
+}
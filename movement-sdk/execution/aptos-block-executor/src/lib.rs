@@ -1 +1,2 @@
-pub mod aptos_block_executor;
\ No newline at end of file
+pub mod aptos_block_executor;
+pub mod change_set;
\ No newline at end of file
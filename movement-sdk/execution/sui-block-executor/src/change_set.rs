@@ -0,0 +1,36 @@
+use sui_types::{
+    effects::{TransactionEffects, TransactionEvents},
+    event::Event
+};
+
+/// Effects and events of an executed transaction.
+#[derive(Debug, Clone)]
+pub struct ExecutedTransaction {
+    pub effects : TransactionEffects,
+    pub events : TransactionEvents,
+}
+
+/// Change set produced by executing a Sui block, one entry per transaction.
+/// Transactions of different execution groups are listed group after group.
+#[derive(Debug, Clone, Default)]
+pub struct SuiChangeSet {
+    pub transactions : Vec<ExecutedTransaction>,
+}
+
+impl SuiChangeSet {
+
+    pub fn new(transactions : Vec<ExecutedTransaction>) -> Self {
+        Self { transactions }
+    }
+
+    /// Effects of the transactions of the block.
+    pub fn effects(&self) -> impl Iterator<Item = &TransactionEffects> {
+        self.transactions.iter().map(|transaction| &transaction.effects)
+    }
+
+    /// Events emitted by the transactions of the block.
+    pub fn events(&self) -> impl Iterator<Item = &Event> {
+        self.transactions.iter().flat_map(|transaction| transaction.events.data.iter())
+    }
+
+}
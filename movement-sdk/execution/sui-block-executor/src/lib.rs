@@ -1,2 +1,4 @@
+pub mod change_set;
 pub mod sui_block_executor;
+pub use change_set::*;
 pub use sui_block_executor::*;
\ No newline at end of file
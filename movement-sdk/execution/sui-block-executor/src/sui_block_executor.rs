@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::fmt::Debug;
 
 use sui_helper_types::{
@@ -6,15 +7,27 @@ use sui_helper_types::{
         input_object::InputObjectProvider,
         epoch::EpochProvider,
         verified_executable_transaction::VerifiedExecutableBlockProvider,
-        object_version::ObjectVersionProvider
+        object_version::ObjectVersionProvider,
+        object_store::ObjectStoreWriter
     },
-    block::{Block, VerifiedExecutableBlock}
+    block::Block
 };
 
 use movement_sdk::{Layer, ExecutionLayer};
+use prometheus::Registry;
+use sui_execution::Executor;
+use sui_protocol_config::ProtocolConfig;
+use sui_types::base_types::{ObjectID, ObjectRef};
+use sui_types::committee::EpochId;
+use sui_types::effects::TransactionEffectsAPI;
+use sui_types::metrics::LimitsMetrics;
+use sui_types::object::Object;
 use sui_types::storage::BackingStore;
+use sui_types::transaction::TransactionDataAPI;
 use std::sync::Arc;
 use sui_types::executable_transaction::VerifiedExecutableTransaction;
+use tokio::sync::RwLock;
+use crate::change_set::{ExecutedTransaction, SuiChangeSet};
 
 /// Sui block executor struct.
 /// ? Feel free to change the ref types to whatever you want.
@@ -26,12 +39,49 @@ pub struct SuiBlockExecutor {
     gas_info_provider : Arc<dyn GasInfoProvider + Send + Sync>,
     input_object_provider : Arc<dyn InputObjectProvider + Send + Sync>,
     verified_executable_block_provider : Arc<dyn VerifiedExecutableBlockProvider + Send + Sync>,
-    object_version_provider : Arc<dyn ObjectVersionProvider + Send + Sync>
+    object_version_provider : Arc<dyn ObjectVersionProvider + Send + Sync>,
+    object_store_writer : Arc<dyn ObjectStoreWriter + Send + Sync>,
+    metrics : Arc<LimitsMetrics>,
+    /// Blocks to execute, oldest first.
+    blocks : Arc<RwLock<VecDeque<Block>>>,
+    /// Change sets not yet taken by the storage layer, oldest first.
+    change_sets : Arc<RwLock<VecDeque<SuiChangeSet>>>
+}
+
+/// What the transactions of a block are executed with.
+struct EpochContext {
+    executor : Arc<dyn Executor + Send + Sync>,
+    protocol_config : ProtocolConfig,
+    epoch_id : EpochId,
+    epoch_timestamp : u64
+}
+
+/// Objects a transaction wrote, and the ones it deleted or wrapped.
+struct ObjectChanges {
+    written : BTreeMap<ObjectID, Object>,
+    deleted : Vec<ObjectRef>
+}
+
+impl ObjectChanges {
+
+    /// Applies the changes of a later transaction on top of these.
+    fn extend(&mut self, later : ObjectChanges) {
+        for object_ref in later.deleted {
+            self.written.remove(&object_ref.0);
+            self.deleted.retain(|deleted| deleted.0 != object_ref.0);
+            self.deleted.push(object_ref);
+        }
+        for (object_id, object) in later.written {
+            self.deleted.retain(|deleted| deleted.0 != object_id);
+            self.written.insert(object_id, object);
+        }
+    }
+
 }
 
 
 impl Debug for SuiBlockExecutor {
-    
+
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SuiBlockExecutor")
             .finish()
@@ -48,7 +98,8 @@ impl SuiBlockExecutor {
         gas_info_provider : Arc<dyn GasInfoProvider + Send + Sync>,
         input_object_provider : Arc<dyn InputObjectProvider + Send + Sync>,
         verified_executable_block_provider : Arc<dyn VerifiedExecutableBlockProvider + Send + Sync>,
-        object_version_provider : Arc<dyn ObjectVersionProvider + Send + Sync>
+        object_version_provider : Arc<dyn ObjectVersionProvider + Send + Sync>,
+        object_store_writer : Arc<dyn ObjectStoreWriter + Send + Sync>
     ) -> Self {
         Self {
             backing_store,
@@ -56,18 +107,83 @@ impl SuiBlockExecutor {
             gas_info_provider,
             input_object_provider,
             verified_executable_block_provider,
-            object_version_provider
+            object_version_provider,
+            object_store_writer,
+            metrics : Arc::new(LimitsMetrics::new(&Registry::new())),
+            blocks : Arc::new(RwLock::new(VecDeque::new())),
+            change_sets : Arc::new(RwLock::new(VecDeque::new()))
         }
     }
 
+    /// Queues a block, e.g., an accepted one, for execution.
+    pub async fn push_block(&self, block : Block) {
+        self.blocks.write().await.push_back(block);
+    }
+
+    /// Gets the next change set, for the storage layer.
+    pub async fn get_next_change_set(&self) -> Option<SuiChangeSet> {
+        self.change_sets.write().await.pop_front()
+    }
+
+    /// Executes a transaction to its effects, and returns them along with the objects it changed.
+    /// A transaction that fails still produces effects, e.g., it is charged gas.
+    async fn execute_transaction(
+        &self,
+        context : &EpochContext,
+        transaction : VerifiedExecutableTransaction
+    ) -> Result<(ExecutedTransaction, ObjectChanges), anyhow::Error> {
+        let transaction_data = transaction.data().transaction_data();
+
+        // load the input objects at their assigned versions and check them against the gas budget
+        let input_objects = self.input_object_provider.input_objects(transaction_data).await?;
+        let receiving_objects = transaction_data.receiving_objects();
+        let (gas_status, checked_input_objects) = self.gas_info_provider.gas_status(
+            transaction_data,
+            input_objects,
+            &receiving_objects
+        ).await?;
+
+        let (kind, signer, gas) = transaction_data.execution_parts();
+        let (inner_temporary_store, effects, _execution_result) = context.executor.execute_transaction_to_effects(
+            self.backing_store.as_ref(),
+            &context.protocol_config,
+            self.metrics.clone(),
+            false, // enable_expensive_checks
+            &HashSet::new(), // certificate_deny_set
+            &context.epoch_id,
+            context.epoch_timestamp,
+            checked_input_objects,
+            gas,
+            gas_status,
+            kind,
+            signer,
+            *transaction.digest()
+        );
+
+        let mut deleted = effects.deleted();
+        deleted.extend(effects.wrapped());
+        let changes = ObjectChanges {
+            written : inner_temporary_store.written,
+            deleted
+        };
+
+        Ok((ExecutedTransaction {
+            effects,
+            events : inner_temporary_store.events
+        }, changes))
+    }
+
+    /// Executes the transactions of a group one after the other.
     async fn execute_transaction_group(
         &self,
+        context : &EpochContext,
         transaction_group : Vec<VerifiedExecutableTransaction>
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<Vec<(ExecutedTransaction, ObjectChanges)>, anyhow::Error> {
+        let mut executed = Vec::with_capacity(transaction_group.len());
         for transaction in transaction_group {
-            // todo: use execute_transaction_to_effects
+            executed.push(self.execute_transaction(context, transaction).await?);
         }
-        unimplemented!();
+        Ok(executed)
     }
 
 }
@@ -81,16 +197,18 @@ impl ExecutionLayer for SuiBlockExecutor {
 
     type Block = Block;
     type BlockId = String; // todo: will update this
-    type ChangeSet = Option<u64>; // todo: will update this
+    type ChangeSet = SuiChangeSet;
 
     // Gets the next block from the previous layer.
     async fn get_next_block(
         &self
     ) -> Result<Option<Self::Block>, anyhow::Error> {
-        unimplemented!(); // ? Don't worry about this for now.
+        Ok(self.blocks.write().await.pop_front())
     }
 
     // Executes a block and produces a change set.
+    // The objects the transactions changed are written to the store once all of them executed,
+    // so that a block that fails to execute leaves the store as it was.
     async fn execute_block(
         &self,
         block: Self::Block
@@ -105,12 +223,31 @@ impl ExecutionLayer for SuiBlockExecutor {
         // set up the object versions for the transactions
         let sequencer_parallel_groups = self.object_version_provider.assign_shared_object_versions(max_parallel_groups).await?;
 
+        let protocol_config = self.epoch_provider.protocol_config().await?;
+        let context = EpochContext {
+            executor : sui_execution::executor(&protocol_config, false, true)?,
+            protocol_config,
+            epoch_id : self.epoch_provider.epoch_id().await?,
+            epoch_timestamp : self.epoch_provider.epoch_timestamp().await?
+        };
+
         // execute the transaction groups in parallel
-        futures::future::try_join_all(
-            sequencer_parallel_groups.into_iter().map(|transaction_group| self.execute_transaction_group(transaction_group))
+        let executed_groups = futures::future::try_join_all(
+            sequencer_parallel_groups.into_iter().map(|transaction_group| self.execute_transaction_group(&context, transaction_group))
         ).await?;
 
-        unimplemented!(); // ! Worry about this for now.
+        let mut changes = ObjectChanges {
+            written : BTreeMap::new(),
+            deleted : Vec::new()
+        };
+        let mut transactions = Vec::new();
+        for (executed, transaction_changes) in executed_groups.into_iter().flatten() {
+            changes.extend(transaction_changes);
+            transactions.push(executed);
+        }
+        self.object_store_writer.update_objects(changes.written, changes.deleted).await?;
+
+        Ok(SuiChangeSet::new(transactions))
 
     }
 
@@ -119,16 +256,111 @@ impl ExecutionLayer for SuiBlockExecutor {
         &self,
         change_set: Self::ChangeSet
     ) -> Result<(), anyhow::Error> {
-        unimplemented!(); // ? Don't worry about this for now.
+        self.change_sets.write().await.push_back(change_set);
+        Ok(())
     }
 
-    // Gets an executed block
+    // Gets an executed block. Executed blocks are not kept, so none is known.
     async fn get_block(
         &self,
-        block_id: Self::BlockId
+        _block_id: Self::BlockId
     ) -> Result<Option<Self::Block>, anyhow::Error> {
-        unimplemented!(); // ? Don't worry about this for now.
+        Ok(None)
     }
 
 }
 
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use sui_block_authority_providers::in_memory::{
+        in_memory_epoch::InMemoryEpoch,
+        in_memory_store::InMemoryStore
+    };
+    use sui_swarm_config::network_config_builder::ConfigBuilder;
+    use sui_test_transaction_builder::TestTransactionBuilder;
+    use sui_types::base_types::SuiAddress;
+    use sui_types::crypto::KeypairTraits;
+    use sui_types::gas_coin::GasCoin;
+    use sui_types::object::Owner;
+    use sui_types::storage::ObjectStore;
+    use sui_types::sui_system_state::SuiSystemStateTrait;
+
+    #[tokio::test]
+    async fn test_coin_transfer() -> Result<(), anyhow::Error> {
+
+        let network_config = ConfigBuilder::new_with_temp_dir().build();
+        let store = Arc::new(InMemoryStore::new(network_config.genesis.objects().iter().cloned()));
+        let epoch = Arc::new(InMemoryEpoch::new(
+            &network_config.genesis.sui_system_object().into_epoch_start_state()
+        ));
+        let executor = SuiBlockExecutor::new(
+            store.clone(),
+            epoch.clone(),
+            epoch.clone(),
+            store.clone(),
+            epoch.clone(),
+            store.clone(),
+            store.clone()
+        );
+
+        let sender_key = &network_config.account_keys[0];
+        let sender = SuiAddress::from(sender_key.public());
+        let recipient = SuiAddress::from(network_config.account_keys[1].public());
+        let gas = store.owned_objects(sender).into_iter().find(|object| object.is_gas_coin()).unwrap();
+        let transaction = TestTransactionBuilder::new(sender, gas.compute_object_reference(), epoch.reference_gas_price())
+            .transfer_sui(Some(1_000), recipient)
+            .build_and_sign(sender_key);
+
+        executor.push_block(Block::new(vec![transaction.into_data()])).await;
+        let block = executor.get_next_block().await?.unwrap();
+        let change_set = executor.execute_block(block).await?;
+        assert_eq!(change_set.transactions.len(), 1);
+        let effects = &change_set.transactions[0].effects;
+        assert!(effects.status().is_ok());
+
+        // the recipient owns a new coin with the amount
+        let (coin_ref, owner) = effects.created()[0];
+        assert_eq!(owner, Owner::AddressOwner(recipient));
+        let coin = ObjectStore::get_object(store.as_ref(), &coin_ref.0)?.unwrap();
+        assert_eq!(GasCoin::try_from(&coin)?.value(), 1_000);
+
+        // the gas coin paid for the amount and the gas, at its new version
+        let gas_after = ObjectStore::get_object(store.as_ref(), &gas.id())?.unwrap();
+        assert_eq!(gas_after.version(), effects.gas_object().0.1);
+        assert!(GasCoin::try_from(&gas_after)?.value() < GasCoin::try_from(&gas)?.value() - 1_000);
+
+        executor.send_change_set(change_set).await?;
+        assert!(executor.get_next_change_set().await.is_some());
+        assert!(executor.get_block("unknown".to_string()).await?.is_none());
+
+        Ok(())
+
+    }
+
+    #[test]
+    fn test_later_changes_override_earlier_ones() {
+        let object = Object::immutable_with_id_for_testing(ObjectID::random());
+        let mut changes = ObjectChanges {
+            written : BTreeMap::from([(object.id(), object.clone())]),
+            deleted : Vec::new()
+        };
+
+        changes.extend(ObjectChanges {
+            written : BTreeMap::new(),
+            deleted : vec![object.compute_object_reference()]
+        });
+        assert!(changes.written.is_empty());
+        assert_eq!(changes.deleted, vec![object.compute_object_reference()]);
+
+        // e.g., wrapped and then unwrapped
+        changes.extend(ObjectChanges {
+            written : BTreeMap::from([(object.id(), object.clone())]),
+            deleted : Vec::new()
+        });
+        assert_eq!(changes.written.len(), 1);
+        assert!(changes.deleted.is_empty());
+    }
+
+}
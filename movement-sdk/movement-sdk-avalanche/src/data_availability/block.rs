@@ -1,17 +1,34 @@
 use avalanche_types::{
-    subnet,
     choices,
-    ids::{self, Id},
+    ids,
     subnet::rpc::consensus::snowman::{
         Decidable,
         Block as Blockable
     }
 };
-use movement_sdk::{Layer, DataAvailabilityLayer};
+use movement_sdk::DataAvailabilityLayer;
+use std::fmt::Debug;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use std::io;
 
+/// Told about the verification and the decision of [`AvalancheBlock`]s,
+/// e.g., by the Vm they were built or parsed by.
+#[async_trait::async_trait]
+pub trait BlockListener<Block> : Debug + Send + Sync {
+
+    async fn verified(&self, block: &Block) -> io::Result<()>;
+
+    async fn accepted(&self, block: &Block) -> io::Result<()>;
+
+    async fn rejected(&self, block: &Block) -> io::Result<()>;
+
+    /// Gets a block the listener was told is verified, and that is not yet
+    /// accepted or rejected.
+    async fn get_verified(&self, block_id: &ids::Id) -> Option<Block>;
+
+}
+
 #[derive(Debug, Clone)]
 pub struct AvalancheBlock<
     Block : Send + Sync, 
@@ -20,6 +37,10 @@ pub struct AvalancheBlock<
 >{
     pub inner_block: Arc<Block>,
     pub data_availability_layer: Arc<RwLock<DA>>,
+    /// Status of the block once it is known to be processing or decided.
+    /// The status of the inner block is reported until then.
+    pub status: Option<choices::status::Status>,
+    pub listener: Option<Arc<dyn BlockListener<Block>>>,
 }
 
 impl <
@@ -33,11 +54,23 @@ impl <
         data_availability_layer: Arc<RwLock<DA>>,
     ) -> Self {
         Self {
-            inner_block : Arc::new(RwLock::new(inner_block)),
+            inner_block : Arc::new(inner_block),
             data_availability_layer,
+            status: None,
+            listener: None,
         }
     }
 
+    pub fn with_status(mut self, status: choices::status::Status) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_listener(mut self, listener: Arc<dyn BlockListener<Block>>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
 }
 
 impl <
@@ -62,22 +95,108 @@ impl <
     }
 
     async fn status(&self) -> choices::status::Status {
-        self.inner_block.status().await
+        match &self.status {
+            Some(status) => status.clone(),
+            None => self.inner_block.status().await,
+        }
     }
 
     async fn accept(&mut self) -> io::Result<()> {
         let data_availability_layer = self.data_availability_layer.read().await;
-        let block_copy = self.inner_block.read().await.clone();
+        let block_copy = (*self.inner_block).clone();
         let result = data_availability_layer.accept_block(block_copy).await;
         result.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        drop(data_availability_layer);
+        self.status = Some(choices::status::Status::Accepted);
+        if let Some(listener) = self.listener.as_ref() {
+            listener.accepted(&self.inner_block).await?;
+        }
         Ok(())
     }
     
     async fn reject(&mut self) -> io::Result<()> {
         let data_availability_layer = self.data_availability_layer.read().await;
-        let block_copy = self.inner_block.read().await.clone();
+        let block_copy = (*self.inner_block).clone();
         let result  = data_availability_layer.reject_block(block_copy).await;
         result.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        drop(data_availability_layer);
+        self.status = Some(choices::status::Status::Rejected);
+        if let Some(listener) = self.listener.as_ref() {
+            listener.rejected(&self.inner_block).await?;
+        }
+        Ok(())
+    }
+
+}
+
+impl <
+    Block : Blockable + Clone + Send + Sync, 
+    BlockId : From<ids::Id> + Send + Sync,
+    DA : DataAvailabilityLayer<Block = Block, BlockId = BlockId> + Sync + Send
+> AvalancheBlock<Block, BlockId, DA> {
+
+    /// Gets the height of the parent block, if it is verified or decided.
+    async fn parent_height(&self) -> io::Result<Option<u64>> {
+        let parent_id = self.inner_block.parent().await;
+        if let Some(listener) = self.listener.as_ref() {
+            if let Some(parent) = listener.get_verified(&parent_id).await {
+                return Ok(Some(parent.height().await));
+            }
+        }
+        let data_availability_layer = self.data_availability_layer.read().await;
+        let parent = data_availability_layer.get_block(parent_id.into()).await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        Ok(match parent {
+            Some(parent) => Some(parent.height().await),
+            None => None,
+        })
+    }
+
+}
+
+#[async_trait::async_trait]
+impl <
+    Block : Blockable + Clone + Send + Sync, 
+    BlockId : From<ids::Id> + Send + Sync,
+    DA : DataAvailabilityLayer<Block = Block, BlockId = BlockId> + Sync + Send
+> Blockable for AvalancheBlock<Block, BlockId, DA> {
+
+    async fn bytes(&self) -> &[u8] {
+        self.inner_block.bytes().await
+    }
+
+    async fn height(&self) -> u64 {
+        self.inner_block.height().await
+    }
+
+    async fn timestamp(&self) -> u64 {
+        self.inner_block.timestamp().await
+    }
+
+    async fn parent(&self) -> ids::Id {
+        self.inner_block.parent().await
+    }
+
+    /// Verifies that the block extends a known parent by one, then verifies
+    /// the inner block, and tells the listener about it.
+    async fn verify(&mut self) -> io::Result<()> {
+        let parent_id = self.inner_block.parent().await;
+        let parent_height = self.parent_height().await?.ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("parent block {} is not known", parent_id),
+        ))?;
+        let height = self.inner_block.height().await;
+        if parent_height.checked_add(1) != Some(height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("block height {} does not follow parent height {}", height, parent_height),
+            ));
+        }
+        Arc::make_mut(&mut self.inner_block).verify().await?;
+        self.status = Some(choices::status::Status::Processing);
+        if let Some(listener) = self.listener.as_ref() {
+            listener.verified(&self.inner_block).await?;
+        }
         Ok(())
     }
 
@@ -87,6 +206,7 @@ impl <
 mod test {
 
     use super::*;
+    use movement_sdk::Layer;
 
     #[derive(Debug, Clone)]
     pub struct MyBlock(String);
@@ -152,13 +272,14 @@ mod test {
     async fn test_avalanche_block() {
         let data_availability_layer = Arc::new(RwLock::new(MyDataAvailabilityLayer));
         let block = MyBlock("hello".to_string());
-        let avalanche_block = AvalancheBlock::new(block, data_availability_layer);
+        let mut avalanche_block = AvalancheBlock::new(block, data_availability_layer);
         let id = avalanche_block.id().await;
         assert_eq!(id, ids::Id::sha256("hello".as_bytes()));
         let status = avalanche_block.status().await;
         assert_eq!(status, choices::status::Status::Unknown);
         let result = avalanche_block.accept().await;
         assert!(result.is_ok());
+        assert_eq!(avalanche_block.status().await, choices::status::Status::Accepted);
         let result = avalanche_block.reject().await;
         assert!(result.is_ok());
     }
@@ -1,73 +1,317 @@
 use avalanche_types::{
     subnet,
     choices,
-    ids::{self, Id},
-    subnet::rpc::consensus::snowman::{
-        Decidable,
-        Block as Blockable
-    }
+    ids,
+    subnet::rpc::consensus::snowman::Block as Blockable,
 };
-use movement_sdk::DataAvailabilityLayer;
+use movement_sdk::{Layer, DataAvailabilityLayer};
+use crate::data_availability::BlockStatusReader;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use std::io;
-use std::collections::HashMap;
-use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json;
 
-pub struct AvalancheDataAvailabilityLayer<Block> {
-    pub db: Arc<RwLock<Box<dyn subnet::rpc::database::Database + Send + Sync>>>,
-    pub verified_blocks: Arc<RwLock<HashMap<ids::Id, Block>>>,
+const STATUS_PREFIX: u8 = 0x0;
+
+const ACCEPTED_PREFIX: u8 = 0x1;
+
+const HEIGHT_PREFIX: u8 = 0x2;
+
+const DELIMITER: u8 = b'/';
+
+/// Key of the number of blocks accepted so far.
+const ACCEPTED_COUNT_KEY: &[u8] = b"accepted_count";
+
+/// Key of the number of accepted blocks taken by the execution layer so far.
+const SERVED_COUNT_KEY: &[u8] = b"served_count";
+
+/// Returns the key a block is persisted under.
+/// 'STATUS_PREFIX' + 'BYTE_DELIMITER' + [block_id]
+fn block_with_status_key(block_id: &ids::Id) -> Vec<u8> {
+    let mut k: Vec<u8> = Vec::with_capacity(ids::LEN + 2);
+    k.push(STATUS_PREFIX);
+    k.push(DELIMITER);
+    k.extend_from_slice(&block_id.to_vec());
+    k
 }
 
-impl <Block : Serialize + DeserializeOwned> AvalancheDataAvailabilityLayer<Block> {
+/// Returns the key the id of the `index`-th accepted block is persisted under.
+/// 'ACCEPTED_PREFIX' + 'BYTE_DELIMITER' + [index]
+fn accepted_block_key(index: u64) -> Vec<u8> {
+    let mut k: Vec<u8> = Vec::with_capacity(10);
+    k.push(ACCEPTED_PREFIX);
+    k.push(DELIMITER);
+    k.extend_from_slice(&index.to_be_bytes());
+    k
+}
 
-    pub fn get_block_with_status_key(
-        block : &Block
-    ) -> Result<ids::Id, anyhow::Error> {
-        let bytes = serde_json::to_vec(&block)?;
-        let id = ids::Id::from_slice(&bytes.as_slice());
-        Ok(id)
+/// Returns the key the id of the block accepted at a height is persisted under.
+/// 'HEIGHT_PREFIX' + 'BYTE_DELIMITER' + [height]
+fn block_at_height_key(height: u64) -> Vec<u8> {
+    let mut k: Vec<u8> = Vec::with_capacity(10);
+    k.push(HEIGHT_PREFIX);
+    k.push(DELIMITER);
+    k.extend_from_slice(&height.to_be_bytes());
+    k
+}
+
+type Db = Box<dyn subnet::rpc::database::Database + Send + Sync>;
+
+/// Reads a value, or `None` if the key is not in the database.
+async fn get(db: &Db, key: &[u8]) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    match db.get(key).await {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if subnet::rpc::errors::is_not_found(&e) => Ok(None),
+        Err(e) => Err(e.into()),
     }
+}
 
-    pub async fn write_block(
-        &self,
-        block: &Block
-    ) -> Result<(), anyhow::Error> {
-        let mut db = self.db.write().await;
-        let key = Self::get_block_with_status_key(block)?.to_vec().as_slice();
-        let value = serde_json::to_vec(block)?.as_slice();
-        db.put(key, value).await?;
-        Ok(())
+/// Reads a counter, zero if it was never written.
+async fn get_count(db: &Db, key: &[u8]) -> Result<u64, anyhow::Error> {
+    match get(db, key).await? {
+        Some(value) => {
+            let bytes: [u8; 8] = value.as_slice().try_into()
+                .map_err(|_| anyhow::anyhow!("invalid counter {}", String::from_utf8_lossy(key)))?;
+            Ok(u64::from_be_bytes(bytes))
+        }
+        None => Ok(0),
     }
+}
+
+/// Wraps a block and its status.
+/// This is the data format that blocks are persisted in.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct BlockWithStatus<Block> {
+    block: Block,
+    status: choices::status::Status,
+}
 
+/// Data availability layer that persists decided blocks in the avalanchego
+/// database, and hands accepted blocks to the execution layer in the order
+/// they were accepted.
+///
+/// Blocks are keyed by their [`Decidable::id`](avalanche_types::subnet::rpc::consensus::snowman::Decidable::id),
+/// the id the consensus engine knows them by, and accepted blocks are indexed
+/// by height. The order blocks are accepted in, and how many of them the
+/// execution layer took, are persisted too, so that a layer
+/// [`open`](Self::open)ed on the same database serves the rest.
+pub struct AvalancheDataAvailabilityLayer<Block> {
+    pub db: Arc<RwLock<Db>>,
+    /// Blocks that are verified but not yet accepted or rejected.
+    pub verified_blocks: Arc<RwLock<HashMap<ids::Id, Block>>>,
+    /// Accepted blocks not yet taken by the execution layer, oldest first.
+    pub accepted_blocks: Arc<RwLock<VecDeque<Block>>>,
+}
+
+impl <Block> Clone for AvalancheDataAvailabilityLayer<Block> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            verified_blocks: self.verified_blocks.clone(),
+            accepted_blocks: self.accepted_blocks.clone(),
+        }
+    }
+}
+
+impl <Block> Debug for AvalancheDataAvailabilityLayer<Block> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AvalancheDataAvailabilityLayer").finish_non_exhaustive()
+    }
 }
 
 impl <Block> AvalancheDataAvailabilityLayer<Block> {
 
+    /// Creates a layer on a database no block was decided in yet, see
+    /// [`open`](Self::open) otherwise.
     pub fn new(
-        db: Arc<RwLock<Box<dyn subnet::rpc::database::Database + Send + Sync>>>,
+        db: Arc<RwLock<Db>>,
         verified_blocks: Arc<RwLock<HashMap<ids::Id, Block>>>,
     ) -> Self {
         Self {
             db,
             verified_blocks,
+            accepted_blocks: Arc::new(RwLock::new(VecDeque::new())),
         }
     }
 
+    /// Creates a layer backed by an in-memory database.
+    pub fn new_in_memory() -> Self {
+        Self::new(
+            Arc::new(RwLock::new(subnet::rpc::database::memdb::Database::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+        )
+    }
+
 }
 
+impl <Block : Blockable + Serialize + DeserializeOwned + Clone> AvalancheDataAvailabilityLayer<Block> {
+
+    /// Returns the key the block and its status are persisted under.
+    pub async fn get_block_with_status_key(
+        block : &Block
+    ) -> Vec<u8> {
+        block_with_status_key(&block.id().await)
+    }
+
+    /// Opens a layer on a database, and queues the accepted blocks the
+    /// execution layer has yet to take.
+    pub async fn open(
+        db: Arc<RwLock<Db>>,
+        verified_blocks: Arc<RwLock<HashMap<ids::Id, Block>>>,
+    ) -> Result<Self, anyhow::Error> {
+        let layer = Self::new(db, verified_blocks);
+        {
+            let db = layer.db.read().await;
+            let mut accepted_blocks = layer.accepted_blocks.write().await;
+            let accepted = get_count(&db, ACCEPTED_COUNT_KEY).await?;
+            for index in get_count(&db, SERVED_COUNT_KEY).await?..accepted {
+                let block_id = get(&db, &accepted_block_key(index)).await?
+                    .ok_or_else(|| anyhow::anyhow!("accepted block {} is missing", index))?;
+                let block_id = ids::Id::from_slice(&block_id);
+                let (block, _) = Self::read_block(&db, &block_id).await?
+                    .ok_or_else(|| anyhow::anyhow!("accepted block {} is missing", block_id))?;
+                accepted_blocks.push_back(block);
+            }
+        }
+        Ok(layer)
+    }
+
+    async fn read_block(
+        db: &Db,
+        block_id: &ids::Id,
+    ) -> Result<Option<(Block, choices::status::Status)>, anyhow::Error> {
+        let value = match get(db, &block_with_status_key(block_id)).await? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let block_with_status: BlockWithStatus<Block> = serde_json::from_slice(&value)?;
+        Ok(Some((block_with_status.block, block_with_status.status)))
+    }
+
+    fn encode_block(
+        block: &Block,
+        status: choices::status::Status,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(serde_json::to_vec(&BlockWithStatus {
+            block: block.clone(),
+            status,
+        })?)
+    }
+
+    async fn put_block(
+        db: &mut Db,
+        block_id: &ids::Id,
+        block: &Block,
+        status: choices::status::Status,
+    ) -> Result<(), anyhow::Error> {
+        let value = Self::encode_block(block, status)?;
+        db.put(&block_with_status_key(block_id), &value).await?;
+        Ok(())
+    }
+
+    /// Persists a block along with its status, and returns its id.
+    pub async fn write_block(
+        &self,
+        block: &Block,
+        status: choices::status::Status,
+    ) -> Result<ids::Id, anyhow::Error> {
+        let block_id = block.id().await;
+        let mut db = self.db.write().await;
+        Self::put_block(&mut db, &block_id, block, status).await?;
+        Ok(block_id)
+    }
+
+    /// Reads a persisted block along with its status.
+    pub async fn get_block_with_status(
+        &self,
+        block_id: &ids::Id,
+    ) -> Result<Option<(Block, choices::status::Status)>, anyhow::Error> {
+        let db = self.db.read().await;
+        Self::read_block(&db, block_id).await
+    }
+
+    /// Tracks a verified block until it is accepted or rejected, and returns
+    /// its id.
+    pub async fn add_verified_block(
+        &self,
+        block: Block,
+    ) -> Result<ids::Id, anyhow::Error> {
+        let block_id = block.id().await;
+        self.verified_blocks.write().await.insert(block_id, block);
+        Ok(block_id)
+    }
+
+    /// Persists the decision on a block. Deciding a block again is a no-op,
+    /// so that an accepted block is only handed to the execution layer once.
+    ///
+    /// The database stays locked from the check to the write, so that a block
+    /// decided concurrently is only decided once. The block, and the indexes
+    /// of an accepted one, are written in a single batch.
+    async fn decide(
+        &self,
+        block: Block,
+        status: choices::status::Status,
+    ) -> Result<(), anyhow::Error> {
+        let block_id = block.id().await;
+        let mut db = self.db.write().await;
+        if let Some((_, decided)) = Self::read_block(&db, &block_id).await? {
+            if decided == status {
+                return Ok(());
+            }
+            return Err(anyhow::anyhow!(
+                "block {} is already {}, cannot mark it {}",
+                block_id,
+                decided.as_str(),
+                status.as_str()
+            ));
+        }
+
+        let accepted = status == choices::status::Status::Accepted;
+        let mut batch = db.new_batch().await?;
+        batch.put(&block_with_status_key(&block_id), &Self::encode_block(&block, status)?).await?;
+        if accepted {
+            let accepted_count = get_count(&db, ACCEPTED_COUNT_KEY).await?;
+            batch.put(&accepted_block_key(accepted_count), &block_id.to_vec()).await?;
+            batch.put(ACCEPTED_COUNT_KEY, &(accepted_count + 1).to_be_bytes()).await?;
+            batch.put(&block_at_height_key(block.height().await), &block_id.to_vec()).await?;
+        }
+        batch.write().await?;
+        if accepted {
+            self.accepted_blocks.write().await.push_back(block);
+        }
+        drop(db);
+        self.verified_blocks.write().await.remove(&block_id);
+        Ok(())
+    }
+
+}
+
+impl <Block : Debug + Clone> Layer for AvalancheDataAvailabilityLayer<Block> {}
+
 #[async_trait::async_trait]
-impl <Block, BlockId> DataAvailabilityLayer for AvalancheDataAvailabilityLayer<Block> {
+impl <
+    Block : Blockable + Serialize + DeserializeOwned + Debug + Clone + Send + Sync
+> DataAvailabilityLayer for AvalancheDataAvailabilityLayer<Block> {
 
     type Block = Block;
-    type BlockId = BlockId;
+    type BlockId = ids::Id;
 
     /// Gets the next block from the previous layer.
+    /// The database is locked before the queue, as when deciding a block, and
+    /// the block is only taken off the queue once it is persisted as served.
     async fn get_next_block(
         &self
     ) -> Result<Option<Self::Block>, anyhow::Error> {
-        todo!()
+        let mut db = self.db.write().await;
+        let mut accepted_blocks = self.accepted_blocks.write().await;
+        if accepted_blocks.is_empty() {
+            return Ok(None);
+        }
+        let served = get_count(&db, SERVED_COUNT_KEY).await?;
+        db.put(SERVED_COUNT_KEY, &(served + 1).to_be_bytes()).await?;
+        Ok(accepted_blocks.pop_front())
     }
 
     /// Accepts a block, effectively sending it to the next layer or place retrievable from the next layer, i.e., the execution layer.
@@ -75,7 +319,7 @@ impl <Block, BlockId> DataAvailabilityLayer for AvalancheDataAvailabilityLayer<B
         &self,
         block: Self::Block
     ) -> Result<(), anyhow::Error> {
-        todo!()
+        self.decide(block, choices::status::Status::Accepted).await
     }
 
     /// Rejects a block (sometimes this won't be used).
@@ -83,7 +327,7 @@ impl <Block, BlockId> DataAvailabilityLayer for AvalancheDataAvailabilityLayer<B
         &self,
         block: Self::Block
     ) -> Result<(), anyhow::Error> {
-        todo!()
+        self.decide(block, choices::status::Status::Rejected).await
     }
 
     /// Gets a block that was either accepted or rejected by the data availability layer.
@@ -91,7 +335,225 @@ impl <Block, BlockId> DataAvailabilityLayer for AvalancheDataAvailabilityLayer<B
         &self,
         block_id: Self::BlockId
     ) -> Result<Option<Self::Block>, anyhow::Error> {
-        todo!()
+        Ok(self.get_block_with_status(&block_id).await?.map(|(block, _)| block))
+    }
+
+}
+
+#[async_trait::async_trait]
+impl <
+    Block : Blockable + Serialize + DeserializeOwned + Debug + Clone + Send + Sync
+> BlockStatusReader for AvalancheDataAvailabilityLayer<Block> {
+
+    async fn get_block_status(
+        &self,
+        block_id: Self::BlockId
+    ) -> Result<Option<choices::status::Status>, anyhow::Error> {
+        Ok(self.get_block_with_status(&block_id).await?.map(|(_, status)| status))
+    }
+
+    async fn last_accepted(
+        &self
+    ) -> Result<Option<Self::BlockId>, anyhow::Error> {
+        let db = self.db.read().await;
+        let accepted = get_count(&db, ACCEPTED_COUNT_KEY).await?;
+        if accepted == 0 {
+            return Ok(None);
+        }
+        Ok(get(&db, &accepted_block_key(accepted - 1)).await?
+            .map(|block_id| ids::Id::from_slice(&block_id)))
+    }
+
+    async fn get_block_id_at_height(
+        &self,
+        height: u64
+    ) -> Result<Option<Self::BlockId>, anyhow::Error> {
+        let db = self.db.read().await;
+        Ok(get(&db, &block_at_height_key(height)).await?
+            .map(|block_id| ids::Id::from_slice(&block_id)))
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::data_availability::block::AvalancheBlock;
+    use avalanche_types::subnet::rpc::consensus::snowman::Decidable;
+    use crate::data_availability::BlockStatusReader;
+    use std::io;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    pub struct MyBlock {
+        height: u64,
+        data: String,
+    }
+
+    fn block(height: u64) -> MyBlock {
+        MyBlock {
+            height,
+            data: format!("block {}", height),
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Decidable for MyBlock {
+        async fn id(&self) -> ids::Id {
+            ids::Id::sha256(self.data.as_bytes())
+        }
+
+        async fn status(&self) -> choices::status::Status {
+            choices::status::Status::Processing
+        }
+
+        async fn accept(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        async fn reject(&mut self) -> io::Result<()> {
+            Ok(())
+        }
     }
 
-}
\ No newline at end of file
+    #[async_trait::async_trait]
+    impl Blockable for MyBlock {
+        async fn bytes(&self) -> &[u8] {
+            self.data.as_bytes()
+        }
+
+        async fn height(&self) -> u64 {
+            self.height
+        }
+
+        async fn timestamp(&self) -> u64 {
+            0
+        }
+
+        async fn parent(&self) -> ids::Id {
+            ids::Id::empty()
+        }
+
+        async fn verify(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_accepted_blocks_are_served_in_order() {
+        let layer = AvalancheDataAvailabilityLayer::new_in_memory();
+        for height in 0..3 {
+            layer.add_verified_block(block(height)).await.unwrap();
+        }
+        layer.accept_block(block(0)).await.unwrap();
+        layer.reject_block(block(1)).await.unwrap();
+        layer.accept_block(block(2)).await.unwrap();
+        // accepting again does not serve the block twice
+        layer.accept_block(block(2)).await.unwrap();
+        assert!(layer.verified_blocks.read().await.is_empty());
+
+        assert_eq!(layer.get_next_block().await.unwrap(), Some(block(0)));
+        assert_eq!(layer.get_next_block().await.unwrap(), Some(block(2)));
+        assert_eq!(layer.get_next_block().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_decided_blocks_are_persisted_with_status() {
+        let layer = AvalancheDataAvailabilityLayer::new_in_memory();
+        layer.accept_block(block(0)).await.unwrap();
+        layer.reject_block(block(1)).await.unwrap();
+
+        // blocks are keyed by the id the consensus engine knows them by
+        let id = block(0).id().await;
+        assert_eq!(id, ids::Id::sha256(b"block 0"));
+        assert_eq!(layer.get_block(id).await.unwrap(), Some(block(0)));
+        let id = block(1).id().await;
+        assert_eq!(
+            layer.get_block_with_status(&id).await.unwrap(),
+            Some((block(1), choices::status::Status::Rejected))
+        );
+        let id = block(2).id().await;
+        assert_eq!(layer.get_block(id).await.unwrap(), None);
+
+        // a block is decided once
+        assert!(layer.reject_block(block(0)).await.is_err());
+
+        // the blocks live in the database, not in the layer
+        let reopened = AvalancheDataAvailabilityLayer::<MyBlock>::open(
+            layer.db.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
+        ).await.unwrap();
+        let id = block(0).id().await;
+        assert_eq!(reopened.get_block(id).await.unwrap(), Some(block(0)));
+    }
+
+    #[tokio::test]
+    async fn test_accepted_blocks_are_served_after_reopening() {
+        let layer = AvalancheDataAvailabilityLayer::new_in_memory();
+        for height in 0..3 {
+            layer.accept_block(block(height)).await.unwrap();
+        }
+        assert_eq!(layer.get_next_block().await.unwrap(), Some(block(0)));
+
+        let reopened = AvalancheDataAvailabilityLayer::<MyBlock>::open(
+            layer.db.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
+        ).await.unwrap();
+        assert_eq!(reopened.get_next_block().await.unwrap(), Some(block(1)));
+        reopened.accept_block(block(3)).await.unwrap();
+
+        let reopened = AvalancheDataAvailabilityLayer::<MyBlock>::open(
+            layer.db.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
+        ).await.unwrap();
+        assert_eq!(reopened.get_next_block().await.unwrap(), Some(block(2)));
+        assert_eq!(reopened.get_next_block().await.unwrap(), Some(block(3)));
+        assert_eq!(reopened.get_next_block().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_accepted_blocks_are_indexed_by_height() {
+        let layer = AvalancheDataAvailabilityLayer::new_in_memory();
+        assert_eq!(layer.last_accepted().await.unwrap(), None);
+        layer.accept_block(block(0)).await.unwrap();
+        layer.reject_block(block(1)).await.unwrap();
+        layer.accept_block(block(2)).await.unwrap();
+
+        assert_eq!(layer.last_accepted().await.unwrap(), Some(block(2).id().await));
+        assert_eq!(layer.get_block_id_at_height(0).await.unwrap(), Some(block(0).id().await));
+        assert_eq!(layer.get_block_id_at_height(1).await.unwrap(), None);
+        assert_eq!(layer.get_block_id_at_height(2).await.unwrap(), Some(block(2).id().await));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_accepts_serve_a_block_once() {
+        let layer = AvalancheDataAvailabilityLayer::new_in_memory();
+        let accepts = (0..8).map(|_| {
+            let layer = layer.clone();
+            tokio::spawn(async move { layer.accept_block(block(0)).await })
+        }).collect::<Vec<_>>();
+        for accept in accepts {
+            accept.await.unwrap().unwrap();
+        }
+        assert_eq!(layer.get_next_block().await.unwrap(), Some(block(0)));
+        assert_eq!(layer.get_next_block().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_avalanche_block() {
+        let layer = Arc::new(RwLock::new(AvalancheDataAvailabilityLayer::new_in_memory()));
+        let mut accepted = AvalancheBlock::new(block(0), layer.clone());
+        let mut rejected = AvalancheBlock::new(block(1), layer.clone());
+        accepted.accept().await.unwrap();
+        rejected.reject().await.unwrap();
+
+        let layer = layer.read().await;
+        assert_eq!(layer.get_next_block().await.unwrap(), Some(block(0)));
+        let id = rejected.id().await;
+        assert_eq!(
+            layer.get_block_with_status(&id).await.unwrap(),
+            Some((block(1), choices::status::Status::Rejected))
+        );
+    }
+
+}
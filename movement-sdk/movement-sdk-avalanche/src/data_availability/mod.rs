@@ -1,2 +1,29 @@
 pub mod data_availability_layer;
-pub mod block;
\ No newline at end of file
+pub mod block;
+
+use avalanche_types::choices;
+use movement_sdk::DataAvailabilityLayer;
+
+/// Data availability layer that records whether the blocks it holds were
+/// accepted or rejected, and indexes the accepted ones.
+#[async_trait::async_trait]
+pub trait BlockStatusReader : DataAvailabilityLayer {
+
+    /// Gets the status of a block that was either accepted or rejected.
+    async fn get_block_status(
+        &self,
+        block_id: Self::BlockId
+    ) -> Result<Option<choices::status::Status>, anyhow::Error>;
+
+    /// Gets the id of the block accepted last, if any.
+    async fn last_accepted(
+        &self
+    ) -> Result<Option<Self::BlockId>, anyhow::Error>;
+
+    /// Gets the id of the block accepted at a height.
+    async fn get_block_id_at_height(
+        &self,
+        height: u64
+    ) -> Result<Option<Self::BlockId>, anyhow::Error>;
+
+}
@@ -1,2 +1,3 @@
 pub mod data_availability;
-pub mod proposer;
\ No newline at end of file
+pub mod proposer;
+pub mod vm;
\ No newline at end of file
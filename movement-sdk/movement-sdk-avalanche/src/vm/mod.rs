@@ -0,0 +1,1060 @@
+//! Avalanche snowman Vm composed of movement-sdk layers.
+//!
+//! [`AvalancheVm`] serves the consensus engine by delegating to the layers:
+//! transactions submitted through its handler go to the sequencer layer,
+//! blocks are built by the proposer layer and decided through the data
+//! availability layer, and accepted blocks flow from the execution layer into
+//! the storage layer.
+
+use avalanche_types::{
+    choices,
+    ids,
+    proto::http::Element,
+    subnet::{
+        self,
+        rpc::{
+            consensus::snowman::{Block as Blockable, Decidable},
+            database::manager::DatabaseManager,
+            health::Checkable,
+            http::handle::Handle,
+            snow::{
+                self,
+                engine::common::{
+                    appsender::client::AppSenderClient,
+                    engine::{AppHandler, CrossChainAppHandler, NetworkAppHandler},
+                    http_handler::{HttpHandler, LockOptions},
+                    message::Message,
+                    vm::{CommonVm, Connector},
+                },
+                validators::client::ValidatorStateClient,
+            },
+            snowman::block::{BatchedChainVm, ChainVm, Getter, Parser},
+        },
+    },
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use movement_sdk::{DataAvailabilityLayer, ExecutionLayer, ProposerLayer, SequencerLayer, StorageLayer};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc::Sender, Mutex, Notify, RwLock};
+use tokio::task::JoinHandle;
+
+use crate::data_availability::{
+    block::{AvalancheBlock, BlockListener},
+    BlockStatusReader,
+};
+
+/// Extension the transaction handler is registered under.
+pub const TRANSACTIONS_EXTENSION: &str = "/transactions";
+
+/// Decodes the blocks of the layers from the bytes returned by their
+/// [`bytes`](Blockable::bytes), e.g., the genesis block and the blocks
+/// gossiped by other validators.
+pub trait FromBytes : Sized {
+
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self>;
+
+}
+
+/// Block the consensus engine is handed.
+pub type VmBlock<D> = AvalancheBlock<<D as DataAvailabilityLayer>::Block, ids::Id, D>;
+
+/// Consensus state of the Vm.
+#[derive(Debug)]
+struct VmState<Block> {
+    bootstrapped: bool,
+    preferred: ids::Id,
+    last_accepted: ids::Id,
+    /// Blocks that are verified but not yet accepted or rejected.
+    verified_blocks: HashMap<ids::Id, Block>,
+    to_engine: Option<Sender<Message>>,
+}
+
+impl <Block> Default for VmState<Block> {
+    fn default() -> Self {
+        Self {
+            bootstrapped: false,
+            preferred: ids::Id::empty(),
+            last_accepted: ids::Id::empty(),
+            verified_blocks: HashMap::new(),
+            to_engine: None,
+        }
+    }
+}
+
+/// Keeps the [`VmState`] up to date with the blocks the engine verifies and
+/// decides, and wakes the pipeline up when a block is accepted.
+#[derive(Debug)]
+struct Decisions<Block> {
+    state: Arc<RwLock<VmState<Block>>>,
+    accepted: Arc<Notify>,
+}
+
+#[async_trait::async_trait]
+impl <Block : Decidable + Debug + Send + Sync> BlockListener<Block> for Decisions<Block>
+where Block : Clone {
+
+    async fn verified(&self, block: &Block) -> io::Result<()> {
+        let id = block.id().await;
+        self.state.write().await.verified_blocks.insert(id, block.clone());
+        Ok(())
+    }
+
+    async fn accepted(&self, block: &Block) -> io::Result<()> {
+        let id = block.id().await;
+        let mut state = self.state.write().await;
+        state.verified_blocks.remove(&id);
+        state.last_accepted = id;
+        drop(state);
+        self.accepted.notify_one();
+        Ok(())
+    }
+
+    async fn rejected(&self, block: &Block) -> io::Result<()> {
+        let id = block.id().await;
+        self.state.write().await.verified_blocks.remove(&id);
+        Ok(())
+    }
+
+    async fn get_verified(&self, block_id: &ids::Id) -> Option<Block> {
+        self.state.read().await.verified_blocks.get(block_id).cloned()
+    }
+
+}
+
+/// Snowman Vm delegating to a sequencer `S`, a proposer `P`, a data
+/// availability layer `D`, an execution layer `E` and a storage layer `St`.
+///
+/// The layers are expected to be wired to each other, e.g., the execution
+/// layer takes its next block from the data availability layer. The Vm
+/// restarts from the block the data availability layer accepted last, and
+/// serves heights from its index.
+pub struct AvalancheVm<S, P, D, E, St>
+where
+    D : DataAvailabilityLayer,
+{
+    pub sequencer: Arc<S>,
+    pub proposer: Arc<P>,
+    pub data_availability: Arc<RwLock<D>>,
+    pub execution: Arc<E>,
+    pub storage: Arc<St>,
+    state: Arc<RwLock<VmState<D::Block>>>,
+    /// Notified when a block is accepted, to run it through the pipeline.
+    accepted: Arc<Notify>,
+    /// Accepted block the execution layer took but failed to execute or send
+    /// on, run through the pipeline again first. Locked while the pipeline
+    /// runs, so that blocks are executed in order.
+    unexecuted: Arc<Mutex<Option<D::Block>>>,
+    /// Background tasks, cancelled on shutdown.
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl <S, P, D, E, St> Clone for AvalancheVm<S, P, D, E, St>
+where
+    D : DataAvailabilityLayer,
+{
+    fn clone(&self) -> Self {
+        Self {
+            sequencer: self.sequencer.clone(),
+            proposer: self.proposer.clone(),
+            data_availability: self.data_availability.clone(),
+            execution: self.execution.clone(),
+            storage: self.storage.clone(),
+            state: self.state.clone(),
+            accepted: self.accepted.clone(),
+            unexecuted: self.unexecuted.clone(),
+            tasks: self.tasks.clone(),
+        }
+    }
+}
+
+impl <S, P, D, E, St> AvalancheVm<S, P, D, E, St>
+where
+    S : SequencerLayer + Send + Sync + 'static,
+    S::Transaction : DeserializeOwned + Send,
+    P : ProposerLayer<Block = D::Block> + Send + Sync + 'static,
+    D : DataAvailabilityLayer<BlockId = ids::Id> + BlockStatusReader + Send + Sync + 'static,
+    D::Block : Blockable + FromBytes + Clone + Debug + Send + Sync + 'static,
+    E : ExecutionLayer<Block = D::Block> + Send + Sync + 'static,
+    E::ChangeSet : Send,
+    St : StorageLayer + Send + Sync + 'static,
+    St::ChangeSet : Send,
+{
+
+    pub fn new(
+        sequencer: S,
+        proposer: P,
+        data_availability: D,
+        execution: E,
+        storage: St,
+    ) -> Self {
+        Self {
+            sequencer: Arc::new(sequencer),
+            proposer: Arc::new(proposer),
+            data_availability: Arc::new(RwLock::new(data_availability)),
+            execution: Arc::new(execution),
+            storage: Arc::new(storage),
+            state: Arc::new(RwLock::new(VmState::default())),
+            accepted: Arc::new(Notify::new()),
+            unexecuted: Arc::new(Mutex::new(None)),
+            tasks: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Wraps a block of the layers for the consensus engine.
+    fn wrap(&self, block: D::Block, status: Option<choices::status::Status>) -> VmBlock<D> {
+        let mut block = AvalancheBlock::new(block, self.data_availability.clone())
+            .with_listener(Arc::new(Decisions {
+                state: self.state.clone(),
+                accepted: self.accepted.clone(),
+            }));
+        block.status = status;
+        block
+    }
+
+    /// Accepts the genesis block unless it already was, and starts from the
+    /// block accepted last. Then spawns the task running accepted blocks
+    /// through the pipeline.
+    pub async fn init(
+        &self,
+        genesis_bytes: &[u8],
+        to_engine: Option<Sender<Message>>,
+    ) -> Result<(), anyhow::Error> {
+        let genesis = D::Block::from_bytes(genesis_bytes)?;
+        let genesis_id = genesis.id().await;
+        let data_availability = self.data_availability.read().await;
+        if data_availability.get_block_status(genesis_id).await?.is_none() {
+            data_availability.accept_block(genesis).await?;
+        }
+        let last_accepted = data_availability.last_accepted().await?.unwrap_or(genesis_id);
+        drop(data_availability);
+        {
+            let mut state = self.state.write().await;
+            state.preferred = last_accepted;
+            state.last_accepted = last_accepted;
+            state.to_engine = to_engine;
+        }
+        self.advance().await?;
+
+        let vm = self.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                vm.accepted.notified().await;
+                if let Err(e) = vm.advance().await {
+                    tracing::error!("failed to run accepted blocks through the pipeline: {}", e);
+                }
+            }
+        });
+        self.tasks.lock().await.push(task);
+        Ok(())
+    }
+
+    /// Executes the accepted blocks the execution layer has yet to, and
+    /// applies the resulting change sets to the storage layer. A block that
+    /// fails is kept to be executed again on the next call.
+    pub async fn advance(&self) -> Result<(), anyhow::Error> {
+        let mut unexecuted = self.unexecuted.lock().await;
+        loop {
+            let block = match unexecuted.take() {
+                Some(block) => block,
+                None => match self.execution.get_next_block().await? {
+                    Some(block) => block,
+                    None => break,
+                },
+            };
+            let result = match self.execution.execute_block(block.clone()).await {
+                Ok(change_set) => self.execution.send_change_set(change_set).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                *unexecuted = Some(block);
+                return Err(e);
+            }
+        }
+        drop(unexecuted);
+        while let Some(change_set) = self.storage.get_next_change_set().await? {
+            self.storage.derive_state(change_set).await?;
+        }
+        Ok(())
+    }
+
+    /// Gets a block that is either verified, accepted or rejected.
+    async fn get_block_with_status(&self, block_id: ids::Id) -> Result<Option<VmBlock<D>>, anyhow::Error> {
+        if let Some(block) = self.state.read().await.verified_blocks.get(&block_id).cloned() {
+            return Ok(Some(self.wrap(block, Some(choices::status::Status::Processing))));
+        }
+        let data_availability = self.data_availability.read().await;
+        let status = match data_availability.get_block_status(block_id).await? {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        Ok(data_availability
+            .get_block(block_id)
+            .await?
+            .map(|block| self.wrap(block, Some(status))))
+    }
+
+}
+
+#[async_trait::async_trait]
+impl <S, P, D, E, St> ChainVm for AvalancheVm<S, P, D, E, St>
+where
+    S : SequencerLayer + Send + Sync + 'static,
+    S::Transaction : DeserializeOwned + Send,
+    P : ProposerLayer<Block = D::Block> + Send + Sync + 'static,
+    D : DataAvailabilityLayer<BlockId = ids::Id> + BlockStatusReader + Send + Sync + 'static,
+    D::Block : Blockable + FromBytes + Clone + Debug + Send + Sync + 'static,
+    E : ExecutionLayer<Block = D::Block> + Send + Sync + 'static,
+    E::ChangeSet : Send,
+    St : StorageLayer + Send + Sync + 'static,
+    St::ChangeSet : Send,
+{
+    type Block = VmBlock<D>;
+
+    /// Builds a block on the preferred one with the proposer layer. The
+    /// engine verifies it next.
+    async fn build_block(&self) -> io::Result<Self::Block> {
+        let preferred = self.state.read().await.preferred;
+        let parent = self.get_block_with_status(preferred).await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to get preferred block: {}", e)))?
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("preferred block {} not found", preferred)))?;
+        let block = self.proposer.build_block((*parent.inner_block).clone()).await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to build block: {}", e)))?;
+        Ok(self.wrap(block, None))
+    }
+
+    async fn issue_tx(&self) -> io::Result<Self::Block> {
+        Err(Error::new(ErrorKind::Unsupported, "issue_tx not implemented"))
+    }
+
+    async fn set_preference(&self, id: ids::Id) -> io::Result<()> {
+        self.state.write().await.preferred = id;
+        Ok(())
+    }
+
+    async fn last_accepted(&self) -> io::Result<ids::Id> {
+        Ok(self.state.read().await.last_accepted)
+    }
+
+    /// The data availability layer indexes blocks by height as it accepts
+    /// them, so the index is always complete.
+    async fn verify_height_index(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn get_block_id_at_height(&self, height: u64) -> io::Result<ids::Id> {
+        self.data_availability.read().await.get_block_id_at_height(height).await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to get block at height {}: {}", height, e)))?
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no block accepted at height {}", height)))
+    }
+
+    async fn state_sync_enabled(&self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+#[async_trait::async_trait]
+impl <S, P, D, E, St> BatchedChainVm for AvalancheVm<S, P, D, E, St>
+where
+    S : SequencerLayer + Send + Sync + 'static,
+    S::Transaction : DeserializeOwned + Send,
+    P : ProposerLayer<Block = D::Block> + Send + Sync + 'static,
+    D : DataAvailabilityLayer<BlockId = ids::Id> + BlockStatusReader + Send + Sync + 'static,
+    D::Block : Blockable + FromBytes + Clone + Debug + Send + Sync + 'static,
+    E : ExecutionLayer<Block = D::Block> + Send + Sync + 'static,
+    E::ChangeSet : Send,
+    St : StorageLayer + Send + Sync + 'static,
+    St::ChangeSet : Send,
+{
+    type Block = VmBlock<D>;
+
+    /// Returns the block and its ancestors, until the count or size budget
+    /// is exhausted or a block is missing.
+    async fn get_ancestors(
+        &self,
+        block_id: ids::Id,
+        max_block_num: i32,
+        max_block_size: i32,
+        _max_block_retrival_time: Duration,
+    ) -> io::Result<Vec<Bytes>> {
+        let mut ancestors = Vec::new();
+        let mut size = 0;
+        let mut next = Some(block_id);
+        while let Some(id) = next {
+            if ancestors.len() >= max_block_num.max(0) as usize {
+                break;
+            }
+            let block = match self.get_block_with_status(id).await {
+                Ok(Some(block)) => block,
+                _ => break,
+            };
+            let bytes = block.bytes().await;
+            if size + bytes.len() > max_block_size.max(0) as usize {
+                break;
+            }
+            size += bytes.len();
+            ancestors.push(Bytes::copy_from_slice(bytes));
+            next = if block.height().await == 0 { None } else { Some(block.parent().await) };
+        }
+        if ancestors.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, format!("block {} not found", block_id)));
+        }
+        Ok(ancestors)
+    }
+
+    async fn batched_parse_block(&self, blocks: &[Vec<u8>]) -> io::Result<Vec<Self::Block>> {
+        let mut parsed = Vec::with_capacity(blocks.len());
+        for bytes in blocks {
+            parsed.push(Parser::parse_block(self, bytes).await?);
+        }
+        Ok(parsed)
+    }
+}
+
+#[async_trait::async_trait]
+impl <S, P, D, E, St> Getter for AvalancheVm<S, P, D, E, St>
+where
+    S : SequencerLayer + Send + Sync + 'static,
+    S::Transaction : DeserializeOwned + Send,
+    P : ProposerLayer<Block = D::Block> + Send + Sync + 'static,
+    D : DataAvailabilityLayer<BlockId = ids::Id> + BlockStatusReader + Send + Sync + 'static,
+    D::Block : Blockable + FromBytes + Clone + Debug + Send + Sync + 'static,
+    E : ExecutionLayer<Block = D::Block> + Send + Sync + 'static,
+    E::ChangeSet : Send,
+    St : StorageLayer + Send + Sync + 'static,
+    St::ChangeSet : Send,
+{
+    type Block = VmBlock<D>;
+
+    async fn get_block(&self, block_id: ids::Id) -> io::Result<Self::Block> {
+        self.get_block_with_status(block_id).await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to get block: {}", e)))?
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("block {} not found", block_id)))
+    }
+}
+
+#[async_trait::async_trait]
+impl <S, P, D, E, St> Parser for AvalancheVm<S, P, D, E, St>
+where
+    S : SequencerLayer + Send + Sync + 'static,
+    S::Transaction : DeserializeOwned + Send,
+    P : ProposerLayer<Block = D::Block> + Send + Sync + 'static,
+    D : DataAvailabilityLayer<BlockId = ids::Id> + BlockStatusReader + Send + Sync + 'static,
+    D::Block : Blockable + FromBytes + Clone + Debug + Send + Sync + 'static,
+    E : ExecutionLayer<Block = D::Block> + Send + Sync + 'static,
+    E::ChangeSet : Send,
+    St : StorageLayer + Send + Sync + 'static,
+    St::ChangeSet : Send,
+{
+    type Block = VmBlock<D>;
+
+    /// Returns the known block if the bytes are of one, or a new block for
+    /// the engine to verify.
+    async fn parse_block(&self, bytes: &[u8]) -> io::Result<Self::Block> {
+        let block = D::Block::from_bytes(bytes)?;
+        let known = self.get_block_with_status(block.id().await).await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to get block: {}", e)))?;
+        Ok(known.unwrap_or_else(|| self.wrap(block, None)))
+    }
+}
+
+#[async_trait::async_trait]
+impl <S, P, D, E, St> CommonVm for AvalancheVm<S, P, D, E, St>
+where
+    S : SequencerLayer + Send + Sync + 'static,
+    S::Transaction : DeserializeOwned + Send,
+    P : ProposerLayer<Block = D::Block> + Send + Sync + 'static,
+    D : DataAvailabilityLayer<BlockId = ids::Id> + BlockStatusReader + Send + Sync + 'static,
+    D::Block : Blockable + FromBytes + Clone + Debug + Send + Sync + 'static,
+    E : ExecutionLayer<Block = D::Block> + Send + Sync + 'static,
+    E::ChangeSet : Send,
+    St : StorageLayer + Send + Sync + 'static,
+    St::ChangeSet : Send,
+{
+    type DatabaseManager = DatabaseManager;
+    type AppSender = AppSenderClient;
+    type ChainHandler = TransactionHandler<S>;
+    type StaticHandler = TransactionHandler<S>;
+    type ValidatorState = ValidatorStateClient;
+
+    /// Initializes the Vm from the genesis block. The layers are expected to
+    /// be set up with their own storage, so the database and the config are
+    /// not used.
+    async fn initialize(
+        &mut self,
+        _ctx: Option<subnet::rpc::context::Context<Self::ValidatorState>>,
+        _db_manager: Self::DatabaseManager,
+        genesis_bytes: &[u8],
+        _upgrade_bytes: &[u8],
+        _config_bytes: &[u8],
+        to_engine: Sender<Message>,
+        _fxs: &[snow::engine::common::vm::Fx],
+        _app_sender: Self::AppSender,
+    ) -> io::Result<()> {
+        self.init(genesis_bytes, Some(to_engine)).await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to initialize Vm: {}", e)))
+    }
+
+    async fn set_state(&self, snow_state: snow::State) -> io::Result<()> {
+        let mut state = self.state.write().await;
+        match snow_state {
+            snow::State::Initializing | snow::State::StateSyncing | snow::State::Bootstrapping => {
+                state.bootstrapped = false;
+            }
+            snow::State::NormalOp => {
+                state.bootstrapped = true;
+            }
+        }
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> io::Result<()> {
+        for task in self.tasks.lock().await.drain(..) {
+            task.abort();
+        }
+        Ok(())
+    }
+
+    async fn version(&self) -> io::Result<String> {
+        Ok(String::from(env!("CARGO_PKG_VERSION")))
+    }
+
+    async fn create_static_handlers(
+        &mut self,
+    ) -> io::Result<HashMap<String, HttpHandler<Self::StaticHandler>>> {
+        Ok(HashMap::new())
+    }
+
+    async fn create_handlers(
+        &mut self,
+    ) -> io::Result<HashMap<String, HttpHandler<Self::ChainHandler>>> {
+        let handler = TransactionHandler {
+            sequencer: self.sequencer.clone(),
+            to_engine: self.state.read().await.to_engine.clone(),
+        };
+        let mut handlers = HashMap::new();
+        handlers.insert(
+            TRANSACTIONS_EXTENSION.to_string(),
+            HttpHandler {
+                lock_option: LockOptions::NoLock,
+                handler,
+                server_addr: None,
+            },
+        );
+        Ok(handlers)
+    }
+}
+
+#[async_trait::async_trait]
+impl <S, P, D, E, St> NetworkAppHandler for AvalancheVm<S, P, D, E, St>
+where
+    S : Send + Sync,
+    P : Send + Sync,
+    D : DataAvailabilityLayer + Send + Sync,
+    D::Block : Send + Sync,
+    E : Send + Sync,
+    St : Send + Sync,
+{
+    async fn app_request(&self, _node_id: &ids::node::Id, _request_id: u32, _deadline: DateTime<Utc>, _request: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn app_request_failed(&self, _node_id: &ids::node::Id, _request_id: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn app_response(&self, _node_id: &ids::node::Id, _request_id: u32, _response: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn app_gossip(&self, _node_id: &ids::node::Id, _msg: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl <S, P, D, E, St> CrossChainAppHandler for AvalancheVm<S, P, D, E, St>
+where
+    S : Send + Sync,
+    P : Send + Sync,
+    D : DataAvailabilityLayer + Send + Sync,
+    D::Block : Send + Sync,
+    E : Send + Sync,
+    St : Send + Sync,
+{
+    async fn cross_chain_app_request(&self, _chain_id: &ids::Id, _request_id: u32, _deadline: DateTime<Utc>, _request: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn cross_chain_app_request_failed(&self, _chain_id: &ids::Id, _request_id: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn cross_chain_app_response(&self, _chain_id: &ids::Id, _request_id: u32, _response: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl <S, P, D, E, St> AppHandler for AvalancheVm<S, P, D, E, St>
+where
+    S : Send + Sync,
+    P : Send + Sync,
+    D : DataAvailabilityLayer + Send + Sync,
+    D::Block : Send + Sync,
+    E : Send + Sync,
+    St : Send + Sync,
+{}
+
+#[async_trait::async_trait]
+impl <S, P, D, E, St> Connector for AvalancheVm<S, P, D, E, St>
+where
+    S : Send + Sync,
+    P : Send + Sync,
+    D : DataAvailabilityLayer + Send + Sync,
+    D::Block : Send + Sync,
+    E : Send + Sync,
+    St : Send + Sync,
+{
+    async fn connected(&self, _id: &ids::node::Id) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn disconnected(&self, _id: &ids::node::Id) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl <S, P, D, E, St> Checkable for AvalancheVm<S, P, D, E, St>
+where
+    S : Send + Sync,
+    P : Send + Sync,
+    D : DataAvailabilityLayer + Send + Sync,
+    D::Block : Send + Sync,
+    E : Send + Sync,
+    St : Send + Sync,
+{
+    async fn health_check(&self) -> io::Result<Vec<u8>> {
+        let state = self.state.read().await;
+        let report = serde_json::json!({
+            "bootstrapped": state.bootstrapped,
+            "last_accepted": state.last_accepted.to_string(),
+            "verified_blocks": state.verified_blocks.len(),
+        });
+        Ok(serde_json::to_vec(&report)?)
+    }
+}
+
+/// Hands the transactions submitted as JSON to the sequencer layer, and
+/// tells the engine there is a block to build.
+#[derive(Debug)]
+pub struct TransactionHandler<S> {
+    sequencer: Arc<S>,
+    to_engine: Option<Sender<Message>>,
+}
+
+impl <S> Clone for TransactionHandler<S> {
+    fn clone(&self) -> Self {
+        Self {
+            sequencer: self.sequencer.clone(),
+            to_engine: self.to_engine.clone(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl <S> Handle for TransactionHandler<S>
+where
+    S : SequencerLayer + Send + Sync,
+    S::Transaction : DeserializeOwned + Send,
+{
+    async fn request(
+        &self,
+        req: &Bytes,
+        _headers: &[Element],
+    ) -> io::Result<(Bytes, Vec<Element>)> {
+        let transaction: S::Transaction = serde_json::from_slice(req)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("failed to decode transaction: {}", e)))?;
+        self.sequencer.receive_transaction(transaction).await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to receive transaction: {}", e)))?;
+        if let Some(to_engine) = self.to_engine.as_ref() {
+            if let Err(e) = to_engine.send(Message::PendingTxs).await {
+                tracing::error!("failed to notify the engine: {}", e);
+            }
+        }
+        Ok((Bytes::from_static(b"{}"), Vec::new()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::data_availability::data_availability_layer::AvalancheDataAvailabilityLayer;
+    use movement_sdk::Layer;
+    use serde::{Deserialize, Serialize};
+    use std::collections::VecDeque;
+
+    type Da = AvalancheDataAvailabilityLayer<MyBlock>;
+
+    /// What a [`MyBlock`] is encoded as, so that its bytes are rebuilt when it
+    /// is read back from the data availability layer.
+    #[derive(Serialize, Deserialize)]
+    struct MyBlockData {
+        parent: ids::Id,
+        height: u64,
+        transactions: Vec<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    #[serde(from = "MyBlockData", into = "MyBlockData")]
+    pub struct MyBlock {
+        parent: ids::Id,
+        height: u64,
+        transactions: Vec<String>,
+        bytes: Vec<u8>,
+    }
+
+    impl MyBlock {
+        fn new(parent: ids::Id, height: u64, transactions: Vec<String>) -> Self {
+            let data = MyBlockData { parent, height, transactions };
+            let bytes = serde_json::to_vec(&data).unwrap();
+            Self { parent: data.parent, height: data.height, transactions: data.transactions, bytes }
+        }
+    }
+
+    impl From<MyBlockData> for MyBlock {
+        fn from(data: MyBlockData) -> Self {
+            Self::new(data.parent, data.height, data.transactions)
+        }
+    }
+
+    impl From<MyBlock> for MyBlockData {
+        fn from(block: MyBlock) -> Self {
+            Self { parent: block.parent, height: block.height, transactions: block.transactions }
+        }
+    }
+
+    impl FromBytes for MyBlock {
+        fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+            let mut block: Self = serde_json::from_slice(bytes)?;
+            block.bytes = bytes.to_vec();
+            Ok(block)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Decidable for MyBlock {
+        async fn id(&self) -> ids::Id {
+            ids::Id::sha256(&self.bytes)
+        }
+
+        async fn status(&self) -> choices::status::Status {
+            choices::status::Status::Processing
+        }
+
+        async fn accept(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        async fn reject(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Blockable for MyBlock {
+        async fn bytes(&self) -> &[u8] {
+            &self.bytes
+        }
+
+        async fn height(&self) -> u64 {
+            self.height
+        }
+
+        async fn timestamp(&self) -> u64 {
+            0
+        }
+
+        async fn parent(&self) -> ids::Id {
+            self.parent
+        }
+
+        async fn verify(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Sequences transactions, and proposes blocks of them.
+    #[derive(Debug, Clone, Default)]
+    pub struct MyProposer {
+        transactions: Arc<RwLock<Vec<String>>>,
+    }
+
+    impl Layer for MyProposer {}
+
+    #[async_trait::async_trait]
+    impl SequencerLayer for MyProposer {
+        type Transaction = String;
+        type TransactionId = String;
+
+        async fn receive_transaction(&self, transaction: String) -> Result<(), anyhow::Error> {
+            self.transactions.write().await.push(transaction);
+            Ok(())
+        }
+
+        async fn get_transaction(&self, transaction_id: String) -> Result<Option<String>, anyhow::Error> {
+            Ok(self.transactions.read().await.iter().find(|t| **t == transaction_id).cloned())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ProposerLayer for MyProposer {
+        type Transaction = String;
+        type Block = MyBlock;
+        type BlockId = ids::Id;
+
+        async fn get_next_transaction(&self) -> Result<Option<String>, anyhow::Error> {
+            Ok(self.transactions.read().await.first().cloned())
+        }
+
+        async fn build_block(&self, parent: MyBlock) -> Result<MyBlock, anyhow::Error> {
+            let transactions = std::mem::take(&mut *self.transactions.write().await);
+            Ok(MyBlock::new(parent.id().await, parent.height + 1, transactions))
+        }
+
+        async fn send_block(&self, _block: MyBlock) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+
+        async fn get_block(&self, _block_id: ids::Id) -> Result<Option<MyBlock>, anyhow::Error> {
+            Ok(None)
+        }
+    }
+
+    /// Executes the blocks accepted through the data availability layer by
+    /// counting their transactions, and stores the counts.
+    #[derive(Debug, Clone)]
+    pub struct MyExecution {
+        data_availability: Da,
+        change_sets: Arc<RwLock<VecDeque<(ids::Id, usize)>>>,
+        state: Arc<RwLock<HashMap<ids::Id, usize>>>,
+        /// Whether the next block fails to execute.
+        fail_next: Arc<RwLock<bool>>,
+    }
+
+    impl Layer for MyExecution {}
+
+    #[async_trait::async_trait]
+    impl ExecutionLayer for MyExecution {
+        type Block = MyBlock;
+        type BlockId = ids::Id;
+        type ChangeSet = (ids::Id, usize);
+
+        async fn get_next_block(&self) -> Result<Option<MyBlock>, anyhow::Error> {
+            self.data_availability.get_next_block().await
+        }
+
+        async fn execute_block(&self, block: MyBlock) -> Result<(ids::Id, usize), anyhow::Error> {
+            if std::mem::take(&mut *self.fail_next.write().await) {
+                return Err(anyhow::anyhow!("failed to execute block"));
+            }
+            Ok((block.id().await, block.transactions.len()))
+        }
+
+        async fn send_change_set(&self, change_set: (ids::Id, usize)) -> Result<(), anyhow::Error> {
+            self.change_sets.write().await.push_back(change_set);
+            Ok(())
+        }
+
+        async fn get_block(&self, block_id: ids::Id) -> Result<Option<MyBlock>, anyhow::Error> {
+            self.data_availability.get_block(block_id).await
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl StorageLayer for MyExecution {
+        type Block = MyBlock;
+        type BlockId = ids::Id;
+        type ChangeSet = (ids::Id, usize);
+        type StateEntry = usize;
+        type Address = ids::Id;
+
+        async fn get_next_change_set(&self) -> Result<Option<(ids::Id, usize)>, anyhow::Error> {
+            Ok(self.change_sets.write().await.pop_front())
+        }
+
+        async fn derive_state(&self, change_set: (ids::Id, usize)) -> Result<(), anyhow::Error> {
+            self.state.write().await.insert(change_set.0, change_set.1);
+            Ok(())
+        }
+
+        async fn get_state_entry(&self, address: ids::Id) -> Result<Option<usize>, anyhow::Error> {
+            Ok(self.state.read().await.get(&address).copied())
+        }
+
+        async fn get_change_set(&self, block_id: ids::Id) -> Result<Option<(ids::Id, usize)>, anyhow::Error> {
+            Ok(self.state.read().await.get(&block_id).map(|n| (block_id, *n)))
+        }
+    }
+
+    type MyVm = AvalancheVm<MyProposer, MyProposer, Da, MyExecution, MyExecution>;
+
+    async fn new_vm() -> (MyVm, MyExecution, MyBlock) {
+        open_vm(Da::new_in_memory()).await
+    }
+
+    async fn open_vm(data_availability: Da) -> (MyVm, MyExecution, MyBlock) {
+        let proposer = MyProposer::default();
+        let execution = MyExecution {
+            data_availability: data_availability.clone(),
+            change_sets: Arc::new(RwLock::new(VecDeque::new())),
+            state: Arc::new(RwLock::new(HashMap::new())),
+            fail_next: Arc::new(RwLock::new(false)),
+        };
+        let vm = AvalancheVm::new(
+            proposer.clone(),
+            proposer,
+            data_availability,
+            execution.clone(),
+            execution.clone(),
+        );
+        let genesis = MyBlock::new(ids::Id::empty(), 0, vec![]);
+        vm.init(&genesis.bytes, None).await.unwrap();
+        (vm, execution, genesis)
+    }
+
+    #[tokio::test]
+    async fn test_blocks_flow_through_the_layers() {
+        let (vm, storage, genesis) = new_vm().await;
+        let genesis_id = genesis.id().await;
+        assert_eq!(ChainVm::last_accepted(&vm).await.unwrap(), genesis_id);
+        assert_eq!(storage.get_state_entry(genesis_id).await.unwrap(), Some(0));
+
+        let handlers = vm.clone().create_handlers().await.unwrap();
+        let handler = &handlers[TRANSACTIONS_EXTENSION].handler;
+        handler.request(&Bytes::from_static(br#""transfer""#), &[]).await.unwrap();
+        assert!(handler.request(&Bytes::from_static(b"not json"), &[]).await.is_err());
+
+        let mut block = ChainVm::build_block(&vm).await.unwrap();
+        block.verify().await.unwrap();
+        let id = block.id().await;
+        assert_eq!(Getter::get_block(&vm, id).await.unwrap().status().await, choices::status::Status::Processing);
+
+        block.accept().await.unwrap();
+        assert_eq!(ChainVm::last_accepted(&vm).await.unwrap(), id);
+        let accepted = Getter::get_block(&vm, id).await.unwrap();
+        assert_eq!(accepted.status().await, choices::status::Status::Accepted);
+
+        // the pipeline runs in the background
+        vm.advance().await.unwrap();
+        assert_eq!(storage.get_state_entry(id).await.unwrap(), Some(1));
+
+        let ancestors = vm.get_ancestors(id, 10, i32::MAX, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(ancestors.len(), 2);
+        assert_eq!(ancestors[1].as_ref(), genesis.bytes.as_slice());
+        assert_eq!(ChainVm::get_block_id_at_height(&vm, 1).await.unwrap(), id);
+        assert!(ChainVm::get_block_id_at_height(&vm, 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_restarts_from_the_last_accepted_block() {
+        let (vm, _, genesis) = new_vm().await;
+        vm.sequencer.receive_transaction("transfer".to_string()).await.unwrap();
+        let mut block = ChainVm::build_block(&vm).await.unwrap();
+        block.verify().await.unwrap();
+        block.accept().await.unwrap();
+        let id = block.id().await;
+        vm.shutdown().await.unwrap();
+
+        let data_availability = vm.data_availability.read().await.clone();
+        let reopened = Da::open(data_availability.db, Arc::new(RwLock::new(HashMap::new()))).await.unwrap();
+        let (vm, _, _) = open_vm(reopened).await;
+        assert_eq!(ChainVm::last_accepted(&vm).await.unwrap(), id);
+        assert_eq!(ChainVm::get_block_id_at_height(&vm, 0).await.unwrap(), genesis.id().await);
+        assert_eq!(ChainVm::get_block_id_at_height(&vm, 1).await.unwrap(), id);
+    }
+
+    #[tokio::test]
+    async fn test_builds_on_the_preferred_block() {
+        let (vm, _, genesis) = new_vm().await;
+        let mut parent = ChainVm::build_block(&vm).await.unwrap();
+        parent.verify().await.unwrap();
+        let parent_id = parent.id().await;
+
+        // the engine has yet to prefer the processing block
+        let block = ChainVm::build_block(&vm).await.unwrap();
+        assert_eq!(block.parent().await, genesis.id().await);
+
+        vm.set_preference(parent_id).await.unwrap();
+        let block = ChainVm::build_block(&vm).await.unwrap();
+        assert_eq!(block.parent().await, parent_id);
+        assert_eq!(block.height().await, 2);
+
+        vm.set_preference(ids::Id::sha256(b"unknown")).await.unwrap();
+        assert!(ChainVm::build_block(&vm).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify_requires_the_parent() {
+        let (vm, _, genesis) = new_vm().await;
+        let genesis_id = genesis.id().await;
+
+        let orphan = MyBlock::new(ids::Id::sha256(b"unknown"), 1, vec![]);
+        let mut parsed = Parser::parse_block(&vm, &orphan.bytes).await.unwrap();
+        assert!(parsed.verify().await.is_err());
+
+        for height in [0, 2] {
+            let block = MyBlock::new(genesis_id, height, vec!["transfer".to_string()]);
+            let mut parsed = Parser::parse_block(&vm, &block.bytes).await.unwrap();
+            assert!(parsed.verify().await.is_err());
+        }
+        assert_eq!(vm.state.read().await.verified_blocks.len(), 0);
+
+        // a child of a processing block
+        let parent = MyBlock::new(genesis_id, 1, vec!["transfer".to_string()]);
+        let child = MyBlock::new(parent.id().await, 2, vec![]);
+        Parser::parse_block(&vm, &parent.bytes).await.unwrap().verify().await.unwrap();
+        Parser::parse_block(&vm, &child.bytes).await.unwrap().verify().await.unwrap();
+        assert_eq!(vm.state.read().await.verified_blocks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_blocks_are_executed_again() {
+        let (vm, storage, _) = new_vm().await;
+        vm.sequencer.receive_transaction("transfer".to_string()).await.unwrap();
+        let mut block = ChainVm::build_block(&vm).await.unwrap();
+        block.verify().await.unwrap();
+        *storage.fail_next.write().await = true;
+        block.accept().await.unwrap();
+        let id = block.id().await;
+
+        // either this call or the background task fails on the block
+        let _ = vm.advance().await;
+        vm.advance().await.unwrap();
+        assert_eq!(storage.get_state_entry(id).await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_parse_block() {
+        let (vm, _, genesis) = new_vm().await;
+        let parsed = Parser::parse_block(&vm, &genesis.bytes).await.unwrap();
+        assert_eq!(parsed.status().await, choices::status::Status::Accepted);
+
+        let block = MyBlock::new(genesis.id().await, 1, vec!["transfer".to_string()]);
+        let mut parsed = Parser::parse_block(&vm, &block.bytes).await.unwrap();
+        assert_eq!(parsed.status().await, choices::status::Status::Processing);
+        assert!(Getter::get_block(&vm, block.id().await).await.is_err());
+
+        parsed.reject().await.unwrap();
+        let rejected = Getter::get_block(&vm, block.id().await).await.unwrap();
+        assert_eq!(rejected.status().await, choices::status::Status::Rejected);
+        assert_eq!(ChainVm::last_accepted(&vm).await.unwrap(), genesis.id().await);
+    }
+
+}
@@ -54,9 +54,10 @@ pub trait ProposerLayer : Layer {
         &self
     ) -> Result<Option<Self::Transaction>, anyhow::Error>;
 
-    /// Constructs a block from some transactions
+    /// Constructs a block from some transactions, on top of a parent block.
     async fn build_block(
         &self,
+        parent: Self::Block
     ) -> Result<Self::Block, anyhow::Error>;
 
     /// Sends a constructed block to the next layer.
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use prometheus::Registry;
+use sui_helper_types::{
+    providers::{
+        epoch::EpochProvider,
+        gas_info::GasInfoProvider,
+        verified_executable_transaction::VerifiedExecutableBlockProvider
+    },
+    block::{Block, VerifiedExecutableBlock}
+};
+use sui_protocol_config::{Chain, ProtocolConfig};
+use sui_types::{
+    base_types::ObjectRef,
+    committee::EpochId,
+    executable_transaction::VerifiedExecutableTransaction,
+    gas::SuiGasStatus,
+    metrics::BytecodeVerifierMetrics,
+    signature::VerifyParams,
+    sui_system_state::epoch_start_sui_system_state::{EpochStartSystemState, EpochStartSystemStateTrait},
+    transaction::{CheckedInputObjects, InputObjects, Transaction, TransactionData, VerifiedTransaction}
+};
+
+/// Provides a single, fixed epoch, e.g., the one a genesis starts.
+#[derive(Clone)]
+pub struct InMemoryEpoch {
+    epoch_id : EpochId,
+    epoch_timestamp : u64,
+    reference_gas_price : u64,
+    protocol_config : ProtocolConfig,
+    metrics : Arc<BytecodeVerifierMetrics>,
+}
+
+impl std::fmt::Debug for InMemoryEpoch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InMemoryEpoch")
+            .field("epoch_id", &self.epoch_id)
+            .field("epoch_timestamp", &self.epoch_timestamp)
+            .field("reference_gas_price", &self.reference_gas_price)
+            .finish()
+    }
+}
+
+impl InMemoryEpoch {
+
+    pub fn new(epoch_start_state : &EpochStartSystemState) -> Self {
+        Self {
+            epoch_id : epoch_start_state.epoch(),
+            epoch_timestamp : epoch_start_state.epoch_start_timestamp_ms(),
+            reference_gas_price : epoch_start_state.reference_gas_price(),
+            protocol_config : ProtocolConfig::get_for_version(epoch_start_state.protocol_version(), Chain::Unknown),
+            metrics : Arc::new(BytecodeVerifierMetrics::new(&Registry::new())),
+        }
+    }
+
+    pub fn reference_gas_price(&self) -> u64 {
+        self.reference_gas_price
+    }
+
+}
+
+#[async_trait::async_trait]
+impl EpochProvider for InMemoryEpoch {
+
+    async fn epoch_id(&self) -> Result<EpochId, anyhow::Error> {
+        Ok(self.epoch_id)
+    }
+
+    async fn epoch_timestamp(&self) -> Result<u64, anyhow::Error> {
+        Ok(self.epoch_timestamp)
+    }
+
+    async fn protocol_config(&self) -> Result<ProtocolConfig, anyhow::Error> {
+        Ok(self.protocol_config.clone())
+    }
+
+}
+
+#[async_trait::async_trait]
+impl GasInfoProvider for InMemoryEpoch {
+
+    async fn gas_status(&self, transaction_data : &TransactionData, input_objects : InputObjects, object_refs : &[ObjectRef]) -> Result<(SuiGasStatus, CheckedInputObjects), anyhow::Error> {
+        Ok(sui_transaction_checks::check_transaction_input(
+            &self.protocol_config,
+            self.reference_gas_price,
+            transaction_data,
+            input_objects,
+            object_refs,
+            &self.metrics,
+        )?)
+    }
+
+}
+
+#[async_trait::async_trait]
+impl VerifiedExecutableBlockProvider for InMemoryEpoch {
+
+    /// Checks the signatures of the transactions and certifies them as system transactions of the epoch.
+    async fn verified_executable_block(&self, block : &Block) -> Result<VerifiedExecutableBlock, anyhow::Error> {
+        let transactions = block.transactions().iter().cloned().map(|data| {
+            let transaction = Transaction::new(data);
+            transaction.verify_signature(&VerifyParams::default())?;
+            Ok(VerifiedExecutableTransaction::new_system(
+                VerifiedTransaction::new_unchecked(transaction),
+                self.epoch_id,
+            ))
+        }).collect::<Result<Vec<_>, anyhow::Error>>()?;
+        Ok(VerifiedExecutableBlock::new(transactions))
+    }
+
+}
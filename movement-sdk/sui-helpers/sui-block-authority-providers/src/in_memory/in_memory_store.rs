@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use sui_helper_types::{
+    providers::{
+        input_object::InputObjectProvider,
+        object_store::ObjectStoreWriter,
+        object_version::ObjectVersionProvider
+    },
+    block::VerifiedExecutableExecutionGroups
+};
+use sui_types::{
+    base_types::{ObjectID, ObjectRef, SequenceNumber, SuiAddress, TransactionDigest, VersionNumber},
+    committee::EpochId,
+    error::{SuiError, SuiResult},
+    object::{Object, Owner},
+    storage::{
+        load_package_object_from_object_store,
+        BackingPackageStore,
+        ChildObjectResolver,
+        ObjectStore,
+        PackageObject,
+        ParentSync
+    },
+    transaction::{InputObjectKind, InputObjects, TransactionData, TransactionDataAPI}
+};
+
+/// Backing store keeping the live version of every object in memory.
+///
+/// It also assigns the versions of shared objects, so that a block can be executed against it end to end.
+/// Concept sourced from MystenLab's in memory store: https://github.com/MystenLabs/sui/blob/552158d9eae200314499809d8977f732f6c2cee7/crates/simulacrum/src/store/in_mem_store.rs
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    objects : RwLock<BTreeMap<ObjectID, Object>>,
+    /// Versions of the shared objects assigned to transactions whose input objects are yet to be loaded.
+    shared_object_versions : RwLock<HashMap<TransactionDigest, Vec<(ObjectID, SequenceNumber)>>>,
+}
+
+impl InMemoryStore {
+
+    /// Creates a store holding the given objects, e.g., the objects of a genesis.
+    pub fn new(objects : impl IntoIterator<Item = Object>) -> Self {
+        Self {
+            objects : RwLock::new(objects.into_iter().map(|object| (object.id(), object)).collect()),
+            shared_object_versions : RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the live objects owned by an address.
+    pub fn owned_objects(&self, owner : SuiAddress) -> Vec<Object> {
+        self.objects.read().unwrap()
+            .values()
+            .filter(|object| matches!(object.owner, Owner::AddressOwner(address) if address == owner))
+            .cloned()
+            .collect()
+    }
+
+}
+
+impl BackingPackageStore for InMemoryStore {
+    fn get_package_object(
+        &self,
+        package_id: &ObjectID,
+    ) -> SuiResult<Option<PackageObject>> {
+        load_package_object_from_object_store(self, package_id)
+    }
+}
+
+impl ChildObjectResolver for InMemoryStore {
+    fn read_child_object(
+        &self,
+        parent: &ObjectID,
+        child: &ObjectID,
+        child_version_upper_bound: SequenceNumber,
+    ) -> SuiResult<Option<Object>> {
+        let child_object = match self.objects.read().unwrap().get(child) {
+            None => return Ok(None),
+            Some(object) => object.clone(),
+        };
+
+        let parent = *parent;
+        if child_object.owner != Owner::ObjectOwner(parent.into()) {
+            return Err(SuiError::InvalidChildObjectAccess {
+                object: *child,
+                given_parent: parent,
+                actual_owner: child_object.owner,
+            });
+        }
+
+        if child_object.version() > child_version_upper_bound {
+            return Err(SuiError::UnsupportedFeatureError {
+                error: "InMemoryStore::read_child_object does not support bounded reads".to_owned(),
+            });
+        }
+
+        Ok(Some(child_object))
+    }
+
+    fn get_object_received_at_version(
+        &self,
+        owner: &ObjectID,
+        receiving_object_id: &ObjectID,
+        receive_object_at_version: SequenceNumber,
+        _epoch_id: EpochId,
+    ) -> SuiResult<Option<Object>> {
+        let recv_object = match self.objects.read().unwrap().get(receiving_object_id) {
+            None => return Ok(None),
+            Some(object) => object.clone(),
+        };
+        if recv_object.owner != Owner::AddressOwner((*owner).into()) {
+            return Ok(None);
+        }
+
+        if recv_object.version() != receive_object_at_version {
+            return Ok(None);
+        }
+        Ok(Some(recv_object))
+    }
+}
+
+impl ObjectStore for InMemoryStore {
+    fn get_object(
+        &self,
+        object_id: &ObjectID,
+    ) -> Result<Option<Object>, SuiError> {
+        Ok(self.objects.read().unwrap().get(object_id).cloned())
+    }
+
+    fn get_object_by_key(
+        &self,
+        object_id: &ObjectID,
+        version: VersionNumber,
+    ) -> Result<Option<Object>, SuiError> {
+        // only the live version is kept
+        Ok(self.objects.read().unwrap()
+            .get(object_id)
+            .filter(|object| object.version() == version)
+            .cloned())
+    }
+}
+
+impl ParentSync for InMemoryStore {
+    fn get_latest_parent_entry_ref_deprecated(
+        &self,
+        _object_id: ObjectID,
+    ) -> SuiResult<Option<ObjectRef>> {
+        panic!("Never called in newer protocol versions")
+    }
+}
+
+#[async_trait::async_trait]
+impl ObjectStoreWriter for InMemoryStore {
+
+    async fn update_objects(&self, written_objects : BTreeMap<ObjectID, Object>, deleted_objects : Vec<ObjectRef>) -> Result<(), anyhow::Error> {
+        let mut objects = self.objects.write().unwrap();
+        for (object_id, _, _) in deleted_objects {
+            objects.remove(&object_id);
+        }
+        objects.extend(written_objects);
+        Ok(())
+    }
+
+}
+
+#[async_trait::async_trait]
+impl InputObjectProvider for InMemoryStore {
+
+    async fn input_objects(&self, transaction_data : &TransactionData) -> Result<InputObjects, anyhow::Error> {
+        let digest = transaction_data.digest();
+        let shared_object_versions : HashMap<ObjectID, SequenceNumber> = self.shared_object_versions.write().unwrap()
+            .remove(&digest)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+
+        let objects = self.objects.read().unwrap();
+        let mut input_objects = Vec::new();
+        for kind in transaction_data.input_objects()? {
+            let version = match &kind {
+                InputObjectKind::MovePackage(_) => None,
+                InputObjectKind::ImmOrOwnedMoveObject((_, version, _)) => Some(*version),
+                InputObjectKind::SharedMoveObject { id, .. } => Some(*shared_object_versions.get(id).ok_or_else(|| {
+                    anyhow::anyhow!("no version of shared object {} is assigned to transaction {}", id, digest)
+                })?),
+            };
+            let object = objects.get(&kind.object_id())
+                .filter(|object| version.map_or(true, |version| object.version() == version))
+                .ok_or_else(|| anyhow::anyhow!("input object {:?} of transaction {} is not available", kind, digest))?;
+            input_objects.push((kind, object.clone()));
+        }
+        Ok(InputObjects::new(input_objects))
+    }
+
+}
+
+#[async_trait::async_trait]
+impl ObjectVersionProvider for InMemoryStore {
+
+    /// Assigns the versions shared objects will have when each transaction executes.
+    /// The groups are assigned one after the other, each in order, the way they are executed.
+    /// As in Sui, a transaction mutating a shared object bumps it to the lamport version of the transaction.
+    async fn assign_shared_object_versions(&self, transactions : VerifiedExecutableExecutionGroups) -> Result<VerifiedExecutableExecutionGroups, anyhow::Error> {
+        let mut next_versions : HashMap<ObjectID, SequenceNumber> = HashMap::new();
+        let mut assigned = HashMap::new();
+        {
+            let objects = self.objects.read().unwrap();
+            for transaction in transactions.groups().iter().flatten() {
+                let transaction_data = transaction.data().transaction_data();
+                let mut shared_object_versions = Vec::new();
+                let mut input_versions = Vec::new();
+                let mut mutated = Vec::new();
+                for kind in transaction_data.input_objects()? {
+                    match kind {
+                        InputObjectKind::SharedMoveObject { id, initial_shared_version, mutable } => {
+                            let version = match next_versions.get(&id) {
+                                Some(version) => *version,
+                                None => objects.get(&id).map_or(initial_shared_version, |object| object.version()),
+                            };
+                            shared_object_versions.push((id, version));
+                            input_versions.push(version);
+                            if mutable {
+                                mutated.push(id);
+                            }
+                        },
+                        InputObjectKind::ImmOrOwnedMoveObject((_, version, _)) => input_versions.push(version),
+                        InputObjectKind::MovePackage(_) => {},
+                    }
+                }
+                input_versions.extend(transaction_data.receiving_objects().into_iter().map(|(_, version, _)| version));
+
+                let lamport_version = SequenceNumber::lamport_increment(input_versions);
+                for id in mutated {
+                    next_versions.insert(id, lamport_version);
+                }
+                assigned.insert(*transaction.digest(), shared_object_versions);
+            }
+        }
+        self.shared_object_versions.write().unwrap().extend(assigned);
+        Ok(transactions)
+    }
+
+    async fn shared_object_versions(&self, transaction : &TransactionDigest) -> Result<Vec<(ObjectID, SequenceNumber)>, anyhow::Error> {
+        Ok(self.shared_object_versions.read().unwrap().get(transaction).cloned().unwrap_or_default())
+    }
+
+}
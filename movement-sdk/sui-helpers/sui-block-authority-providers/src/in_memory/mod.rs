@@ -0,0 +1,2 @@
+pub mod in_memory_epoch;
+pub mod in_memory_store;
\ No newline at end of file
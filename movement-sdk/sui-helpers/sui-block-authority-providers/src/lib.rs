@@ -1 +1,2 @@
+pub mod in_memory;
 pub mod object_version;
\ No newline at end of file
@@ -1,3 +1,4 @@
+use sui_types::base_types::{ObjectID, SequenceNumber, TransactionDigest};
 use sui_helper_types::{
     providers::object_version::ObjectVersionProvider,
     block::VerifiedExecutableExecutionGroups
@@ -14,8 +15,12 @@ impl ObjectVersionProvider for ObjectVersionRocksDB {
     // todo: implement against rocksdb store
     // todo: add methods to the trait in the `sui-helper-types` crate
 
-    async fn assign_shared_object_versions(&self, transactions : VerifiedExecutableExecutionGroups) -> Result<VerifiedExecutableExecutionGroups, anyhow::Error> {
-        unimplemented!();
+    async fn assign_shared_object_versions(&self, _transactions : VerifiedExecutableExecutionGroups) -> Result<VerifiedExecutableExecutionGroups, anyhow::Error> {
+        Err(anyhow::anyhow!("ObjectVersionRocksDB does not assign shared object versions yet"))
+    }
+
+    async fn shared_object_versions(&self, _transaction : &TransactionDigest) -> Result<Vec<(ObjectID, SequenceNumber)>, anyhow::Error> {
+        Err(anyhow::anyhow!("ObjectVersionRocksDB does not track shared object versions yet"))
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,60 @@
+//! Groups synthetic blocks into parallel execution groups.
+//!
+//! Each transaction of a block mutates an owned object of its own and accesses shared objects drawn from a pool; the smaller the pool, the more transactions conflict.
+//! Besides the time grouping takes, the parallelism of the groups is printed: the largest group bounds how fast the block can execute.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sui_helper_types::block::parallel::{parallel_groups, ObjectAccess};
+
+const BLOCK_SIZE : u64 = 1_000;
+
+const SHARED_OBJECTS_PER_TRANSACTION : usize = 2;
+
+/// Builds a block whose transactions access shared objects from a pool of `pool_size`, mutably with probability `mutable`.
+fn synthetic_block(pool_size : u64, mutable : f64) -> Vec<Vec<ObjectAccess<u64>>> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..BLOCK_SIZE).map(|transaction| {
+        // owned objects are past the pool, one per transaction
+        let mut accesses = vec![ObjectAccess::Mutable(pool_size + transaction)];
+        for _ in 0..SHARED_OBJECTS_PER_TRANSACTION {
+            let object = rng.gen_range(0, pool_size);
+            accesses.push(if rng.gen_bool(mutable) {
+                ObjectAccess::Mutable(object)
+            } else {
+                ObjectAccess::ReadOnly(object)
+            });
+        }
+        accesses
+    }).collect()
+}
+
+fn bench_parallel_groups(c : &mut Criterion) {
+    let mut group = c.benchmark_group("parallel_groups");
+    for pool_size in [10_000, 1_000, 100] {
+        for mutable in [0.0, 0.1, 0.5] {
+            let block = synthetic_block(pool_size, mutable);
+
+            let groups = parallel_groups(block.clone(), |accesses| accesses.clone());
+            let largest = groups.iter().map(Vec::len).max().unwrap_or(0);
+            println!(
+                "pool {:>6}, mutable {:.1}: {:>4} groups, largest {:>4}, parallelism {:.1}x",
+                pool_size,
+                mutable,
+                groups.len(),
+                largest,
+                BLOCK_SIZE as f64 / largest.max(1) as f64
+            );
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("pool {}", pool_size), mutable),
+                &block,
+                |b, block| b.iter(|| parallel_groups(block.clone(), |accesses| accesses.clone())),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parallel_groups);
+criterion_main!(benches);
@@ -2,12 +2,34 @@ use sui_types::{
     transaction::SenderSignedData,
     executable_transaction::VerifiedExecutableTransaction
 };
+use crate::block::parallel::{object_accesses, parallel_groups};
 
 /// A SuiBlock is a block as we would most often expect it to be constructed. 
 /// It contains only user signed data. 
 #[derive(Debug, Clone)]
 pub struct Block(Vec<SenderSignedData>);
 
+impl Block {
+
+    pub fn new(transactions : Vec<SenderSignedData>) -> Self {
+        Self(transactions)
+    }
+
+    pub fn transactions(&self) -> &[SenderSignedData] {
+        &self.0
+    }
+
+}
+
+impl IntoIterator for Block {
+    type Item = SenderSignedData;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
 /// A VerifiedBlock is a block that has been verified by the SuiBlockExecutor; it contains `CertificateEnvelope`s for each transaction.
 /// In most cases, this should be internally constructed.
 #[derive(Debug, Clone)]
@@ -16,6 +38,18 @@ pub struct VerifiedExecutableBlock(Vec<VerifiedExecutableTransaction>);
 #[derive(Debug, Clone)]
 pub struct VerifiedExecutableExecutionGroups(Vec<Vec<VerifiedExecutableTransaction>>);
 
+impl VerifiedExecutableExecutionGroups {
+
+    pub fn new(groups : Vec<Vec<VerifiedExecutableTransaction>>) -> Self {
+        Self(groups)
+    }
+
+    pub fn groups(&self) -> &[Vec<VerifiedExecutableTransaction>] {
+        &self.0
+    }
+
+}
+
 impl IntoIterator for VerifiedExecutableExecutionGroups {
     type Item = Vec<VerifiedExecutableTransaction>;
     type IntoIter = std::vec::IntoIter<Self::Item>;
@@ -31,9 +65,13 @@ impl VerifiedExecutableBlock {
         Self(transactions)
     }
 
+    pub fn transactions(&self) -> &[VerifiedExecutableTransaction] {
+        &self.0
+    }
+
     pub fn get_max_parallel_groups(&self) -> VerifiedExecutableExecutionGroups {
-        // todo: see readme
-        unimplemented!();
+        // see readme, conflicting transactions are grouped so that each group can run on its own
+        VerifiedExecutableExecutionGroups(parallel_groups(self.0.clone(), object_accesses))
     }
 
 
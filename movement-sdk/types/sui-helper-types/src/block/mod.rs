@@ -1,2 +1,3 @@
 pub mod block;
+pub mod parallel;
 pub use block::*;
\ No newline at end of file
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use sui_types::{
+    base_types::ObjectID,
+    executable_transaction::VerifiedExecutableTransaction,
+    transaction::{InputObjectKind, TransactionDataAPI}
+};
+
+/// How a transaction accesses an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectAccess<K> {
+    /// The object may be mutated, e.g., an owned object or a mutable shared object.
+    Mutable(K),
+    /// The object is only read, e.g., a shared object taken by immutable reference.
+    ReadOnly(K),
+}
+
+impl <K> ObjectAccess<K> {
+
+    pub fn key(&self) -> &K {
+        match self {
+            ObjectAccess::Mutable(key) | ObjectAccess::ReadOnly(key) => key,
+        }
+    }
+
+    pub fn is_mutable(&self) -> bool {
+        matches!(self, ObjectAccess::Mutable(_))
+    }
+
+}
+
+/// Returns the objects a transaction accesses.
+/// Owned objects, including the gas coins and the received objects, count as mutable, since an immutable object cannot be told apart from an owned one without reading it.
+/// Packages are only read and never conflict.
+pub fn object_accesses(transaction : &VerifiedExecutableTransaction) -> Vec<ObjectAccess<ObjectID>> {
+    let transaction_data = transaction.data().transaction_data();
+    // the input objects of a transaction that passed signing checks are well formed
+    let mut accesses : Vec<_> = transaction_data.input_objects().unwrap_or_default()
+        .into_iter()
+        .filter_map(|kind| match kind {
+            InputObjectKind::MovePackage(_) => None,
+            InputObjectKind::ImmOrOwnedMoveObject((id, _, _)) => Some(ObjectAccess::Mutable(id)),
+            InputObjectKind::SharedMoveObject { id, mutable : true, .. } => Some(ObjectAccess::Mutable(id)),
+            InputObjectKind::SharedMoveObject { id, mutable : false, .. } => Some(ObjectAccess::ReadOnly(id)),
+        })
+        .collect();
+    accesses.extend(transaction_data.receiving_objects().into_iter().map(|(id, _, _)| ObjectAccess::Mutable(id)));
+    accesses
+}
+
+/// Partitions items into groups that can be executed in parallel.
+///
+/// Two items conflict when they access the same object and at least one of them may mutate it; conflicting items, and items conflicting with those, end up in the same group.
+/// So no object mutated in a group is accessed by any other group, while objects only read may be accessed by several groups.
+/// Items keep their relative order within a group, and groups are ordered by their first item.
+///
+/// This is a union-find over the objects the items access, in $O(n * ||S||)$ rather than the brute force $O(n^2 * ||S||)$ of the readme.
+pub fn parallel_groups<T, K>(
+    items : Vec<T>,
+    mut accesses : impl FnMut(&T) -> Vec<ObjectAccess<K>>
+) -> Vec<Vec<T>>
+where K : Hash + Eq {
+
+    // who accesses each object, in order
+    let mut accessors : HashMap<K, (Vec<usize>, bool)> = HashMap::new();
+    for (index, item) in items.iter().enumerate() {
+        for access in accesses(item) {
+            let mutable = access.is_mutable();
+            let key = match access {
+                ObjectAccess::Mutable(key) | ObjectAccess::ReadOnly(key) => key,
+            };
+            let (indices, mutated) = accessors.entry(key).or_insert_with(|| (Vec::new(), false));
+            if indices.last() != Some(&index) {
+                indices.push(index);
+            }
+            *mutated |= mutable;
+        }
+    }
+
+    let mut sets = DisjointSets::new(items.len());
+    for (indices, mutated) in accessors.values() {
+        if *mutated {
+            for pair in indices.windows(2) {
+                sets.union(pair[0], pair[1]);
+            }
+        }
+    }
+
+    // groups are created in the order of their first item
+    let mut group_of_root : HashMap<usize, usize> = HashMap::new();
+    let mut groups : Vec<Vec<T>> = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let root = sets.find(index);
+        let group = *group_of_root.entry(root).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(item);
+    }
+    groups
+
+}
+
+/// Union-find with path halving and union by size.
+struct DisjointSets {
+    parents : Vec<usize>,
+    sizes : Vec<usize>,
+}
+
+impl DisjointSets {
+
+    fn new(len : usize) -> Self {
+        Self {
+            parents : (0..len).collect(),
+            sizes : vec![1; len],
+        }
+    }
+
+    fn find(&mut self, mut index : usize) -> usize {
+        while self.parents[index] != index {
+            self.parents[index] = self.parents[self.parents[index]];
+            index = self.parents[index];
+        }
+        index
+    }
+
+    fn union(&mut self, a : usize, b : usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.sizes[a] < self.sizes[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parents[b] = a;
+        self.sizes[a] += self.sizes[b];
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    use ObjectAccess::{Mutable, ReadOnly};
+
+    /// A synthetic transaction: its position in the block and the objects it accesses.
+    type Item = (usize, Vec<ObjectAccess<u8>>);
+
+    fn groups(accesses : Vec<Vec<ObjectAccess<u8>>>) -> Vec<Vec<Item>> {
+        parallel_groups(accesses.into_iter().enumerate().collect(), |(_, accesses)| accesses.clone())
+    }
+
+    fn indices(groups : &[Vec<Item>]) -> Vec<Vec<usize>> {
+        groups.iter().map(|group| group.iter().map(|(index, _)| *index).collect()).collect()
+    }
+
+    #[test]
+    fn test_read_only_objects_overlap() {
+        let groups = groups(vec![
+            vec![ReadOnly(0), Mutable(1)],
+            vec![ReadOnly(0), Mutable(2)],
+            vec![Mutable(1)],
+            vec![ReadOnly(0), Mutable(3)],
+        ]);
+        assert_eq!(indices(&groups), vec![vec![0, 2], vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn test_conflicts_chain() {
+        // 0 and 1 share nothing, but both conflict with 2
+        let groups = groups(vec![
+            vec![Mutable(1)],
+            vec![Mutable(2)],
+            vec![ReadOnly(1), ReadOnly(2)],
+            vec![Mutable(3)],
+            vec![],
+        ]);
+        assert_eq!(indices(&groups), vec![vec![0, 1, 2], vec![3], vec![4]]);
+    }
+
+    fn access() -> impl Strategy<Value = ObjectAccess<u8>> {
+        // a small pool of objects, so that transactions conflict
+        prop_oneof![
+            (0u8..32).prop_map(Mutable),
+            (0u8..32).prop_map(ReadOnly),
+        ]
+    }
+
+    fn block() -> impl Strategy<Value = Vec<Vec<ObjectAccess<u8>>>> {
+        prop::collection::vec(prop::collection::vec(access(), 0..4), 0..64)
+    }
+
+    proptest! {
+
+        #[test]
+        fn test_groups_partition_the_block_in_order(block in block()) {
+            let len = block.len();
+            let groups = indices(&groups(block));
+            let mut seen : Vec<usize> = groups.iter().flatten().copied().collect();
+            for group in &groups {
+                prop_assert!(!group.is_empty());
+                prop_assert!(group.windows(2).all(|pair| pair[0] < pair[1]));
+            }
+            prop_assert!(groups.windows(2).all(|pair| pair[0][0] < pair[1][0]));
+            seen.sort();
+            prop_assert_eq!(seen, (0..len).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn test_mutable_objects_stay_in_one_group(block in block()) {
+            let groups = groups(block);
+            let touched : Vec<(HashSet<u8>, HashSet<u8>)> = groups.iter().map(|group| {
+                let accesses = group.iter().flat_map(|(_, accesses)| accesses.iter());
+                (
+                    accesses.clone().filter(|access| access.is_mutable()).map(|access| *access.key()).collect(),
+                    accesses.map(|access| *access.key()).collect(),
+                )
+            }).collect();
+            for (i, (mutated, _)) in touched.iter().enumerate() {
+                for (j, (_, accessed)) in touched.iter().enumerate() {
+                    if i != j {
+                        prop_assert!(mutated.is_disjoint(accessed));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn test_read_only_blocks_are_fully_parallel(keys in prop::collection::vec(prop::collection::vec(0u8..32, 0..4), 0..64)) {
+            let len = keys.len();
+            let groups = groups(keys.into_iter().map(|keys| keys.into_iter().map(ReadOnly).collect()).collect());
+            prop_assert_eq!(groups.len(), len);
+        }
+
+    }
+
+}
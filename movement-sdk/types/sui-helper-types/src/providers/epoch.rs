@@ -1,3 +1,4 @@
+use sui_protocol_config::ProtocolConfig;
 use sui_types::committee::EpochId;
 
 #[async_trait::async_trait]
@@ -8,6 +9,8 @@ pub trait EpochProvider {
 
     /// Provides the current epoch timestamp.
     async fn epoch_timestamp(&self) -> Result<u64, anyhow::Error>;
-    
 
-}
\ No newline at end of file
+    /// Provides the protocol config of the current epoch.
+    async fn protocol_config(&self) -> Result<ProtocolConfig, anyhow::Error>;
+
+}
@@ -1,5 +1,6 @@
 use sui_types::{
     transaction::{
+        CheckedInputObjects,
         InputObjects,
         TransactionData
     },
@@ -10,8 +11,9 @@ use sui_types::{
 #[async_trait::async_trait]
 pub trait GasInfoProvider {
 
-    /// Provides up to date input objects for a transaction.
+    /// Checks the input objects of a transaction against its gas budget, and provides its gas status along with the checked input objects.
+    /// `object_refs` are the objects the transaction receives.
     /// Should be similar to this: https://github.com/MystenLabs/sui/blob/552158d9eae200314499809d8977f732f6c2cee7/crates/sui-transaction-checks/src/lib.rs#L50
-    async fn gas_status(&self, transaction_data : &TransactionData, input_objects : &InputObjects, object_refs : &[ObjectRef]) -> Result<SuiGasStatus, anyhow::Error>;
+    async fn gas_status(&self, transaction_data : &TransactionData, input_objects : InputObjects, object_refs : &[ObjectRef]) -> Result<(SuiGasStatus, CheckedInputObjects), anyhow::Error>;
     
-}
\ No newline at end of file
+}
@@ -2,4 +2,5 @@ pub mod epoch;
 pub mod input_object;
 pub mod gas_info;
 pub mod verified_executable_transaction;
-pub mod object_version;
\ No newline at end of file
+pub mod object_version;
+pub mod object_store;
\ No newline at end of file
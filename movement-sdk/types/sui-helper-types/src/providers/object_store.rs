@@ -0,0 +1,14 @@
+use std::collections::BTreeMap;
+use sui_types::{
+    base_types::{ObjectID, ObjectRef},
+    object::Object
+};
+
+#[async_trait::async_trait]
+pub trait ObjectStoreWriter {
+
+    /// Writes the objects a transaction created or mutated, and removes the ones it deleted or wrapped.
+    /// Should be similar to this: https://github.com/MystenLabs/sui/blob/552158d9eae200314499809d8977f732f6c2cee7/crates/simulacrum/src/store/in_mem_store.rs
+    async fn update_objects(&self, written_objects : BTreeMap<ObjectID, Object>, deleted_objects : Vec<ObjectRef>) -> Result<(), anyhow::Error>;
+
+}
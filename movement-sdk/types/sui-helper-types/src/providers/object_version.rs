@@ -1,4 +1,5 @@
 use crate::block::VerifiedExecutableExecutionGroups;
+use sui_types::base_types::{ObjectID, SequenceNumber, TransactionDigest};
 
 // todo: expand this trait to include more analogs to these operations: https://github.com/MystenLabs/sui/blob/6ec723bcbdc4c36358d444cbfcd88ae1378761a5/crates/sui-core/src/authority/authority_per_epoch_store.rs#L301
 #[async_trait::async_trait]
@@ -7,4 +8,7 @@ pub trait ObjectVersionProvider {
     /// Assignes sequence numbers to objects in the transactions
     async fn assign_shared_object_versions(&self, transactions : VerifiedExecutableExecutionGroups) -> Result<VerifiedExecutableExecutionGroups, anyhow::Error>;
 
+    /// Provides the versions of the shared objects assigned to a transaction, so that its input objects can be loaded at those versions.
+    async fn shared_object_versions(&self, transaction : &TransactionDigest) -> Result<Vec<(ObjectID, SequenceNumber)>, anyhow::Error>;
+
 }
\ No newline at end of file